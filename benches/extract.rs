@@ -0,0 +1,98 @@
+//! Throughput of reading archives with many small entries, where the cost
+//! is dominated by header parsing and I/O calls rather than decompression.
+//!
+//! Run with `cargo bench 2>/dev/null` to keep the debug output off the
+//! terminal.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use xpack::{NullSink, ZipArchive};
+
+const ENTRIES: usize = 2000;
+const ENTRY_SIZE: usize = 256;
+
+/// Writes a stored (uncompressed) archive with `entries` entries of
+/// `size` bytes each.
+fn write_archive(path: &Path, entries: usize, size: usize) {
+    let mut archive: Vec<u8> = vec![];
+    let mut central: Vec<u8> = vec![];
+    for i in 0..entries {
+        let name: String = format!("dir/file{:05}.txt", i);
+        let data: Vec<u8> = (0..size).map(|j| b'a' + ((i + j) % 26) as u8).collect();
+        let crc: u32 = crc32fast::hash(&data);
+        let offset: u32 = archive.len() as u32;
+
+        // Local file header, stored, no data descriptor
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        archive.extend_from_slice(&crc.to_le_bytes());
+        archive.extend_from_slice(&(size as u32).to_le_bytes());
+        archive.extend_from_slice(&(size as u32).to_le_bytes());
+        archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&data);
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&[20, 3, 20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&(size as u32).to_le_bytes());
+        central.extend_from_slice(&(size as u32).to_le_bytes());
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 12]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let central_offset: u32 = archive.len() as u32;
+    archive.extend_from_slice(&central);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(entries as u16).to_le_bytes());
+    archive.extend_from_slice(&(entries as u16).to_le_bytes());
+    archive.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&central_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    fs::write(path, archive).unwrap();
+}
+
+fn many_small_entries(c: &mut Criterion) {
+    let path: PathBuf = std::env::temp_dir().join("xpack_bench_small_entries.zip");
+    write_archive(&path, ENTRIES, ENTRY_SIZE);
+
+    let mut group = c.benchmark_group("many_small_entries");
+    group.throughput(Throughput::Bytes((ENTRIES * ENTRY_SIZE) as u64));
+    group.bench_function("entries", |b| {
+        b.iter(|| {
+            let archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+            assert_eq!(archive.entries().count(), ENTRIES);
+        })
+    });
+    group.bench_function("extract", |b| {
+        b.iter(|| {
+            let archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+            archive.extract_to(&mut NullSink).unwrap();
+        })
+    });
+    #[cfg(feature = "mmap")]
+    group.bench_function("extract_mmap", |b| {
+        b.iter(|| {
+            let archive = ZipArchive::open_mmap(path.to_str().unwrap()).unwrap();
+            archive.extract_to(&mut NullSink).unwrap();
+        })
+    });
+    group.bench_function("by_name", |b| {
+        let archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        b.iter(|| {
+            for i in (0..ENTRIES).step_by(97) {
+                let name: String = format!("dir/file{:05}.txt", i);
+                archive.by_name(&name).unwrap().read_to_vec().unwrap();
+            }
+        })
+    });
+    group.finish();
+    let _ = fs::remove_file(&path);
+}
+
+criterion_group!(benches, many_small_entries);
+criterion_main!(benches);
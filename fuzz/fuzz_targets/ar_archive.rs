@@ -0,0 +1,16 @@
+//! Reads the input as an ar archive.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xpack::ar::ArArchive;
+use xpack::{ExtractOptions, MemorySink};
+
+fuzz_target!(|data: &[u8]| {
+    let mut archive = ArArchive::new(data);
+    archive.set_options(ExtractOptions {
+        max_entry_size: Some(16 << 20),
+        max_total_size: Some(64 << 20),
+        ..Default::default()
+    });
+    let _ = archive.extract_to(&mut MemorySink::new());
+});
@@ -0,0 +1,14 @@
+//! Salvages entries from the input by scanning for local headers.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use xpack::repair::salvage;
+use xpack::MemorySink;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = salvage(
+        &mut Cursor::new(data),
+        &mut MemorySink::new().with_limit(Some(64 << 20)),
+    );
+});
@@ -0,0 +1,18 @@
+//! Reads the input as a 7z archive.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use xpack::sevenz::SevenZipArchive;
+use xpack::{ExtractOptions, MemorySink};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut archive) = SevenZipArchive::new(Cursor::new(data)) {
+        archive.set_options(ExtractOptions {
+            max_entry_size: Some(16 << 20),
+            max_total_size: Some(64 << 20),
+            ..Default::default()
+        });
+        let _ = archive.extract_to(&mut MemorySink::new());
+    }
+});
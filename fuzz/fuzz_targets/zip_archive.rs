@@ -0,0 +1,27 @@
+//! Opens the input as a ZIP archive and decodes every entry.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use xpack::{ExtractOptions, MemorySink, ZipArchive, ZipFileEntry};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut archive) = ZipArchive::new(Cursor::new(data)) else {
+        return;
+    };
+    // Keep bombs from running the fuzzer out of memory
+    archive.set_options(ExtractOptions {
+        max_entry_size: Some(16 << 20),
+        max_total_size: Some(64 << 20),
+        ..Default::default()
+    });
+    let entries: Vec<ZipFileEntry> = archive.entries().take(1024).flatten().collect();
+    for entry in &entries {
+        let _ = archive.read(entry);
+        let _ = archive.read_raw(entry);
+        let _ = entry.unix_permissions();
+        let _ = entry.precise_modified();
+    }
+    let _ = archive.by_name("a");
+    let _ = archive.extract_to(&mut MemorySink::new());
+});
@@ -0,0 +1,15 @@
+//! Reads the input as a ZIP stream, from the local headers alone.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xpack::{ExtractOptions, MemorySink, ZipStreamReader};
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = ZipStreamReader::new(data);
+    reader.set_options(ExtractOptions {
+        max_entry_size: Some(16 << 20),
+        max_total_size: Some(64 << 20),
+        ..Default::default()
+    });
+    let _ = reader.extract_to(&mut MemorySink::new());
+});
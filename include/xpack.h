@@ -0,0 +1,85 @@
+#ifndef XPACK_H
+#define XPACK_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// An archive opened with [`xpack_open`], opaque to C.
+typedef struct XpackArchive XpackArchive;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Opens the ZIP archive at `path`, a UTF-8 string. Returns null on error.
+// Close the archive with [`xpack_close`].
+//
+// # Safety
+//
+// `path` must be null or point to a NUL-terminated string.
+struct XpackArchive *xpack_open(const char *path);
+
+// Closes an archive returned by [`xpack_open`]. Null is ignored.
+//
+// # Safety
+//
+// `archive` must be null or returned by `xpack_open` and not closed yet.
+void xpack_close(struct XpackArchive *archive);
+
+// Returns the number of entries, directories included, or -1 on error.
+//
+// # Safety
+//
+// `archive` must be null or an open archive.
+int64_t xpack_entry_count(const struct XpackArchive *archive);
+
+// Returns the name of entry `index`, or null on error. The string belongs
+// to the archive and is valid until it's closed.
+//
+// # Safety
+//
+// `archive` must be null or an open archive.
+const char *xpack_entry_name(const struct XpackArchive *archive, size_t index);
+
+// Extracts entry `index` below the directory `output_dir`, a UTF-8
+// string, creating missing directories and overwriting existing files.
+// Returns 0, or -1 on error.
+//
+// # Safety
+//
+// `archive` must be null or an open archive, and `output_dir` null or a
+// NUL-terminated string.
+int xpack_extract_entry(const struct XpackArchive *archive, size_t index, const char *output_dir);
+
+// Decodes entry `index` into a new buffer, stored with its length in
+// `*data` and `*len`. Returns 0, or -1 on error. Free the buffer with
+// [`xpack_free_buffer`].
+//
+// # Safety
+//
+// `archive` must be null or an open archive, and `data` and `len` null or
+// valid for writes.
+int xpack_read_entry(const struct XpackArchive *archive, size_t index, uint8_t **data, size_t *len);
+
+// Frees a buffer returned by [`xpack_read_entry`]. Null is ignored.
+//
+// # Safety
+//
+// `data` and `len` must be null or as returned by `xpack_read_entry`, and
+// not freed yet.
+void xpack_free_buffer(uint8_t *data, size_t len);
+
+// Returns the message of the last error on this thread, or null if there
+// was none. The string is valid until the next error on this thread.
+const char *xpack_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* XPACK_H */
@@ -0,0 +1,245 @@
+//! AppleDouble files, which carry the extended attributes of a file where
+//! the file itself can't.
+//!
+//! The Archive Utility of macOS stores them in ZIP archives as
+//! `__MACOSX/<dir>/._<name>` next to the file they describe. Their layout,
+//! all numbers big-endian:
+//! [4 bytes]  Magic, 0x00051607
+//! [4 bytes]  Version, 0x00020000
+//! [16 bytes] Filler
+//! [2 bytes]  Number of entries
+//! Followed by entry descriptors:
+//! [4 bytes]  Entry ID: 2 resource fork, 9 Finder info
+//! [4 bytes]  Offset of the entry data in the file
+//! [4 bytes]  Length of the entry data
+//!
+//! The Finder info entry holds 32 bytes of Finder info, 2 bytes of padding
+//! and, as macOS writes it, the other attributes behind an `ATTR` header:
+//! [4 bytes]  Magic, "ATTR"
+//! [4 bytes]  Debug tag
+//! [4 bytes]  Total size, of the file up to the end of attribute data
+//! [4 bytes]  Offset of the attribute data
+//! [4 bytes]  Length of the attribute data
+//! [12 bytes] Reserved
+//! [2 bytes]  Flags
+//! [2 bytes]  Number of attributes
+//! Followed by one record per attribute, each aligned on 4 bytes:
+//! [4 bytes]  Offset of the value in the file
+//! [4 bytes]  Length of the value
+//! [2 bytes]  Flags
+//! [1 byte]   Name length, including the NUL
+//! [variable] Name, NUL-terminated
+
+use std::io;
+
+const MAGIC: u32 = 0x0005_1607;
+const VERSION: u32 = 0x0002_0000;
+const ATTR_MAGIC: &[u8; 4] = b"ATTR";
+
+const RESOURCE_FORK_ID: u32 = 2;
+const FINDER_INFO_ID: u32 = 9;
+
+/// Attributes stored in their own AppleDouble entries rather than in the
+/// `ATTR` list
+pub const FINDER_INFO: &str = "com.apple.FinderInfo";
+pub const RESOURCE_FORK: &str = "com.apple.ResourceFork";
+
+// Where the Finder info goes when writing: after the header and the two
+// entry descriptors
+const FINDER_INFO_OFFSET: usize = 26 + 2 * 12;
+const FINDER_INFO_SIZE: usize = 32;
+const ATTR_HEADER_SIZE: usize = 36;
+
+/// Name of the AppleDouble companion of the entry `name`, e.g.
+/// `__MACOSX/docs/._a.txt` for `docs/a.txt`.
+pub fn companion_name(name: &str) -> String {
+    let name: &str = name.trim_end_matches('/');
+    match name.rsplit_once('/') {
+        Some((dir, base)) => format!("__MACOSX/{}/._{}", dir, base),
+        None => format!("__MACOSX/._{}", name),
+    }
+}
+
+/// Name of the entry the AppleDouble companion `name` describes, if it is
+/// one, without any trailing slash.
+pub fn companion_target(name: &str) -> Option<String> {
+    let rest: &str = name.strip_prefix("__MACOSX/")?;
+    let target: String = match rest.rsplit_once('/') {
+        Some((dir, base)) => format!("{}/{}", dir, base.strip_prefix("._")?),
+        None => rest.strip_prefix("._")?.to_string(),
+    };
+    Some(target).filter(|target| !target.is_empty() && !target.ends_with('/'))
+}
+
+/// Reads the extended attributes of an AppleDouble file, the Finder info
+/// and resource fork included, in the order stored.
+pub fn parse(data: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid AppleDouble file");
+    let u32_at = |offset: usize| -> io::Result<u32> {
+        let bytes: &[u8] = data.get(offset..offset + 4).ok_or_else(invalid)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let slice = |offset: u32, length: u32| -> io::Result<&[u8]> {
+        let start: usize = offset as usize;
+        data.get(start..start + length as usize).ok_or_else(invalid)
+    };
+    if data.len() < 26 || u32_at(0)? != MAGIC || u32_at(4)? != VERSION {
+        return Err(invalid());
+    }
+    let count: usize = u16::from_be_bytes([data[24], data[25]]) as usize;
+
+    let mut xattrs: Vec<(String, Vec<u8>)> = vec![];
+    for descriptor in (26..26 + count * 12).step_by(12) {
+        let (id, offset, length) = (
+            u32_at(descriptor)?,
+            u32_at(descriptor + 4)?,
+            u32_at(descriptor + 8)?,
+        );
+        let entry: &[u8] = slice(offset, length)?;
+        match id {
+            RESOURCE_FORK_ID if !entry.is_empty() => {
+                xattrs.push((RESOURCE_FORK.to_string(), entry.to_vec()));
+            }
+            FINDER_INFO_ID => {
+                let finder_info: &[u8] = &entry[..entry.len().min(FINDER_INFO_SIZE)];
+                if finder_info.iter().any(|&byte| byte != 0) {
+                    xattrs.push((FINDER_INFO.to_string(), finder_info.to_vec()));
+                }
+                let attr: usize = offset as usize + FINDER_INFO_SIZE + 2;
+                if entry.len() < FINDER_INFO_SIZE + 2 + ATTR_HEADER_SIZE
+                    || &data[attr..attr + 4] != ATTR_MAGIC
+                {
+                    continue;
+                }
+                let attributes: usize =
+                    u16::from_be_bytes([data[attr + 34], data[attr + 35]]) as usize;
+                let mut record: usize = attr + ATTR_HEADER_SIZE;
+                for _ in 0..attributes {
+                    let (value_offset, value_length) = (u32_at(record)?, u32_at(record + 4)?);
+                    let name_length: usize = *data.get(record + 10).ok_or_else(invalid)? as usize;
+                    let name: &[u8] = data
+                        .get(record + 11..record + 11 + name_length)
+                        .ok_or_else(invalid)?;
+                    let name: &[u8] = name.strip_suffix(&[0]).unwrap_or(name);
+                    let name: &str = std::str::from_utf8(name).map_err(|_| invalid())?;
+                    let value: &[u8] = slice(value_offset, value_length)?;
+                    xattrs.push((name.to_string(), value.to_vec()));
+                    record = (record + 11 + name_length + 3) & !3;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Writes an AppleDouble file carrying `xattrs` the way macOS does: the
+/// Finder info, then the other attributes behind an `ATTR` header, then the
+/// resource fork.
+pub fn encode(xattrs: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let find = |wanted: &str| {
+        xattrs
+            .iter()
+            .find(|(name, _)| name == wanted)
+            .map(|(_, value)| value.as_slice())
+    };
+    let others: Vec<&(String, Vec<u8>)> = xattrs
+        .iter()
+        .filter(|(name, _)| name != FINDER_INFO && name != RESOURCE_FORK)
+        .collect();
+    let resource_fork: &[u8] = find(RESOURCE_FORK).unwrap_or_default();
+    let mut finder_info: Vec<u8> = find(FINDER_INFO).unwrap_or_default().to_vec();
+    finder_info.resize(FINDER_INFO_SIZE, 0);
+
+    // Records first, then the values, so the offsets of the values are
+    // known once the records are laid out
+    let attr: usize = FINDER_INFO_OFFSET + FINDER_INFO_SIZE + 2;
+    let mut records: Vec<u8> = vec![];
+    let data_start: usize = attr
+        + ATTR_HEADER_SIZE
+        + others
+            .iter()
+            .map(|(name, _)| (11 + name.len() + 1 + 3) & !3)
+            .sum::<usize>();
+    let mut values: Vec<u8> = vec![];
+    for (name, value) in &others {
+        records.extend_from_slice(&((data_start + values.len()) as u32).to_be_bytes());
+        records.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        records.extend_from_slice(&0u16.to_be_bytes());
+        records.push((name.len() + 1) as u8);
+        records.extend_from_slice(name.as_bytes());
+        records.push(0);
+        records.resize((records.len() + 3) & !3, 0);
+        values.extend_from_slice(value);
+    }
+    let total_size: usize = data_start + values.len();
+
+    let mut out: Vec<u8> = Vec::with_capacity(total_size + resource_fork.len());
+    out.extend_from_slice(&MAGIC.to_be_bytes());
+    out.extend_from_slice(&VERSION.to_be_bytes());
+    out.extend_from_slice(b"Mac OS X        ");
+    out.extend_from_slice(&2u16.to_be_bytes());
+    for (id, offset, length) in [
+        (
+            FINDER_INFO_ID,
+            FINDER_INFO_OFFSET,
+            total_size - FINDER_INFO_OFFSET,
+        ),
+        (RESOURCE_FORK_ID, total_size, resource_fork.len()),
+    ] {
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(length as u32).to_be_bytes());
+    }
+    out.extend_from_slice(&finder_info);
+    out.extend_from_slice(&[0u8; 2]);
+    out.extend_from_slice(ATTR_MAGIC);
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend_from_slice(&(total_size as u32).to_be_bytes());
+    out.extend_from_slice(&(data_start as u32).to_be_bytes());
+    out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+    out.extend_from_slice(&[0u8; 12]);
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(others.len() as u16).to_be_bytes());
+    out.extend_from_slice(&records);
+    out.extend_from_slice(&values);
+    out.extend_from_slice(resource_fork);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_companion_names() {
+        assert_eq!(companion_name("docs/a.txt"), "__MACOSX/docs/._a.txt");
+        assert_eq!(companion_name("docs/"), "__MACOSX/._docs");
+        assert_eq!(
+            companion_target("__MACOSX/docs/._a.txt").as_deref(),
+            Some("docs/a.txt")
+        );
+        assert_eq!(companion_target("__MACOSX/._docs").as_deref(), Some("docs"));
+        assert_eq!(companion_target("__MACOSX/docs/"), None);
+        assert_eq!(companion_target("docs/._a.txt"), None);
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let mut finder_info: Vec<u8> = b"TEXTttxt".to_vec();
+        finder_info.resize(32, 0);
+        let xattrs: Vec<(String, Vec<u8>)> = vec![
+            (FINDER_INFO.to_string(), finder_info),
+            (
+                "com.apple.quarantine".to_string(),
+                b"0083;5f5e1000;Safari;".to_vec(),
+            ),
+            ("user.a".to_string(), vec![1, 2, 3]),
+            (RESOURCE_FORK.to_string(), b"resource fork".to_vec()),
+        ];
+        let data: Vec<u8> = encode(&xattrs);
+        assert_eq!(parse(&data)?, xattrs);
+        assert!(parse(&data[..40]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+//! Reading of Unix `ar` archives: static libraries and Debian packages.
+//!
+//! An ar archive is an 8-byte signature followed by members, each a 60-byte
+//! ASCII header and the member data padded to an even size. Member names
+//! longer than the 16 bytes of the name field are stored in one of two
+//! ways: GNU archives collect them in a `//` member and refer to them by
+//! offset, BSD archives put them in front of the data. Symbol tables that
+//! `ranlib` adds to static libraries are skipped.
+
+use crate::sink::Sink;
+use crate::writer::S_IFREG;
+use crate::zip::{Deadline, EntryLimits, ExtractOptions, ZipFileEntry};
+use std::io::{self, Read};
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const SIGNATURE: &[u8] = b"!<arch>\n";
+
+// Signature of GNU thin archives, which only refer to the member files
+const THIN_SIGNATURE: &[u8] = b"!<thin>\n";
+
+// Largest GNU long name table accepted
+const MAX_NAME_TABLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The metadata of a single ar member.
+#[derive(Debug, Clone)]
+pub struct ArHeader {
+    pub name: String,
+    pub mtime: u64,
+    pub uid: u32,
+    pub gid: u32,
+    /// Unix mode as recorded, usually with the regular file type
+    pub mode: u32,
+    pub size: u64,
+    /// Offset of the header in the archive
+    pub offset: u64,
+}
+
+impl ArHeader {
+    /// Describes the member in the form sinks expect, like
+    /// [`TarHeader::to_entry`](crate::tar::TarHeader::to_entry) does.
+    pub fn to_entry(&self, data: &[u8]) -> io::Result<ZipFileEntry> {
+        let size: u32 = u32::try_from(self.size).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is too large to extract", self.name),
+            )
+        })?;
+        Ok(ZipFileEntry {
+            filename: self.name.clone(),
+            crc32: crc32fast::hash(data),
+            compressed_size: size,
+            uncompressed_size: size,
+            file_offset: self.offset,
+            modified: UNIX_EPOCH.checked_add(Duration::from_secs(self.mtime)),
+            external_attributes: (S_IFREG | self.mode & 0o7777) << 16,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads an ar archive front to back from any reader.
+pub struct ArArchive<R: Read> {
+    reader: R,
+    position: u64,
+    // Unread data of the current member, followed by its padding
+    data_left: u64,
+    padding: u64,
+    current: Option<ArHeader>,
+    // The GNU long name table, once read
+    names: Vec<u8>,
+    read_only: bool,
+    options: ExtractOptions,
+    total_deadline: Option<Deadline>,
+    // Bytes of member data read so far, for the total size limit
+    written: u64,
+}
+
+impl<R: Read> ArArchive<R> {
+    pub fn new(reader: R) -> ArArchive<R> {
+        ArArchive {
+            reader,
+            position: 0,
+            data_left: 0,
+            padding: 0,
+            current: None,
+            names: vec![],
+            read_only: false,
+            options: ExtractOptions::default(),
+            total_deadline: None,
+            written: 0,
+        }
+    }
+
+    /// Creates an archive that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn new_readonly(reader: R) -> ArArchive<R> {
+        ArArchive {
+            read_only: true,
+            ..ArArchive::new(reader)
+        }
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Advances to the next member, skipping whatever is left of the data
+    /// of the current one and any symbol table. Returns `None` at the end
+    /// of the archive.
+    pub fn next_header(&mut self) -> io::Result<Option<ArHeader>> {
+        if self.position == 0 {
+            let mut signature: [u8; 8] = [0u8; 8];
+            self.read_exact(&mut signature)?;
+            if signature == THIN_SIGNATURE {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Thin ar archives don't contain their members",
+                ));
+            }
+            if signature != SIGNATURE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Not an ar archive",
+                ));
+            }
+        }
+        self.skip(self.data_left)?;
+        // The padding after the last member is sometimes missing
+        let padding: u64 = io::copy(&mut (&mut self.reader).take(self.padding), &mut io::sink())?;
+        self.position += padding;
+        self.data_left = 0;
+        self.padding = 0;
+        self.current = None;
+
+        loop {
+            // Member header, ASCII padded with spaces:
+            // [16 bytes] Name
+            // [12 bytes] Modification time (decimal)
+            // [6 bytes]  Owner id (decimal)
+            // [6 bytes]  Group id (decimal)
+            // [8 bytes]  Mode (octal)
+            // [10 bytes] Data size (decimal)
+            // [2 bytes]  Magic ("`\n")
+            let offset: u64 = self.position;
+            let mut block: [u8; 60] = [0u8; 60];
+            let filled: usize = read_full(&mut self.reader, &mut block)?;
+            self.position += filled as u64;
+            match filled {
+                0 => return Ok(None),
+                60 => {}
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Ar archive ends in the middle of a header",
+                    ))
+                }
+            }
+            if &block[58..60] != b"`\n" {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No ar member header at offset {}", offset),
+                ));
+            }
+            let mut size: u64 = parse_number(&block[48..58], 10)?;
+            let padding: u64 = size % 2;
+            let raw_name: &str = std::str::from_utf8(&block[..16])
+                .map_err(|_| invalid_name(offset))?
+                .trim_end_matches(' ');
+
+            let name: String = match raw_name {
+                // Symbol tables: GNU 32 and 64-bit ones and BSD ones
+                "/" | "/SYM64/" | "__.SYMDEF" | "__.SYMDEF SORTED" => {
+                    self.skip(size.saturating_add(padding))?;
+                    continue;
+                }
+                "//" => {
+                    if size > MAX_NAME_TABLE_SIZE {
+                        return Err(invalid_name(offset));
+                    }
+                    let mut names: Vec<u8> = vec![0u8; size as usize];
+                    self.read_exact(&mut names)?;
+                    self.names = names;
+                    self.skip(padding)?;
+                    continue;
+                }
+                _ if raw_name.starts_with("#1/") => {
+                    // BSD: the name is the first bytes of the data
+                    let length: u64 = parse_number(&raw_name.as_bytes()[3..], 10)?;
+                    if length > size || length > 4096 {
+                        return Err(invalid_name(offset));
+                    }
+                    let mut name: Vec<u8> = vec![0u8; length as usize];
+                    self.read_exact(&mut name)?;
+                    size -= length;
+                    let end: usize = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                    let name: String = String::from_utf8_lossy(&name[..end]).into_owned();
+                    if name.starts_with("__.SYMDEF") {
+                        self.skip(size.saturating_add(padding))?;
+                        continue;
+                    }
+                    name
+                }
+                _ if raw_name.starts_with('/') => {
+                    // GNU: an offset into the long name table, where names
+                    // end with "/\n"
+                    let start: usize = parse_number(&raw_name.as_bytes()[1..], 10)? as usize;
+                    let rest: &[u8] = self
+                        .names
+                        .get(start..)
+                        .ok_or_else(|| invalid_name(offset))?;
+                    let end: usize = rest
+                        .windows(2)
+                        .position(|window| window == b"/\n")
+                        .ok_or_else(|| invalid_name(offset))?;
+                    String::from_utf8_lossy(&rest[..end]).into_owned()
+                }
+                // GNU names end with a slash, which allows spaces in them
+                _ => raw_name.strip_suffix('/').unwrap_or(raw_name).to_string(),
+            };
+
+            let header: ArHeader = ArHeader {
+                name,
+                mtime: parse_number(&block[16..28], 10)?,
+                uid: parse_number(&block[28..34], 10)? as u32,
+                gid: parse_number(&block[34..40], 10)? as u32,
+                mode: parse_number(&block[40..48], 8)? as u32,
+                size,
+                offset,
+            };
+            self.data_left = size;
+            self.padding = padding;
+            self.current = Some(header.clone());
+            return Ok(Some(header));
+        }
+    }
+
+    /// Reads the data of the member last returned by
+    /// [`next_header`](ArArchive::next_header).
+    pub fn read_data(&mut self) -> io::Result<Vec<u8>> {
+        let name: String = self
+            .current
+            .as_ref()
+            .map(|header| header.name.clone())
+            .unwrap_or_default();
+        let limits: EntryLimits =
+            self.options
+                .entry_limits(self.total_deadline, &ZipFileEntry::default(), self.written);
+        limits.check_time(&name)?;
+        limits.check_size(&name, self.data_left)?;
+        // Grow the buffer as data arrives rather than trusting the header
+        // with a large allocation up front
+        let mut data: Vec<u8> = vec![];
+        (&mut self.reader)
+            .take(self.data_left)
+            .read_to_end(&mut data)?;
+        if (data.len() as u64) < self.data_left {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Ar archive ends in the middle of {}", name),
+            ));
+        }
+        self.position += self.data_left;
+        self.written += self.data_left;
+        self.data_left = 0;
+        Ok(data)
+    }
+
+    /// Reads every remaining member and hands it to `sink`.
+    pub fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        self.total_deadline = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        while let Some(header) = self.next_header()? {
+            let data: Vec<u8> = self.read_data()?;
+            sink.write_entry(&header.to_entry(&data)?, &data)?;
+        }
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if read_full(&mut self.reader, buf)? < buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Ar archive ends in the middle of a header",
+            ));
+        }
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped: u64 = io::copy(&mut (&mut self.reader).take(count), &mut io::sink())?;
+        self.position += skipped;
+        if skipped < count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Ar archive ends in the middle of a member",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads until `buf` is full or the reader ends, and returns the number of
+/// bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled: usize = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn invalid_name(offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid name in ar member header at offset {}", offset),
+    )
+}
+
+/// Parses a header field of digits padded with spaces; an empty field is 0.
+fn parse_number(field: &[u8], radix: u32) -> io::Result<u64> {
+    let digits: &[u8] = field.trim_ascii();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, radix).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid number in ar header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use std::fs::File;
+
+    #[test]
+    fn test_ar_formats() -> io::Result<()> {
+        for filename in ["test_gnu.a", "test_bsd.a"] {
+            let mut archive = ArArchive::new(File::open(get_test_file_path(filename))?);
+            let mut headers: Vec<ArHeader> = vec![];
+            while let Some(header) = archive.next_header()? {
+                headers.push(header);
+            }
+            let names: Vec<(&str, u64)> = headers
+                .iter()
+                .map(|header| (header.name.as_str(), header.size))
+                .collect();
+            assert_eq!(
+                names,
+                [("a_long_object_name.o", 1056), ("test2.txt", 14)],
+                "{}",
+                filename
+            );
+            assert_eq!(headers[1].mode, 0o100600);
+            assert_eq!(headers[1].mtime, 1_700_000_000);
+
+            let mut sink: MemorySink = MemorySink::new();
+            ArArchive::new(File::open(get_test_file_path(filename))?).extract_to(&mut sink)?;
+            let files = sink.into_entries();
+            assert!(files["a_long_object_name.o"].starts_with(b"\x7fELF"));
+            assert_eq!(files["test2.txt"], b"this is test2\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ar_deb() -> io::Result<()> {
+        let mut archive = ArArchive::new(File::open(get_test_file_path("test_package.deb"))?);
+        let mut names: Vec<String> = vec![];
+        while let Some(header) = archive.next_header()? {
+            if header.name == "debian-binary" {
+                assert_eq!(archive.read_data()?, b"2.0\n");
+            }
+            names.push(header.name);
+        }
+        assert_eq!(names, ["debian-binary", "control.tar.gz", "data.tar.gz"]);
+
+        let err: io::Error = ArArchive::new(&b"!<thin>\n"[..]).next_header().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        Ok(())
+    }
+}
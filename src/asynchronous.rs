@@ -0,0 +1,259 @@
+//! Reading ZIP archives from async readers (tokio).
+//!
+//! Only the I/O is async: the bytes needed for a step (the end of the file,
+//! the central directory, one entry) are read into memory and parsed with
+//! the same code as [`ZipArchive`](crate::zip::ZipArchive). Decompression
+//! runs on the calling task, so very large entries are better decoded with
+//! `spawn_blocking`.
+
+use crate::extra::{find_extra_field, ZIP64_EXTRA_ID};
+use crate::sink::Sink;
+use crate::zip::{
+    central_directory_at, check_data_descriptor, decode_entry_data, find_end_central_dir,
+    CentralDirectory, Deadline, EntryLimits, ExtractOptions, RecordBuffers, SkipLog, ZipFileEntry,
+    EOCD_MAX_SEARCH, FLAG_DATA_DESCRIPTOR, LOCAL_FILE_HEADER_SIGNATURE,
+};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+/// A ZIP archive read from an [`AsyncRead`] + [`AsyncSeek`] source, e.g. a
+/// `tokio::fs::File` or an upload buffered in a `Cursor<Vec<u8>>`.
+pub struct AsyncZipArchive<R> {
+    reader: R,
+    directory: CentralDirectory,
+    // Where the end of central directory record starts, i.e. where the
+    // central directory ends
+    directory_end: u64,
+    // Parsed on first use
+    entries: Option<Vec<ZipFileEntry>>,
+    read_only: bool,
+    options: ExtractOptions,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncZipArchive<R> {
+    /// Reads the end of central directory record from `reader`.
+    pub async fn new(reader: R) -> io::Result<AsyncZipArchive<R>> {
+        AsyncZipArchive::with_mode(reader, false).await
+    }
+
+    /// Like [`AsyncZipArchive::new`], in read-only mode: only sinks
+    /// reporting [`Sink::is_read_only`] are accepted by
+    /// [`AsyncZipArchive::extract_to`].
+    pub async fn new_readonly(reader: R) -> io::Result<AsyncZipArchive<R>> {
+        AsyncZipArchive::with_mode(reader, true).await
+    }
+
+    async fn with_mode(mut reader: R, read_only: bool) -> io::Result<AsyncZipArchive<R>> {
+        let file_size: u64 = reader.seek(SeekFrom::End(0)).await?;
+        let search_size: u64 = min(EOCD_MAX_SEARCH, file_size);
+        reader
+            .seek(SeekFrom::Start(file_size - search_size))
+            .await?;
+        let mut tail: Vec<u8> = vec![0u8; search_size as usize];
+        reader.read_exact(&mut tail).await?;
+
+        let Some((record, position)) = find_end_central_dir(&tail, file_size, &[])? else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "End of central directory record not found",
+            ));
+        };
+        let mut signature: [u8; 4] = [0u8; 4];
+        reader
+            .seek(SeekFrom::Start(record.recorded_offset(&[])))
+            .await?;
+        reader.read_exact(&mut signature).await?;
+        Ok(AsyncZipArchive {
+            reader,
+            directory: central_directory_at(&record, position, &[], signature),
+            directory_end: record.directory_end(position),
+            entries: None,
+            read_only,
+            options: ExtractOptions::default(),
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Returns the entries of the archive. The whole central directory is
+    /// read and parsed by the first call.
+    pub async fn entries(&mut self) -> io::Result<&[ZipFileEntry]> {
+        if self.entries.is_none() {
+            let start: u64 = self.directory.offset;
+            let length: u64 = self.directory_end.saturating_sub(start);
+            self.reader.seek(SeekFrom::Start(start)).await?;
+            let mut directory: Vec<u8> = vec![];
+            (&mut self.reader)
+                .take(length)
+                .read_to_end(&mut directory)
+                .await?;
+
+            let mut rest: &[u8] = &directory;
+            let mut buffers: RecordBuffers = RecordBuffers::new();
+            let mut entries: Vec<ZipFileEntry> = vec![];
+            while let Some(record) = buffers.read_record(&mut rest, &self.directory)? {
+                entries.push(record.to_entry());
+            }
+            self.entries = Some(entries);
+        }
+        Ok(self.entries.as_deref().unwrap_or_default())
+    }
+
+    /// Looks up an entry by its exact decoded name; the first one wins.
+    pub async fn by_name(&mut self, name: &str) -> io::Result<ZipFileEntry> {
+        let entries: &[ZipFileEntry] = self.entries().await?;
+        match entries.iter().find(|entry| entry.filename == name) {
+            Some(entry) => Ok(entry.clone()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No entry named {} in archive", name),
+            )),
+        }
+    }
+
+    /// Decodes `entry` into memory. Returns `Ok(None)` when the entry uses
+    /// an unsupported compression method.
+    pub async fn read(&mut self, entry: &ZipFileEntry) -> io::Result<Option<Vec<u8>>> {
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let limits: EntryLimits = self.options.entry_limits(total_deadline, entry, 0);
+        self.read_with_limits(entry, limits).await
+    }
+
+    /// Decodes every entry and hands it to `sink`, like
+    /// [`ZipArchive::extract_to`](crate::zip::ZipArchive::extract_to).
+    pub async fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let entries: Vec<ZipFileEntry> = self.entries().await?.to_vec();
+        let mut written: u64 = 0;
+        let mut skipped: SkipLog = SkipLog::new(self.options.unsupported);
+        for entry in entries {
+            if let Some(reason) = self.options.codecs.unsupported_reason(&entry) {
+                skipped.skip(sink, &entry, reason)?;
+                continue;
+            }
+            let limits: EntryLimits = self.options.entry_limits(total_deadline, &entry, written);
+            match self.read_with_limits(&entry, limits).await? {
+                Some(data) => {
+                    written += data.len() as u64;
+                    sink.write_entry(&entry, &data)?;
+                }
+                None => skipped.skip(sink, &entry, "invalid local header".to_string())?,
+            }
+        }
+        skipped.finish()
+    }
+
+    async fn read_with_limits(
+        &mut self,
+        entry: &ZipFileEntry,
+        limits: EntryLimits,
+    ) -> io::Result<Option<Vec<u8>>> {
+        limits.check_time(&entry.filename)?;
+        self.reader.seek(SeekFrom::Start(entry.file_offset)).await?;
+        let mut local_header: [u8; 30] = [0u8; 30];
+        self.reader.read_exact(&mut local_header).await?;
+        if local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
+            eprintln!("Invalid local file header signature");
+            return Ok(None);
+        }
+        let local_flags: u16 = u16::from_le_bytes(local_header[6..8].try_into().unwrap());
+        let local_name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
+        let local_extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+        self.reader
+            .seek(SeekFrom::Current(local_name_length as i64))
+            .await?;
+        let mut extra_buf: Vec<u8> = vec![0u8; local_extra_length as usize];
+        self.reader.read_exact(&mut extra_buf).await?;
+
+        let mut compressed_data_buf: Vec<u8> = vec![];
+        (&mut self.reader)
+            .take(entry.sizes().1)
+            .read_to_end(&mut compressed_data_buf)
+            .await?;
+        if compressed_data_buf.len() != entry.sizes().1 as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Archive ends in the middle of {}", entry.filename),
+            ));
+        }
+        if (local_flags | entry.flags) & FLAG_DATA_DESCRIPTOR != 0 {
+            // Up to 24 bytes, depending on the optional signature and on
+            // whether the sizes are Zip64
+            let mut descriptor: Vec<u8> = vec![];
+            (&mut self.reader)
+                .take(24)
+                .read_to_end(&mut descriptor)
+                .await?;
+            let zip64: bool = find_extra_field(&extra_buf, ZIP64_EXTRA_ID).is_some();
+            check_data_descriptor(&mut &descriptor[..], entry, zip64)?;
+        }
+
+        let data: Option<Cow<'_, [u8]>> = decode_entry_data(
+            entry,
+            Cow::Owned(compressed_data_buf),
+            limits,
+            &self.options.codecs,
+        )?;
+        Ok(data.map(Cow::into_owned))
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_async_matches_sync() -> io::Result<()> {
+        for filename in [
+            "test_multiple.zip",
+            "test_stored_descriptor.zip",
+            "test_encodings.zip",
+            "test_sfx.exe",
+            "test_zip64_offsets.zip",
+            "test_zip64_descriptor.zip",
+        ] {
+            let path: PathBuf = get_test_file_path(filename);
+            let file = tokio::fs::File::open(&path).await?;
+            let mut archive = AsyncZipArchive::new_readonly(file).await?;
+            let mut sink = MemorySink::new();
+            archive.extract_to(&mut sink).await?;
+
+            let expected: HashMap<String, Vec<u8>> =
+                ZipArchive::open_readonly(path.to_str().unwrap())?.extract_all_to_memory()?;
+            assert_eq!(sink.into_entries(), expected, "{}", filename);
+        }
+
+        let bytes: Vec<u8> = std::fs::read(get_test_file_path("test_multiple.zip"))?;
+        let mut archive = AsyncZipArchive::new(io::Cursor::new(bytes)).await?;
+        assert_eq!(archive.entries().await?.len(), 2);
+        let entry: ZipFileEntry = archive.by_name("test2.txt").await?;
+        assert_eq!(archive.read(&entry).await?.unwrap(), b"this is test2\n");
+        assert!(archive.by_name("missing.txt").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+//! Cooperative cancellation of extractions.
+//!
+//! A [`CancellationToken`] set in
+//! [`ExtractOptions::cancel`](crate::zip::ExtractOptions::cancel) is checked
+//! between entries and between the chunks an entry is decoded in, the same
+//! places as the time limits. Once it is cancelled, from another thread or
+//! a signal handler, the extraction stops with
+//! [`XpackError::Cancelled`](crate::error::XpackError::Cancelled). What the
+//! sink already received stays: files are handed to it whole, so none is
+//! left half written, and [`DiskSink`](crate::sink::DiskSink) removes the
+//! temporary file of an atomic write that fails.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared by clones, telling an extraction to stop.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Asks every extraction holding a clone of this token to stop. Only
+    /// stores to an atomic, so it can be called from a signal handler.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,184 @@
+//! Compressed single-file formats: gzip, bzip2, xz and Zstandard.
+//!
+//! Unlike archives these hold a single stream of data without a name, so
+//! there is nothing to list or extract, only data to compress and
+//! decompress. Decoders read concatenated streams as one, as `gzip -d`
+//! does.
+
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    Gzip,
+    Bzip2,
+    Xz,
+    /// Zstandard. Needs the `zstd` feature.
+    Zstd,
+}
+
+impl StreamFormat {
+    /// The usual file name extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            StreamFormat::Gzip => "gz",
+            StreamFormat::Bzip2 => "bz2",
+            StreamFormat::Xz => "xz",
+            StreamFormat::Zstd => "zst",
+        }
+    }
+
+    /// Recognizes the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<StreamFormat> {
+        let extension: String = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "tgz" => Some(StreamFormat::Gzip),
+            "bz2" | "tbz2" => Some(StreamFormat::Bzip2),
+            "xz" | "txz" => Some(StreamFormat::Xz),
+            "zst" | "tzst" => Some(StreamFormat::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the format from the leading bytes of the data.
+    pub fn detect(head: &[u8]) -> Option<StreamFormat> {
+        if head.starts_with(b"\x1f\x8b") {
+            Some(StreamFormat::Gzip)
+        } else if head.len() >= 4 && head.starts_with(b"BZh") && head[3].is_ascii_digit() {
+            // The digit is the block size in 100 kB
+            Some(StreamFormat::Bzip2)
+        } else if head.starts_with(b"\xfd7zXZ\0") {
+            Some(StreamFormat::Xz)
+        } else if head.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(StreamFormat::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name a file called `path` has once decompressed: without
+    /// the extension, and with `.tar` for the short forms like `.tgz`.
+    pub fn decompressed_path(path: &str) -> Option<String> {
+        let (stem, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            _ if stem.is_empty() || stem.ends_with('/') => None,
+            "gz" | "bz2" | "xz" | "zst" => Some(stem.to_string()),
+            "tgz" | "tbz2" | "txz" | "tzst" => Some(format!("{}.tar", stem)),
+            _ => None,
+        }
+    }
+
+    /// Wraps `input` in a decoder for the format.
+    pub fn decoder<'a, R: Read + 'a>(self, input: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            StreamFormat::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(input))),
+            StreamFormat::Bzip2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(input))),
+            StreamFormat::Xz => Ok(Box::new(lzma_rust2::XzReader::new(input, true))),
+            #[cfg(feature = "zstd")]
+            StreamFormat::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(input)?)),
+            #[cfg(not(feature = "zstd"))]
+            StreamFormat::Zstd => Err(zstd_unsupported()),
+        }
+    }
+
+    /// Compresses everything `input` holds into `output` and returns
+    /// `output`. Levels go from 0, the fastest, to 9, the smallest; no level
+    /// uses the format's default.
+    pub fn compress<R: Read, W: Write>(
+        self,
+        input: &mut R,
+        output: W,
+        level: Option<u32>,
+    ) -> io::Result<W> {
+        let level: Option<u32> = level.map(|level| level.min(9));
+        match self {
+            StreamFormat::Gzip => {
+                let level: flate2::Compression =
+                    level.map_or(flate2::Compression::default(), flate2::Compression::new);
+                let mut encoder = flate2::write::GzEncoder::new(output, level);
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            StreamFormat::Bzip2 => {
+                // bzip2 has no level 0
+                let level: bzip2::Compression = level
+                    .map_or(bzip2::Compression::default(), |level| {
+                        bzip2::Compression::new(level.max(1))
+                    });
+                let mut encoder = bzip2::write::BzEncoder::new(output, level);
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            StreamFormat::Xz => {
+                let options: lzma_rust2::XzOptions =
+                    lzma_rust2::XzOptions::with_preset(level.unwrap_or(6));
+                let mut encoder = lzma_rust2::XzWriter::new(output, options)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            StreamFormat::Zstd => {
+                // 0 is zstd's default level
+                let level: i32 = level.map_or(0, |level| level as i32);
+                let mut encoder = zstd::stream::write::Encoder::new(output, level)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            #[cfg(not(feature = "zstd"))]
+            StreamFormat::Zstd => Err(zstd_unsupported()),
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Zstandard compression needs the zstd feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_round_trips() -> io::Result<()> {
+        let data: Vec<u8> = b"compressible ".repeat(1000);
+        let mut formats: Vec<StreamFormat> =
+            vec![StreamFormat::Gzip, StreamFormat::Bzip2, StreamFormat::Xz];
+        if cfg!(feature = "zstd") {
+            formats.push(StreamFormat::Zstd);
+        }
+        for format in formats {
+            for level in [None, Some(0), Some(9)] {
+                let compressed: Vec<u8> = format.compress(&mut &data[..], vec![], level)?;
+                assert_eq!(StreamFormat::detect(&compressed), Some(format));
+                let mut decompressed: Vec<u8> = vec![];
+                format
+                    .decoder(&compressed[..])?
+                    .read_to_end(&mut decompressed)?;
+                assert_eq!(decompressed, data, "{:?} {:?}", format, level);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_names() {
+        assert_eq!(
+            StreamFormat::from_path("a/b.tar.XZ"),
+            Some(StreamFormat::Xz)
+        );
+        assert_eq!(StreamFormat::from_path("notes.txt"), None);
+        assert_eq!(
+            StreamFormat::decompressed_path("a/b.txt.zst").as_deref(),
+            Some("a/b.txt")
+        );
+        assert_eq!(
+            StreamFormat::decompressed_path("b.tgz").as_deref(),
+            Some("b.tar")
+        );
+        assert_eq!(StreamFormat::decompressed_path("dir/.gz"), None);
+        assert_eq!(StreamFormat::decompressed_path("b.zip"), None);
+    }
+}
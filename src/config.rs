@@ -0,0 +1,272 @@
+//! Tuning settings loaded from a configuration file.
+//!
+//! The file is TOML, by default `~/.config/xpack/config.toml` (or under
+//! `$XDG_CONFIG_HOME`). Only the subset needed here is understood: tables,
+//! and keys set to strings, integers or floats on a single line.
+//! Sizes are integers or strings such as `"256K"` (see [`parse_size`]);
+//! times are seconds. Every key is optional:
+//!
+//! ```toml
+//! read_buffer_size = "256K"
+//! write_buffer_size = "1M"
+//! threads = 8
+//!
+//! [limits]
+//! timeout = 600
+//! entry_timeout = 30.5
+//! max_entry_size = "4G"
+//! max_total_size = "64G"
+//! max_ratio = 1000
+//!
+//! [extract]
+//! overwrite = "skip"   # overwrite, skip, rename or update
+//! ```
+
+use crate::sink::OverwritePolicy;
+use crate::units::parse_size;
+use crate::zip::ExtractOptions;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings from a configuration file. Fields left out of the file are
+/// `None`, leaving the choice to command-line flags or the defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XpackConfig {
+    /// Bytes read from archives at once
+    pub read_buffer_size: Option<usize>,
+    /// Bytes buffered before writing to new archives
+    pub write_buffer_size: Option<usize>,
+    /// Files compressed at once when creating archives
+    pub threads: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub entry_timeout: Option<Duration>,
+    pub max_entry_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub max_ratio: Option<f64>,
+    /// What extraction does with existing files
+    pub overwrite: Option<OverwritePolicy>,
+}
+
+/// A value on the right of `=`.
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl XpackConfig {
+    /// Where the configuration is looked for by default:
+    /// `$XDG_CONFIG_HOME/xpack/config.toml`, or `~/.config/xpack/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base: PathBuf = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("xpack").join("config.toml"))
+    }
+
+    /// Reads the configuration at `path`, or returns the empty one if there
+    /// is no file there.
+    pub fn load(path: &Path) -> io::Result<XpackConfig> {
+        match fs::read_to_string(path) {
+            Ok(text) => XpackConfig::parse(&text)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(XpackConfig::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses a configuration. Unknown keys are errors, so a typo doesn't
+    /// go unnoticed.
+    pub fn parse(text: &str) -> io::Result<XpackConfig> {
+        let mut config: XpackConfig = XpackConfig::default();
+        let mut table: String = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            let line: &str = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name: &str = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| invalid(format!("unterminated table header {}", line)))?;
+                table = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected key = value, found {}", line)))?;
+            let key: String = match table.as_str() {
+                "" => key.trim().to_string(),
+                table => format!("{}.{}", table, key.trim()),
+            };
+            let value: Value = parse_value(value.trim()).map_err(invalid)?;
+            config.set(&key, value).map_err(invalid)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match key {
+            "read_buffer_size" => self.read_buffer_size = Some(size(key, value)? as usize),
+            "write_buffer_size" => self.write_buffer_size = Some(size(key, value)? as usize),
+            "threads" => match value {
+                Value::Integer(threads) if threads > 0 => self.threads = Some(threads as usize),
+                _ => return Err(format!("{} must be a positive integer", key)),
+            },
+            "limits.timeout" => self.timeout = Some(seconds(key, value)?),
+            "limits.entry_timeout" => self.entry_timeout = Some(seconds(key, value)?),
+            "limits.max_entry_size" => self.max_entry_size = Some(size(key, value)?),
+            "limits.max_total_size" => self.max_total_size = Some(size(key, value)?),
+            "limits.max_ratio" => self.max_ratio = Some(number(key, value)?),
+            "extract.overwrite" => {
+                self.overwrite = Some(match value {
+                    Value::String(policy) => match policy.as_str() {
+                        "overwrite" => OverwritePolicy::Overwrite,
+                        "skip" => OverwritePolicy::Skip,
+                        "rename" => OverwritePolicy::Rename,
+                        "update" => OverwritePolicy::Update,
+                        _ => return Err(format!("unknown overwrite policy {}", policy)),
+                    },
+                    _ => return Err(format!("{} must be a string", key)),
+                })
+            }
+            _ => return Err(format!("unknown key {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Extraction options with the limits of this configuration.
+    pub fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            timeout: self.timeout,
+            entry_timeout: self.entry_timeout,
+            max_entry_size: self.max_entry_size,
+            max_total_size: self.max_total_size,
+            max_ratio: self.max_ratio,
+            ..Default::default()
+        }
+    }
+}
+
+// Cuts a `#` comment off the end of `line`, unless it's inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted: bool = false;
+    let mut escaped: bool = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let body: &str = quoted
+            .strip_suffix('"')
+            .ok_or_else(|| format!("unterminated string {}", value))?;
+        let mut string: String = String::with_capacity(body.len());
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+            string.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                other => return Err(format!("unsupported escape \\{}", other.unwrap_or(' '))),
+            });
+        }
+        return Ok(Value::String(string));
+    }
+    let digits: String = value.replace('_', "");
+    if let Ok(integer) = digits.parse::<i64>() {
+        return Ok(Value::Integer(integer));
+    }
+    match digits.parse::<f64>() {
+        Ok(float) if float.is_finite() => Ok(Value::Float(float)),
+        _ => Err(format!("unsupported value {}", value)),
+    }
+}
+
+fn size(key: &str, value: Value) -> Result<u64, String> {
+    match value {
+        Value::Integer(bytes) if bytes >= 0 => Ok(bytes as u64),
+        Value::String(size) => {
+            parse_size(&size).ok_or_else(|| format!("{} isn't a size such as 64K", size))
+        }
+        _ => Err(format!("{} must be a size such as 1048576 or \"1M\"", key)),
+    }
+}
+
+fn number(key: &str, value: Value) -> Result<f64, String> {
+    match value {
+        Value::Integer(number) if number > 0 => Ok(number as f64),
+        Value::Float(number) if number > 0.0 => Ok(number),
+        _ => Err(format!("{} must be a positive number", key)),
+    }
+}
+
+fn seconds(key: &str, value: Value) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(number(key, value)?)
+        .map_err(|_| format!("{} is too large a number of seconds", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> io::Result<()> {
+        let config: XpackConfig = XpackConfig::parse(
+            "# Tuning for the NAS\n\
+             read_buffer_size = \"256K\"\n\
+             write_buffer_size = 1_048_576  # bytes\n\
+             threads = 8\n\
+             \n\
+             [limits]\n\
+             entry_timeout = 30.5\n\
+             max_total_size = \"64G\"\n\
+             max_ratio = 1000\n\
+             [ extract ]\n\
+             overwrite = \"skip\"\n",
+        )?;
+        assert_eq!(
+            config,
+            XpackConfig {
+                read_buffer_size: Some(256 << 10),
+                write_buffer_size: Some(1 << 20),
+                threads: Some(8),
+                entry_timeout: Some(Duration::from_secs_f64(30.5)),
+                max_total_size: Some(64 << 30),
+                max_ratio: Some(1000.0),
+                overwrite: Some(OverwritePolicy::Skip),
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.extract_options().max_total_size, Some(64 << 30));
+
+        let err: io::Error = XpackConfig::parse("[limits]\nmax_entry = 5\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown key limits.max_entry");
+        assert!(XpackConfig::parse("threads = 0").is_err());
+        assert!(XpackConfig::parse("threads").is_err());
+        assert!(XpackConfig::parse("[extract]\noverwrite = \"ask\"").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,577 @@
+//! Reading of cpio archives.
+//!
+//! A cpio archive is a sequence of headers, each followed by the entry name
+//! and data, ending with an entry named `TRAILER!!!`. Four header formats
+//! are read: the portable ASCII ones, newc (used by Linux initramfs images
+//! and RPM), its variant crc with a checksum of the data, and odc (POSIX),
+//! and the old binary format of either byte order.
+//!
+//! Hard links share an inode number. newc archives store the data once,
+//! with the last name of the file, and the earlier names with no data;
+//! [`CpioArchive::extract_to`] writes those names with a copy of the data
+//! once it's read, so that none of them come out empty.
+
+use crate::extra::ExtraField;
+use crate::sink::{Sink, SpecialFile};
+use crate::writer::{S_IFDIR, S_IFREG};
+use crate::zip::{Deadline, EntryLimits, ExtractOptions, ZipFileEntry};
+use std::io::{self, Read};
+use std::time::{Duration, UNIX_EPOCH};
+
+// File type bits of a Unix mode, besides the ones shared with the writer
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFIFO: u32 = 0o010000;
+
+const TRAILER: &str = "TRAILER!!!";
+
+// Longer names than this are taken for corruption
+const MAX_NAME_SIZE: u32 = 64 * 1024;
+
+/// The header format of a cpio entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpioFormat {
+    /// "070701", hexadecimal fields
+    Newc,
+    /// "070702", newc with a checksum of the data
+    Crc,
+    /// "070707", octal fields
+    Odc,
+    /// 16-bit fields, little-endian if true
+    Binary(bool),
+}
+
+/// Returns true if `head` starts with the magic number of a cpio header.
+pub fn is_cpio_header(head: &[u8]) -> bool {
+    head.starts_with(b"070701")
+        || head.starts_with(b"070702")
+        || head.starts_with(b"070707")
+        || head.starts_with(b"\xc7\x71")
+        || head.starts_with(b"\x71\xc7")
+}
+
+/// The metadata of a single cpio entry.
+#[derive(Debug, Clone)]
+pub struct CpioHeader {
+    pub name: String,
+    pub format: CpioFormat,
+    /// Unix mode, file type included
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u64,
+    pub size: u64,
+    /// Device and inode number of the file, which hard links share
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    pub ino: u32,
+    /// Device numbers of character and block devices
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    /// Sum of the data bytes, in crc archives
+    pub checksum: Option<u32>,
+    /// Offset of the header in the archive
+    pub offset: u64,
+}
+
+impl CpioHeader {
+    pub fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == S_IFREG
+    }
+
+    /// Describes the entry in the form sinks expect, like
+    /// [`TarHeader::to_entry`](crate::tar::TarHeader::to_entry) does.
+    pub fn to_entry(&self, data: &[u8]) -> io::Result<ZipFileEntry> {
+        let size: u32 = u32::try_from(self.size).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is too large to extract", self.name),
+            )
+        })?;
+        let mut filename: String = self.name.clone();
+        let file_type: u32 = if self.is_directory() {
+            S_IFDIR
+        } else {
+            S_IFREG
+        };
+        if self.is_directory() && !filename.ends_with('/') {
+            filename.push('/');
+        }
+        Ok(ZipFileEntry {
+            filename,
+            crc32: crc32fast::hash(data),
+            compressed_size: size,
+            uncompressed_size: size,
+            file_offset: self.offset,
+            modified: UNIX_EPOCH.checked_add(Duration::from_secs(self.mtime)),
+            extra_fields: vec![ExtraField::Unix {
+                uid: self.uid,
+                gid: self.gid,
+            }],
+            external_attributes: (file_type | self.mode & 0o7777) << 16,
+            ..Default::default()
+        })
+    }
+
+    /// Describes a device or FIFO entry.
+    fn special_file(&self) -> SpecialFile {
+        let (major, minor, mode) = (self.rdev_major, self.rdev_minor, self.mode & 0o7777);
+        match self.file_type() {
+            S_IFCHR => SpecialFile::CharDevice { major, minor, mode },
+            S_IFBLK => SpecialFile::BlockDevice { major, minor, mode },
+            _ => SpecialFile::Fifo { mode },
+        }
+    }
+
+    fn link_key(&self) -> (u32, u32, u32) {
+        (self.dev_major, self.dev_minor, self.ino)
+    }
+}
+
+/// Reads a cpio archive front to back from any reader.
+pub struct CpioArchive<R: Read> {
+    reader: R,
+    position: u64,
+    // Unread data of the current entry, followed by its padding
+    data_left: u64,
+    padding: u64,
+    current: Option<CpioHeader>,
+    read_only: bool,
+    options: ExtractOptions,
+    total_deadline: Option<Deadline>,
+    // Bytes of entry data read so far, for the total size limit
+    written: u64,
+    done: bool,
+}
+
+impl<R: Read> CpioArchive<R> {
+    pub fn new(reader: R) -> CpioArchive<R> {
+        CpioArchive {
+            reader,
+            position: 0,
+            data_left: 0,
+            padding: 0,
+            current: None,
+            read_only: false,
+            options: ExtractOptions::default(),
+            total_deadline: None,
+            written: 0,
+            done: false,
+        }
+    }
+
+    /// Creates an archive that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn new_readonly(reader: R) -> CpioArchive<R> {
+        CpioArchive {
+            read_only: true,
+            ..CpioArchive::new(reader)
+        }
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Advances to the next entry, skipping whatever is left of the data of
+    /// the current one. Returns `None` at the trailer.
+    pub fn next_header(&mut self) -> io::Result<Option<CpioHeader>> {
+        self.skip(self.data_left.saturating_add(self.padding))?;
+        self.data_left = 0;
+        self.padding = 0;
+        self.current = None;
+        if self.done {
+            return Ok(None);
+        }
+
+        let offset: u64 = self.position;
+        let mut magic: [u8; 6] = [0u8; 6];
+        self.read_exact(&mut magic[..2])?;
+        let format: CpioFormat = match magic[..2] {
+            [0xc7, 0x71] => CpioFormat::Binary(true),
+            [0x71, 0xc7] => CpioFormat::Binary(false),
+            _ => {
+                self.read_exact(&mut magic[2..])?;
+                match &magic {
+                    b"070701" => CpioFormat::Newc,
+                    b"070702" => CpioFormat::Crc,
+                    b"070707" => CpioFormat::Odc,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("No cpio header at offset {}", offset),
+                        ))
+                    }
+                }
+            }
+        };
+        let (mut header, name_size, header_size, align): (CpioHeader, u32, u64, u64) = match format
+        {
+            CpioFormat::Newc | CpioFormat::Crc => {
+                // [6 bytes] Magic
+                // [8 bytes] Inode number (hex, as are all fields)
+                // [8 bytes] Mode
+                // [8 bytes] Owner id
+                // [8 bytes] Group id
+                // [8 bytes] Number of links
+                // [8 bytes] Modification time
+                // [8 bytes] Data size
+                // [8 bytes] Device major
+                // [8 bytes] Device minor
+                // [8 bytes] Special file device major
+                // [8 bytes] Special file device minor
+                // [8 bytes] Name size, terminating NUL included
+                // [8 bytes] Checksum (crc only)
+                let mut fields: [u8; 104] = [0u8; 104];
+                self.read_exact(&mut fields)?;
+                let field = |i: usize| parse_number(&fields[i * 8..i * 8 + 8], 16);
+                let header: CpioHeader = CpioHeader {
+                    name: String::new(),
+                    format,
+                    mode: field(1)? as u32,
+                    uid: field(2)? as u32,
+                    gid: field(3)? as u32,
+                    nlink: field(4)? as u32,
+                    mtime: field(5)?,
+                    size: field(6)?,
+                    dev_major: field(7)? as u32,
+                    dev_minor: field(8)? as u32,
+                    ino: field(0)? as u32,
+                    rdev_major: field(9)? as u32,
+                    rdev_minor: field(10)? as u32,
+                    checksum: match format {
+                        CpioFormat::Crc => Some(field(12)? as u32),
+                        _ => None,
+                    },
+                    offset,
+                };
+                (header, field(11)? as u32, 110, 4)
+            }
+            CpioFormat::Odc => {
+                // [6 bytes]  Magic
+                // [6 bytes]  Device (octal, as are all fields)
+                // [6 bytes]  Inode number
+                // [6 bytes]  Mode
+                // [6 bytes]  Owner id
+                // [6 bytes]  Group id
+                // [6 bytes]  Number of links
+                // [6 bytes]  Special file device
+                // [11 bytes] Modification time
+                // [6 bytes]  Name size, terminating NUL included
+                // [11 bytes] Data size
+                let mut fields: [u8; 70] = [0u8; 70];
+                self.read_exact(&mut fields)?;
+                let field = |start: usize, len: usize| parse_number(&fields[start..start + len], 8);
+                let (dev, rdev): (u64, u64) = (field(0, 6)?, field(36, 6)?);
+                let header: CpioHeader = CpioHeader {
+                    name: String::new(),
+                    format,
+                    mode: field(12, 6)? as u32,
+                    uid: field(18, 6)? as u32,
+                    gid: field(24, 6)? as u32,
+                    nlink: field(30, 6)? as u32,
+                    mtime: field(42, 11)?,
+                    size: field(59, 11)?,
+                    dev_major: (dev >> 8) as u32,
+                    dev_minor: (dev & 0xff) as u32,
+                    ino: field(6, 6)? as u32,
+                    rdev_major: (rdev >> 8) as u32,
+                    rdev_minor: (rdev & 0xff) as u32,
+                    checksum: None,
+                    offset,
+                };
+                (header, field(53, 6)? as u32, 76, 1)
+            }
+            CpioFormat::Binary(little_endian) => {
+                // [2 bytes] Magic (0o070707)
+                // [2 bytes] Device
+                // [2 bytes] Inode number
+                // [2 bytes] Mode
+                // [2 bytes] Owner id
+                // [2 bytes] Group id
+                // [2 bytes] Number of links
+                // [2 bytes] Special file device
+                // [4 bytes] Modification time (high half first)
+                // [2 bytes] Name size, terminating NUL included
+                // [4 bytes] Data size (high half first)
+                let mut fields: [u8; 24] = [0u8; 24];
+                self.read_exact(&mut fields)?;
+                let field = |i: usize| -> u32 {
+                    let bytes: [u8; 2] = [fields[i * 2], fields[i * 2 + 1]];
+                    match little_endian {
+                        true => u16::from_le_bytes(bytes) as u32,
+                        false => u16::from_be_bytes(bytes) as u32,
+                    }
+                };
+                let header: CpioHeader = CpioHeader {
+                    name: String::new(),
+                    format,
+                    mode: field(2),
+                    uid: field(3),
+                    gid: field(4),
+                    nlink: field(5),
+                    mtime: ((field(7) << 16) | field(8)) as u64,
+                    size: ((field(10) << 16) | field(11)) as u64,
+                    dev_major: field(0) >> 8,
+                    dev_minor: field(0) & 0xff,
+                    ino: field(1),
+                    rdev_major: field(6) >> 8,
+                    rdev_minor: field(6) & 0xff,
+                    checksum: None,
+                    offset,
+                };
+                (header, field(9), 26, 2)
+            }
+        };
+
+        if name_size == 0 || name_size > MAX_NAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad name size in cpio header at offset {}", offset),
+            ));
+        }
+        let mut name: Vec<u8> = vec![0u8; name_size as usize];
+        self.read_exact(&mut name)?;
+        self.skip(align_padding(header_size + name_size as u64, align))?;
+        let end: usize = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        header.name = String::from_utf8_lossy(&name[..end]).into_owned();
+        if header.name == TRAILER {
+            // Writers pad the archive to a block size after the trailer,
+            // which is left unread
+            self.done = true;
+            return Ok(None);
+        }
+
+        self.data_left = header.size;
+        self.padding = align_padding(header.size, align);
+        self.current = Some(header.clone());
+        Ok(Some(header))
+    }
+
+    /// Reads the data of the entry last returned by
+    /// [`next_header`](CpioArchive::next_header), checking its checksum in
+    /// crc archives.
+    pub fn read_data(&mut self) -> io::Result<Vec<u8>> {
+        let (name, checksum): (String, Option<u32>) = self
+            .current
+            .as_ref()
+            .map(|header| (header.name.clone(), header.checksum))
+            .unwrap_or_default();
+        let limits: EntryLimits =
+            self.options
+                .entry_limits(self.total_deadline, &ZipFileEntry::default(), self.written);
+        limits.check_time(&name)?;
+        limits.check_size(&name, self.data_left)?;
+        // Grow the buffer as data arrives rather than trusting the header
+        // with a large allocation up front
+        let mut data: Vec<u8> = vec![];
+        (&mut self.reader)
+            .take(self.data_left)
+            .read_to_end(&mut data)?;
+        if (data.len() as u64) < self.data_left {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Cpio archive ends in the middle of {}", name),
+            ));
+        }
+        self.position += self.data_left;
+        self.written += self.data_left;
+        self.data_left = 0;
+        if let Some(checksum) = checksum {
+            let sum: u32 = data
+                .iter()
+                .fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32));
+            if sum != checksum {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Checksum mismatch for {}", name),
+                ));
+            }
+        }
+        Ok(data)
+    }
+
+    /// Reads every remaining entry and hands files and directories to
+    /// `sink`, and devices, FIFOs and names of files written before to
+    /// [`Sink::write_special`]. Symbolic links and sockets are skipped, see
+    /// [`Sink::skip_entry`].
+    pub fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        self.total_deadline = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        // Names of hard linked files whose data was written, and names
+        // waiting for the data of the last name of their file
+        let mut linked: Vec<((u32, u32, u32), String)> = vec![];
+        let mut pending: Vec<CpioHeader> = vec![];
+        while let Some(header) = self.next_header()? {
+            match header.file_type() {
+                S_IFREG => {
+                    let key: (u32, u32, u32) = header.link_key();
+                    if header.nlink > 1 && header.size == 0 {
+                        match linked.iter().find(|(other, _)| *other == key) {
+                            Some((_, target)) => {
+                                let link: SpecialFile = SpecialFile::HardLink {
+                                    target: target.clone(),
+                                };
+                                sink.write_special(&header.to_entry(&[])?, &link)?;
+                            }
+                            None => pending.push(header),
+                        }
+                        continue;
+                    }
+                    let data: Vec<u8> = self.read_data()?;
+                    sink.write_entry(&header.to_entry(&data)?, &data)?;
+                    if header.nlink > 1 {
+                        for other in pending.iter().filter(|other| other.link_key() == key) {
+                            let mut entry: ZipFileEntry = header.to_entry(&data)?;
+                            entry.filename = other.name.clone();
+                            sink.write_entry(&entry, &data)?;
+                        }
+                        pending.retain(|other| other.link_key() != key);
+                        linked.push((key, header.name));
+                    }
+                }
+                S_IFDIR => sink.write_entry(&header.to_entry(&[])?, &[])?,
+                S_IFCHR | S_IFBLK | S_IFIFO => {
+                    sink.write_special(&header.to_entry(&[])?, &header.special_file())?;
+                }
+                S_IFLNK => sink.skip_entry(&header.to_entry(&[])?, "symbolic link")?,
+                mode => {
+                    let reason: String = format!("file type {:o}", mode);
+                    sink.skip_entry(&header.to_entry(&[])?, &reason)?;
+                }
+            }
+        }
+        // Hard linked files that are empty after all
+        for header in pending {
+            sink.write_entry(&header.to_entry(&[])?, &[])?;
+        }
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Cpio archive ends in the middle of a header",
+            ),
+            _ => e,
+        })?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped: u64 = io::copy(&mut (&mut self.reader).take(count), &mut io::sink())?;
+        self.position += skipped;
+        if skipped < count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Cpio archive ends in the middle of an entry",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the bytes needed after `size` bytes to reach a multiple of
+/// `align`.
+fn align_padding(size: u64, align: u64) -> u64 {
+    (align - size % align) % align
+}
+
+/// Parses an ASCII header field of hexadecimal or octal digits.
+fn parse_number(field: &[u8], radix: u32) -> io::Result<u64> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, radix).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid number in cpio header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use std::fs::File;
+
+    #[test]
+    fn test_cpio_formats() -> io::Result<()> {
+        for (filename, format) in [
+            ("test_newc.cpio", CpioFormat::Newc),
+            ("test_crc.cpio", CpioFormat::Crc),
+            ("test_odc.cpio", CpioFormat::Odc),
+            ("test_bin.cpio", CpioFormat::Binary(true)),
+        ] {
+            let mut archive = CpioArchive::new(File::open(get_test_file_path(filename))?);
+            let mut headers: Vec<CpioHeader> = vec![];
+            while let Some(header) = archive.next_header()? {
+                headers.push(header);
+            }
+            let names: Vec<&str> = headers.iter().map(|header| header.name.as_str()).collect();
+            assert_eq!(
+                names,
+                ["dir", "dir/test1.txt", "test2.txt", "hard.txt", "link"],
+                "{}",
+                filename
+            );
+            assert_eq!(headers[0].format, format);
+            assert!(headers[0].is_directory());
+            assert_eq!(headers[2].mode, 0o100600);
+            assert_eq!(headers[2].mtime, 1_700_000_000);
+            assert_eq!(headers[4].file_type(), S_IFLNK);
+
+            // Both names of the hard linked file get the data, whichever
+            // one the archive stores it with
+            let mut sink: MemorySink = MemorySink::new();
+            CpioArchive::new(File::open(get_test_file_path(filename))?).extract_to(&mut sink)?;
+            let files = sink.into_entries();
+            assert_eq!(files.len(), 3, "{}", filename);
+            assert_eq!(files["dir/test1.txt"], b"this is test1\n");
+            assert_eq!(files["test2.txt"], b"this is test2\n");
+            assert_eq!(files["hard.txt"], b"this is test2\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpio_errors() -> io::Result<()> {
+        // A changed byte of data no longer matches the crc checksum
+        let mut data: Vec<u8> = std::fs::read(get_test_file_path("test_crc.cpio"))?;
+        let position: usize = data
+            .windows(13)
+            .position(|window| window == b"this is test1")
+            .unwrap();
+        data[position] = b'T';
+        let err: io::Error = CpioArchive::new(&data[..])
+            .extract_to(&mut MemorySink::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Checksum mismatch for dir/test1.txt");
+
+        // No trailer
+        let data: Vec<u8> = std::fs::read(get_test_file_path("test_newc.cpio"))?;
+        let err: io::Error = CpioArchive::new(&data[..300])
+            .extract_to(&mut MemorySink::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Reports of entries with identical contents.
+//!
+//! [`DedupeSink`] hashes every decoded entry with SHA-256 and keeps only the
+//! digest, so archives of any size are analysed in the memory needed for
+//! their largest entry. Entries of several archives can be fed to the same
+//! sink to find copies across them.
+
+use crate::hash::to_hex;
+use crate::sink::Sink;
+use crate::zip::ZipFileEntry;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+
+/// Where a copy of some contents was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// Label of the archive, see [`DedupeSink::set_archive`]
+    pub archive: String,
+    pub name: String,
+}
+
+/// Entries sharing the same contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    /// Lowercase hex SHA-256 of the contents
+    pub digest: String,
+    /// Size of the contents, in bytes
+    pub size: u64,
+    /// Every copy, in the order they were read
+    pub copies: Vec<Location>,
+}
+
+impl DuplicateGroup {
+    /// Bytes taken by every copy but the first.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.copies.len() as u64 - 1)
+    }
+}
+
+/// What [`DedupeSink::report`] found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DedupeReport {
+    /// Contents found more than once, most wasted bytes first
+    pub groups: Vec<DuplicateGroup>,
+    /// Number of files hashed
+    pub files: u64,
+    /// Their total size, in bytes
+    pub bytes: u64,
+}
+
+impl DedupeReport {
+    /// Bytes that storing each contents once would save.
+    pub fn wasted(&self) -> u64 {
+        self.groups.iter().map(DuplicateGroup::wasted).sum()
+    }
+}
+
+/// Hashes the contents of every file entry to find duplicates. Directories
+/// and special files are left out, and so are empty files, which are all
+/// alike and waste nothing.
+#[derive(Default)]
+pub struct DedupeSink {
+    archive: String,
+    // Digest to the size of the contents and where they were found
+    seen: HashMap<[u8; 32], (u64, Vec<Location>)>,
+    files: u64,
+    bytes: u64,
+}
+
+impl DedupeSink {
+    pub fn new() -> DedupeSink {
+        DedupeSink::default()
+    }
+
+    /// Labels the copies found from now on as coming from `archive`.
+    pub fn set_archive(&mut self, archive: &str) {
+        self.archive = archive.to_string();
+    }
+
+    pub fn report(self) -> DedupeReport {
+        let mut groups: Vec<DuplicateGroup> = self
+            .seen
+            .into_iter()
+            .filter(|(_, (_, copies))| copies.len() > 1)
+            .map(|(digest, (size, copies))| DuplicateGroup {
+                digest: to_hex(&digest),
+                size,
+                copies,
+            })
+            .collect();
+        // Ties broken on the first copy so reports don't depend on the
+        // order of the hash map
+        groups.sort_by(|a, b| {
+            b.wasted().cmp(&a.wasted()).then_with(|| {
+                (&a.copies[0].archive, &a.copies[0].name)
+                    .cmp(&(&b.copies[0].archive, &b.copies[0].name))
+            })
+        });
+        DedupeReport {
+            groups,
+            files: self.files,
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl Sink for DedupeSink {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        if entry.filename.ends_with('/') {
+            return Ok(());
+        }
+        self.files += 1;
+        self.bytes += data.len() as u64;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let digest: [u8; 32] = Sha256::digest(data).into();
+        let copy: Location = Location {
+            archive: self.archive.clone(),
+            name: entry.filename.clone(),
+        };
+        self.seen
+            .entry(digest)
+            .or_insert_with(|| (data.len() as u64, vec![]))
+            .1
+            .push(copy);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str) -> ZipFileEntry {
+        ZipFileEntry {
+            filename: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_duplicates_across_archives() -> io::Result<()> {
+        let mut sink = DedupeSink::new();
+        sink.set_archive("a.zip");
+        sink.write_entry(&file("one.txt"), b"hello")?;
+        sink.write_entry(&file("big.bin"), &[7; 100])?;
+        sink.write_entry(&file("dir/"), b"")?;
+        sink.write_entry(&file("empty"), b"")?;
+        sink.write_entry(&file("empty2"), b"")?;
+        sink.set_archive("b.zip");
+        sink.write_entry(&file("copy.txt"), b"hello")?;
+        sink.write_entry(&file("again.txt"), b"hello")?;
+        sink.write_entry(&file("big-copy.bin"), &[7; 100])?;
+        sink.write_entry(&file("other.txt"), b"world")?;
+
+        let report: DedupeReport = sink.report();
+        assert_eq!(report.files, 8);
+        assert_eq!(report.bytes, 220);
+        assert_eq!(report.wasted(), 110);
+        assert_eq!(report.groups.len(), 2);
+        assert_eq!(report.groups[0].size, 100);
+        assert_eq!(report.groups[0].wasted(), 100);
+        let names: Vec<(&str, &str)> = report.groups[1]
+            .copies
+            .iter()
+            .map(|copy| (copy.archive.as_str(), copy.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("a.zip", "one.txt"),
+                ("b.zip", "copy.txt"),
+                ("b.zip", "again.txt")
+            ]
+        );
+        assert_eq!(
+            report.groups[1].digest,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! Comparison of two archives.
+//!
+//! Entries are matched by normalized name, so `./a/b`, `a\b` and `a//b` in
+//! one archive match `a/b` in the other, and compared by size and CRC-32
+//! as recorded in the central directory, without decompressing anything.
+//! [`unified_diff`] compares the contents of text entries line by line.
+
+use crate::zip::ZipFileEntry;
+use std::collections::BTreeMap;
+
+// Text entries whose lines, once the common start and end are left out,
+// would need a table of more cells than this to compare are reported as
+// differing without a diff
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+/// Normalizes an entry name for matching: `\` becomes `/`, and empty and
+/// `.` components are dropped along with leading slashes. Directories keep
+/// their trailing slash.
+pub fn normalize_name(name: &str) -> String {
+    let name: String = name.replace('\\', "/");
+    let mut normalized: String = name
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<&str>>()
+        .join("/");
+    if name.ends_with('/') && !normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// The entries of an archive by normalized name, in name order.
+#[derive(Debug, Default)]
+pub struct EntryIndex {
+    entries: BTreeMap<String, ZipFileEntry>,
+}
+
+impl EntryIndex {
+    /// Indexes `entries`. When several have the same normalized name, the
+    /// first one is kept, like [`ZipArchive::by_name`](crate::zip::ZipArchive::by_name)
+    /// does.
+    pub fn new<I: IntoIterator<Item = ZipFileEntry>>(entries: I) -> EntryIndex {
+        let mut index: BTreeMap<String, ZipFileEntry> = BTreeMap::new();
+        for entry in entries {
+            let name: String = normalize_name(&entry.filename);
+            if index.contains_key(&name) {
+                eprintln!("Ignoring duplicate entry {}", entry.filename);
+                continue;
+            }
+            index.insert(name, entry);
+        }
+        EntryIndex { entries: index }
+    }
+
+    /// Looks up an entry by name, normalized first.
+    pub fn get(&self, name: &str) -> Option<&ZipFileEntry> {
+        self.entries.get(&normalize_name(name))
+    }
+
+    /// Returns the normalized names and entries in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ZipFileEntry)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A difference between two archives, see [`diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// An entry only the first archive has
+    Removed(ZipFileEntry),
+    /// An entry only the second archive has
+    Added(ZipFileEntry),
+    /// An entry both have, with a different size or CRC-32
+    Modified {
+        old: ZipFileEntry,
+        new: ZipFileEntry,
+    },
+}
+
+impl Difference {
+    /// Normalized name of the entry.
+    pub fn name(&self) -> String {
+        match self {
+            Difference::Removed(entry) | Difference::Added(entry) => {
+                normalize_name(&entry.filename)
+            }
+            Difference::Modified { new, .. } => normalize_name(&new.filename),
+        }
+    }
+}
+
+/// Lists the differences between the `old` and `new` archives in name
+/// order.
+pub fn diff(old: &EntryIndex, new: &EntryIndex) -> Vec<Difference> {
+    let mut differences: Vec<(String, Difference)> = vec![];
+    for (name, entry) in old.iter() {
+        match new.entries.get(name) {
+            None => differences.push((name.to_string(), Difference::Removed(entry.clone()))),
+            Some(other)
+                if other.crc32 != entry.crc32
+                    || other.uncompressed_size != entry.uncompressed_size =>
+            {
+                differences.push((
+                    name.to_string(),
+                    Difference::Modified {
+                        old: entry.clone(),
+                        new: other.clone(),
+                    },
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, entry) in new.iter() {
+        if !old.entries.contains_key(name) {
+            differences.push((name.to_string(), Difference::Added(entry.clone())));
+        }
+    }
+    differences.sort_by(|(a, _), (b, _)| a.cmp(b));
+    differences
+        .into_iter()
+        .map(|(_, difference)| difference)
+        .collect()
+}
+
+/// Returns `data` as text if it looks like text: valid UTF-8 without NUL
+/// bytes.
+pub fn as_text(data: &[u8]) -> Option<&str> {
+    if data.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(data).ok()
+}
+
+/// Formats the line differences between `old` and `new` as unified diff
+/// hunks with `context` unchanged lines around changes, without the file
+/// header lines. Returns `None` when the texts are too large to compare.
+pub fn unified_diff(old: &str, new: &str, context: usize) -> Option<String> {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let script: Vec<Edit> = edit_script(&old, &new)?;
+
+    // Hunks: runs of edits whose unchanged gaps are at most twice the
+    // context, with the context around them
+    let mut output: String = String::new();
+    let changed: Vec<usize> = (0..script.len())
+        .filter(|&i| !matches!(script[i], Edit::Keep(..)))
+        .collect();
+    let mut i: usize = 0;
+    while i < changed.len() {
+        let mut last: usize = i;
+        while last + 1 < changed.len() && changed[last + 1] - changed[last] <= 2 * context + 1 {
+            last += 1;
+        }
+        let start: usize = changed[i].saturating_sub(context);
+        let end: usize = (changed[last] + context + 1).min(script.len());
+        let hunk: &[Edit] = &script[start..end];
+
+        let (old_start, new_start) = script[start].positions();
+        let old_count: usize = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(..)))
+            .count();
+        let new_count: usize = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(..)))
+            .count();
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+        for edit in hunk {
+            let (prefix, line) = match *edit {
+                Edit::Keep(i, _) => (' ', old[i]),
+                Edit::Delete(i, _) => ('-', old[i]),
+                Edit::Insert(_, j) => ('+', new[j]),
+            };
+            output.push(prefix);
+            output.push_str(line);
+            if !line.ends_with('\n') {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        i = last + 1;
+    }
+    Some(output)
+}
+
+/// Formats the line range of a hunk the way `diff -u` does: 1-based start
+/// and count, the count left out when it's 1, and the start being the line
+/// before the hunk when it's empty.
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// A step of an edit script, with the positions in the old and new lines
+/// it's at.
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    Keep(usize, usize),
+    Delete(usize, usize),
+    Insert(usize, usize),
+}
+
+impl Edit {
+    fn positions(self) -> (usize, usize) {
+        match self {
+            Edit::Keep(i, j) | Edit::Delete(i, j) | Edit::Insert(i, j) => (i, j),
+        }
+    }
+}
+
+/// Computes the shortest edit script from `old` to `new` from their longest
+/// common subsequence of lines.
+fn edit_script(old: &[&str], new: &[&str]) -> Option<Vec<Edit>> {
+    let prefix: usize = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix: usize = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    let cells: usize = (a.len() + 1).checked_mul(b.len() + 1)?;
+    if cells > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    // lengths[i * width + j]: longest common subsequence of a[i..] and b[j..]
+    let width: usize = b.len() + 1;
+    let mut lengths: Vec<u32> = vec![0; cells];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if a[i] == b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut script: Vec<Edit> = (0..prefix).map(|i| Edit::Keep(i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let (x, y) = (prefix + i, prefix + j);
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            script.push(Edit::Keep(x, y));
+            (i, j) = (i + 1, j + 1);
+        } else if j == b.len()
+            || (i < a.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            script.push(Edit::Delete(x, y));
+            i += 1;
+        } else {
+            script.push(Edit::Insert(x, y));
+            j += 1;
+        }
+    }
+    let (x, y) = (prefix + a.len(), prefix + b.len());
+    script.extend((0..suffix).map(|k| Edit::Keep(x + k, y + k)));
+    Some(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, crc32: u32, size: u32) -> ZipFileEntry {
+        ZipFileEntry {
+            filename: name.to_string(),
+            crc32,
+            uncompressed_size: size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_indexes() {
+        assert_eq!(normalize_name("./a//b\\c.txt"), "a/b/c.txt");
+        assert_eq!(normalize_name("/dir/./"), "dir/");
+
+        let old: EntryIndex = EntryIndex::new([
+            entry("./same.txt", 1, 1),
+            entry("changed.txt", 2, 2),
+            entry("removed.txt", 3, 3),
+            entry("same.txt", 9, 9),
+        ]);
+        let new: EntryIndex = EntryIndex::new([
+            entry("added.txt", 4, 4),
+            entry("changed.txt", 5, 2),
+            entry("same.txt", 1, 1),
+        ]);
+        assert_eq!(old.len(), 3);
+        assert_eq!(old.get("same.txt").unwrap().crc32, 1);
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Difference::Added(entry("added.txt", 4, 4)),
+                Difference::Modified {
+                    old: entry("changed.txt", 2, 2),
+                    new: entry("changed.txt", 5, 2),
+                },
+                Difference::Removed(entry("removed.txt", 3, 3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let new: String = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 9\n", "")
+            + "last";
+        assert_eq!(
+            unified_diff(&old, &new, 1).unwrap(),
+            "@@ -1,3 +1,3 @@\n line 1\n-line 2\n+line two\n line 3\n\
+             @@ -8,3 +8,3 @@\n line 8\n-line 9\n line 10\n+last\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified_diff(&old, &old, 3).unwrap(), "");
+        assert_eq!(as_text(b"bin\0ary"), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! Decoding of entry filenames.
+//!
+//! ZIP predates Unicode: names are UTF-8 only when general purpose bit 11 is
+//! set, otherwise they are IBM code page 437. Info-ZIP tools also attach a
+//! Unicode Path extra field (0x7075) carrying a UTF-8 copy of the name.
+
+use std::borrow::Cow;
+
+// General purpose bit 11: filename and comment are encoded in UTF-8
+pub const FLAG_UTF8: u16 = 0x0800;
+
+const UNICODE_PATH_EXTRA_ID: u16 = 0x7075;
+
+// Code points for bytes 0x80..=0xFF in code page 437
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Decodes CP437 bytes. Bytes below 0x80 are taken as ASCII.
+pub fn decode_cp437(raw: &[u8]) -> Cow<'_, str> {
+    if raw.is_ascii() {
+        // ASCII is valid UTF-8, so this never allocates
+        return String::from_utf8_lossy(raw);
+    }
+    Cow::Owned(
+        raw.iter()
+            .map(|&b| match b {
+                0x00..=0x7f => b as char,
+                _ => CP437_HIGH[(b - 0x80) as usize],
+            })
+            .collect(),
+    )
+}
+
+/// Decodes a raw filename using the general purpose `flags` and the entry's
+/// `extra` field.
+///
+/// A Unicode Path extra field wins when its CRC matches the raw name, i.e.
+/// when the name wasn't changed by a tool unaware of the extra field.
+pub fn decode_filename<'a>(raw: &'a [u8], flags: u16, extra: &'a [u8]) -> Cow<'a, str> {
+    if let Some(name) = unicode_path(raw, extra) {
+        return Cow::Borrowed(name);
+    }
+    decode_comment(raw, flags)
+}
+
+/// Decodes a raw entry comment, which is UTF-8 when bit 11 of the general
+/// purpose `flags` is set and CP437 otherwise.
+pub fn decode_comment(raw: &[u8], flags: u16) -> Cow<'_, str> {
+    if flags & FLAG_UTF8 != 0 {
+        return String::from_utf8_lossy(raw);
+    }
+    decode_cp437(raw)
+}
+
+fn unicode_path<'a>(raw: &[u8], extra: &'a [u8]) -> Option<&'a str> {
+    // Extra field blocks:
+    // [2 bytes]  Header ID
+    // [2 bytes]  Data size
+    // [variable] Data
+    //
+    // Unicode Path data:
+    // [1 byte]   Version (1)
+    // [4 bytes]  CRC-32 of the raw filename
+    // [variable] UTF-8 filename
+    let mut rest: &[u8] = extra;
+    while rest.len() >= 4 {
+        let id: u16 = u16::from_le_bytes([rest[0], rest[1]]);
+        let size: usize = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data: &[u8] = rest.get(4..4 + size)?;
+        if id == UNICODE_PATH_EXTRA_ID && size >= 5 && data[0] == 1 {
+            let name_crc: u32 = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            if name_crc == crc32fast::hash(raw) {
+                return std::str::from_utf8(&data[5..]).ok();
+            }
+        }
+        rest = &rest[4 + size..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unicode_path_extra(raw: &[u8], name: &str) -> Vec<u8> {
+        let mut extra: Vec<u8> = vec![];
+        extra.extend_from_slice(&UNICODE_PATH_EXTRA_ID.to_le_bytes());
+        extra.extend_from_slice(&(5 + name.len() as u16).to_le_bytes());
+        extra.push(1);
+        extra.extend_from_slice(&crc32fast::hash(raw).to_le_bytes());
+        extra.extend_from_slice(name.as_bytes());
+        extra
+    }
+
+    #[test]
+    fn test_cp437_decoding() {
+        assert_eq!(decode_cp437(b"plain.txt"), "plain.txt");
+        assert_eq!(decode_cp437(b"caf\x82 \xe1\xff"), "café ß\u{a0}");
+    }
+
+    #[test]
+    fn test_utf8_flag() {
+        let raw: &[u8] = "naïve".as_bytes();
+        assert_eq!(decode_filename(raw, FLAG_UTF8, &[]), "naïve");
+        assert_eq!(decode_filename(raw, 0, &[]), "na├»ve");
+    }
+
+    #[test]
+    fn test_unicode_path_extra_field() {
+        let raw: &[u8] = b"bad\x81.txt";
+        let extra: Vec<u8> = unicode_path_extra(raw, "über.txt");
+        assert_eq!(decode_filename(raw, 0, &extra), "über.txt");
+
+        // A stale CRC means the raw name was renamed after the extra was written
+        let stale: Vec<u8> = unicode_path_extra(b"other", "über.txt");
+        assert_eq!(decode_filename(raw, 0, &stale), "badü.txt");
+    }
+}
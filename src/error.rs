@@ -0,0 +1,180 @@
+//! Errors specific to xpack.
+//!
+//! The library reports failures as `io::Error` throughout. Conditions callers
+//! may want to react to are raised as an [`XpackError`] wrapped in an
+//! `io::Error`, and can be recovered with [`XpackError::from_io`].
+
+use crate::zip::{Encryption, ZipFileEntry};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum XpackError {
+    /// Decoding `entry` ran past a time limit. `per_entry` tells whether the
+    /// entry limit or the limit for the whole operation was hit.
+    Timeout {
+        entry: String,
+        limit: Duration,
+        per_entry: bool,
+    },
+    /// Decompressing `entry` ran past one of the size limits guarding
+    /// against decompression bombs.
+    LimitExceeded { entry: String, limit: Limit },
+    /// The archive is split into `disks` parts and was opened from its last
+    /// part alone.
+    SplitArchive { disks: u16 },
+    /// `entry` has the same name as an earlier entry, with
+    /// [`DuplicatePolicy::Error`](crate::zip::DuplicatePolicy::Error).
+    DuplicateEntry { entry: String },
+    /// `entry` can't be decoded, e.g. because it is encrypted, with
+    /// [`UnsupportedPolicy::Error`](crate::zip::UnsupportedPolicy::Error).
+    Unsupported { entry: String, reason: String },
+    /// `entry` is encrypted, which xpack can't decrypt. Raised instead of
+    /// [`XpackError::Unsupported`], and also with
+    /// [`UnsupportedPolicy::Warn`](crate::zip::UnsupportedPolicy::Warn):
+    /// skipping the entry would quietly leave out a file.
+    PasswordRequired {
+        entry: String,
+        encryption: Encryption,
+    },
+    /// Entries that can't be decoded were skipped, with
+    /// [`UnsupportedPolicy::Collect`](crate::zip::UnsupportedPolicy::Collect).
+    /// Every other entry was extracted.
+    SkippedEntries { skipped: Vec<SkippedEntry> },
+    /// The extraction was cancelled through its
+    /// [`CancellationToken`](crate::cancel::CancellationToken) while at
+    /// `entry`.
+    Cancelled { entry: String },
+}
+
+/// An entry left out of an extraction, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedEntry {
+    pub entry: String,
+    /// E.g. `"encrypted with ZipCrypto, password required"` or
+    /// `"unsupported compression method 98"`
+    pub reason: String,
+}
+
+/// A limit on decompressed output, see
+/// [`ExtractOptions`](crate::zip::ExtractOptions).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    EntrySize(u64),
+    TotalSize(u64),
+    Ratio(f64),
+}
+
+impl XpackError {
+    /// Returns the xpack error carried by `error`, if any.
+    pub fn from_io(error: &io::Error) -> Option<&XpackError> {
+        error.get_ref()?.downcast_ref::<XpackError>()
+    }
+
+    /// The error for `entry`, which can't be decoded for `reason`:
+    /// [`XpackError::PasswordRequired`] if it is encrypted, otherwise
+    /// [`XpackError::Unsupported`].
+    pub fn unsupported(entry: &ZipFileEntry, reason: String) -> XpackError {
+        match entry.encryption() {
+            Encryption::None => XpackError::Unsupported {
+                entry: entry.filename.clone(),
+                reason,
+            },
+            encryption => XpackError::PasswordRequired {
+                entry: entry.filename.clone(),
+                encryption,
+            },
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            XpackError::Timeout { .. } => io::ErrorKind::TimedOut,
+            XpackError::LimitExceeded { .. } => io::ErrorKind::FileTooLarge,
+            XpackError::SplitArchive { .. } => io::ErrorKind::Unsupported,
+            XpackError::DuplicateEntry { .. } => io::ErrorKind::AlreadyExists,
+            XpackError::Unsupported { .. } | XpackError::SkippedEntries { .. } => {
+                io::ErrorKind::Unsupported
+            }
+            XpackError::PasswordRequired { .. } => io::ErrorKind::PermissionDenied,
+            // Not `Interrupted`, which readers retry on
+            XpackError::Cancelled { .. } => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for XpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XpackError::Timeout {
+                entry,
+                limit,
+                per_entry,
+            } => write!(
+                f,
+                "{} exceeded the {} timeout of {:?}",
+                entry,
+                if *per_entry { "entry" } else { "total" },
+                limit
+            ),
+            XpackError::LimitExceeded { entry, limit } => match limit {
+                Limit::EntrySize(max) => {
+                    write!(f, "{} exceeds the entry size limit of {} bytes", entry, max)
+                }
+                Limit::TotalSize(max) => write!(
+                    f,
+                    "{} takes the output past the total size limit of {} bytes",
+                    entry, max
+                ),
+                Limit::Ratio(ratio) => write!(
+                    f,
+                    "{} exceeds the compression ratio limit of {}",
+                    entry, ratio
+                ),
+            },
+            XpackError::SplitArchive { disks } => write!(
+                f,
+                "Archive is split into {} parts, open it as a split archive",
+                disks
+            ),
+            XpackError::DuplicateEntry { entry } => {
+                write!(f, "{} appears more than once in the archive", entry)
+            }
+            XpackError::Unsupported { entry, reason } => {
+                write!(f, "Can't extract {}: {}", entry, reason)
+            }
+            XpackError::SkippedEntries { skipped } => {
+                let noun: &str = if skipped.len() == 1 {
+                    "entry"
+                } else {
+                    "entries"
+                };
+                write!(
+                    f,
+                    "Skipped {} {} that can't be extracted:",
+                    skipped.len(),
+                    noun
+                )?;
+                for skipped in skipped {
+                    write!(f, "\n  {}: {}", skipped.entry, skipped.reason)?;
+                }
+                Ok(())
+            }
+            XpackError::PasswordRequired { entry, encryption } => write!(
+                f,
+                "Can't extract {}: encrypted with {}, password required",
+                entry, encryption
+            ),
+            XpackError::Cancelled { entry } => write!(f, "Cancelled at {}", entry),
+        }
+    }
+}
+
+impl std::error::Error for XpackError {}
+
+impl From<XpackError> for io::Error {
+    fn from(error: XpackError) -> io::Error {
+        io::Error::new(error.kind(), error)
+    }
+}
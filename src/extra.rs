@@ -0,0 +1,302 @@
+//! Parsing of extra fields.
+//!
+//! Local file headers and central directory records end with a list of
+//! extra field blocks that carry what the fixed-size headers can't: 64-bit
+//! sizes, precise timestamps, Unix ownership, encryption parameters, ...
+//!
+//! Every block starts with the same header:
+//! [2 bytes]  Header ID
+//! [2 bytes]  Data size
+//! [variable] Data
+//!
+//! The same entry may carry different blocks, or different data for the same
+//! block, in its local header and in its central directory record.
+
+pub const ZIP64_EXTRA_ID: u16 = 0x0001;
+pub const NTFS_EXTRA_ID: u16 = 0x000a;
+pub const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+pub const UNIX_EXTRA_ID: u16 = 0x7875;
+pub const AES_EXTRA_ID: u16 = 0x9901;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtraField {
+    /// Zip64 extended information. Holds a 64-bit value for each of the
+    /// uncompressed size, compressed size and local header offset whose
+    /// 32-bit header field is saturated (0xFFFFFFFF), in that order, so
+    /// which value is which depends on the header.
+    Zip64 {
+        values: Vec<u64>,
+        disk_start: Option<u32>,
+    },
+    /// Extended timestamp, in seconds since the Unix epoch. Central
+    /// directory records usually only carry the modification time.
+    ExtendedTimestamp {
+        modified: Option<i32>,
+        accessed: Option<i32>,
+        created: Option<i32>,
+    },
+    /// Info-ZIP new Unix extra field, owner user and group IDs
+    Unix { uid: u32, gid: u32 },
+    /// WinZip AES encryption
+    Aes {
+        vendor_version: u16,
+        /// 1, 2 and 3 for 128, 192 and 256-bit keys
+        strength: u8,
+        /// Compression method of the data once decrypted
+        compression_method: u16,
+    },
+    /// NTFS timestamps, in 100 ns intervals since 1601-01-01
+    Ntfs {
+        modified: u64,
+        accessed: u64,
+        created: u64,
+    },
+    /// A block of any other type, or a known one that couldn't be parsed
+    Unknown { id: u16, data: Vec<u8> },
+}
+
+impl ExtraField {
+    /// Header ID of the block this field was parsed from.
+    pub fn id(&self) -> u16 {
+        match self {
+            ExtraField::Zip64 { .. } => ZIP64_EXTRA_ID,
+            ExtraField::ExtendedTimestamp { .. } => EXTENDED_TIMESTAMP_EXTRA_ID,
+            ExtraField::Unix { .. } => UNIX_EXTRA_ID,
+            ExtraField::Aes { .. } => AES_EXTRA_ID,
+            ExtraField::Ntfs { .. } => NTFS_EXTRA_ID,
+            ExtraField::Unknown { id, .. } => *id,
+        }
+    }
+}
+
+/// Parses the extra field blocks in `extra`. Blocks that are malformed come
+/// back as [`ExtraField::Unknown`]; a block running past the end of `extra`
+/// ends the list.
+pub fn parse_extra_fields(extra: &[u8]) -> Vec<ExtraField> {
+    let mut fields: Vec<ExtraField> = vec![];
+    let mut rest: &[u8] = extra;
+    while rest.len() >= 4 {
+        let id: u16 = u16::from_le_bytes([rest[0], rest[1]]);
+        let size: usize = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let Some(data) = rest.get(4..4 + size) else {
+            break;
+        };
+        let field: Option<ExtraField> = match id {
+            ZIP64_EXTRA_ID => parse_zip64(data),
+            EXTENDED_TIMESTAMP_EXTRA_ID => parse_extended_timestamp(data),
+            UNIX_EXTRA_ID => parse_unix(data),
+            AES_EXTRA_ID => parse_aes(data),
+            NTFS_EXTRA_ID => parse_ntfs(data),
+            _ => None,
+        };
+        fields.push(field.unwrap_or_else(|| ExtraField::Unknown {
+            id,
+            data: data.to_vec(),
+        }));
+        rest = &rest[4 + size..];
+    }
+    fields
+}
+
+/// Returns the data of the first block with the given `id` in `extra`,
+/// without parsing any block.
+pub fn find_extra_field(extra: &[u8], id: u16) -> Option<&[u8]> {
+    let mut rest: &[u8] = extra;
+    while rest.len() >= 4 {
+        let size: usize = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data: &[u8] = rest.get(4..4 + size)?;
+        if u16::from_le_bytes([rest[0], rest[1]]) == id {
+            return Some(data);
+        }
+        rest = &rest[4 + size..];
+    }
+    None
+}
+
+fn parse_zip64(data: &[u8]) -> Option<ExtraField> {
+    // [8 bytes]  Uncompressed size, compressed size and local header
+    //            offset, each only if saturated in the header
+    // [4 bytes]  Number of the disk the entry starts on, optional
+    let mut chunks = data.chunks_exact(8);
+    let values: Vec<u64> = chunks
+        .by_ref()
+        .take(3)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let rest: &[u8] = &data[values.len() * 8..];
+    let disk_start: Option<u32> = match rest.len() {
+        0 => None,
+        4 => Some(u32::from_le_bytes(rest.try_into().unwrap())),
+        _ => return None,
+    };
+    Some(ExtraField::Zip64 { values, disk_start })
+}
+
+fn parse_extended_timestamp(data: &[u8]) -> Option<ExtraField> {
+    // [1 byte]   Flags: bit 0 modification, bit 1 access, bit 2 creation
+    //            time present
+    // [4 bytes]  Each time whose flag is set, as far as the data goes
+    let (flags, mut rest) = data.split_first()?;
+    let mut times: [Option<i32>; 3] = [None; 3];
+    for (bit, time) in times.iter_mut().enumerate() {
+        if flags & (1 << bit) == 0 || rest.len() < 4 {
+            continue;
+        }
+        *time = Some(i32::from_le_bytes(rest[..4].try_into().unwrap()));
+        rest = &rest[4..];
+    }
+    let [modified, accessed, created] = times;
+    Some(ExtraField::ExtendedTimestamp {
+        modified,
+        accessed,
+        created,
+    })
+}
+
+fn parse_unix(data: &[u8]) -> Option<ExtraField> {
+    // [1 byte]   Version (1)
+    // [1 byte]   UID size
+    // [variable] UID
+    // [1 byte]   GID size
+    // [variable] GID
+    let (&version, rest) = data.split_first()?;
+    if version != 1 {
+        return None;
+    }
+    let (uid, rest) = parse_id(rest)?;
+    let (gid, _) = parse_id(rest)?;
+    Some(ExtraField::Unix { uid, gid })
+}
+
+/// Reads a size-prefixed little-endian ID that fits in 32 bits.
+fn parse_id(data: &[u8]) -> Option<(u32, &[u8])> {
+    let (&size, rest) = data.split_first()?;
+    let bytes: &[u8] = rest.get(..size as usize)?;
+    let mut value: u64 = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        if byte != 0 && i >= 4 {
+            return None;
+        }
+        if i < 4 {
+            value |= (byte as u64) << (8 * i);
+        }
+    }
+    Some((value as u32, &rest[size as usize..]))
+}
+
+fn parse_aes(data: &[u8]) -> Option<ExtraField> {
+    // [2 bytes]  Vendor version (1 for AE-1, 2 for AE-2)
+    // [2 bytes]  Vendor ID ("AE")
+    // [1 byte]   Key strength
+    // [2 bytes]  Actual compression method
+    if data.len() != 7 || &data[2..4] != b"AE" {
+        return None;
+    }
+    Some(ExtraField::Aes {
+        vendor_version: u16::from_le_bytes([data[0], data[1]]),
+        strength: data[4],
+        compression_method: u16::from_le_bytes([data[5], data[6]]),
+    })
+}
+
+fn parse_ntfs(data: &[u8]) -> Option<ExtraField> {
+    // [4 bytes]  Reserved
+    // Attributes:
+    // [2 bytes]  Tag, 1 for timestamps
+    // [2 bytes]  Size, 24 for timestamps
+    // [8 bytes]  Modification, access and creation time
+    let mut rest: &[u8] = data.get(4..)?;
+    while rest.len() >= 4 {
+        let tag: u16 = u16::from_le_bytes([rest[0], rest[1]]);
+        let size: usize = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let attribute: &[u8] = rest.get(4..4 + size)?;
+        if tag == 1 && size == 24 {
+            let time =
+                |i: usize| u64::from_le_bytes(attribute[i * 8..i * 8 + 8].try_into().unwrap());
+            return Some(ExtraField::Ntfs {
+                modified: time(0),
+                accessed: time(1),
+                created: time(2),
+            });
+        }
+        rest = &rest[4 + size..];
+    }
+    None
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+    use std::io;
+
+    fn block(id: u16, data: &[u8]) -> Vec<u8> {
+        let mut block: Vec<u8> = id.to_le_bytes().to_vec();
+        block.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        block.extend_from_slice(data);
+        block
+    }
+
+    #[test]
+    fn test_parse_extra_fields() {
+        let mut extra: Vec<u8> = vec![];
+        extra.extend(block(ZIP64_EXTRA_ID, &[5, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]));
+        extra.extend(block(AES_EXTRA_ID, &[2, 0, b'A', b'E', 3, 8, 0]));
+        let mut ntfs: Vec<u8> = vec![0, 0, 0, 0, 1, 0, 24, 0];
+        for time in [10u64, 20, 30] {
+            ntfs.extend_from_slice(&time.to_le_bytes());
+        }
+        extra.extend(block(NTFS_EXTRA_ID, &ntfs));
+        extra.extend(block(0xcafe, b"raw"));
+        // Truncated block header, ignored
+        extra.extend_from_slice(&[0x55, 0x54, 9]);
+
+        assert_eq!(
+            parse_extra_fields(&extra),
+            [
+                ExtraField::Zip64 {
+                    values: vec![0x1_0000_0005],
+                    disk_start: Some(2),
+                },
+                ExtraField::Aes {
+                    vendor_version: 2,
+                    strength: 3,
+                    compression_method: 8,
+                },
+                ExtraField::Ntfs {
+                    modified: 10,
+                    accessed: 20,
+                    created: 30,
+                },
+                ExtraField::Unknown {
+                    id: 0xcafe,
+                    data: b"raw".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_info_zip_extra_fields() -> io::Result<()> {
+        // Written by Info-ZIP with extended timestamps and Unix ownership
+        let archive = ZipArchive::open(get_test_file_path("test_multiple.zip").to_str().unwrap())?;
+        let entry = archive.by_name("test1.txt")?;
+        let fields: &[ExtraField] = &entry.metadata().extra_fields;
+        assert!(matches!(
+            fields[0],
+            ExtraField::ExtendedTimestamp {
+                modified: Some(1731603432),
+                ..
+            }
+        ));
+        assert_eq!(
+            fields[1],
+            ExtraField::Unix {
+                uid: 1000,
+                gid: 1000
+            }
+        );
+        Ok(())
+    }
+}
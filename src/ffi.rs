@@ -0,0 +1,292 @@
+//! C interface for embedding xpack as an unzip library.
+//!
+//! Build a shared or static library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib` (or
+//! `staticlib`) and include `include/xpack.h`, generated from this module
+//! by cbindgen.
+//!
+//! Functions report failure with a null pointer or a negative return value
+//! and keep a message for [`xpack_last_error`]. Panics don't cross the
+//! boundary; they're reported as errors too.
+
+use crate::sink::{DiskSink, Sink};
+use crate::zip::{ZipArchive, ZipFileEntry};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::File;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// An archive opened with [`xpack_open`], opaque to C.
+pub struct XpackArchive {
+    archive: ZipArchive<File>,
+    entries: Vec<ZipFileEntry>,
+    // Entry names as C strings, owned here so xpack_entry_name can lend them
+    names: Vec<CString>,
+}
+
+fn set_last_error(message: String) {
+    // Names can hold NUL bytes, which C strings can't
+    let message: CString = CString::new(message.replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into `failed` and a message for
+/// [`xpack_last_error`].
+fn guard<T, F: FnOnce() -> io::Result<T>>(failed: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            failed
+        }
+        Err(_) => {
+            set_last_error("xpack panicked".to_string());
+            failed
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Borrows a C string argument, rejecting null and invalid UTF-8.
+///
+/// # Safety
+///
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn str_argument<'a>(s: *const c_char, name: &str) -> io::Result<&'a str> {
+    if s.is_null() {
+        return Err(invalid(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| invalid(&format!("{} isn't valid UTF-8", name)))
+}
+
+impl XpackArchive {
+    fn entry(&self, index: usize) -> io::Result<&ZipFileEntry> {
+        self.entries.get(index).ok_or_else(|| {
+            invalid(&format!(
+                "Entry {} is out of range, the archive has {}",
+                index,
+                self.entries.len()
+            ))
+        })
+    }
+
+    fn read(&self, index: usize) -> io::Result<Vec<u8>> {
+        let entry: &ZipFileEntry = self.entry(index)?;
+        self.archive.read(entry)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Can't decode {}", entry.filename),
+            )
+        })
+    }
+}
+
+/// Opens the ZIP archive at `path`, a UTF-8 string. Returns null on error.
+/// Close the archive with [`xpack_close`].
+///
+/// # Safety
+///
+/// `path` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_open(path: *const c_char) -> *mut XpackArchive {
+    guard(ptr::null_mut(), || {
+        let path: &str = str_argument(path, "path")?;
+        let archive: ZipArchive<File> = ZipArchive::open(path)?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        let names: Vec<CString> = entries
+            .iter()
+            .map(|entry| CString::new(entry.filename.replace('\0', "")).unwrap())
+            .collect();
+        Ok(Box::into_raw(Box::new(XpackArchive {
+            archive,
+            entries,
+            names,
+        })))
+    })
+}
+
+/// Closes an archive returned by [`xpack_open`]. Null is ignored.
+///
+/// # Safety
+///
+/// `archive` must be null or returned by `xpack_open` and not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_close(archive: *mut XpackArchive) {
+    if !archive.is_null() {
+        drop(Box::from_raw(archive));
+    }
+}
+
+/// Returns the number of entries, directories included, or -1 on error.
+///
+/// # Safety
+///
+/// `archive` must be null or an open archive.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_entry_count(archive: *const XpackArchive) -> i64 {
+    guard(-1, || match archive.as_ref() {
+        Some(archive) => Ok(archive.entries.len() as i64),
+        None => Err(invalid("archive is null")),
+    })
+}
+
+/// Returns the name of entry `index`, or null on error. The string belongs
+/// to the archive and is valid until it's closed.
+///
+/// # Safety
+///
+/// `archive` must be null or an open archive.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_entry_name(
+    archive: *const XpackArchive,
+    index: usize,
+) -> *const c_char {
+    guard(ptr::null(), || {
+        let archive: &XpackArchive = archive.as_ref().ok_or_else(|| invalid("archive is null"))?;
+        archive.entry(index)?;
+        Ok(archive.names[index].as_ptr())
+    })
+}
+
+/// Extracts entry `index` below the directory `output_dir`, a UTF-8
+/// string, creating missing directories and overwriting existing files.
+/// Returns 0, or -1 on error.
+///
+/// # Safety
+///
+/// `archive` must be null or an open archive, and `output_dir` null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_extract_entry(
+    archive: *const XpackArchive,
+    index: usize,
+    output_dir: *const c_char,
+) -> c_int {
+    guard(-1, || {
+        let archive: &XpackArchive = archive.as_ref().ok_or_else(|| invalid("archive is null"))?;
+        let output_dir: &str = str_argument(output_dir, "output_dir")?;
+        let data: Vec<u8> = archive.read(index)?;
+        DiskSink::new(output_dir).write_entry(archive.entry(index)?, &data)?;
+        Ok(0)
+    })
+}
+
+/// Decodes entry `index` into a new buffer, stored with its length in
+/// `*data` and `*len`. Returns 0, or -1 on error. Free the buffer with
+/// [`xpack_free_buffer`].
+///
+/// # Safety
+///
+/// `archive` must be null or an open archive, and `data` and `len` null or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_read_entry(
+    archive: *const XpackArchive,
+    index: usize,
+    data: *mut *mut u8,
+    len: *mut usize,
+) -> c_int {
+    guard(-1, || {
+        let archive: &XpackArchive = archive.as_ref().ok_or_else(|| invalid("archive is null"))?;
+        if data.is_null() || len.is_null() {
+            return Err(invalid("data and len must not be null"));
+        }
+        let buffer: Box<[u8]> = archive.read(index)?.into_boxed_slice();
+        *len = buffer.len();
+        *data = Box::into_raw(buffer) as *mut u8;
+        Ok(0)
+    })
+}
+
+/// Frees a buffer returned by [`xpack_read_entry`]. Null is ignored.
+///
+/// # Safety
+///
+/// `data` and `len` must be null or as returned by `xpack_read_entry`, and
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn xpack_free_buffer(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Returns the message of the last error on this thread, or null if there
+/// was none. The string is valid until the next error on this thread.
+#[no_mangle]
+pub extern "C" fn xpack_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+
+    fn test_file(filename: &str) -> CString {
+        CString::new(get_test_file_path(filename).to_str().unwrap()).unwrap()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(xpack_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_ffi_read_and_extract() {
+        let path: CString = test_file("test_multiple.zip");
+        let out_dir = std::env::temp_dir().join("xpack_test_ffi");
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let out: CString = CString::new(out_dir.to_str().unwrap()).unwrap();
+        unsafe {
+            let archive: *mut XpackArchive = xpack_open(path.as_ptr());
+            assert!(!archive.is_null());
+            assert_eq!(xpack_entry_count(archive), 2);
+            let name: &CStr = CStr::from_ptr(xpack_entry_name(archive, 1));
+            assert_eq!(name.to_str().unwrap(), "test2.txt");
+
+            let (mut data, mut len): (*mut u8, usize) = (ptr::null_mut(), 0);
+            assert_eq!(xpack_read_entry(archive, 0, &mut data, &mut len), 0);
+            let contents: Vec<u8> = std::slice::from_raw_parts(data, len).to_vec();
+            xpack_free_buffer(data, len);
+
+            assert_eq!(xpack_extract_entry(archive, 0, out.as_ptr()), 0);
+            assert_eq!(std::fs::read(out_dir.join("test1.txt")).unwrap(), contents);
+            xpack_close(archive);
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap(); // Cleanup
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let missing: CString = test_file("missing.zip");
+        unsafe {
+            assert!(xpack_open(missing.as_ptr()).is_null());
+            assert!(last_error().contains("No such file"));
+            assert!(xpack_open(ptr::null()).is_null());
+            assert_eq!(last_error(), "path is null");
+            assert_eq!(xpack_entry_count(ptr::null()), -1);
+
+            let path: CString = test_file("test_multiple.zip");
+            let archive: *mut XpackArchive = xpack_open(path.as_ptr());
+            assert!(xpack_entry_name(archive, 2).is_null());
+            assert_eq!(last_error(), "Entry 2 is out of range, the archive has 2");
+            xpack_close(archive);
+        }
+    }
+}
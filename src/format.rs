@@ -0,0 +1,118 @@
+//! Detection of archive formats from their leading bytes.
+//!
+//! File extensions lie (`.zip` files that are really tarballs, `.tgz` saved
+//! as `.tar`), so commands look at the first bytes instead.
+
+use crate::ar;
+use crate::cpio::is_cpio_header;
+use crate::sevenz::SIGNATURE;
+use crate::tar::is_tar_header;
+use std::io::{self, Read};
+
+/// Number of leading bytes needed to recognize every supported format, i.e.
+/// one tar header block.
+pub const HEAD_SIZE: u64 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    /// A gzip stream, which has to be decompressed to find the actual format
+    Gzip,
+    SevenZip,
+    /// A RAR4 or RAR5 archive, read with the `rar` feature
+    Rar,
+    /// A cpio archive in any of its header formats
+    Cpio,
+    /// A Unix ar archive, such as a static library or a Debian package
+    Ar,
+}
+
+impl ArchiveFormat {
+    /// Recognizes the format of the data starting with `head`, which should
+    /// hold the first [`HEAD_SIZE`] bytes when that many are available.
+    pub fn detect(head: &[u8]) -> Option<ArchiveFormat> {
+        // Local file header, or end of central directory for an empty archive
+        if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            return Some(ArchiveFormat::Zip);
+        }
+        // ID1, ID2 and the deflate compression method
+        if head.starts_with(b"\x1f\x8b\x08") {
+            return Some(ArchiveFormat::Gzip);
+        }
+        if head.starts_with(SIGNATURE) {
+            return Some(ArchiveFormat::SevenZip);
+        }
+        // Shared by RAR4 and RAR5, which differ in the bytes that follow
+        if head.starts_with(b"Rar!\x1a\x07") {
+            return Some(ArchiveFormat::Rar);
+        }
+        if head.starts_with(ar::SIGNATURE) {
+            return Some(ArchiveFormat::Ar);
+        }
+        if is_cpio_header(head) {
+            return Some(ArchiveFormat::Cpio);
+        }
+        if is_tar_header(head) {
+            return Some(ArchiveFormat::Tar);
+        }
+        None
+    }
+}
+
+/// Reads up to [`HEAD_SIZE`] bytes from the start of `reader`.
+pub fn read_head<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut head: Vec<u8> = vec![];
+    reader.take(HEAD_SIZE).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tar::TarArchive;
+    use crate::test_util::get_test_file_path;
+    use flate2::read::MultiGzDecoder;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_detect_formats() -> io::Result<()> {
+        for (filename, format) in [
+            ("test_single.zip", Some(ArchiveFormat::Zip)),
+            ("test_ustar.tar", Some(ArchiveFormat::Tar)),
+            ("test_gnu.tar.gz", Some(ArchiveFormat::Gzip)),
+            ("test_lzma2.7z", Some(ArchiveFormat::SevenZip)),
+            ("test_rar5.rar", Some(ArchiveFormat::Rar)),
+            ("test_newc.cpio", Some(ArchiveFormat::Cpio)),
+            ("test_bin.cpio", Some(ArchiveFormat::Cpio)),
+            ("test_package.deb", Some(ArchiveFormat::Ar)),
+        ] {
+            let head: Vec<u8> = read_head(&mut File::open(get_test_file_path(filename))?)?;
+            assert_eq!(ArchiveFormat::detect(&head), format, "{}", filename);
+        }
+        assert_eq!(ArchiveFormat::detect(b"just some text"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_tar_composition() -> io::Result<()> {
+        let compressed = File::open(get_test_file_path("test_gnu.tar.gz"))?;
+        let mut decoder = MultiGzDecoder::new(compressed);
+        let head: Vec<u8> = read_head(&mut decoder)?;
+        assert_eq!(ArchiveFormat::detect(&head), Some(ArchiveFormat::Tar));
+
+        let mut archive = TarArchive::new(io::Cursor::new(head).chain(decoder));
+        let mut paths: Vec<String> = vec![];
+        while let Some(header) = archive.next_header()? {
+            paths.push(header.path);
+        }
+        let bytes: Vec<u8> = fs::read(get_test_file_path("test_gnu.tar"))?;
+        let mut plain = TarArchive::new(&bytes[..]);
+        let mut expected: Vec<String> = vec![];
+        while let Some(header) = plain.next_header()? {
+            expected.push(header.path);
+        }
+        assert_eq!(paths, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! Searching the contents of entries without extracting them.
+
+use crate::sink::Sink;
+use crate::writer::glob_match;
+use crate::zip::ZipFileEntry;
+use regex::bytes::Regex;
+use std::io::{self, Write};
+
+// Entries with a NUL byte this close to the start are taken for binary,
+// as git and GNU grep do
+const BINARY_CHECK_SIZE: usize = 8000;
+
+/// Writes the lines of file entries that match a regular expression to
+/// `out`, as `name:line number:line`. Entries are matched as bytes, so
+/// contents that aren't UTF-8 are searched too. Binary entries, with a NUL
+/// byte near the start, only get a note that they match, unless
+/// [`with_binary`](GrepSink::with_binary) is set.
+pub struct GrepSink<W> {
+    regex: Regex,
+    out: W,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    binary: bool,
+    only_matching: bool,
+    names_only: bool,
+    matched_entries: usize,
+}
+
+impl<W: Write> GrepSink<W> {
+    pub fn new(regex: Regex, out: W) -> GrepSink<W> {
+        GrepSink {
+            regex,
+            out,
+            include: vec![],
+            exclude: vec![],
+            binary: false,
+            only_matching: false,
+            names_only: false,
+            matched_entries: 0,
+        }
+    }
+
+    /// Only searches entries whose name matches one of `globs`, see
+    /// [`glob_match`]. No globs means every entry.
+    pub fn with_include(mut self, globs: Vec<String>) -> GrepSink<W> {
+        self.include = globs;
+        self
+    }
+
+    /// Leaves out entries whose name matches one of `globs`.
+    pub fn with_exclude(mut self, globs: Vec<String>) -> GrepSink<W> {
+        self.exclude = globs;
+        self
+    }
+
+    /// Searches binary entries line by line like text ones.
+    pub fn with_binary(mut self, binary: bool) -> GrepSink<W> {
+        self.binary = binary;
+        self
+    }
+
+    /// Writes each match on its own line instead of the whole line.
+    pub fn with_only_matching(mut self, only_matching: bool) -> GrepSink<W> {
+        self.only_matching = only_matching;
+        self
+    }
+
+    /// Writes the name of each entry that matches, once, instead of lines.
+    pub fn with_names_only(mut self, names_only: bool) -> GrepSink<W> {
+        self.names_only = names_only;
+        self
+    }
+
+    /// Returns the number of entries that matched so far.
+    pub fn matched_entries(&self) -> usize {
+        self.matched_entries
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn is_selected(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob_match(glob, name)))
+            && !self.exclude.iter().any(|glob| glob_match(glob, name))
+    }
+}
+
+impl<W: Write> Sink for GrepSink<W> {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        let name: &str = &entry.filename;
+        if name.ends_with('/') || data.is_empty() || !self.is_selected(name) {
+            return Ok(());
+        }
+        let is_binary: bool = data[..data.len().min(BINARY_CHECK_SIZE)].contains(&0);
+        if self.names_only || (is_binary && !self.binary) {
+            if !self.regex.is_match(data) {
+                return Ok(());
+            }
+            self.matched_entries += 1;
+            return match self.names_only {
+                true => writeln!(self.out, "{}", name),
+                false => writeln!(self.out, "Binary entry {} matches", name),
+            };
+        }
+
+        let mut matched: bool = false;
+        let text: &[u8] = data.strip_suffix(b"\n").unwrap_or(data);
+        for (number, line) in text.split(|&b| b == b'\n').enumerate() {
+            if self.only_matching {
+                for found in self.regex.find_iter(line) {
+                    let found: String = String::from_utf8_lossy(found.as_bytes()).into_owned();
+                    writeln!(self.out, "{}:{}:{}", name, number + 1, found)?;
+                    matched = true;
+                }
+            } else if self.regex.is_match(line) {
+                let line: String = String::from_utf8_lossy(line).into_owned();
+                writeln!(self.out, "{}:{}:{}", name, number + 1, line)?;
+                matched = true;
+            }
+        }
+        self.matched_entries += matched as usize;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+
+    fn grep(mut sink: GrepSink<Vec<u8>>, entries: &[(&str, &[u8])]) -> io::Result<String> {
+        for (name, data) in entries {
+            let entry: ZipFileEntry = ZipFileEntry {
+                filename: name.to_string(),
+                ..Default::default()
+            };
+            sink.write_entry(&entry, data)?;
+        }
+        Ok(String::from_utf8(sink.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_grep_archive() -> io::Result<()> {
+        let archive =
+            ZipArchive::open_readonly(get_test_file_path("test_multiple.zip").to_str().unwrap())?;
+        let mut sink = GrepSink::new(Regex::new(r"test\d").unwrap(), vec![]);
+        archive.extract_to(&mut sink)?;
+        assert_eq!(sink.matched_entries(), 2);
+        let output: String = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(
+            output,
+            "test1.txt:1:this is test1\ntest2.txt:1:this is test2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_grep_options() -> io::Result<()> {
+        let entries: &[(&str, &[u8])] = &[
+            ("src/a.rs", b"fn main() {}\nlet x = main;\n"),
+            ("docs/a.md", b"main page"),
+            ("bin/tool", b"\x7fELF\0\0main"),
+            ("dir/", b""),
+        ];
+        let regex: Regex = Regex::new("main").unwrap();
+        assert_eq!(
+            grep(GrepSink::new(regex.clone(), vec![]), entries)?,
+            "src/a.rs:1:fn main() {}\nsrc/a.rs:2:let x = main;\ndocs/a.md:1:main page\n\
+             Binary entry bin/tool matches\n"
+        );
+        let sink = GrepSink::new(regex.clone(), vec![])
+            .with_include(vec!["*.rs".to_string(), "*.md".to_string()])
+            .with_exclude(vec!["docs/**".to_string()])
+            .with_only_matching(true);
+        assert_eq!(grep(sink, entries)?, "src/a.rs:1:main\nsrc/a.rs:2:main\n");
+        let sink = GrepSink::new(regex, vec![]).with_names_only(true);
+        assert_eq!(grep(sink, entries)?, "src/a.rs\ndocs/a.md\nbin/tool\n");
+        Ok(())
+    }
+}
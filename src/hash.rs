@@ -0,0 +1,119 @@
+//! Checksum manifests of archive contents.
+//!
+//! [`HashSink`] hashes every decoded entry and writes one line per entry in
+//! the format of `sha256sum` and friends, so a manifest can be compared with
+//! one made from extracted files, or checked with `sha256sum -c` after
+//! extracting.
+
+use crate::sink::Sink;
+use crate::zip::ZipFileEntry;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    Sha224,
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Returns the lowercase hex digest of `data`.
+    pub fn hex_digest(self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha224 => to_hex(&Sha224::digest(data)),
+            HashAlgorithm::Sha256 => to_hex(&Sha256::digest(data)),
+            HashAlgorithm::Sha384 => to_hex(&Sha384::digest(data)),
+            HashAlgorithm::Sha512 => to_hex(&Sha512::digest(data)),
+        }
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex: String = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+/// Formats a manifest line the way `sha256sum` does: the digest, two spaces
+/// and the name. Names containing a backslash or a line break have them
+/// escaped, and the line starts with a backslash to say so.
+pub fn manifest_line(digest: &str, name: &str) -> String {
+    if !name.contains(['\\', '\n', '\r']) {
+        return format!("{}  {}", digest, name);
+    }
+    let mut escaped: String = String::with_capacity(name.len() + 2);
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    format!("\\{}  {}", digest, escaped)
+}
+
+/// Writes a manifest line to `out` for every file entry. Directories and
+/// special files are left out, as they have no contents to hash.
+pub struct HashSink<W> {
+    algorithm: HashAlgorithm,
+    out: W,
+}
+
+impl<W: Write> HashSink<W> {
+    pub fn new(algorithm: HashAlgorithm, out: W) -> HashSink<W> {
+        HashSink { algorithm, out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Sink for HashSink<W> {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        if entry.filename.ends_with('/') {
+            return Ok(());
+        }
+        let digest: String = self.algorithm.hex_digest(data);
+        writeln!(self.out, "{}", manifest_line(&digest, &entry.filename))
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+
+    #[test]
+    fn test_hash_manifest() -> io::Result<()> {
+        let archive =
+            ZipArchive::open_readonly(get_test_file_path("test_multiple.zip").to_str().unwrap())?;
+        let mut sink = HashSink::new(HashAlgorithm::Sha256, vec![]);
+        archive.extract_to(&mut sink)?;
+        // Digests as printed by `sha256sum` for the extracted files
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "ed28944726c3791d0e3a0dba82d06cdc2b4fe2acad6301dd6f71f05a11aaeb43  test1.txt\n\
+             a59faaacb8e2b927d29cb0d7b692aba38544b1d52e1af0819fa643e768590491  test2.txt\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_line_escapes_names() {
+        assert_eq!(manifest_line("ab", "dir/a b.txt"), "ab  dir/a b.txt");
+        assert_eq!(manifest_line("ab", "a\\b\nc"), "\\ab  a\\\\b\\nc");
+    }
+}
@@ -0,0 +1,309 @@
+//! Reading archives over HTTP(S) with range requests.
+//!
+//! The central directory sits at the end of a ZIP archive and points to
+//! every entry, so an archive on a server that honours `Range` headers can
+//! be listed, and single entries extracted, by fetching a few small ranges
+//! instead of the whole file.
+
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, Instant};
+
+// Smallest range fetched at once. Headers are read a few bytes at a time,
+// so reading ahead saves a round trip for nearly every read.
+const MIN_FETCH: u64 = 64 * 1024;
+
+// Longest a connection attempt or a single read may stall, so a server
+// that stops answering fails the read even without a deadline
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Presents a file on an HTTP(S) server as a seekable reader, fetching the
+/// ranges that are read.
+pub struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    position: u64,
+    // Last fetched range, which serves reads until one falls outside it
+    block_start: u64,
+    block: Vec<u8>,
+    fetched: u64,
+    deadline: Option<Instant>,
+}
+
+impl HttpReader {
+    /// Asks the server for the size of the file at `url`. Fails if the
+    /// server doesn't support range requests.
+    pub fn open(url: &str) -> io::Result<HttpReader> {
+        HttpReader::open_with_deadline(url, None)
+    }
+
+    /// Like [`HttpReader::open`], but every request, including the one made
+    /// here, fails with [`io::ErrorKind::TimedOut`] once `deadline` passes.
+    pub fn open_with_deadline(url: &str, deadline: Option<Instant>) -> io::Result<HttpReader> {
+        // Content encoding would make ranges refer to compressed bytes, and
+        // ureq only asks for it with its gzip feature, which is disabled
+        let agent: ureq::Agent = ureq::AgentBuilder::new()
+            .timeout_connect(STALL_TIMEOUT)
+            .timeout_read(STALL_TIMEOUT)
+            .build();
+        let response: ureq::Response = get_range(&agent, url, deadline, 0, 1)?;
+        let len: Option<u64> = match response.status() {
+            206 => response
+                .header("Content-Range")
+                .and_then(|range| range.rsplit_once('/'))
+                .and_then(|(_, total)| total.trim().parse().ok()),
+            _ => None,
+        };
+        let Some(len) = len else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} doesn't support range requests", url),
+            ));
+        };
+        Ok(HttpReader {
+            agent,
+            url: url.to_string(),
+            len,
+            position: 0,
+            block_start: 0,
+            block: vec![],
+            fetched: 0,
+            deadline,
+        })
+    }
+
+    /// Returns true if `path` is an `http://` or `https://` URL.
+    pub fn is_url(path: &str) -> bool {
+        path.starts_with("http://") || path.starts_with("https://")
+    }
+
+    /// Number of bytes downloaded so far.
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+
+    /// Fetches the bytes from `start` up to `end`, exclusive.
+    fn fetch(&mut self, start: u64, end: u64) -> io::Result<()> {
+        let response: ureq::Response =
+            get_range(&self.agent, &self.url, self.deadline, start, end)?;
+        if response.status() != 206 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Server ignored the range request for {}", self.url),
+            ));
+        }
+        let mut block: Vec<u8> = Vec::with_capacity((end - start) as usize);
+        response
+            .into_reader()
+            .take(end - start)
+            .read_to_end(&mut block)?;
+        if (block.len() as u64) < end - start {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Server sent a short range for {}", self.url),
+            ));
+        }
+        self.fetched += block.len() as u64;
+        self.block_start = start;
+        self.block = block;
+        Ok(())
+    }
+}
+
+/// Requests the bytes from `start` up to `end`, exclusive, giving up on the
+/// request and the body that follows it when `deadline` passes.
+fn get_range(
+    agent: &ureq::Agent,
+    url: &str,
+    deadline: Option<Instant>,
+    start: u64,
+    end: u64,
+) -> io::Result<ureq::Response> {
+    let mut request: ureq::Request = agent
+        .get(url)
+        .set("Range", &format!("bytes={}-{}", start, end - 1));
+    if let Some(deadline) = deadline {
+        let remaining: Duration = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Timed out reading {}", url),
+            ));
+        }
+        request = request.timeout(remaining);
+    }
+    request.call().map_err(to_io_error)
+}
+
+fn to_io_error(e: ureq::Error) -> io::Error {
+    let timed_out: bool = e
+        .source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .is_some_and(|source| source.kind() == io::ErrorKind::TimedOut);
+    match e {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+        e if timed_out => io::Error::new(io::ErrorKind::TimedOut, e.to_string()),
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_end: u64 = self.block_start + self.block.len() as u64;
+        if self.position < self.block_start || self.position >= block_end {
+            let wanted: u64 = (buf.len() as u64).max(MIN_FETCH);
+            let end: u64 = self.len.min(self.position + wanted);
+            self.fetch(self.position, end)?;
+        }
+        let offset: usize = (self.position - self.block_start) as usize;
+        let available: &[u8] = &self.block[offset..];
+        let read: usize = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target: Option<u64> = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        match target {
+            Some(target) => {
+                self.position = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Serves `data` to range requests, one request per connection, and
+    /// returns the URL.
+    fn serve(data: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!("http://{}/archive.zip", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = respond(stream.unwrap(), &data);
+            }
+        });
+        url
+    }
+
+    fn respond(mut stream: TcpStream, data: &[u8]) -> io::Result<()> {
+        let mut range: Option<(usize, usize)> = None;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        loop {
+            let mut line: String = String::new();
+            reader.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                let (start, end) = value.trim().split_once('-').unwrap();
+                range = Some((start.parse().unwrap(), end.parse().unwrap()));
+            }
+        }
+        let (start, end) = range.unwrap_or((0, data.len() - 1));
+        write!(
+            stream,
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            start,
+            end,
+            data.len(),
+            end + 1 - start
+        )?;
+        stream.write_all(&data[start..=end])
+    }
+
+    #[test]
+    fn test_reads_single_entry_over_http() -> io::Result<()> {
+        // A megabyte of data in front of the archive, like a self-extractor
+        // stub, which reading an entry must not download
+        let mut data: Vec<u8> = vec![0x5a; 1 << 20];
+        data.extend(std::fs::read(get_test_file_path("test_multiple.zip"))?);
+        let url: String = serve(data);
+
+        let archive = ZipArchive::new_readonly(HttpReader::open(&url)?)?;
+        let text: Vec<u8> = archive.by_name("test2.txt")?.read_to_vec()?.unwrap();
+        assert_eq!(text, b"this is test2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetches_only_needed_ranges() -> io::Result<()> {
+        let mut data: Vec<u8> = vec![0x5a; 1 << 20];
+        data.extend(b"tail");
+        let mut reader = HttpReader::open(&serve(data))?;
+        let mut tail: [u8; 4] = [0u8; 4];
+        reader.seek(SeekFrom::End(-4))?;
+        reader.read_exact(&mut tail)?;
+        assert_eq!(&tail, b"tail");
+        assert_eq!(reader.fetched(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_requires_range_support() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut line: String = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nPK");
+        });
+        let err = HttpReader::open(&url).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_stalled_server_times_out() {
+        // Accepts the connection and reads the request but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let stream = listener.incoming().next().unwrap().unwrap();
+            let mut line: String = String::new();
+            let mut reader = BufReader::new(stream);
+            while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                line.clear();
+            }
+            std::thread::sleep(Duration::from_secs(30));
+        });
+        let started: Instant = Instant::now();
+        let deadline: Option<Instant> = Some(started + Duration::from_millis(200));
+        let err = HttpReader::open_with_deadline(&url, deadline)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let passed: Option<Instant> = Some(Instant::now());
+        let err = HttpReader::open_with_deadline(&url, passed).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
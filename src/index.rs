@@ -0,0 +1,319 @@
+//! Sidecar files holding the parsed central directory of a ZIP archive.
+//!
+//! Parsing the central directory of an archive with hundreds of thousands
+//! of entries takes a while: every record is decoded, its name converted
+//! and its extra fields parsed. [`ZipArchive::write_index`] saves the result
+//! and [`ZipArchive::load_index`] reads it back on later opens, so only the
+//! bytes of the central directory are read, to check that the index still
+//! matches the archive.
+//!
+//! Layout, all numbers little-endian:
+//! [4 bytes]  Magic, "XPIX"
+//! [2 bytes]  Version, 1
+//! [8 bytes]  Size of the archive
+//! [8 bytes]  Offset of the central directory
+//! [4 bytes]  CRC-32 of the archive from the central directory to its end
+//! [8 bytes]  Number of entries
+//! Followed by one record per entry:
+//! [2 bytes]  Version made by
+//! [2 bytes]  Version needed to extract
+//! [2 bytes]  General purpose bit flag
+//! [2 bytes]  Compression method
+//! [4 bytes]  CRC-32
+//! [4 bytes]  Compressed size
+//! [4 bytes]  Uncompressed size
+//! [8 bytes]  Offset of the local header
+//! [4 bytes]  External attributes
+//! [2 bytes]  Internal attributes
+//! [1 byte]   1 if a modification time follows, else 0
+//! [12 bytes] Seconds (8) and nanoseconds (4) since the Unix epoch
+//! [variable] File name and comment, each as a 4-byte length and UTF-8
+//! [2 bytes]  Number of extra fields, each a tag byte and its values
+//!
+//! [`ZipArchive::write_index`]: crate::zip::ZipArchive::write_index
+//! [`ZipArchive::load_index`]: crate::zip::ZipArchive::load_index
+
+use crate::extra::ExtraField;
+use crate::zip::ZipFileEntry;
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"XPIX";
+const VERSION: u16 = 1;
+
+// Tags of the extra fields
+const ZIP64: u8 = 0;
+const EXTENDED_TIMESTAMP: u8 = 1;
+const UNIX: u8 = 2;
+const AES: u8 = 3;
+const NTFS: u8 = 4;
+const UNKNOWN: u8 = 5;
+
+/// What identifies the archive an index was written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Fingerprint {
+    pub archive_size: u64,
+    pub directory_offset: u64,
+    pub directory_crc32: u32,
+}
+
+/// Encodes the index of `entries`.
+pub(crate) fn encode(fingerprint: Fingerprint, entries: &[ZipFileEntry]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(34 + entries.len() * 64);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&fingerprint.archive_size.to_le_bytes());
+    out.extend_from_slice(&fingerprint.directory_offset.to_le_bytes());
+    out.extend_from_slice(&fingerprint.directory_crc32.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for entry in entries {
+        out.extend_from_slice(&entry.version_made_by.to_le_bytes());
+        out.extend_from_slice(&entry.version_needed.to_le_bytes());
+        out.extend_from_slice(&entry.flags.to_le_bytes());
+        out.extend_from_slice(&entry.compression_method.to_le_bytes());
+        out.extend_from_slice(&entry.crc32.to_le_bytes());
+        out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        out.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+        out.extend_from_slice(&entry.file_offset.to_le_bytes());
+        out.extend_from_slice(&entry.external_attributes.to_le_bytes());
+        out.extend_from_slice(&entry.internal_attributes.to_le_bytes());
+        match entry
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(elapsed) => {
+                out.push(1);
+                out.extend_from_slice(&elapsed.as_secs().to_le_bytes());
+                out.extend_from_slice(&elapsed.subsec_nanos().to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        put_bytes(&mut out, entry.filename.as_bytes());
+        put_bytes(&mut out, entry.comment.as_bytes());
+        out.extend_from_slice(&(entry.extra_fields.len() as u16).to_le_bytes());
+        for field in &entry.extra_fields {
+            put_extra_field(&mut out, field);
+        }
+    }
+    out
+}
+
+/// Decodes an index, returning the fingerprint of its archive and its
+/// entries.
+pub(crate) fn decode(data: &[u8]) -> io::Result<(Fingerprint, Vec<ZipFileEntry>)> {
+    let mut input: Input<'_> = Input { data };
+    if input.take(4)? != MAGIC || input.u16()? != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not an xpack index, or one of another version",
+        ));
+    }
+    let fingerprint: Fingerprint = Fingerprint {
+        archive_size: input.u64()?,
+        directory_offset: input.u64()?,
+        directory_crc32: input.u32()?,
+    };
+    let count: u64 = input.u64()?;
+    // Every record takes at least 50 bytes, so a bad count can't make this
+    // allocate more than the index is worth
+    let mut entries: Vec<ZipFileEntry> =
+        Vec::with_capacity(count.min(data.len() as u64 / 50) as usize);
+    for _ in 0..count {
+        let mut entry: ZipFileEntry = ZipFileEntry {
+            version_made_by: input.u16()?,
+            version_needed: input.u16()?,
+            flags: input.u16()?,
+            compression_method: input.u16()?,
+            crc32: input.u32()?,
+            compressed_size: input.u32()?,
+            uncompressed_size: input.u32()?,
+            file_offset: input.u64()?,
+            external_attributes: input.u32()?,
+            internal_attributes: input.u16()?,
+            ..Default::default()
+        };
+        if input.u8()? == 1 {
+            let (secs, nanos) = (input.u64()?, input.u32()?);
+            let elapsed: Option<Duration> = match nanos {
+                0..=999_999_999 => {
+                    Duration::from_secs(secs).checked_add(Duration::from_nanos(nanos as u64))
+                }
+                _ => None,
+            };
+            let Some(modified) = elapsed.and_then(|elapsed| UNIX_EPOCH.checked_add(elapsed)) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Index has an invalid modification time",
+                ));
+            };
+            entry.modified = Some(modified);
+        }
+        entry.filename = input.string()?;
+        entry.comment = input.string()?;
+        for _ in 0..input.u16()? {
+            entry.extra_fields.push(input.extra_field()?);
+        }
+        entries.push(entry);
+    }
+    Ok((fingerprint, entries))
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_option(out: &mut Vec<u8>, value: Option<i32>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+fn put_extra_field(out: &mut Vec<u8>, field: &ExtraField) {
+    match field {
+        ExtraField::Zip64 { values, disk_start } => {
+            out.push(ZIP64);
+            out.push(values.len() as u8);
+            for value in values {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            put_option(out, disk_start.map(|disk| disk as i32));
+        }
+        ExtraField::ExtendedTimestamp {
+            modified,
+            accessed,
+            created,
+        } => {
+            out.push(EXTENDED_TIMESTAMP);
+            for time in [modified, accessed, created] {
+                put_option(out, *time);
+            }
+        }
+        ExtraField::Unix { uid, gid } => {
+            out.push(UNIX);
+            out.extend_from_slice(&uid.to_le_bytes());
+            out.extend_from_slice(&gid.to_le_bytes());
+        }
+        ExtraField::Aes {
+            vendor_version,
+            strength,
+            compression_method,
+        } => {
+            out.push(AES);
+            out.extend_from_slice(&vendor_version.to_le_bytes());
+            out.push(*strength);
+            out.extend_from_slice(&compression_method.to_le_bytes());
+        }
+        ExtraField::Ntfs {
+            modified,
+            accessed,
+            created,
+        } => {
+            out.push(NTFS);
+            for time in [modified, accessed, created] {
+                out.extend_from_slice(&time.to_le_bytes());
+            }
+        }
+        ExtraField::Unknown { id, data } => {
+            out.push(UNKNOWN);
+            out.extend_from_slice(&id.to_le_bytes());
+            put_bytes(out, data);
+        }
+    }
+}
+
+/// The unread rest of an index.
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated xpack index",
+            ));
+        }
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let length: usize = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid name in index"))
+    }
+
+    fn option(&mut self) -> io::Result<Option<i32>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.u32()? as i32)),
+        }
+    }
+
+    fn extra_field(&mut self) -> io::Result<ExtraField> {
+        Ok(match self.u8()? {
+            ZIP64 => {
+                let count: u8 = self.u8()?;
+                let values: Vec<u64> = (0..count).map(|_| self.u64()).collect::<io::Result<_>>()?;
+                let disk_start: Option<u32> = self.option()?.map(|disk| disk as u32);
+                ExtraField::Zip64 { values, disk_start }
+            }
+            EXTENDED_TIMESTAMP => ExtraField::ExtendedTimestamp {
+                modified: self.option()?,
+                accessed: self.option()?,
+                created: self.option()?,
+            },
+            UNIX => ExtraField::Unix {
+                uid: self.u32()?,
+                gid: self.u32()?,
+            },
+            AES => ExtraField::Aes {
+                vendor_version: self.u16()?,
+                strength: self.u8()?,
+                compression_method: self.u16()?,
+            },
+            NTFS => ExtraField::Ntfs {
+                modified: self.u64()?,
+                accessed: self.u64()?,
+                created: self.u64()?,
+            },
+            UNKNOWN => ExtraField::Unknown {
+                id: self.u16()?,
+                data: self.bytes()?.to_vec(),
+            },
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown extra field tag {} in index", tag),
+                ))
+            }
+        })
+    }
+}
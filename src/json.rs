@@ -0,0 +1,287 @@
+//! Machine-readable output.
+//!
+//! Commands print one JSON object per line (JSON Lines), so a listing can be
+//! streamed and consumed with `jq` or a line-by-line reader. Objects are flat
+//! and only hold strings, numbers and nulls, which doesn't justify a
+//! serialization dependency. Requests to [`Server`](crate::serve::Server)
+//! are objects of the same kind, read with [`parse_object`].
+
+use crate::zip::ZipFileEntry;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Builds a single-line JSON object field by field.
+pub struct JsonObject {
+    buf: String,
+}
+
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject {
+            buf: String::from("{"),
+        }
+    }
+
+    pub fn string(mut self, key: &str, value: &str) -> JsonObject {
+        self.key(key);
+        push_string(&mut self.buf, value);
+        self
+    }
+
+    pub fn number(mut self, key: &str, value: u64) -> JsonObject {
+        self.key(key);
+        write!(self.buf, "{}", value).unwrap();
+        self
+    }
+
+    pub fn null(mut self, key: &str) -> JsonObject {
+        self.key(key);
+        self.buf.push_str("null");
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buf.push('}');
+        self.buf
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        push_string(&mut self.buf, key);
+        self.buf.push(':');
+    }
+}
+
+impl Default for JsonObject {
+    fn default() -> JsonObject {
+        JsonObject::new()
+    }
+}
+
+/// Starts an object describing `entry`: name, sizes, compression method,
+/// CRC-32 and modification time in seconds since the Unix epoch (null when
+/// the archive doesn't record a valid one).
+pub fn entry_object(entry: &ZipFileEntry) -> JsonObject {
+    let object: JsonObject = JsonObject::new()
+        .string("name", &entry.filename)
+        .number("uncompressed_size", entry.uncompressed_size as u64)
+        .number("compressed_size", entry.compressed_size as u64)
+        .string("method", &entry.method().name())
+        .string("crc32", &format!("{:08x}", entry.crc32));
+    let mtime: Option<u64> = entry
+        .modified
+        .and_then(|modified: SystemTime| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+    match mtime {
+        Some(mtime) => object.number("mtime", mtime),
+        None => object.null("mtime"),
+    }
+}
+
+/// A value of a flat JSON object, see [`parse_object`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    String(String),
+    Number(u64),
+    Null,
+}
+
+/// Parses a flat JSON object, whose values are strings, non-negative
+/// integers or nulls, like those [`JsonObject`] builds. Later keys replace
+/// earlier ones with the same name.
+pub fn parse_object(text: &str) -> io::Result<HashMap<String, JsonValue>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut chars: Peekable<Chars<'_>> = text.trim().chars().peekable();
+    let mut object: HashMap<String, JsonValue> = HashMap::new();
+    if chars.next() != Some('{') {
+        return Err(invalid("Expected a JSON object"));
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            if chars.next() != Some('"') {
+                return Err(invalid("Expected a quoted key"));
+            }
+            let key: String = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(invalid("Expected ':' after a key"));
+            }
+            skip_whitespace(&mut chars);
+            let value: JsonValue = match chars.next() {
+                Some('"') => JsonValue::String(parse_string(&mut chars)?),
+                Some('n') if chars.by_ref().take(3).eq("ull".chars()) => JsonValue::Null,
+                Some(c @ '0'..='9') => {
+                    let mut digits: String = c.to_string();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    let number: u64 = digits.parse().map_err(|_| invalid("Number out of range"))?;
+                    JsonValue::Number(number)
+                }
+                _ => return Err(invalid("Expected a string, number or null")),
+            };
+            object.insert(key, value);
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(invalid("Expected ',' or '}' after a value")),
+            }
+        }
+    }
+    if chars.next().is_some() {
+        return Err(invalid("Unexpected data after the object"));
+    }
+    Ok(object)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+/// Reads a string up to its closing quote, the opening one being consumed.
+fn parse_string(chars: &mut Peekable<Chars<'_>>) -> io::Result<String> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut value: String = String::new();
+    loop {
+        match chars.next() {
+            None => return Err(invalid("Unterminated string")),
+            Some('"') => return Ok(value),
+            Some('\\') => {
+                let c: char = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let high: u32 = parse_hex4(chars)?;
+                        // Characters outside the BMP come as surrogate pairs
+                        let code: u32 = if (0xD800..0xDC00).contains(&high) {
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err(invalid("Unpaired surrogate in string"));
+                            }
+                            let low: u32 = parse_hex4(chars)?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(invalid("Unpaired surrogate in string"));
+                            }
+                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                        } else {
+                            high
+                        };
+                        char::from_u32(code).ok_or_else(|| invalid("Invalid escape in string"))?
+                    }
+                    _ => return Err(invalid("Invalid escape in string")),
+                };
+                value.push(c);
+            }
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars<'_>>) -> io::Result<u32> {
+    let digits: String = chars.by_ref().take(4).collect();
+    match u32::from_str_radix(&digits, 16) {
+        Ok(code) if digits.len() == 4 => Ok(code),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid escape in string",
+        )),
+    }
+}
+
+/// Appends `value` as a quoted JSON string.
+fn push_string(buf: &mut String, value: &str) {
+    buf.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(buf, "\\u{:04x}", c as u32).unwrap(),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_entry_object() {
+        let entry = ZipFileEntry {
+            filename: String::from("dir/\"quoted\"\n.txt"),
+            crc32: 0xbeef,
+            compressed_size: 10,
+            uncompressed_size: 20,
+            compression_method: 8,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            entry_object(&entry).string("status", "ok").finish(),
+            r#"{"name":"dir/\"quoted\"\n.txt","uncompressed_size":20,"compressed_size":10,"method":"deflated","crc32":"0000beef","mtime":1600000000,"status":"ok"}"#
+        );
+
+        let entry = ZipFileEntry {
+            filename: String::from("bell\u{7}"),
+            ..Default::default()
+        };
+        let json: String = entry_object(&entry).finish();
+        assert!(json.starts_with(r#"{"name":"bell\u0007","#), "{}", json);
+        assert!(json.ends_with(r#""method":"stored","crc32":"00000000","mtime":null}"#));
+    }
+    #[test]
+    fn test_parse_object() -> io::Result<()> {
+        let json: String = JsonObject::new()
+            .string("name", "dir/\"quoted\"\n\u{7}.txt")
+            .number("size", 20)
+            .null("mtime")
+            .finish();
+        let object: HashMap<String, JsonValue> = parse_object(&json)?;
+        assert_eq!(
+            object["name"],
+            JsonValue::String("dir/\"quoted\"\n\u{7}.txt".to_string())
+        );
+        assert_eq!(object["size"], JsonValue::Number(20));
+        assert_eq!(object["mtime"], JsonValue::Null);
+
+        let object: HashMap<String, JsonValue> =
+            parse_object(r#" { "op" : "cat", "entry": "caf\u00e9 \ud83d\ude00" } "#)?;
+        assert_eq!(object["entry"], JsonValue::String("café 😀".to_string()));
+        assert!(parse_object("{}")?.is_empty());
+
+        for invalid in [
+            "",
+            "[]",
+            r#"{"a":1,}"#,
+            r#"{"a":true}"#,
+            r#"{"a":-1}"#,
+            r#"{"a":"unterminated}"#,
+            r#"{"a":"\ud83d"}"#,
+            r#"{"a":1} trailing"#,
+        ] {
+            assert!(parse_object(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+}
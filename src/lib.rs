@@ -0,0 +1,84 @@
+//! Xpack: a small library for reading and extracting ZIP, tar, 7z, cpio and
+//! ar archives.
+
+pub mod appledouble;
+pub mod ar;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod cancel;
+pub mod codec;
+#[cfg(feature = "fs")]
+pub mod config;
+pub mod cpio;
+pub mod dedupe;
+pub mod diff;
+pub mod encoding;
+pub mod error;
+pub mod extra;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod grep;
+pub mod hash;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod index;
+pub mod json;
+pub mod method;
+#[cfg(feature = "rar")]
+pub mod rar;
+pub mod recursive;
+pub mod repair;
+#[cfg(feature = "fs")]
+pub mod serve;
+pub mod sevenz;
+pub mod sink;
+#[cfg(feature = "fs")]
+pub mod split;
+pub mod stream;
+pub mod tar;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod throttle;
+pub mod units;
+pub mod validate;
+pub mod vfs;
+pub mod writer;
+pub mod zip;
+
+pub use ar::ArArchive;
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncZipArchive;
+pub use cancel::CancellationToken;
+pub use codec::StreamFormat;
+#[cfg(feature = "fs")]
+pub use config::XpackConfig;
+pub use cpio::CpioArchive;
+pub use dedupe::DedupeSink;
+pub use error::{Limit, SkippedEntry, XpackError};
+pub use extra::ExtraField;
+pub use grep::GrepSink;
+pub use hash::{HashAlgorithm, HashSink};
+pub use method::{Codecs, Decompressor};
+#[cfg(feature = "rar")]
+pub use rar::RarArchive;
+pub use recursive::RecursiveSink;
+#[cfg(feature = "fs")]
+pub use serve::Server;
+pub use sevenz::SevenZipArchive;
+#[cfg(feature = "fs")]
+pub use sink::{Action, DiskSink, DryRunSink, Outcome, OverwritePolicy, SpecialFilePolicy};
+pub use sink::{MemorySink, NullSink, RenameSink, Sink, SpecialFile};
+#[cfg(feature = "fs")]
+pub use split::SplitReader;
+pub use stream::ZipStreamReader;
+pub use throttle::{RateLimiter, Throttled, ThrottledFilesystem};
+#[cfg(feature = "fs")]
+pub use vfs::OsFilesystem;
+pub use vfs::{Filesystem, MemoryFilesystem};
+pub use writer::{Compression, EntryMetadata, Method, NewEntry, Profile, ZipWriter};
+pub use zip::{CompressionMethod, Encryption};
+pub use zip::{
+    DuplicatePolicy, Entry, EntryReader, ExtractOptions, UnsupportedPolicy, ZipArchive,
+    ZipFileEntry,
+};
@@ -1,385 +1,3207 @@
-use clap::Parser;
-use std::io::Write;
-use std::io::{self, SeekFrom};
-use std::path::Path;
-use std::{
-    cmp::min,
-    fs::File,
-    io::{Read, Seek},
+use clap::{CommandFactory, Parser, Subcommand};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use xpack::appledouble;
+use xpack::ar::{ArArchive, ArHeader};
+use xpack::cpio::{CpioArchive, CpioHeader};
+use xpack::dedupe::DedupeReport;
+use xpack::diff::{as_text, diff, normalize_name, unified_diff, Difference, EntryIndex};
+use xpack::format::{read_head, ArchiveFormat};
+#[cfg(feature = "http")]
+use xpack::http::HttpReader;
+use xpack::json::{entry_object, JsonObject};
+#[cfg(feature = "rar")]
+use xpack::rar::RarArchive;
+use xpack::recursive::{DEFAULT_MAX_DEPTH, DEFAULT_MAX_TOTAL_SIZE};
+use xpack::repair::{salvage, RepairReport};
+use xpack::sevenz::{SevenZipArchive, SevenZipEntry};
+use xpack::tar::{TarArchive, TarEntryKind, TarHeader, TarWriter};
+use xpack::throttle::lower_io_priority;
+use xpack::units::{format_size, parse_size};
+use xpack::validate::{Mismatch, ValidatingSink, Validators};
+use xpack::vfs::read_xattrs;
+use xpack::zip::{
+    numbered_name, read_end_central_dir, walk_central_directory, DuplicatePolicy, HeaderMismatch,
 };
+use xpack::{
+    Action, CancellationToken, Compression, DedupeSink, DiskSink, DryRunSink, Encryption, Entry,
+    EntryMetadata, ExtraField, ExtractOptions, GrepSink, HashAlgorithm, HashSink, Method, NewEntry,
+    NullSink, OsFilesystem, Outcome, OverwritePolicy, Profile, RateLimiter, RecursiveSink,
+    RenameSink, Server, Sink, SpecialFile, SpecialFilePolicy, StreamFormat, Throttled,
+    ThrottledFilesystem, UnsupportedPolicy, XpackConfig, XpackError, ZipArchive, ZipFileEntry,
+    ZipStreamReader, ZipWriter,
+};
+
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Never write to the filesystem, not even temporary files
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Hold reads of archives and writes of extracted files and new
+    /// archives to this many bytes per second each, e.g. 10M
+    #[arg(long, global = true, value_name = "RATE", value_parser = parse_rate)]
+    bwlimit: Option<u64>,
+
+    /// Give the disk I/O of xpack idle priority, so it only gets the disk
+    /// when nothing else wants it. Linux only
+    #[arg(long, global = true)]
+    nice_io: bool,
+
+    /// Read settings from this file instead of
+    /// ~/.config/xpack/config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Time and size limits for decoding entries, what to do with the ones that
+/// can't be decoded, and how they are read
+#[derive(clap::Args)]
+struct LimitArgs {
+    /// Abort if the whole operation takes longer than this
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Abort if decoding a single entry takes longer than this
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    entry_timeout: Option<Duration>,
+
+    /// Abort if a single entry decompresses to more than this
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    max_entry_size: Option<u64>,
+
+    /// Abort if all entries together decompress to more than this
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    max_total_size: Option<u64>,
+
+    /// Abort if an entry decompresses to more than this many times its
+    /// compressed size
+    #[arg(long, value_name = "RATIO")]
+    max_ratio: Option<f64>,
+
+    /// What to do with encrypted entries and ones compressed with an
+    /// unsupported method
+    #[arg(long, value_enum, default_value = "warn")]
+    on_unsupported: Unsupported,
+
+    /// Read up to this much compressed data ahead while entries of a ZIP
+    /// archive are decompressed on another thread
+    #[arg(long, value_name = "BYTES")]
+    readahead: Option<usize>,
+}
+
+impl LimitArgs {
+    fn to_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            timeout: self.timeout.or(config().timeout),
+            entry_timeout: self.entry_timeout.or(config().entry_timeout),
+            max_entry_size: self.max_entry_size.or(config().max_entry_size),
+            max_total_size: self.max_total_size.or(config().max_total_size),
+            max_ratio: self.max_ratio.or(config().max_ratio),
+            unsupported: self.on_unsupported.to_policy(),
+            readahead: self.readahead,
+            cancel: Some(interrupt_token()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Cancelled by the first Ctrl-C, see [`interrupt_token`].
+static INTERRUPT: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Returns the token cancelled when the user presses Ctrl-C, installing the
+/// SIGINT handler on first use. Commands decoding entries then stop after
+/// the chunk being decoded and report what they completed; a second Ctrl-C
+/// exits at once.
+fn interrupt_token() -> CancellationToken {
+    INTERRUPT
+        .get_or_init(|| {
+            #[cfg(unix)]
+            {
+                extern "C" fn on_interrupt(_: libc::c_int) {
+                    if let Some(token) = INTERRUPT.get() {
+                        if token.is_cancelled() {
+                            // SAFETY: _exit is async-signal-safe
+                            unsafe { libc::_exit(EXIT_INTERRUPTED as libc::c_int) };
+                        }
+                        token.cancel();
+                    }
+                }
+                let handler: extern "C" fn(libc::c_int) = on_interrupt;
+                // SAFETY: the handler only loads and stores atomics and calls
+                // _exit, which are async-signal-safe
+                unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+            }
+            CancellationToken::new()
+        })
+        .clone()
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    /// One JSON object per entry and line
+    Json,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SortKey {
+    Name,
+    Size,
+    Ratio,
+    Date,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Algorithm {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    fn to_hash(self) -> HashAlgorithm {
+        match self {
+            Algorithm::Sha224 => HashAlgorithm::Sha224,
+            Algorithm::Sha256 => HashAlgorithm::Sha256,
+            Algorithm::Sha384 => HashAlgorithm::Sha384,
+            Algorithm::Sha512 => HashAlgorithm::Sha512,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum MethodArg {
+    Store,
+    Deflate,
+    /// Zstandard, if built with the zstd feature
+    Zstd,
+}
+
+impl MethodArg {
+    fn to_method(self) -> Method {
+        match self {
+            MethodArg::Store => Method::Store,
+            MethodArg::Deflate => Method::Deflate,
+            MethodArg::Zstd => Method::Zstd,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ProfileArg {
+    /// EPUB and OpenDocument: mimetype first, stored
+    Epub,
+    /// Android packages: stored entries aligned like zipalign
+    Apk,
+    /// Java archives: META-INF/MANIFEST.MF first
+    Jar,
+}
+
+impl ProfileArg {
+    fn to_profile(self) -> Profile {
+        match self {
+            ProfileArg::Epub => Profile::Epub,
+            ProfileArg::Apk => Profile::Apk,
+            ProfileArg::Jar => Profile::Jar,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum TargetFormat {
+    Zip,
+    Tar,
+    /// Gzip compressed tar
+    #[value(alias = "tgz")]
+    TarGz,
+}
+
+impl TargetFormat {
+    /// Guesses the format from the extension of `path`.
+    fn from_path(path: &str) -> Option<TargetFormat> {
+        let path: String = path.to_ascii_lowercase();
+        if path.ends_with(".zip") {
+            Some(TargetFormat::Zip)
+        } else if path.ends_with(".tar") {
+            Some(TargetFormat::Tar)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(TargetFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum CompressFormat {
+    #[value(alias = "gz")]
+    Gzip,
+    #[value(alias = "bz2")]
+    Bzip2,
+    Xz,
+    /// Zstandard, if built with the zstd feature
+    #[value(alias = "zst")]
+    Zstd,
+}
+
+impl CompressFormat {
+    fn to_format(self) -> StreamFormat {
+        match self {
+            CompressFormat::Gzip => StreamFormat::Gzip,
+            CompressFormat::Bzip2 => StreamFormat::Bzip2,
+            CompressFormat::Xz => StreamFormat::Xz,
+            CompressFormat::Zstd => StreamFormat::Zstd,
+        }
+    }
+}
+
+/// A sed-style `s/REGEX/REPLACEMENT/FLAGS` expression, see `--transform`.
+#[derive(Clone)]
+struct Substitution {
+    regex: Regex,
+    // In the syntax of the regex crate, `${1}` for a group
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    fn apply(&self, name: &str) -> String {
+        if self.global {
+            self.regex
+                .replace_all(name, self.replacement.as_str())
+                .into_owned()
+        } else {
+            self.regex
+                .replace(name, self.replacement.as_str())
+                .into_owned()
+        }
+    }
+}
+
+/// Parses a `--bwlimit` rate such as `512K` or `10M`, see [`parse_size`].
+fn parse_rate(rate: &str) -> Result<u64, String> {
+    match parse_size(rate) {
+        Some(rate) if rate > 0 => Ok(rate),
+        _ => Err(format!(
+            "{} isn't a rate in bytes per second such as 10M",
+            rate
+        )),
+    }
+}
+
+/// Parses a size limit such as `512K` or `2G`, see [`parse_size`].
+fn parse_bytes(size: &str) -> Result<u64, String> {
+    parse_size(size).ok_or_else(|| format!("{} isn't a number of bytes such as 512M", size))
+}
+
+/// Budgets set with `--bwlimit`, one for reads and one for writes, shared
+/// by everything the command opens.
+struct Bandwidth {
+    reads: RateLimiter,
+    writes: RateLimiter,
+}
+
+static BANDWIDTH: OnceLock<Bandwidth> = OnceLock::new();
+
+fn read_limiter() -> Option<RateLimiter> {
+    BANDWIDTH.get().map(|bandwidth| bandwidth.reads.clone())
+}
+
+fn write_limiter() -> Option<RateLimiter> {
+    BANDWIDTH.get().map(|bandwidth| bandwidth.writes.clone())
+}
+
+/// When `--timeout` runs out for the command, set once at the start of
+/// [`run`]. Archives read over HTTP(S) give up on requests that are still
+/// waiting for the server then.
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// Settings from `--config` or the default configuration file, set once
+/// at the start of [`run`].
+static CONFIG: OnceLock<XpackConfig> = OnceLock::new();
+
+fn config() -> &'static XpackConfig {
+    CONFIG.get_or_init(XpackConfig::default)
+}
+
+/// Puts the read buffer of the configuration, if it sets one, in front of
+/// an archive being read.
+fn read_buffered<R: Read + Seek + 'static>(reader: R) -> Box<dyn ReadSeek> {
+    match config().read_buffer_size {
+        Some(capacity) => Box::new(BufReader::with_capacity(capacity, reader)),
+        None => Box::new(reader),
+    }
+}
+
+/// [`read_buffered`] for streams that can't seek.
+fn read_buffered_stream<R: Read + 'static>(reader: R) -> Box<dyn Read> {
+    match config().read_buffer_size {
+        Some(capacity) => Box::new(BufReader::with_capacity(capacity, reader)),
+        None => Box::new(reader),
+    }
+}
+
+/// Buffers a new archive or stream being written, with the write buffer of
+/// the configuration.
+fn write_buffered<W: Write>(writer: W) -> BufWriter<W> {
+    // What `BufWriter::new` uses
+    const DEFAULT_CAPACITY: usize = 8 * 1024;
+    BufWriter::with_capacity(
+        config().write_buffer_size.unwrap_or(DEFAULT_CAPACITY),
+        writer,
+    )
+}
+
+/// Parses a time limit, a positive number of seconds such as 30 or 2.5.
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    match seconds.parse::<f64>() {
+        Ok(number) if number > 0.0 => Duration::try_from_secs_f64(number)
+            .map_err(|_| format!("{} is too large a number of seconds", seconds)),
+        _ => Err(format!("{} isn't a positive number of seconds", seconds)),
+    }
+}
+
+/// Parses an octal mode such as `644` or `0755` for `--chmod` and
+/// `--dir-mode`.
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("{} isn't an octal mode up to 7777", mode)),
+    }
+}
+
+/// Parses a `--transform` expression. Any character after the `s` delimits
+/// the parts, and can be escaped with a backslash inside them. As in sed,
+/// `\1` to `\9` in the replacement stand for groups and `&` for the whole
+/// match.
+fn parse_substitution(expr: &str) -> Result<Substitution, String> {
+    let usage: String = format!("{} isn't of the form s/REGEX/REPLACEMENT/FLAGS", expr);
+    let mut chars = expr.chars();
+    let (Some('s'), Some(delimiter)) = (chars.next(), chars.next()) else {
+        return Err(usage);
+    };
+    let mut parts: Vec<String> = vec![String::new()];
+    let mut escaped: bool = false;
+    for c in chars {
+        let part: &mut String = parts.last_mut().unwrap();
+        if escaped {
+            // An escaped delimiter stands for itself, other escapes are
+            // left for the regex and replacement to interpret
+            if c != delimiter {
+                part.push('\\');
+            }
+            part.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            part.push(c);
+        }
+    }
+    let Ok([pattern, replacement, flags]) = <[String; 3]>::try_from(parts) else {
+        return Err(usage);
+    };
+    let mut global: bool = false;
+    let mut builder: RegexBuilder = RegexBuilder::new(&pattern);
+    for flag in flags.chars() {
+        match flag {
+            'g' => global = true,
+            'i' => {
+                builder.case_insensitive(true);
+            }
+            other => return Err(format!("Unknown flag {} in {}", other, expr)),
+        }
+    }
+    let regex: Regex = builder.build().map_err(|e| e.to_string())?;
+
+    let mut translated: String = String::new();
+    let mut rest = replacement.chars();
+    while let Some(c) = rest.next() {
+        match c {
+            '\\' => match rest.next() {
+                Some(digit @ '0'..='9') => translated.push_str(&format!("${{{}}}", digit)),
+                Some('$') => translated.push_str("$$"),
+                Some(other) => translated.push(other),
+                None => translated.push('\\'),
+            },
+            '&' => translated.push_str("${0}"),
+            '$' => translated.push_str("$$"),
+            c => translated.push(c),
+        }
+    }
+    Ok(Substitution {
+        regex,
+        replacement: translated,
+        global,
+    })
+}
+
+/// Which entry `merge` and `extract` keep when several have the same name.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Duplicates {
+    /// Keep the first entry
+    FirstWins,
+    /// Keep the last entry, in the place of the first
+    LastWins,
+    /// Fail without writing anything
+    Error,
+    /// Keep every entry, renaming later ones 'name (1).ext', etc.
+    Rename,
+}
+
+impl Duplicates {
+    fn to_policy(self) -> DuplicatePolicy {
+        match self {
+            Duplicates::FirstWins => DuplicatePolicy::FirstWins,
+            Duplicates::LastWins => DuplicatePolicy::LastWins,
+            Duplicates::Error => DuplicatePolicy::Error,
+            Duplicates::Rename => DuplicatePolicy::Rename,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Unsupported {
+    /// Fail at the first one
+    Error,
+    /// Skip them with a warning
+    Warn,
+    /// Skip them, then fail listing every one skipped
+    Collect,
+}
+
+impl Unsupported {
+    fn to_policy(self) -> UnsupportedPolicy {
+        match self {
+            Unsupported::Error => UnsupportedPolicy::Error,
+            Unsupported::Warn => UnsupportedPolicy::Warn,
+            Unsupported::Collect => UnsupportedPolicy::Collect,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SpecialFiles {
+    Skip,
+    /// Create hard links, device nodes and FIFOs
+    Create,
+}
+
+/// What to do with files that already exist. Without a flag, existing files
+/// are skipped when run from a terminal and overwritten otherwise.
+#[derive(clap::Args)]
+#[group(multiple = false)]
+struct OverwriteArgs {
+    /// Replace existing files
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Keep existing files
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Write entries next to existing files under a new name
+    #[arg(long)]
+    rename: bool,
+
+    /// Replace existing files only with entries modified after them
+    #[arg(long)]
+    update: bool,
+}
+
+impl OverwriteArgs {
+    /// The policy given with a flag, or else in the configuration.
+    fn explicit_policy(&self) -> Option<OverwritePolicy> {
+        if self.overwrite {
+            Some(OverwritePolicy::Overwrite)
+        } else if self.skip_existing {
+            Some(OverwritePolicy::Skip)
+        } else if self.rename {
+            Some(OverwritePolicy::Rename)
+        } else if self.update {
+            Some(OverwritePolicy::Update)
+        } else {
+            config().overwrite
+        }
+    }
+
+    fn to_policy(&self) -> OverwritePolicy {
+        match self.explicit_policy() {
+            Some(policy) => policy,
+            // Someone is watching, don't destroy their files by surprise
+            None if io::stdin().is_terminal() => OverwritePolicy::Skip,
+            None => OverwritePolicy::Overwrite,
+        }
+    }
+
+    /// Whether to ask about each existing file: no policy was given and
+    /// someone can answer, on a terminal that shows the question.
+    fn should_prompt(&self) -> bool {
+        self.explicit_policy().is_none() && io::stdin().is_terminal() && io::stderr().is_terminal()
+    }
+}
+
+/// Asks on the terminal what to do with each existing file, like unzip.
+/// `A` and `N` answer for the remaining files too; `r` writes the entry
+/// under an unused name, `name (1).ext`. The end of input answers `N`.
+fn prompt_conflict(
+) -> impl Fn(&ZipFileEntry, &Path) -> io::Result<OverwritePolicy> + Send + Sync + 'static {
+    let remembered: Mutex<Option<OverwritePolicy>> = Mutex::new(None);
+    move |_entry, path| {
+        let mut remembered = remembered.lock().unwrap();
+        if let Some(policy) = *remembered {
+            return Ok(policy);
+        }
+        loop {
+            eprint!(
+                "replace {}? [y]es, [n]o, [A]ll, [N]one, [r]ename: ",
+                path.display()
+            );
+            let mut answer: String = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                eprintln!();
+                *remembered = Some(OverwritePolicy::Skip);
+                return Ok(OverwritePolicy::Skip);
+            }
+            match answer.trim() {
+                "y" => return Ok(OverwritePolicy::Overwrite),
+                "n" => return Ok(OverwritePolicy::Skip),
+                "r" => return Ok(OverwritePolicy::Rename),
+                "A" => {
+                    *remembered = Some(OverwritePolicy::Overwrite);
+                    return Ok(OverwritePolicy::Overwrite);
+                }
+                "N" => {
+                    *remembered = Some(OverwritePolicy::Skip);
+                    return Ok(OverwritePolicy::Skip);
+                }
+                other => eprintln!("error: invalid response [{}]", other),
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the entries of an archive
+    List {
+        archive_path: String,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Show sizes with binary prefixes, e.g. 1.5 MiB
+        #[arg(long)]
+        human_readable: bool,
+
+        /// Order entries by name, by size or date (largest and newest
+        /// first) or by compression ratio (best compressed first) instead
+        /// of their order in the archive
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+    },
+    /// Decompress and CRC-check every entry without writing anything
+    #[command(alias = "verify")]
+    Test {
+        archive_path: String,
+
+        /// Check that entry contents match their extension
+        #[arg(long)]
+        validate: bool,
+
+        /// Also check that every local header agrees with the central
+        /// directory on filename, compression method, CRC-32 and sizes
+        #[arg(long)]
+        paranoid: bool,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Print a checksum of every entry, in the format of `sha256sum`,
+    /// without writing anything
+    Hash {
+        archive_path: String,
+
+        #[arg(long, value_enum, default_value = "sha256")]
+        algo: Algorithm,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Report entries with identical contents, within an archive and
+    /// across the archives given, and the bytes the copies waste
+    Dedupe {
+        #[arg(required = true)]
+        archive_paths: Vec<String>,
+
+        /// Print the duplicates found; archives are never modified
+        #[arg(long, required = true)]
+        report: bool,
+
+        /// Show sizes with binary prefixes, e.g. 1.5 MiB
+        #[arg(long)]
+        human_readable: bool,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Write a single entry to standard output
+    Cat {
+        archive_path: String,
+        entry_name: String,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Print the lines of entries matching a regular expression, as
+    /// `entry:line:text`, without extracting anything
+    Grep {
+        pattern: String,
+        archive_path: String,
+
+        /// Match without regard to case
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Print only the matched parts of lines, each on its own line
+        #[arg(short, long)]
+        only_matching: bool,
+
+        /// Print only the names of entries that match
+        #[arg(short = 'l', long)]
+        files_with_matches: bool,
+
+        /// Search binary entries like text instead of only noting that
+        /// they match
+        #[arg(short = 'a', long)]
+        text: bool,
+
+        /// Only search entries matching this glob, e.g. '*.log'; repeatable
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Leave out entries matching this glob; repeatable
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Extract every entry of an archive
+    Extract {
+        archive_path: String,
+
+        #[arg(short, long, default_value = ".")]
+        path_to_unpack: String,
+
+        /// Fail instead of creating missing output directories
+        #[arg(long)]
+        no_mkdir: bool,
+
+        /// Check that entry contents match their extension
+        #[arg(long)]
+        validate: bool,
+
+        /// Decode and check every entry and print what would be written,
+        /// without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Give extracted files the owner recorded in the archive, which
+        /// usually requires root
+        #[arg(long)]
+        preserve_ownership: bool,
+
+        /// What to do with hard links, devices and FIFOs in tar archives
+        #[arg(long, value_enum, default_value = "skip")]
+        special_files: SpecialFiles,
+
+        /// Remove this many leading components from entry names, leaving
+        /// out entries with nothing left
+        #[arg(long, value_name = "N", default_value = "0")]
+        strip_components: usize,
+
+        /// Rename entries with a sed-style substitution, e.g.
+        /// 's/^docs\//manual\//'; flags g (every match) and i (ignore
+        /// case). Repeatable, applied in order after --strip-components
+        #[arg(long = "transform", value_name = "s/REGEX/REPLACEMENT/FLAGS", value_parser = parse_substitution)]
+        transforms: Vec<Substitution>,
+
+        /// Give every extracted file these octal permissions instead of
+        /// the ones recorded in the archive, e.g. 644
+        #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+        chmod: Option<u32>,
+
+        /// Give every directory entry these octal permissions instead of
+        /// the ones recorded in the archive, e.g. 755
+        #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+        dir_mode: Option<u32>,
+
+        /// Which entry to extract when several in a ZIP archive have the
+        /// same name; directories are merged whatever this says
+        #[arg(long, value_enum, default_value = "first-wins")]
+        duplicates: Duplicates,
+
+        /// Also unpack entries that are archives themselves, by their
+        /// leading bytes, into a directory named after the entry plus `.d`
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// How many levels of nested archives --recursive unpacks
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_DEPTH)]
+        max_depth: usize,
+
+        /// Write each file under a temporary name and rename it into place
+        /// once complete, so an interrupted extraction leaves no partial
+        /// files under entry names
+        #[arg(long)]
+        atomic: bool,
+
+        /// Flush each extracted file to disk before going on
+        #[arg(long)]
+        fsync: bool,
+
+        /// Leave blocks of zeros in extracted files as holes, making
+        /// sparse files of disk images and the like
+        #[arg(long)]
+        sparse: bool,
+
+        /// Restore extended attributes from tar archives and from the
+        /// AppleDouble entries under __MACOSX/ of ZIP archives, which
+        /// aren't written as files then
+        #[arg(long)]
+        xattrs: bool,
+
+        /// Write the entries into a tar archive at this path, `-` meaning
+        /// standard output, instead of extracting them to disk
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = [
+                "path_to_unpack", "no_mkdir", "dry_run", "format", "preserve_ownership",
+                "atomic", "fsync", "sparse", "xattrs",
+            ]
+        )]
+        to_tar: Option<String>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        #[command(flatten)]
+        overwrite: OverwriteArgs,
+    },
+    /// Create a ZIP archive from files and directories
+    Create {
+        archive_path: String,
+
+        /// Files and directories to add, directories recursively
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        #[arg(long, value_enum, default_value = "deflate")]
+        method: MethodArg,
+
+        /// Compression level, from 0 (store) to 9 (smallest)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: Option<u32>,
+
+        /// Compress files with extensions of compressed formats (.png,
+        /// .mp4, .zip, ...) too, instead of storing them
+        #[arg(long)]
+        compress_all: bool,
+
+        /// Compression for entries matching a glob, e.g. '*.log=deflate:9';
+        /// the first matching rule wins
+        #[arg(long = "compression", value_name = "GLOB=METHOD[:LEVEL]")]
+        rules: Vec<String>,
+
+        /// Write the same bytes for the same contents every time: entries
+        /// sorted by name, no timestamps, owner 0:0 and permissions 0644 or
+        /// 0755
+        #[arg(long)]
+        reproducible: bool,
+
+        /// Record extended attributes, such as macOS resource forks and
+        /// quarantine flags, in AppleDouble entries under __MACOSX/
+        #[arg(long)]
+        xattrs: bool,
+
+        /// Follow the conventions of a format built on ZIP: the entries it
+        /// wants first, which ones are stored and how their data is aligned
+        #[arg(long, value_enum)]
+        profile: Option<ProfileArg>,
+
+        /// Compress this many files at once, by default one per CPU
+        #[arg(long)]
+        threads: Option<NonZeroUsize>,
+
+        /// Update an existing archive: add new files, recompress files whose
+        /// size or modification time changed and copy the other entries
+        /// as they are
+        #[arg(short, long)]
+        update: bool,
+    },
+    /// Compare two ZIP archives: entries only in one of them and entries
+    /// whose size or CRC-32 differ
+    Diff {
+        old_path: String,
+        new_path: String,
+
+        /// Show line differences of modified text entries
+        #[arg(long)]
+        content: bool,
+
+        /// Unchanged lines shown around differences
+        #[arg(long, default_value = "3")]
+        context: usize,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Write the entries of an archive to a new archive, possibly of another
+    /// format, one entry at a time
+    Convert {
+        archive_path: String,
+
+        /// Where to write the new archive, `-` for standard output
+        output_path: String,
+
+        /// Format of the new archive, by default guessed from its extension
+        #[arg(long, value_enum)]
+        to: Option<TargetFormat>,
+
+        /// Compression method of ZIP entries
+        #[arg(long, value_enum, default_value = "deflate")]
+        method: MethodArg,
+
+        /// Compression level of ZIP entries or of the gzip stream, from 0
+        /// (store) to 9 (smallest)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: Option<u32>,
+
+        /// Overwrite the output if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Compress a single file with gzip, bzip2, xz or Zstandard, keeping
+    /// the original
+    Compress {
+        /// The file to compress, `-` for standard input
+        input_path: String,
+
+        #[arg(long, value_enum, default_value = "gzip")]
+        format: CompressFormat,
+
+        /// Compression level, from 0 (fastest) to 9 (smallest)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: Option<u32>,
+
+        /// Where to write the compressed file, `-` for standard output; by
+        /// default the input name with the format's extension added
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Overwrite the output if it exists
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Decompress a single gzip, bzip2, xz or Zstandard file, keeping the
+    /// original
+    Decompress {
+        /// The file to decompress, `-` for standard input
+        input_path: String,
+
+        /// Format of the input, by default recognized from its first bytes
+        #[arg(long, value_enum)]
+        format: Option<CompressFormat>,
+
+        /// Where to write the decompressed file, `-` for standard output; by
+        /// default the input name without its extension
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Overwrite the output if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Abort if the file decompresses to more than this
+        #[arg(long, value_name = "BYTES")]
+        max_size: Option<u64>,
+    },
+    /// Print a shell completion script, e.g. for
+    /// `xpack completions bash > /etc/bash_completion.d/xpack`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page, or write pages for xpack and each command
+    Man {
+        /// Write xpack.1 and xpack-<command>.1 pages into this directory
+        #[arg(long, value_name = "DIR")]
+        dir: Option<String>,
+    },
+    /// Merge ZIP archives into one, copying entries without recompressing
+    /// them
+    Merge {
+        output_path: String,
+
+        #[arg(required = true)]
+        archive_paths: Vec<String>,
+
+        /// Which entry to keep when several archives have one of the same
+        /// name; directories are merged whatever this says
+        #[arg(long, value_enum, default_value = "first-wins")]
+        duplicates: Duplicates,
+
+        /// Overwrite the output if it exists
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Extract whatever can be recovered from a damaged archive by scanning
+    /// for local file headers instead of reading the central directory
+    Repair {
+        archive_path: String,
+
+        #[arg(short, long, default_value = ".")]
+        path_to_unpack: String,
+
+        /// Fail instead of creating missing output directories
+        #[arg(long)]
+        no_mkdir: bool,
+
+        #[command(flatten)]
+        overwrite: OverwriteArgs,
+    },
+    /// Save the parsed central directory of a ZIP archive next to it, as
+    /// `<archive>.xpidx`, so later commands on the archive skip parsing it
+    Index {
+        archive_path: String,
+
+        /// Where to write the index instead
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Keep archives open and answer list, cat and extract requests, one
+    /// JSON object per line, on a Unix socket or standard input
+    Serve {
+        /// Listen on a Unix socket at this path instead of reading requests
+        /// from standard input
+        #[arg(long, value_name = "PATH")]
+        socket: Option<String>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+}
+
+impl Command {
+    /// The limits of the commands that read archives once and exit. `serve`
+    /// applies its limits to each request instead.
+    fn limits(&self) -> Option<&LimitArgs> {
+        match self {
+            Command::Test { limits, .. }
+            | Command::Hash { limits, .. }
+            | Command::Dedupe { limits, .. }
+            | Command::Cat { limits, .. }
+            | Command::Grep { limits, .. }
+            | Command::Extract { limits, .. }
+            | Command::Diff { limits, .. }
+            | Command::Convert { limits, .. } => Some(limits),
+            _ => None,
+        }
+    }
+}
+
+/// Exit status of an extraction that read the whole archive but left
+/// entries out: some failed to be written, were skipped with
+/// `--on-unsupported collect`, or don't match their extension with
+/// `--validate`. Other errors exit with 1.
+const EXIT_INCOMPLETE: u8 = 2;
+
+/// Exit status of a command stopped by Ctrl-C, as shells report a process
+/// killed by SIGINT.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Error of an extraction that wrote every entry it could, see
+/// [`EXIT_INCOMPLETE`].
+#[derive(Debug)]
+struct Incomplete(String);
+
+impl fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Incomplete {}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("xpack: {}", e);
+            if let Some(XpackError::Cancelled { .. }) = XpackError::from_io(&e) {
+                return ExitCode::from(EXIT_INTERRUPTED);
+            }
+            let incomplete: bool = e.get_ref().is_some_and(|e| e.is::<Incomplete>())
+                || matches!(
+                    XpackError::from_io(&e),
+                    Some(XpackError::SkippedEntries { .. })
+                );
+            match incomplete {
+                true => ExitCode::from(EXIT_INCOMPLETE),
+                false => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> io::Result<()> {
+    // A file named with --config has to exist, the default one doesn't
+    let settings: XpackConfig = match &args.config {
+        Some(path) if !path.exists() => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist", path.display()),
+            ));
+        }
+        Some(path) => XpackConfig::load(path)?,
+        None => match XpackConfig::default_path() {
+            Some(path) => XpackConfig::load(&path)?,
+            None => XpackConfig::default(),
+        },
+    };
+    let _ = CONFIG.set(settings);
+    if args.nice_io {
+        if let Err(e) = lower_io_priority() {
+            eprintln!("warning: can't lower the I/O priority: {}", e);
+        }
+    }
+    if let Some(rate) = args.bwlimit {
+        let _ = BANDWIDTH.set(Bandwidth {
+            reads: RateLimiter::new(rate),
+            writes: RateLimiter::new(rate),
+        });
+    }
+    let timeout: Option<Duration> = args
+        .command
+        .limits()
+        .and_then(|limits| limits.timeout.or(config().timeout));
+    if let Some(deadline) = timeout.and_then(|timeout| Instant::now().checked_add(timeout)) {
+        let _ = DEADLINE.set(deadline);
+    }
+    match args.command {
+        Command::List {
+            archive_path,
+            format,
+            human_readable,
+            sort,
+        } => {
+            // Stream the listing so huge archives start printing immediately
+            // and `xpack list | head` stops reading once the pipe closes,
+            // unless it has to be sorted first.
+            let out = BufWriter::new(io::stdout().lock());
+            let mut lister = Lister::new(out, format, human_readable, sort);
+            let mut result: io::Result<()> = Ok(());
+            match open_input(&archive_path)? {
+                Input::Zip(file) if Path::new(&index_path(&archive_path)).exists() => {
+                    let archive: ZipArchive<_> = open_indexed(&archive_path, file, true)?;
+                    for entry in archive.entries() {
+                        result = lister.add(zip_listing(&entry?));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::Zip(file) => walk_central_directory(file, |record| {
+                    result = lister.add(zip_listing(&record.to_entry()));
+                    match result {
+                        Ok(()) => ControlFlow::Continue(()),
+                        Err(_) => ControlFlow::Break(()),
+                    }
+                })?,
+                Input::Split(path) => {
+                    let archive = ZipArchive::open_split_readonly(&path)?;
+                    for entry in archive.entries() {
+                        result = lister.add(zip_listing(&entry?));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::ZipStream(reader) => {
+                    let mut reader = ZipStreamReader::new_readonly(reader);
+                    while let Some((entry, _)) = reader.next_entry()? {
+                        result = lister.add(zip_listing(&entry));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::Tar(reader) => {
+                    let mut archive = TarArchive::new_readonly(reader);
+                    while let Some(header) = archive.next_header()? {
+                        result =
+                            lister.add(stream_listing(&header.path, header.size, header.mtime));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::Cpio(reader) => {
+                    let mut archive = CpioArchive::new_readonly(reader);
+                    while let Some(header) = archive.next_header()? {
+                        result =
+                            lister.add(stream_listing(&header.name, header.size, header.mtime));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::Ar(reader) => {
+                    let mut archive = ArArchive::new_readonly(reader);
+                    while let Some(header) = archive.next_header()? {
+                        result =
+                            lister.add(stream_listing(&header.name, header.size, header.mtime));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Input::SevenZip(file) => {
+                    let archive = SevenZipArchive::new_readonly(file)?;
+                    for entry in archive.entries() {
+                        result = lister.add(zip_listing(&entry.to_entry(&[])?));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+                #[cfg(feature = "rar")]
+                Input::Rar(path) => {
+                    for entry in RarArchive::open_readonly(&path)?.entries()? {
+                        result = lister.add(zip_listing(&entry.to_entry()?));
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            match result.and_then(|_| lister.finish()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        Command::Test {
+            archive_path,
+            validate,
+            paranoid,
+            format,
+            limits,
+        } => {
+            if paranoid {
+                let archive: ZipArchive<_> = ZipArchive::new_readonly(open_zip(&archive_path)?)?;
+                report_header_mismatches(&archive.check_local_headers()?)?;
+            }
+            let options: ExtractOptions = limits.to_options();
+            if format == OutputFormat::Json {
+                let sink = JsonSink::new(NullSink, |_, _, object: JsonObject| {
+                    Ok(object.string("status", "ok"))
+                });
+                extract_with(&archive_path, true, options, validate, sink)?;
+                return Ok(());
+            }
+            extract_with(&archive_path, true, options, validate, NullSink)?;
+            println!("No errors detected in {}", archive_path);
+            Ok(())
+        }
+        Command::Hash {
+            archive_path,
+            algo,
+            limits,
+        } => {
+            let out = BufWriter::new(io::stdout().lock());
+            let sink = HashSink::new(algo.to_hash(), out);
+            let result: io::Result<()> =
+                extract_with(&archive_path, true, limits.to_options(), false, sink)
+                    .and_then(|sink| sink.into_inner().flush());
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        Command::Dedupe {
+            archive_paths,
+            report: _,
+            human_readable,
+            limits,
+        } => {
+            let mut sink: DedupeSink = DedupeSink::new();
+            for archive_path in &archive_paths {
+                sink.set_archive(archive_path);
+                sink = extract_with(archive_path, true, limits.to_options(), false, sink)?;
+            }
+            let out = BufWriter::new(io::stdout().lock());
+            let result: io::Result<()> =
+                write_dedupe_report(out, &sink.report(), archive_paths.len() > 1, human_readable);
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        Command::Grep {
+            pattern,
+            archive_path,
+            ignore_case,
+            only_matching,
+            files_with_matches,
+            text,
+            include,
+            exclude,
+            limits,
+        } => {
+            let regex: regex::bytes::Regex = regex::bytes::RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            let out = BufWriter::new(io::stdout().lock());
+            let sink = GrepSink::new(regex, out)
+                .with_include(include)
+                .with_exclude(exclude)
+                .with_binary(text)
+                .with_only_matching(only_matching)
+                .with_names_only(files_with_matches);
+            let result: io::Result<()> =
+                extract_with(&archive_path, true, limits.to_options(), false, sink)
+                    .and_then(|sink| sink.into_inner().flush());
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        Command::Diff {
+            old_path,
+            new_path,
+            content,
+            context,
+            format,
+            limits,
+        } => {
+            let mut old: ZipArchive<_> = ZipArchive::new_readonly(open_zip(&old_path)?)?;
+            let mut new: ZipArchive<_> = ZipArchive::new_readonly(open_zip(&new_path)?)?;
+            old.set_options(limits.to_options());
+            new.set_options(limits.to_options());
+            let old_index: EntryIndex =
+                EntryIndex::new(old.entries().collect::<io::Result<Vec<_>>>()?);
+            let new_index: EntryIndex =
+                EntryIndex::new(new.entries().collect::<io::Result<Vec<_>>>()?);
+            let mut out = BufWriter::new(io::stdout().lock());
+            for difference in diff(&old_index, &new_index) {
+                if format == OutputFormat::Json {
+                    writeln!(out, "{}", difference_object(&difference))?;
+                    continue;
+                }
+                match &difference {
+                    Difference::Removed(entry) => writeln!(out, "- {}", entry.filename)?,
+                    Difference::Added(entry) => writeln!(out, "+ {}", entry.filename)?,
+                    Difference::Modified {
+                        old: old_entry,
+                        new: new_entry,
+                    } => {
+                        writeln!(
+                            out,
+                            "M {} ({} -> {} bytes, CRC-32 {:08x} -> {:08x})",
+                            new_entry.filename,
+                            old_entry.uncompressed_size,
+                            new_entry.uncompressed_size,
+                            old_entry.crc32,
+                            new_entry.crc32
+                        )?;
+                        if content {
+                            let (old_data, new_data) = (old.read(old_entry)?, new.read(new_entry)?);
+                            write_content_diff(
+                                &mut out,
+                                &difference.name(),
+                                old_data,
+                                new_data,
+                                context,
+                            )?;
+                        }
+                    }
+                }
+            }
+            out.flush()
+        }
+        Command::Convert {
+            archive_path,
+            output_path,
+            to,
+            method,
+            level,
+            force,
+            limits,
+        } => {
+            let Some(target) = to.or_else(|| TargetFormat::from_path(&output_path)) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Can't tell the format of {} from its name, pass --to",
+                        output_path
+                    ),
+                ));
+            };
+            let compression: Compression = Compression::new(method.to_method(), level);
+            write_stream_output(&archive_path, &output_path, force, args.read_only, |out| {
+                convert(&archive_path, limits.to_options(), target, compression, out)
+            })
+        }
+        Command::Compress {
+            input_path,
+            format,
+            level,
+            output,
+            force,
+        } => {
+            let format: StreamFormat = format.to_format();
+            let output_path: String = match output {
+                Some(output) => output,
+                None if input_path == "-" => "-".to_string(),
+                None => format!("{}.{}", input_path, format.extension()),
+            };
+            let mut input: Box<dyn Read> = open_stream_input(&input_path)?;
+            write_stream_output(&input_path, &output_path, force, args.read_only, |out| {
+                format.compress(&mut input, out, level)?.flush()
+            })
+        }
+        Command::Decompress {
+            input_path,
+            format,
+            output,
+            force,
+            max_size,
+        } => {
+            let mut input: Box<dyn Read> = open_stream_input(&input_path)?;
+            let head: Vec<u8> = read_head(&mut input)?;
+            let Some(format) = format
+                .map(CompressFormat::to_format)
+                .or_else(|| StreamFormat::detect(&head))
+            else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} isn't gzip, bzip2, xz or Zstandard compressed",
+                        input_path
+                    ),
+                ));
+            };
+            let output_path: String = match output {
+                Some(output) => output,
+                None if input_path == "-" => "-".to_string(),
+                None => StreamFormat::decompressed_path(&input_path).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Can't tell what to call {} once decompressed, pass --output",
+                            input_path
+                        ),
+                    )
+                })?,
+            };
+            // Put the sniffed bytes back in front of the rest of the file
+            let mut decoder: Box<dyn Read> = format.decoder(Cursor::new(head).chain(input))?;
+            write_stream_output(
+                &input_path,
+                &output_path,
+                force,
+                args.read_only,
+                |mut out| {
+                    let copied: u64 = match max_size {
+                        // One byte more than allowed tells a file of exactly the
+                        // limit from a larger one
+                        Some(limit) => {
+                            io::copy(&mut decoder.take(limit.saturating_add(1)), &mut out)?
+                        }
+                        None => io::copy(&mut decoder, &mut out)?,
+                    };
+                    if max_size.is_some_and(|limit| copied > limit) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{} decompresses to more than {} bytes",
+                                input_path,
+                                max_size.unwrap_or_default()
+                            ),
+                        ));
+                    }
+                    out.flush()
+                },
+            )
+        }
+        Command::Completions { shell } => {
+            let mut out = io::stdout().lock();
+            clap_complete::generate(shell, &mut Args::command(), "xpack", &mut out);
+            out.flush()
+        }
+        Command::Man { dir } => {
+            let Some(dir) = dir else {
+                let mut out = io::stdout().lock();
+                clap_mangen::Man::new(Args::command()).render(&mut out)?;
+                return out.flush();
+            };
+            if args.read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Man writes pages with --dir and can't run read-only",
+                ));
+            }
+            fs::create_dir_all(&dir)?;
+            clap_mangen::generate_to(Args::command(), &dir)
+        }
+        Command::Merge {
+            output_path,
+            archive_paths,
+            duplicates,
+            force,
+        } => {
+            if args.read_only && output_path != "-" {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Writing {} can't run read-only, write to standard output (-) instead",
+                        output_path
+                    ),
+                ));
+            }
+            let mut archives: Vec<(String, ZipArchive<Box<dyn ReadSeek>>)> = vec![];
+            for path in archive_paths {
+                // Creating the output would truncate an input before it's read
+                if let (Ok(input), Ok(output)) =
+                    (fs::canonicalize(&path), fs::canonicalize(&output_path))
+                {
+                    if input == output {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Can't merge {} into itself", path),
+                        ));
+                    }
+                }
+                let archive: ZipArchive<Box<dyn ReadSeek>> =
+                    ZipArchive::new_readonly(open_zip(&path)?)?;
+                archives.push((path, archive));
+            }
+            let plan: Vec<(usize, ZipFileEntry)> = merge_plan(&archives, duplicates)?;
+            write_output(&output_path, force, args.read_only, |out| {
+                let mut writer = ZipWriter::new(out);
+                let jobs = plan.into_iter().map(|(i, entry)| {
+                    let data: io::Result<Vec<u8>> = archives[i].1.read_raw(&entry);
+                    let metadata: EntryMetadata = EntryMetadata::from_entry(&entry);
+                    move || {
+                        Ok(NewEntry::Copy {
+                            entry,
+                            data: data?,
+                            metadata,
+                        })
+                    }
+                });
+                // Nothing to compress, so no point in more threads
+                writer.add_parallel(jobs, 1)?;
+                writer.finish()?.flush()
+            })
+        }
+        Command::Cat {
+            archive_path,
+            entry_name,
+            limits,
+        } => {
+            let data: Vec<u8> = match open_input(&archive_path)? {
+                Input::Zip(file) => {
+                    let mut archive: ZipArchive<_> = open_indexed(&archive_path, file, true)?;
+                    archive.set_options(limits.to_options());
+                    read_zip_entry(&archive, &entry_name)?
+                }
+                Input::Split(path) => {
+                    let mut archive = ZipArchive::open_split_readonly(&path)?;
+                    archive.set_options(limits.to_options());
+                    read_zip_entry(&archive, &entry_name)?
+                }
+                Input::ZipStream(reader) => {
+                    let mut reader = ZipStreamReader::new_readonly(reader);
+                    reader.set_options(limits.to_options());
+                    find_zip_stream_entry(&mut reader, &entry_name)?
+                }
+                Input::Tar(reader) => {
+                    let mut archive = TarArchive::new_readonly(reader);
+                    archive.set_options(limits.to_options());
+                    find_tar_entry(&mut archive, &entry_name)?;
+                    archive.read_data()?
+                }
+                Input::Cpio(reader) => {
+                    let mut archive = CpioArchive::new_readonly(reader);
+                    archive.set_options(limits.to_options());
+                    find_cpio_entry(&mut archive, &entry_name)?;
+                    archive.read_data()?
+                }
+                Input::Ar(reader) => {
+                    let mut archive = ArArchive::new_readonly(reader);
+                    archive.set_options(limits.to_options());
+                    find_ar_entry(&mut archive, &entry_name)?;
+                    archive.read_data()?
+                }
+                Input::SevenZip(file) => {
+                    let mut archive = SevenZipArchive::new_readonly(file)?;
+                    archive.set_options(limits.to_options());
+                    let entry: SevenZipEntry = find_sevenz_entry(&archive, &entry_name)?;
+                    archive.read(&entry)?
+                }
+                #[cfg(feature = "rar")]
+                Input::Rar(path) => {
+                    let mut archive: RarArchive = RarArchive::open_readonly(&path)?;
+                    archive.set_options(limits.to_options());
+                    archive.read(&entry_name)?
+                }
+            };
+            let mut out = io::stdout().lock();
+            match out.write_all(&data).and_then(|_| out.flush()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        Command::Extract {
+            archive_path,
+            path_to_unpack,
+            no_mkdir,
+            validate,
+            dry_run,
+            format,
+            preserve_ownership,
+            special_files,
+            strip_components,
+            transforms,
+            chmod,
+            dir_mode,
+            duplicates,
+            recursive,
+            max_depth,
+            atomic,
+            fsync,
+            sparse,
+            xattrs,
+            to_tar,
+            limits,
+            overwrite,
+        } => {
+            let mut options: ExtractOptions = ExtractOptions {
+                duplicates: duplicates.to_policy(),
+                ..limits.to_options()
+            };
+            let max_depth: usize = if recursive { max_depth } else { 0 };
+            // Nested archives multiply what a small archive unpacks to
+            if recursive {
+                options.max_total_size = options.max_total_size.or(Some(DEFAULT_MAX_TOTAL_SIZE));
+            }
+            if let Some(tar_path) = to_tar {
+                if args.read_only && tar_path != "-" {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!(
+                            "Writing {} can't run read-only, write to standard output with --to-tar -",
+                            tar_path
+                        ),
+                    ));
+                }
+                return write_stream_output(
+                    &archive_path,
+                    &tar_path,
+                    true,
+                    args.read_only,
+                    |out| {
+                        let mut sink = RenameSink::new(TarWriter::new(out))
+                            .with_strip_components(strip_components)
+                            .with_file_mode(chmod)
+                            .with_dir_mode(dir_mode);
+                        if !transforms.is_empty() {
+                            sink = sink.with_transform(substitute(transforms));
+                        }
+                        let recursive = RecursiveSink::new(&mut sink, max_depth, true, &options);
+                        extract_recursive(
+                            &archive_path,
+                            args.read_only,
+                            options,
+                            validate,
+                            recursive,
+                        )?;
+                        sink.into_inner().finish()?.flush()
+                    },
+                );
+            }
+            let mut sink: DiskSink = DiskSink::new(&path_to_unpack)
+                .with_create_dirs(!no_mkdir)
+                .with_overwrite(overwrite.to_policy())
+                .with_preserve_ownership(preserve_ownership)
+                .with_strip_components(strip_components)
+                .with_file_mode(chmod)
+                .with_dir_mode(dir_mode)
+                .with_atomic(atomic)
+                .with_fsync(fsync)
+                .with_sparse(sparse)
+                .with_xattrs(xattrs)
+                .with_special_files(match special_files {
+                    SpecialFiles::Skip => SpecialFilePolicy::Skip,
+                    SpecialFiles::Create => SpecialFilePolicy::Create,
+                });
+            if let Some(limiter) = write_limiter() {
+                sink = sink.with_filesystem(ThrottledFilesystem::new(OsFilesystem, limiter));
+            }
+            // JSON output and dry runs are for scripts, which get the
+            // default policy
+            if format == OutputFormat::Text && !dry_run && overwrite.should_prompt() {
+                sink = sink.with_conflict_handler(prompt_conflict());
+            }
+            if !transforms.is_empty() {
+                sink = sink.with_transform(substitute(transforms));
+            }
+            if dry_run && format == OutputFormat::Json {
+                let sink = JsonSink::new(DryRunSink::new(sink), |sink: &DryRunSink, _, object| {
+                    Ok(match sink.actions().last() {
+                        Some((_, action)) => action_object(object, action.clone()),
+                        None => object,
+                    })
+                });
+                let sink = RecursiveSink::new(sink, max_depth, args.read_only, &options);
+                extract_with(&archive_path, args.read_only, options, validate, sink)?;
+                return Ok(());
+            }
+            if !dry_run {
+                // Entries that fail to be written are reported at the end
+                // instead of ending the extraction
+                let mut sink: DiskSink = sink.with_keep_going(true);
+                let result: io::Result<()> = if format == OutputFormat::Json {
+                    let sink = JsonSink::new(&mut sink, |sink: &&mut DiskSink, _, object| {
+                        let outcome: Option<&Outcome> = sink.outcomes().last().map(|(_, o)| o);
+                        Ok(outcome_object(object, outcome))
+                    });
+                    let sink = RecursiveSink::new(sink, max_depth, args.read_only, &options);
+                    extract_recursive(&archive_path, args.read_only, options, validate, sink)
+                } else {
+                    let sink = RecursiveSink::new(&mut sink, max_depth, args.read_only, &options);
+                    extract_recursive(&archive_path, args.read_only, options, validate, sink)
+                };
+                if format == OutputFormat::Text && !sink.outcomes().is_empty() {
+                    print_summary(sink.outcomes());
+                }
+                return check_outcomes(sink.outcomes(), result);
+            }
 
-#[derive(Parser)]
-#[command(author, version, about)]
-struct Args {
-    #[arg(short, long)]
-    archive_path: String,
+            let sink =
+                RecursiveSink::new(DryRunSink::new(sink), max_depth, args.read_only, &options);
+            let sink: DryRunSink =
+                extract_with(&archive_path, args.read_only, options, validate, sink)?.into_inner();
+            let mut conflicts: usize = 0;
+            for (name, action) in sink.actions() {
+                conflicts += action.is_conflict() as usize;
+                match action {
+                    Action::CreateDir(path) => println!("  mkdir      {}", path.display()),
+                    Action::Create(path) => println!("  create     {}", path.display()),
+                    Action::Overwrite(path) => println!("  overwrite  {}", path.display()),
+                    Action::Rename { existing, to } => println!(
+                        "  rename     {} (exists: {})",
+                        to.display(),
+                        existing.display()
+                    ),
+                    Action::Skip(path) => println!("  skip       {} (exists)", path.display()),
+                    Action::Omit => println!("  omit       {} (stripped)", name),
+                }
+            }
+            println!(
+                "Dry run: {} entries, {} conflict with existing files, nothing written",
+                sink.actions().len(),
+                conflicts
+            );
+            Ok(())
+        }
+        Command::Create {
+            archive_path,
+            paths,
+            method,
+            level,
+            compress_all,
+            rules,
+            reproducible,
+            xattrs,
+            profile,
+            threads,
+            update,
+        } => {
+            if args.read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Create writes an archive and can't run read-only",
+                ));
+            }
+            // An archive being updated is read while the new one is written
+            // next to it, which then replaces it
+            let existing: Option<ZipArchive> = if update && Path::new(&archive_path).exists() {
+                Some(ZipArchive::open_readonly(&archive_path)?)
+            } else {
+                None
+            };
+            let write_path: String = match existing {
+                Some(_) => format!("{}.xpack-tmp", archive_path),
+                None => archive_path.clone(),
+            };
+            let out = Throttled::new(File::create(&write_path)?, write_limiter());
+            let mut writer = ZipWriter::new(write_buffered(out))
+                .with_compression(Compression::new(method.to_method(), level))
+                .with_store_compressed(!compress_all)
+                .with_reproducible(reproducible)
+                .with_profile(profile.map(ProfileArg::to_profile));
+            for rule in &rules {
+                let Some((pattern, compression)) = rule.rsplit_once('=') else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Expected GLOB=METHOD[:LEVEL], got {}", rule),
+                    ));
+                };
+                let compression: Compression = compression
+                    .parse()
+                    .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                writer = writer.with_rule(pattern, compression);
+            }
+            // Don't add the archive to itself when it's written inside one
+            // of the directories being added
+            let skip: Vec<PathBuf> = vec![
+                fs::canonicalize(&archive_path)?,
+                fs::canonicalize(&write_path)?,
+            ];
+            let mut sources: Vec<Source> = vec![];
+            for path in &paths {
+                collect_sources(Path::new(path), &skip, &mut sources)?;
+            }
+            // Paths given twice, or inside a directory also given, are
+            // added once
+            let mut seen: HashSet<String> = HashSet::new();
+            sources.retain(|source| seen.insert(source.name.clone()));
+            if xattrs {
+                sources = add_xattr_companions(sources)?;
+            }
+            if reproducible {
+                sources.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+            if let Some(profile) = profile {
+                let profile: Profile = profile.to_profile();
+                sources.sort_by_key(|source| profile.rank(&source.name));
+            }
+            let threads: usize = (threads.map(NonZeroUsize::get))
+                .or(config().threads)
+                .or_else(|| thread::available_parallelism().ok().map(NonZeroUsize::get))
+                .unwrap_or(1);
+            let Some(existing) = existing else {
+                let jobs = sources.iter().map(|source| move || load_source(source));
+                writer.add_parallel(jobs, threads)?;
+                writer.finish()?.flush()?;
+                return Ok(());
+            };
+            let result: io::Result<()> = update_archive(&mut writer, &existing, &sources, threads)
+                .and_then(|_| writer.finish()?.flush());
+            if let Err(e) = result {
+                let _ = fs::remove_file(&write_path);
+                return Err(e);
+            }
+            drop(existing);
+            fs::rename(&write_path, &archive_path)
+        }
+        Command::Repair {
+            archive_path,
+            path_to_unpack,
+            no_mkdir,
+            overwrite,
+        } => {
+            if args.read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Repair writes the recovered entries and can't run read-only",
+                ));
+            }
+            let mut sink: DiskSink = DiskSink::new(&path_to_unpack)
+                .with_create_dirs(!no_mkdir)
+                .with_overwrite(overwrite.to_policy());
+            if overwrite.should_prompt() {
+                sink = sink.with_conflict_handler(prompt_conflict());
+            }
+            if let Some(limiter) = write_limiter() {
+                sink = sink.with_filesystem(ThrottledFilesystem::new(OsFilesystem, limiter));
+            }
+            let mut file =
+                read_buffered(Throttled::new(File::open(&archive_path)?, read_limiter()));
+            let report: RepairReport = salvage(&mut file, &mut sink)?;
+            for (offset, reason) in &report.failed {
+                eprintln!("  entry at {}: {}", offset, reason);
+            }
+            println!(
+                "Recovered {} entries from {}, {} could not be recovered",
+                report.recovered.len(),
+                archive_path,
+                report.failed.len()
+            );
+            Ok(())
+        }
+        Command::Index {
+            archive_path,
+            output,
+        } => {
+            let output: String = output.unwrap_or_else(|| index_path(&archive_path));
+            if args.read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Writing index {} can't run read-only", output),
+                ));
+            }
+            let archive: ZipArchive<_> = ZipArchive::new_readonly(open_zip(&archive_path)?)?;
+            let mut out = write_buffered(File::create(&output)?);
+            archive.write_index(&mut out)?;
+            out.flush()?;
+            println!("Wrote index of {} to {}", archive_path, output);
+            Ok(())
+        }
+        Command::Serve { socket, limits } => {
+            let server: Server = Server::new()
+                .with_options(limits.to_options())
+                .with_read_only(args.read_only);
+            match socket {
+                Some(socket) => serve_socket(server, &socket, args.read_only),
+                None => match server.handle(io::stdin().lock(), io::stdout().lock()) {
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                    other => other,
+                },
+            }
+        }
+    }
+}
+
+/// Answers clients on a Unix socket created at `path`, which is removed
+/// once the server stops. A socket left behind by a server that's gone is
+/// replaced.
+#[cfg(unix)]
+fn serve_socket(server: Server, path: &str, read_only: bool) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if read_only {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Creating socket {} can't run read-only, read requests from standard input instead",
+                path
+            ),
+        ));
+    }
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket", path),
+            ));
+        }
+        Ok(_) if UnixStream::connect(path).is_ok() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path),
+            ));
+        }
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    // Clients can read and extract whatever this user can, so only the
+    // owner may connect. The umask applies as the socket is created, leaving
+    // no moment in which others could connect
+    // SAFETY: umask can't fail, and nothing else creates files meanwhile
+    let umask: libc::mode_t = unsafe { libc::umask(0o177) };
+    let listener: io::Result<UnixListener> = UnixListener::bind(path);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener: UnixListener = listener?;
+    eprintln!("Listening on {}", path);
+    let result: io::Result<()> = server.serve(listener);
+    let _ = fs::remove_file(path);
+    result
+}
 
-    #[arg(short, long)]
-    path_to_unpack: String,
+#[cfg(not(unix))]
+fn serve_socket(_server: Server, _path: &str, _read_only: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Sockets need a Unix platform, read requests from standard input instead",
+    ))
 }
 
-const LOCAL_FILE_HEADER_SIGNATURE: i32 = 0x04034b50;
-const CENTRAL_DIR_SIGNATURE: i32 = 0x02014b50;
-const END_CENTRAL_DIR_SIGNATURE: i32 = 0x06054b50;
+/// Prints every group of duplicates, most wasted bytes first, with the
+/// names of its copies, prefixed with their archive when `archives` is set,
+/// and the totals.
+fn write_dedupe_report<W: Write>(
+    mut out: W,
+    report: &DedupeReport,
+    archives: bool,
+    human_readable: bool,
+) -> io::Result<()> {
+    let size = |bytes: u64| match human_readable {
+        true => format_size(bytes),
+        false => format!("{} bytes", bytes),
+    };
+    for group in &report.groups {
+        writeln!(
+            out,
+            "{} copies of {} ({} wasted), sha256 {}",
+            group.copies.len(),
+            size(group.size),
+            size(group.wasted()),
+            group.digest
+        )?;
+        for copy in &group.copies {
+            match archives {
+                true => writeln!(out, "  {}: {}", copy.archive, copy.name)?,
+                false => writeln!(out, "  {}", copy.name)?,
+            }
+        }
+    }
+    writeln!(
+        out,
+        "{} sets of duplicates, {} wasted of {} in {} files",
+        report.groups.len(),
+        size(report.wasted()),
+        size(report.bytes),
+        report.files
+    )?;
+    out.flush()
+}
 
-struct EndCentralDirectory {
-    dir_offset: u32,
+/// Where `xpack index` saves the index of the archive at `archive_path` by
+/// default, and where other commands look for it.
+fn index_path(archive_path: &str) -> String {
+    format!("{}.xpidx", archive_path)
 }
 
-#[derive(Debug)]
-struct ZipFileEntry {
-    filename: String,
-    compressed_size: u32,
-    uncompressed_size: u32,
-    compression_method: u16,
-    file_offset: u32,
-}
-fn main() {
-    let args = Args::parse();
-    let archive_path: &str = &args.archive_path;
-    let mut path_to_unpack: String = args.path_to_unpack;
-    if !path_to_unpack.ends_with("/") {
-        path_to_unpack.push('/');
+/// Opens the ZIP archive at `archive_path`, read from `reader`, with the
+/// index saved next to it if there is one. An index that no longer matches
+/// the archive is ignored with a warning.
+fn open_indexed<R: Read + Seek>(
+    archive_path: &str,
+    reader: R,
+    read_only: bool,
+) -> io::Result<ZipArchive<R>> {
+    let mut archive: ZipArchive<R> = if read_only {
+        ZipArchive::new_readonly(reader)?
+    } else {
+        ZipArchive::new(reader)?
+    };
+    let index: String = index_path(archive_path);
+    match File::open(&index) {
+        Ok(mut file) => {
+            if let Err(e) = archive.load_index(&mut file) {
+                eprintln!("warning: ignoring index {}: {}", index, e);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("warning: ignoring index {}: {}", index, e),
+    }
+    Ok(archive)
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A file or directory to add to a new archive.
+struct Source {
+    name: String,
+    path: PathBuf,
+    metadata: fs::Metadata,
+    /// For the AppleDouble companion of a file, the extended attributes of
+    /// the file it carries
+    xattrs: Option<Vec<(String, Vec<u8>)>>,
+}
+
+/// Collects the file or directory at `path`, directories with everything
+/// below them in name order, leaving out the canonical paths in `skip`.
+fn collect_sources(path: &Path, skip: &[PathBuf], sources: &mut Vec<Source>) -> io::Result<()> {
+    if skip.contains(&fs::canonicalize(path)?) {
+        return Ok(());
+    }
+    let name: String = entry_name(path)?;
+    let metadata: fs::Metadata = fs::metadata(path)?;
+    let is_dir: bool = metadata.is_dir();
+    if !name.is_empty() {
+        sources.push(Source {
+            name,
+            path: path.to_path_buf(),
+            metadata,
+            xattrs: None,
+        });
+    }
+    if !is_dir {
+        return Ok(());
+    }
+    let mut children: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|child| child.map(|child| child.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    children.sort();
+    for child in children {
+        collect_sources(&child, skip, sources)?;
+    }
+    Ok(())
+}
+
+/// Adds the AppleDouble companion of every source with extended
+/// attributes right after it, the way the Archive Utility of macOS does.
+fn add_xattr_companions(sources: Vec<Source>) -> io::Result<Vec<Source>> {
+    let mut with_companions: Vec<Source> = Vec::with_capacity(sources.len());
+    for source in sources {
+        let xattrs: Vec<(String, Vec<u8>)> = read_xattrs(&source.path)?;
+        let companion: Option<Source> = (!xattrs.is_empty()).then(|| Source {
+            name: appledouble::companion_name(&source.name),
+            path: source.path.clone(),
+            metadata: source.metadata.clone(),
+            xattrs: Some(xattrs),
+        });
+        with_companions.push(source);
+        with_companions.extend(companion);
+    }
+    Ok(with_companions)
+}
+
+/// Writes the entries of `existing` to `writer` in order, replaced by the
+/// matching source if it changed, then the sources that are new. Entries
+/// without a source are kept.
+fn update_archive<W: Write>(
+    writer: &mut ZipWriter<W>,
+    existing: &ZipArchive,
+    sources: &[Source],
+    threads: usize,
+) -> io::Result<()> {
+    let mut by_name: HashMap<&str, &Source> = sources
+        .iter()
+        .map(|source| (source.name.as_str(), source))
+        .collect();
+    let mut plan: Vec<(Option<ZipFileEntry>, Option<&Source>)> = vec![];
+    for entry in existing.entries() {
+        let entry: ZipFileEntry = entry?;
+        match by_name.remove(entry.filename.trim_end_matches('/')) {
+            Some(source) if !is_unchanged(&entry, source) => plan.push((None, Some(source))),
+            source => plan.push((Some(entry), source)),
+        }
     }
-    let res: Result<Option<u64>, io::Error> = read_end_central_dir(archive_path);
-    let entries: Option<Vec<ZipFileEntry>> =
-        read_central_directory(archive_path, res.unwrap()).unwrap();
-    if let Some(entries_vec) = &entries {
-        for item in entries_vec {
-            extract_file(archive_path, item, path_to_unpack.as_str());
+    for source in sources {
+        if by_name.contains_key(source.name.as_str()) {
+            plan.push((None, Some(source)));
         }
     }
+
+    // Unchanged data is read here, on this thread, as the writer asks for
+    // more jobs, so only entries in flight are held in memory
+    type Job<'a> = Box<dyn FnOnce() -> io::Result<NewEntry> + Send + 'a>;
+    let jobs = plan.into_iter().map(|(entry, source)| -> Job<'_> {
+        let Some(entry) = entry else {
+            let source: &Source = source.unwrap();
+            return Box::new(move || load_source(source));
+        };
+        let data: io::Result<Vec<u8>> = existing.read_raw(&entry);
+        let metadata: EntryMetadata = match source {
+            Some(source) => source_metadata(source),
+            None => EntryMetadata::from_entry(&entry),
+        };
+        Box::new(move || {
+            Ok(NewEntry::Copy {
+                entry,
+                data: data?,
+                metadata,
+            })
+        })
+    });
+    writer.add_parallel(jobs, threads)
+}
+
+/// Whether the file `source` has the size and modification time recorded
+/// for `entry`, to the precision the archive recorded it with. Directories
+/// are always rewritten, which is cheap.
+fn is_unchanged(entry: &ZipFileEntry, source: &Source) -> bool {
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+    let file: Option<u64> = source.metadata.modified().ok().and_then(seconds);
+    let recorded: Option<u64> = entry.precise_modified().and_then(seconds);
+    let (Some(file), Some(recorded)) = (file, recorded) else {
+        return false;
+    };
+    let extended: bool = entry.extra_fields.iter().any(|field| {
+        matches!(
+            field,
+            ExtraField::ExtendedTimestamp {
+                modified: Some(_),
+                ..
+            }
+        )
+    });
+    // MS-DOS times are rounded down to even seconds
+    let same_time: bool = if extended {
+        recorded == file
+    } else {
+        recorded == file & !1
+    };
+    !source.metadata.is_dir()
+        && source.metadata.len() == entry.uncompressed_size as u64
+        && same_time
 }
 
-fn read_end_central_dir(path: &str) -> io::Result<Option<u64>> {
-    let mut f: File = File::open(path)?;
+/// Reads `source` into the entry to add.
+fn load_source(source: &Source) -> io::Result<NewEntry> {
+    let metadata: EntryMetadata = source_metadata(source);
+    if let Some(xattrs) = &source.xattrs {
+        return Ok(NewEntry::File {
+            name: source.name.clone(),
+            data: appledouble::encode(xattrs),
+            metadata: EntryMetadata {
+                permissions: Some(0o644),
+                ..metadata
+            },
+        });
+    }
+    if source.metadata.is_dir() {
+        return Ok(NewEntry::Directory {
+            name: source.name.clone(),
+            metadata,
+        });
+    }
+    Ok(NewEntry::File {
+        name: source.name.clone(),
+        data: fs::read(&source.path)?,
+        metadata,
+    })
+}
 
-    f.seek(SeekFrom::End(0))?;
-    let file_size: u64 = f.stream_position()?;
-    eprintln!("File size: {} bytes", file_size);
+fn source_metadata(source: &Source) -> EntryMetadata {
+    #[cfg(unix)]
+    let (permissions, owner) = {
+        use std::os::unix::fs::MetadataExt;
+        let metadata: &fs::Metadata = &source.metadata;
+        (
+            Some(metadata.mode() & 0o7777),
+            Some((metadata.uid(), metadata.gid())),
+        )
+    };
+    #[cfg(not(unix))]
+    let (permissions, owner) = (None, None);
+    EntryMetadata {
+        modified: source.metadata.modified().ok(),
+        permissions,
+        owner,
+    }
+}
 
-    // as we need to check the last 1024
-    let search_size: u64 = min(1024, file_size);
-    f.seek(SeekFrom::End(-(search_size as i64)))?;
-    let mut buf: Vec<u8> = vec![0; search_size as usize];
-    f.read_exact(&mut buf)?;
+/// Names an entry after `path` the way `zip` does: relative, with `/`
+/// separators and without leading `./` or `/`.
+fn entry_name(path: &Path) -> io::Result<String> {
+    let mut parts: Vec<String> = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} points outside the current directory", path.display()),
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(parts.join("/"))
+}
 
-    let signature_bytes: [u8; 4] = END_CENTRAL_DIR_SIGNATURE.to_le_bytes();
+/// An opened archive, by how it has to be read.
+enum Input {
+    /// A ZIP file or URL that can be read from its central directory
+    Zip(Box<dyn ReadSeek>),
+    /// The last part of a split ZIP archive, by path
+    Split(String),
+    /// A ZIP archive that can only be read front to back, e.g. from stdin
+    ZipStream(Box<dyn Read>),
+    Tar(Box<dyn Read>),
+    SevenZip(Box<dyn ReadSeek>),
+    Cpio(Box<dyn Read>),
+    Ar(Box<dyn Read>),
+    /// A RAR archive, by path, the only way UnRAR reads them
+    #[cfg(feature = "rar")]
+    Rar(String),
+}
 
-    let mut signature_position: i64 = -1;
+/// Opens the archive at `archive_path`, `-` meaning standard input, and
+/// recognizes its format from the leading bytes. Gzip streams are
+/// decompressed on the fly and the format of their contents is detected in
+/// turn. 7z archives that don't come from a file are read into memory.
+/// Data that isn't recognized is read as ZIP, since self-extracting
+/// archives start with an executable. HTTP(S) URLs are read with range
+/// requests.
+fn open_input(archive_path: &str) -> io::Result<Input> {
+    let stream: Box<dyn Read> = if archive_path == "-" {
+        read_buffered_stream(Throttled::new(io::stdin().lock(), read_limiter()))
+    } else {
+        let mut file: Box<dyn ReadSeek> = open_seekable(archive_path)?;
+        let head: Vec<u8> = read_head(&mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        match ArchiveFormat::detect(&head) {
+            Some(ArchiveFormat::Tar) => return Ok(Input::Tar(Box::new(file))),
+            Some(ArchiveFormat::Cpio) => return Ok(Input::Cpio(Box::new(file))),
+            Some(ArchiveFormat::Ar) => return Ok(Input::Ar(Box::new(file))),
+            Some(ArchiveFormat::SevenZip) => return Ok(Input::SevenZip(file)),
+            Some(ArchiveFormat::Rar) => return rar_input(archive_path),
+            Some(ArchiveFormat::Gzip) => Box::new(MultiGzDecoder::new(file)),
+            _ => {
+                // The last part of a split archive looks like any other zip
+                // until its end of central directory record is read
+                if let Err(e) = read_end_central_dir(&mut file) {
+                    if let Some(XpackError::SplitArchive { .. }) = XpackError::from_io(&e) {
+                        if !is_url(archive_path) {
+                            return Ok(Input::Split(archive_path.to_string()));
+                        }
+                    }
+                }
+                file.seek(SeekFrom::Start(0))?;
+                return Ok(Input::Zip(file));
+            }
+        }
+    };
+    stream_input(stream)
+}
 
-    for i in (0..buf.len().saturating_sub(4)).rev() {
-        if buf[i..i + 4] == signature_bytes {
-            signature_position = i as i64;
-            break;
+/// Recognizes the format of an archive that can only be read front to
+/// back, see [`open_input`].
+fn stream_input(mut stream: Box<dyn Read>) -> io::Result<Input> {
+    loop {
+        let head: Vec<u8> = read_head(&mut stream)?;
+        let format: Option<ArchiveFormat> = ArchiveFormat::detect(&head);
+        // Put the sniffed bytes back in front of the rest of the stream
+        stream = Box::new(Cursor::new(head).chain(stream));
+        match format {
+            Some(ArchiveFormat::Gzip) => stream = Box::new(MultiGzDecoder::new(stream)),
+            Some(ArchiveFormat::Tar) => return Ok(Input::Tar(stream)),
+            Some(ArchiveFormat::Cpio) => return Ok(Input::Cpio(stream)),
+            Some(ArchiveFormat::Ar) => return Ok(Input::Ar(stream)),
+            Some(ArchiveFormat::SevenZip) => {
+                // The 7z header is at the end, so the whole stream is needed
+                // before anything can be read
+                let mut data: Vec<u8> = vec![];
+                stream.read_to_end(&mut data)?;
+                return Ok(Input::SevenZip(Box::new(Cursor::new(data))));
+            }
+            Some(ArchiveFormat::Rar) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "RAR archives can only be read from a file",
+                ))
+            }
+            _ => return Ok(Input::ZipStream(stream)),
         }
     }
+}
 
-    if signature_position == -1 {
-        eprintln!("Signature not found!");
-        return Ok(None);
+#[cfg(feature = "rar")]
+fn rar_input(archive_path: &str) -> io::Result<Input> {
+    if is_url(archive_path) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "RAR archives can only be read from a file",
+        ));
     }
+    Ok(Input::Rar(archive_path.to_string()))
+}
 
-    // Read the record bytes and print them before parsing
-    let pos: usize = signature_position as usize;
-    // End of Central Directory Record:
-    // [Signature (4 bytes)]
-    // [Disk Number (2 bytes)]
-    // [Start Disk (2 bytes)]
-    // [Disk Entries (2 bytes)]
-    // [Total Entries (2 bytes)]
-    // [Directory Size (4 bytes)]
-    // [Directory Offset (4 bytes)]
-    // [Comment Length (2 bytes)]
-    // [Optional Comment (variable)]
-    let record_bytes: &[u8] = &buf[pos + 4..pos + 22]; // 18 bytes after signature
+#[cfg(not(feature = "rar"))]
+fn rar_input(archive_path: &str) -> io::Result<Input> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} is a RAR archive, which needs xpack built with the rar feature",
+            archive_path
+        ),
+    ))
+}
+
+#[cfg(feature = "http")]
+fn is_url(path: &str) -> bool {
+    HttpReader::is_url(path)
+}
+
+#[cfg(not(feature = "http"))]
+fn is_url(_path: &str) -> bool {
+    false
+}
+
+fn open_seekable(path: &str) -> io::Result<Box<dyn ReadSeek>> {
+    #[cfg(feature = "http")]
+    if HttpReader::is_url(path) {
+        let reader: HttpReader = HttpReader::open_with_deadline(path, DEADLINE.get().copied())?;
+        return Ok(read_buffered(Throttled::new(reader, read_limiter())));
+    }
+    Ok(read_buffered(Throttled::new(
+        File::open(path)?,
+        read_limiter(),
+    )))
+}
 
-    let end_central_dir: EndCentralDirectory = EndCentralDirectory {
-        dir_offset: u32::from_le_bytes(record_bytes[12..16].try_into().unwrap()),
+/// Lists the entries `merge` copies, as indexes in `archives` and entries
+/// in the order they're written. Entries are matched by normalized name.
+fn open_stream_input(path: &str) -> io::Result<Box<dyn Read>> {
+    let stream: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(path)?)
     };
+    Ok(read_buffered_stream(Throttled::new(stream, read_limiter())))
+}
 
-    Ok(Some(end_central_dir.dir_offset as u64))
-}
-fn read_central_directory(
-    path: &str,
-    offset: Option<u64>,
-) -> io::Result<Option<Vec<ZipFileEntry>>> {
-    // Central Directory Header:
-    // [4 bytes]  Signature
-    // [2 bytes]  Version made by
-    // [2 bytes]  Version needed
-    // [2 bytes]  General purpose bit flag
-    // [2 bytes]  Compression method
-    // [2 bytes]  Last modified time
-    // [2 bytes]  Last modified date
-    // [4 bytes]  CRC-32
-    // [4 bytes]  Compressed size
-    // [4 bytes]  Uncompressed size
-    // [2 bytes]  Filename length
-    // [2 bytes]  Extra field length
-    // [2 bytes]  File comment length
-    // [2 bytes]  Disk number start
-    // [2 bytes]  Internal file attributes
-    // [4 bytes]  External file attributes
-    // [4 bytes]  Local header offset
-    // [variable] Filename
-    // [variable] Extra field
-    // [variable] File comment
-    let mut f: File = File::open(path)?;
-    let mut file_entries: Vec<ZipFileEntry> = vec![];
-    let mut current_offset: u64 = offset.unwrap();
+/// Runs `write` with the output of `compress`, `decompress` or `convert`,
+/// see [`write_output`], after making sure it isn't `input_path`.
+fn write_stream_output<F>(
+    input_path: &str,
+    output_path: &str,
+    force: bool,
+    read_only: bool,
+    write: F,
+) -> io::Result<()>
+where
+    F: FnOnce(BufWriter<Box<dyn Write>>) -> io::Result<()>,
+{
+    // Creating the output would truncate the input before it's read
+    if let (Ok(input), Ok(output)) = (fs::canonicalize(input_path), fs::canonicalize(output_path)) {
+        if input == output {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is both the input and the output", input_path),
+            ));
+        }
+    }
+    write_output(output_path, force, read_only, write)
+}
 
-    loop {
-        f.seek(SeekFrom::Start(current_offset))?;
-
-        // Read signature
-        let mut buf: [u8; 4] = [0u8; 4];
-        match f.read_exact(&mut buf) {
-            Ok(_) => {
-                if buf != CENTRAL_DIR_SIGNATURE.to_le_bytes() {
-                    break;
+/// Runs `write` with the file at `output_path`, `-` meaning standard
+/// output. An existing file is only replaced with `force`, and then only
+/// once `write` succeeds; a file created here is removed if it fails.
+fn write_output<F>(output_path: &str, force: bool, read_only: bool, write: F) -> io::Result<()>
+where
+    F: FnOnce(BufWriter<Box<dyn Write>>) -> io::Result<()>,
+{
+    if output_path == "-" {
+        let out: Box<dyn Write> = Box::new(io::stdout().lock());
+        return match write(write_buffered(out)) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            other => other,
+        };
+    }
+    if read_only {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Writing {} can't run read-only, write to standard output (-) instead",
+                output_path
+            ),
+        ));
+    }
+    let exists: bool = fs::symlink_metadata(output_path).is_ok();
+    if exists && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, pass --force to overwrite it",
+                output_path
+            ),
+        ));
+    }
+    // An existing output is replaced by a complete new one, or not at all
+    let write_path: String = match exists {
+        true => format!("{}.xpack-tmp", output_path),
+        false => output_path.to_string(),
+    };
+    let file: File = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&write_path)?;
+    let out: Box<dyn Write> = Box::new(Throttled::new(file, write_limiter()));
+    let result: io::Result<()> = write(write_buffered(out)).and_then(|()| match exists {
+        true => fs::rename(&write_path, output_path),
+        false => Ok(()),
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&write_path);
+    }
+    result
+}
+
+fn merge_plan(
+    archives: &[(String, ZipArchive<Box<dyn ReadSeek>>)],
+    duplicates: Duplicates,
+) -> io::Result<Vec<(usize, ZipFileEntry)>> {
+    let mut plan: Vec<Option<(usize, ZipFileEntry)>> = vec![];
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, (path, archive)) in archives.iter().enumerate() {
+        for entry in archive.entries() {
+            let mut entry: ZipFileEntry = entry?;
+            let mut name: String = normalize_name(&entry.filename);
+            if let Some(&earlier) = seen.get(&name) {
+                match duplicates {
+                    _ if entry.filename.ends_with('/') => continue,
+                    Duplicates::FirstWins => {
+                        eprintln!("Skipping {} of {}, added already", entry.filename, path);
+                        continue;
+                    }
+                    Duplicates::LastWins => {
+                        eprintln!("Replacing {} with the entry of {}", entry.filename, path);
+                        plan[earlier] = None;
+                    }
+                    Duplicates::Error => {
+                        let first: &str = plan[earlier]
+                            .as_ref()
+                            .map_or("", |(j, _)| archives[*j].0.as_str());
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} is in both {} and {}", entry.filename, first, path),
+                        ));
+                    }
+                    Duplicates::Rename => {
+                        let renamed: String = numbered_name(&entry.filename, |candidate| {
+                            seen.contains_key(&normalize_name(candidate))
+                        });
+                        eprintln!("Adding {} of {} as {}", entry.filename, path, renamed);
+                        entry.filename = renamed;
+                        name = normalize_name(&entry.filename);
+                    }
                 }
             }
-            Err(_) => break,
-        }
-
-        // Skip version made by (2), version needed (2), flags (2)
-        f.seek(SeekFrom::Current(6))?;
-
-        // Read compression method
-        let mut compression_method_buf = [0u8; 2];
-        f.read_exact(&mut compression_method_buf)?;
-        let compression_method = u16::from_le_bytes(compression_method_buf);
-
-        // Skip last mod time (2), last mod date (2), CRC32 (4)
-        f.seek(SeekFrom::Current(8))?;
-
-        // Read sizes
-        let mut compressions_buf: [u8; 8] = [0u8; 8];
-        f.read_exact(&mut compressions_buf)?;
-        let compressed_size: u32 = u32::from_le_bytes(compressions_buf[0..4].try_into().unwrap());
-        let uncompressed_size: u32 = u32::from_le_bytes(compressions_buf[4..8].try_into().unwrap());
-
-        // Read lengths
-        let mut lengths_buf: [u8; 6] = [0u8; 6];
-        f.read_exact(&mut lengths_buf)?;
-        let filename_length: u16 = u16::from_le_bytes(lengths_buf[0..2].try_into().unwrap());
-        let extra_length: u16 = u16::from_le_bytes(lengths_buf[2..4].try_into().unwrap());
-        let comment_length: u16 = u16::from_le_bytes(lengths_buf[4..6].try_into().unwrap());
-
-        // Skip to local header offset
-        f.seek(SeekFrom::Current(8))?;
-
-        // Read local header offset
-        let mut offset_buf: [u8; 4] = [0u8; 4];
-        f.read_exact(&mut offset_buf)?;
-        let file_offset: u32 = u32::from_le_bytes(offset_buf);
-
-        // Read filename
-        let mut filename_buf: Vec<u8> = vec![0u8; filename_length as usize];
-        f.read_exact(&mut filename_buf)?;
-        let filename: String =
-            String::from_utf8(filename_buf).map_err(|e: std::string::FromUtf8Error| {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, e)
-            })?;
-
-        file_entries.push(ZipFileEntry {
-            filename,
-            compressed_size,
-            uncompressed_size,
-            compression_method,
-            file_offset,
-        });
-        // Skip extra field and comment
-        f.seek(SeekFrom::Current((extra_length + comment_length) as i64))?;
-        current_offset = f.stream_position()?;
+            seen.insert(name, plan.len());
+            plan.push(Some((i, entry)));
+        }
+    }
+    Ok(plan.into_iter().flatten().collect())
+}
+
+/// Opens `path` as a seekable ZIP archive, for commands that need the
+/// central directory.
+fn open_zip(path: &str) -> io::Result<Box<dyn ReadSeek>> {
+    match open_input(path)? {
+        Input::Zip(file) => Ok(file),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} isn't a ZIP archive that can be read in place", path),
+        )),
+    }
+}
+
+fn difference_object(difference: &Difference) -> String {
+    let object: JsonObject = JsonObject::new().string("name", &difference.name());
+    match difference {
+        Difference::Removed(entry) => object
+            .string("status", "removed")
+            .number("old_size", entry.uncompressed_size as u64)
+            .number("old_crc32", entry.crc32 as u64),
+        Difference::Added(entry) => object
+            .string("status", "added")
+            .number("new_size", entry.uncompressed_size as u64)
+            .number("new_crc32", entry.crc32 as u64),
+        Difference::Modified { old, new } => object
+            .string("status", "modified")
+            .number("old_size", old.uncompressed_size as u64)
+            .number("new_size", new.uncompressed_size as u64)
+            .number("old_crc32", old.crc32 as u64)
+            .number("new_crc32", new.crc32 as u64),
     }
-    eprintln!("file_entries: {:?}", file_entries);
+    .finish()
+}
 
-    Ok(Some(file_entries))
+/// Writes the line differences of two versions of the entry `name` in the
+/// format of `diff -u`, or a note when they aren't both text.
+fn write_content_diff<W: Write>(
+    out: &mut W,
+    name: &str,
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+    context: usize,
+) -> io::Result<()> {
+    let (Some(old), Some(new)) = (old, new) else {
+        return writeln!(out, "  (unsupported compression method)");
+    };
+    let hunks: Option<String> = match (as_text(&old), as_text(&new)) {
+        (Some(old), Some(new)) => unified_diff(old, new, context),
+        _ => return writeln!(out, "  (binary contents differ)"),
+    };
+    match hunks {
+        Some(hunks) => write!(out, "--- a/{}\n+++ b/{}\n{}", name, name, hunks),
+        None => writeln!(out, "  (too large to compare line by line)"),
+    }
 }
 
-fn extract_file(
-    path: &str,
-    entry: &ZipFileEntry,
-    path_to_unpack: &str,
-) -> io::Result<Option<Vec<u8>>> {
-    eprintln!("Starting extract_file with:");
-    eprintln!("  filename: {}", entry.filename);
+/// Writes every entry of the archive at `archive_path` to a new archive in
+/// `out`. `compression` applies to ZIP entries, its level to gzip streams
+/// too.
+fn convert<W: Write>(
+    archive_path: &str,
+    options: ExtractOptions,
+    target: TargetFormat,
+    compression: Compression,
+    out: W,
+) -> io::Result<()> {
+    match target {
+        TargetFormat::Zip => {
+            let writer = ZipWriter::new(out).with_compression(compression);
+            extract_with(archive_path, true, options, false, writer)?
+                .finish()?
+                .flush()
+        }
+        TargetFormat::Tar => {
+            let writer = TarWriter::new(out);
+            extract_with(archive_path, true, options, false, writer)?
+                .finish()?
+                .flush()
+        }
+        TargetFormat::TarGz => {
+            let level: flate2::Compression = compression
+                .level
+                .map_or(flate2::Compression::default(), flate2::Compression::new);
+            let writer = TarWriter::new(GzEncoder::new(out, level));
+            extract_with(archive_path, true, options, false, writer)?
+                .finish()?
+                .finish()?
+                .flush()
+        }
+    }
+}
 
-    let mut f: File = File::open(path)?;
-    f.seek(SeekFrom::Start(entry.file_offset as u64))?;
+/// Extracts the archive at `archive_path` into `sink`, see [`open_input`].
+fn extract_archive<S: Sink>(
+    archive_path: &str,
+    read_only: bool,
+    options: ExtractOptions,
+    sink: &mut S,
+) -> io::Result<()> {
+    match open_input(archive_path)? {
+        Input::Zip(file) => {
+            let mut archive: ZipArchive<_> = open_indexed(archive_path, file, read_only)?;
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        input => extract_input(input, read_only, options, sink),
+    }
+}
 
-    // Read and verify local file header
-    let mut local_header: [u8; 30] = [0u8; 30];
-    f.read_exact(&mut local_header)?;
+fn extract_input<S: Sink>(
+    input: Input,
+    read_only: bool,
+    options: ExtractOptions,
+    sink: &mut S,
+) -> io::Result<()> {
+    match input {
+        Input::Zip(file) => {
+            let mut archive: ZipArchive<_> = if read_only {
+                ZipArchive::new_readonly(file)?
+            } else {
+                ZipArchive::new(file)?
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        Input::Split(path) => {
+            let mut archive = if read_only {
+                ZipArchive::open_split_readonly(&path)?
+            } else {
+                ZipArchive::open_split(&path)?
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        Input::ZipStream(reader) => {
+            let mut reader = if read_only {
+                ZipStreamReader::new_readonly(reader)
+            } else {
+                ZipStreamReader::new(reader)
+            };
+            reader.set_options(options);
+            reader.extract_to(sink)
+        }
+        Input::Tar(reader) => {
+            let mut archive = if read_only {
+                TarArchive::new_readonly(reader)
+            } else {
+                TarArchive::new(reader)
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        Input::Cpio(reader) => {
+            let mut archive = if read_only {
+                CpioArchive::new_readonly(reader)
+            } else {
+                CpioArchive::new(reader)
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        Input::Ar(reader) => {
+            let mut archive = if read_only {
+                ArArchive::new_readonly(reader)
+            } else {
+                ArArchive::new(reader)
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        Input::SevenZip(file) => {
+            let mut archive = if read_only {
+                SevenZipArchive::new_readonly(file)?
+            } else {
+                SevenZipArchive::new(file)?
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+        #[cfg(feature = "rar")]
+        Input::Rar(path) => {
+            let mut archive: RarArchive = if read_only {
+                RarArchive::open_readonly(&path)?
+            } else {
+                RarArchive::open(&path)?
+            };
+            archive.set_options(options);
+            archive.extract_to(sink)
+        }
+    }
+}
 
-    // Check signature
-    if local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
-        eprintln!("Invalid local file header signature");
-        return Ok(None);
+/// Extracts the archive into `sink`, through a [`ValidatingSink`] reporting
+/// mismatched contents when `validate` is set, and returns the sink. Entries
+/// that don't match their extension fail with [`Incomplete`] once the whole
+/// archive is read.
+fn extract_with<S: Sink>(
+    archive_path: &str,
+    read_only: bool,
+    options: ExtractOptions,
+    validate: bool,
+    mut sink: S,
+) -> io::Result<S> {
+    if !validate {
+        extract_archive(archive_path, read_only, options, &mut sink)?;
+        return Ok(sink);
     }
+    let validators: Validators = Validators::with_builtins();
+    let mut sink = ValidatingSink::new(sink, &validators);
+    extract_archive(archive_path, read_only, options, &mut sink)?;
+    report_mismatches(sink.mismatches())?;
+    Ok(sink.into_inner())
+}
 
-    let local_name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
-    let local_extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+/// Renames entries with each of `transforms` in turn, for `--transform`.
+fn substitute(transforms: Vec<Substitution>) -> impl Fn(&str) -> Option<String> + Send + Sync {
+    move |name| {
+        let renamed: String = transforms
+            .iter()
+            .fold(name.to_string(), |name, substitution| {
+                substitution.apply(&name)
+            });
+        Some(renamed)
+    }
+}
 
-    // Skip variable length fields
-    f.seek(SeekFrom::Current(
-        (local_name_length + local_extra_length) as i64,
-    ))?;
+/// Extracts the archive at `archive_path` into `sink`, adding the entries
+/// of nested archives skipped with `--on-unsupported collect` to the error.
+fn extract_recursive<S: Sink>(
+    archive_path: &str,
+    read_only: bool,
+    options: ExtractOptions,
+    validate: bool,
+    mut sink: RecursiveSink<S>,
+) -> io::Result<()> {
+    let result: io::Result<()> =
+        extract_with(archive_path, read_only, options, validate, &mut sink).map(|_| ());
+    sink.finish(result)
+}
 
-    // Read compressed data
-    let mut compressed_data_buf: Vec<u8> = vec![0u8; entry.compressed_size as usize];
-    f.read_exact(&mut compressed_data_buf)?;
+/// Prints a JSON line for every entry handed to the wrapped sink: the
+/// entry's fields, completed by `status` once the entry is passed on, or
+/// by the error if passing it on failed. Skipped entries get a line too.
+struct JsonSink<S, F> {
+    inner: S,
+    status: F,
+}
 
-    match entry.compression_method {
-        0 => {
-            eprintln!("No compression, returning raw data");
-            Ok(Some(compressed_data_buf))
-        }
-        8 => {
-            eprintln!("Using Deflate decompression");
-            eprintln!("Compressed size: {}", compressed_data_buf.len());
+impl<S, F> JsonSink<S, F>
+where
+    S: Sink,
+    F: FnMut(&S, &ZipFileEntry, JsonObject) -> io::Result<JsonObject>,
+{
+    fn new(inner: S, status: F) -> JsonSink<S, F> {
+        JsonSink { inner, status }
+    }
+}
 
-            use flate2::read::DeflateDecoder;
-            use std::io::Read;
+impl<S, F> Sink for JsonSink<S, F>
+where
+    S: Sink,
+    F: FnMut(&S, &ZipFileEntry, JsonObject) -> io::Result<JsonObject>,
+{
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
 
-            let mut decoder: DeflateDecoder<&[u8]> = DeflateDecoder::new(&compressed_data_buf[..]);
-            let mut decompressed_data: Vec<u8> =
-                Vec::with_capacity(entry.uncompressed_size as usize);
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        if let Err(e) = self.inner.write_entry(entry, data) {
+            let object: JsonObject = entry_object(entry)
+                .string("status", "failed")
+                .string("error", &e.to_string());
+            println!("{}", object.finish());
+            return Err(e);
+        }
+        let object: JsonObject = (self.status)(&self.inner, entry, entry_object(entry))?;
+        println!("{}", object.finish());
+        Ok(())
+    }
 
-            match decoder.read_to_end(&mut decompressed_data) {
-                Ok(size) => {
-                    eprintln!("Successfully decompressed {} bytes", size);
-                    if size != entry.uncompressed_size as usize {
-                        eprintln!(
-                            "Warning: Decompressed size {} differs from expected {}",
-                            size, entry.uncompressed_size
-                        );
-                    }
-                    let folder_path: &Path = Path::new(&path_to_unpack);
-                    let full_path: String = format!("{}{}", path_to_unpack, entry.filename);
-                    let path: &Path = Path::new(&full_path);
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        self.inner.write_special(entry, special)
+    }
 
-                    match folder_path.exists() {
-                        true => {
-                            let mut file: File = File::create(path)?;
-                            file.write_all(&decompressed_data)?;
-                            file.flush()?;
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        let object: JsonObject = entry_object(entry)
+            .string("status", "skipped")
+            .string("reason", reason);
+        println!("{}", object.finish());
+        self.inner.skip_entry(entry, reason)
+    }
+}
 
-                            eprintln!("Successfully saved file to {}", full_path);
+/// Adds what extracting an entry does to its object, e.g.
+/// `"status":"renamed"` with the path written to.
+fn action_object(object: JsonObject, action: Action) -> JsonObject {
+    let (status, path) = match &action {
+        Action::Omit => return object.string("status", "omitted").null("path"),
+        Action::CreateDir(path) => ("directory", path),
+        Action::Create(path) => ("created", path),
+        Action::Overwrite(path) => ("overwritten", path),
+        Action::Rename { to, .. } => ("renamed", to),
+        Action::Skip(path) => ("skipped", path),
+    };
+    object
+        .string("status", status)
+        .string("path", &path.to_string_lossy())
+}
 
-                            Ok(Some(decompressed_data))
-                        }
-                        false => {
-                            eprintln!("FAIL: Output path doesnt exist: {:?}", path_to_unpack);
-                            Err(std::io::Error::new(
-                                std::io::ErrorKind::InvalidData,
-                                "Output path doesnt exist",
-                            ))
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Decompression error: {}", e);
-                    eprintln!("Full compressed data: {:02X?}", compressed_data_buf);
-                    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-                }
-            }
+/// Adds what became of an extracted entry to its object, see
+/// [`action_object`].
+fn outcome_object(object: JsonObject, outcome: Option<&Outcome>) -> JsonObject {
+    match outcome {
+        Some(Outcome::Done(action)) => action_object(object, action.clone()),
+        Some(Outcome::Skipped(reason)) => {
+            object.string("status", "skipped").string("reason", reason)
         }
-        _ => {
-            eprintln!(
-                "Unsupported compression method: {}",
-                entry.compression_method
-            );
-            Ok(None)
+        Some(Outcome::Failed(error)) => object.string("status", "failed").string("error", error),
+        None => object,
+    }
+}
+
+/// Prints how many entries were extracted, skipped and failed, followed
+/// by the ones that weren't extracted and why.
+fn print_summary(outcomes: &[(String, Outcome)]) {
+    let skipped: usize = outcomes
+        .iter()
+        .filter(|(_, outcome)| outcome.skip_reason().is_some())
+        .count();
+    let failed: usize = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    println!(
+        "Extracted {} entries, {} skipped, {} failed",
+        outcomes.len() - skipped - failed,
+        skipped,
+        failed
+    );
+    for (name, outcome) in outcomes {
+        match (outcome, outcome.skip_reason()) {
+            (Outcome::Failed(error), _) => println!("  failed   {}: {}", name, error),
+            (_, Some(reason)) => println!("  skipped  {} ({})", name, reason),
+            _ => {}
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::{fs, path::PathBuf};
+/// Fails with [`Incomplete`] when entries failed to extract although the
+/// archive was read to the end.
+fn check_outcomes(outcomes: &[(String, Outcome)], result: io::Result<()>) -> io::Result<()> {
+    result?;
+    let failed: usize = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    match failed {
+        0 => Ok(()),
+        1 => Err(io::Error::other(Incomplete(
+            "1 entry failed to extract".to_string(),
+        ))),
+        n => Err(io::Error::other(Incomplete(format!(
+            "{} entries failed to extract",
+            n
+        )))),
+    }
+}
+
+/// An entry of a listing, with its JSON object already rendered.
+struct Listed {
+    name: String,
+    size: u64,
+    compressed_size: u64,
+    /// Seconds since the Unix epoch
+    mtime: Option<u64>,
+    /// See [`entry_markers`]
+    markers: Vec<&'static str>,
+    json: String,
+}
+
+impl Listed {
+    /// Compressed size over uncompressed size; empty entries count as
+    /// stored.
+    fn ratio(&self) -> f64 {
+        match self.size {
+            0 => 1.0,
+            size => self.compressed_size as f64 / size as f64,
+        }
+    }
+}
 
-    fn read_file_contents<P: AsRef<Path>>(path: P) -> io::Result<String> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        Ok(contents)
+/// Lists an entry of a ZIP, 7z or RAR archive.
+fn zip_listing(entry: &ZipFileEntry) -> Listed {
+    let (size, compressed_size) = entry.sizes();
+    let markers: Vec<&'static str> = entry_markers(entry);
+    Listed {
+        name: entry.filename.clone(),
+        size,
+        compressed_size,
+        mtime: entry
+            .precise_modified()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_secs()),
+        json: entry_object(entry)
+            .string("markers", &markers.join(","))
+            .finish(),
+        markers,
     }
+}
 
-    fn get_test_file_path(filename: &str, only_folder: bool) -> PathBuf {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.push("test_files/");
-        if !only_folder {
-            path.push(filename);
-        };
-        path
+/// What the general purpose flags of `entry` say about it: `encrypted`,
+/// `strong-encryption`, `patched` (the data is a patch against another
+/// file) and `utf-8` (the name is UTF-8).
+fn entry_markers(entry: &ZipFileEntry) -> Vec<&'static str> {
+    let mut markers: Vec<&'static str> = vec![];
+    match entry.encryption() {
+        Encryption::None => {}
+        Encryption::Strong => markers.push("strong-encryption"),
+        _ => markers.push("encrypted"),
+    }
+    if entry.is_patched() {
+        markers.push("patched");
+    }
+    if entry.has_utf8_name() {
+        markers.push("utf-8");
+    }
+    markers
+}
+
+/// Lists an entry of a tar, cpio or ar archive. Those have 64-bit sizes
+/// and no CRC, so they don't go through [`ZipFileEntry`].
+fn stream_listing(name: &str, size: u64, mtime: u64) -> Listed {
+    let object: JsonObject = JsonObject::new()
+        .string("name", name)
+        .number("uncompressed_size", size)
+        .number("compressed_size", size)
+        .string("method", "stored")
+        .null("crc32")
+        .number("mtime", mtime)
+        .string("markers", "");
+    Listed {
+        name: name.to_string(),
+        size,
+        compressed_size: size,
+        mtime: Some(mtime),
+        markers: vec![],
+        json: object.finish(),
     }
+}
+
+/// Writes a listing as entries come, or once they're all in when sorted,
+/// followed in text format by the totals.
+struct Lister<W: Write> {
+    out: W,
+    format: OutputFormat,
+    human_readable: bool,
+    sort: Option<SortKey>,
+    held: Vec<Listed>,
+    count: u64,
+    total_size: u64,
+    total_compressed_size: u64,
+}
 
-    #[test]
-    fn test_archive_multiple_files() -> io::Result<()> {
-        let test_path = get_test_file_path("test_multiple.zip", false);
-        let res: Result<Option<u64>, io::Error> = read_end_central_dir(test_path.to_str().unwrap());
-        let entries: Option<Vec<ZipFileEntry>> =
-            read_central_directory(test_path.to_str().unwrap(), res.unwrap()).unwrap();
-        if let Some(entries_vec) = &entries {
-            assert_eq!(entries_vec.len(), 2);
-            assert_eq!(entries_vec.first().unwrap().filename, "test1.txt");
-            assert_eq!(entries_vec.last().unwrap().filename, "test2.txt");
+impl<W: Write> Lister<W> {
+    fn new(out: W, format: OutputFormat, human_readable: bool, sort: Option<SortKey>) -> Lister<W> {
+        Lister {
+            out,
+            format,
+            human_readable,
+            sort,
+            held: vec![],
+            count: 0,
+            total_size: 0,
+            total_compressed_size: 0,
         }
+    }
 
-        Ok(())
+    fn add(&mut self, listed: Listed) -> io::Result<()> {
+        self.count += 1;
+        self.total_size = self.total_size.saturating_add(listed.size);
+        self.total_compressed_size = self
+            .total_compressed_size
+            .saturating_add(listed.compressed_size);
+        if self.sort.is_some() {
+            self.held.push(listed);
+            return Ok(());
+        }
+        self.write(&listed)
     }
 
-    #[test]
-    fn test_archive_single_file() -> io::Result<()> {
-        let test_path = get_test_file_path("test_single.zip", false);
-        let res: Result<Option<u64>, io::Error> = read_end_central_dir(test_path.to_str().unwrap());
-        let entries: Option<Vec<ZipFileEntry>> =
-            read_central_directory(test_path.to_str().unwrap(), res.unwrap()).unwrap();
-        if let Some(entries_vec) = &entries {
-            assert_eq!(entries_vec.len(), 1);
-            assert_eq!(entries_vec.first().unwrap().filename, "test1.txt");
+    fn finish(mut self) -> io::Result<()> {
+        let mut held: Vec<Listed> = std::mem::take(&mut self.held);
+        // Stable, so ties keep the archive order
+        match self.sort {
+            Some(SortKey::Name) => held.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some(SortKey::Size) => held.sort_by_key(|listed| std::cmp::Reverse(listed.size)),
+            Some(SortKey::Ratio) => held.sort_by(|a, b| a.ratio().total_cmp(&b.ratio())),
+            Some(SortKey::Date) => held.sort_by_key(|listed| std::cmp::Reverse(listed.mtime)),
+            None => {}
         }
-        // fs::remove_file(test_path)?;  // Cleanup
-        Ok(())
+        for listed in &held {
+            self.write(listed)?;
+        }
+        if self.format == OutputFormat::Text {
+            writeln!(self.out, "{:->10} {:->10}  {:->8}", "", "", "")?;
+            let count: String = match self.count {
+                1 => String::from("1 entry"),
+                count => format!("{} entries", count),
+            };
+            let (size, compressed_size) = (
+                self.size(self.total_size),
+                self.size(self.total_compressed_size),
+            );
+            writeln!(self.out, "{:>10} {:>10}  {}", size, compressed_size, count)?;
+        }
+        self.out.flush()
     }
 
-    #[test]
-    fn test_e2e_single_file() -> io::Result<()> {
-        let test_path = get_test_file_path("test_single.zip", false);
-        let test_path_only = get_test_file_path("test_single.zip", true);
-        let res: Result<Option<u64>, io::Error> = read_end_central_dir(test_path.to_str().unwrap());
-        let entries: Option<Vec<ZipFileEntry>> =
-            read_central_directory(test_path.to_str().unwrap(), res.unwrap()).unwrap();
-        if let Some(entries_vec) = &entries {
-            assert_eq!(entries_vec.len(), 1);
-            assert_eq!(entries_vec.first().unwrap().filename, "test1.txt");
-            for item in entries_vec {
-                extract_file(
-                    test_path.to_str().unwrap(),
-                    item,
-                    test_path_only.to_str().unwrap(),
-                );
+    fn write(&mut self, listed: &Listed) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => {
+                let (size, compressed_size) =
+                    (self.size(listed.size), self.size(listed.compressed_size));
+                write!(
+                    self.out,
+                    "{:>10} {:>10}  {}",
+                    size, compressed_size, listed.name
+                )?;
+                match listed.markers.is_empty() {
+                    true => writeln!(self.out),
+                    false => writeln!(self.out, "  [{}]", listed.markers.join(", ")),
+                }
             }
+            OutputFormat::Json => writeln!(self.out, "{}", listed.json),
         }
-        let files_in_folder: Vec<String> = fs::read_dir(&test_path_only)?
-            .filter_map(|f| Some(f.ok()?.file_name().to_str()?.to_owned()))
-            .collect();
+    }
 
-        assert_eq!(files_in_folder.last().unwrap(), "test1.txt");
-        let unarchived_file_path = Path::new(files_in_folder.last().unwrap());
-        fs::remove_file(Path::new(&test_path_only.join(unarchived_file_path)))?; // Cleanup
-        Ok(())
+    fn size(&self, bytes: u64) -> String {
+        if self.human_readable {
+            format_size(bytes)
+        } else {
+            bytes.to_string()
+        }
+    }
+}
+
+/// Decodes the entry named `entry_name` of a seekable archive.
+fn read_zip_entry<R: Read + Seek>(
+    archive: &ZipArchive<R>,
+    entry_name: &str,
+) -> io::Result<Vec<u8>> {
+    let entry: Entry<'_, R> = archive.by_name(entry_name)?;
+    if let Some(reason) = entry.metadata().unsupported_reason() {
+        return Err(XpackError::unsupported(entry.metadata(), reason).into());
+    }
+    let data: Option<Vec<u8>> = entry.read_to_vec()?;
+    data.ok_or_else(|| {
+        XpackError::unsupported(entry.metadata(), "invalid local header".to_string()).into()
+    })
+}
+
+/// Reads entries from `reader` until the one named `entry_name`.
+fn find_zip_stream_entry<R: Read>(
+    reader: &mut ZipStreamReader<R>,
+    entry_name: &str,
+) -> io::Result<Vec<u8>> {
+    while let Some((entry, data)) = reader.next_entry()? {
+        if entry.filename == entry_name {
+            return data.ok_or_else(|| {
+                let reason: String = entry.unsupported_reason().unwrap_or_default();
+                XpackError::unsupported(&entry, reason).into()
+            });
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No entry named {} in archive", entry_name),
+    ))
+}
+
+/// Advances `archive` to the regular file named `entry_name`.
+fn find_tar_entry<R: Read>(archive: &mut TarArchive<R>, entry_name: &str) -> io::Result<TarHeader> {
+    while let Some(header) = archive.next_header()? {
+        if header.path == entry_name && header.kind == TarEntryKind::File {
+            return Ok(header);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No entry named {} in archive", entry_name),
+    ))
+}
+
+/// Advances `archive` to the regular file named `entry_name`, or to the
+/// name of the same file that holds the data when it's a hard link.
+fn find_cpio_entry<R: Read>(
+    archive: &mut CpioArchive<R>,
+    entry_name: &str,
+) -> io::Result<CpioHeader> {
+    let mut link: Option<CpioHeader> = None;
+    while let Some(header) = archive.next_header()? {
+        if !header.is_file() {
+            continue;
+        }
+        let found: bool = match &link {
+            Some(link) => {
+                (header.dev_major, header.dev_minor, header.ino)
+                    == (link.dev_major, link.dev_minor, link.ino)
+                    && header.size > 0
+            }
+            None => header.name == entry_name,
+        };
+        if !found {
+            continue;
+        }
+        if header.nlink > 1 && header.size == 0 {
+            link = Some(header);
+            continue;
+        }
+        return Ok(header);
+    }
+    // An empty file with several names
+    link.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No entry named {} in archive", entry_name),
+        )
+    })
+}
+
+fn find_ar_entry<R: Read>(archive: &mut ArArchive<R>, entry_name: &str) -> io::Result<ArHeader> {
+    while let Some(header) = archive.next_header()? {
+        if header.name == entry_name {
+            return Ok(header);
+        }
     }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No entry named {} in archive", entry_name),
+    ))
+}
+
+fn find_sevenz_entry<R: Read + Seek>(
+    archive: &SevenZipArchive<R>,
+    entry_name: &str,
+) -> io::Result<SevenZipEntry> {
+    archive
+        .entries()
+        .iter()
+        .find(|entry| entry.name == entry_name && !entry.is_directory)
+        .cloned()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No entry named {} in archive", entry_name),
+            )
+        })
+}
+
+/// Prints local headers that disagree with the central directory and fails
+/// if there are any.
+fn report_header_mismatches(mismatches: &[HeaderMismatch]) -> io::Result<()> {
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    for mismatch in mismatches {
+        eprintln!(
+            "  {}: local {} {} doesn't match central directory {}",
+            mismatch.filename, mismatch.field, mismatch.local, mismatch.central
+        );
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} local header fields don't match the central directory",
+            mismatches.len()
+        ),
+    ))
+}
 
-    #[test]
-    #[should_panic(expected = "No such file or directory")]
-    fn test_non_existent_archive() {
-        let test_path = get_test_file_path("non_existent.zip", false);
-        let res: Result<Option<u64>, io::Error> = read_end_central_dir(test_path.to_str().unwrap());
-        read_central_directory(test_path.to_str().unwrap(), res.unwrap()).unwrap();
+fn report_mismatches(mismatches: &[Mismatch]) -> io::Result<()> {
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    eprintln!("{} entries don't match their extension:", mismatches.len());
+    for mismatch in mismatches {
+        eprintln!("  {}: {}", mismatch.filename, mismatch.reason);
     }
+    Err(io::Error::other(Incomplete(match mismatches.len() {
+        1 => "1 entry doesn't match its extension".to_string(),
+        n => format!("{} entries don't match their extension", n),
+    })))
 }
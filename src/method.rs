@@ -0,0 +1,275 @@
+//! Decompressors for the compression methods of ZIP entries.
+//!
+//! Extraction looks the method of each entry up in a [`Codecs`] registry,
+//! set through [`ExtractOptions::codecs`](crate::ExtractOptions::codecs).
+//! The default registry knows Deflate, bzip2, LZMA, xz and, with the `zstd`
+//! feature, Zstandard; more can be registered, or the built-in ones
+//! replaced, by implementing [`Decompressor`]. Stored entries need no
+//! decompressor and are always supported.
+
+use crate::zip::{CompressionMethod, ZipFileEntry, FLAG_ENCRYPTED};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Arc, OnceLock};
+
+// General purpose bit 1 of LZMA entries: the stream ends with an end marker
+const FLAG_LZMA_END_MARKER: u16 = 0x0002;
+
+/// Decodes the data of entries compressed with one method.
+pub trait Decompressor: Send + Sync {
+    /// Wraps `input`, a reader of the compressed data of `entry`, in a
+    /// reader of the decompressed data. Limits and the CRC are checked by
+    /// the caller.
+    fn decoder<'a>(
+        &self,
+        entry: &ZipFileEntry,
+        input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>>;
+}
+
+/// Compression methods and the decompressors that decode them.
+#[derive(Clone)]
+pub struct Codecs {
+    decompressors: HashMap<u16, Arc<dyn Decompressor>>,
+}
+
+impl Codecs {
+    /// A registry without any decompressor, which only extracts stored
+    /// entries.
+    pub fn empty() -> Codecs {
+        Codecs {
+            decompressors: HashMap::new(),
+        }
+    }
+
+    /// The registry used by default, shared rather than built per call.
+    pub fn builtin() -> &'static Codecs {
+        static BUILTIN: OnceLock<Codecs> = OnceLock::new();
+        BUILTIN.get_or_init(Codecs::default)
+    }
+
+    /// Decodes `method` with `decompressor`, replacing any decompressor
+    /// registered for it before.
+    pub fn register<D: Decompressor + 'static>(
+        &mut self,
+        method: CompressionMethod,
+        decompressor: D,
+    ) -> &mut Codecs {
+        self.decompressors
+            .insert(method.id(), Arc::new(decompressor));
+        self
+    }
+
+    pub fn get(&self, method: CompressionMethod) -> Option<&dyn Decompressor> {
+        self.decompressors.get(&method.id()).map(|d| d.as_ref())
+    }
+
+    /// Why `entry` can't be decoded with these codecs, if it can't: it is
+    /// encrypted, its data is a patch, or no decompressor is registered
+    /// for its method.
+    pub fn unsupported_reason(&self, entry: &ZipFileEntry) -> Option<String> {
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Some(format!(
+                "encrypted with {}, password required",
+                entry.encryption()
+            ));
+        }
+        if entry.is_patched() {
+            return Some("patched data, which needs the file it patches".to_string());
+        }
+        match entry.method() {
+            CompressionMethod::Stored => None,
+            method if self.decompressors.contains_key(&method.id()) => None,
+            method => Some(format!("unsupported compression method {}", method.id())),
+        }
+    }
+}
+
+impl Default for Codecs {
+    fn default() -> Codecs {
+        let mut codecs: Codecs = Codecs::empty();
+        codecs
+            .register(CompressionMethod::Deflated, Deflate)
+            .register(CompressionMethod::Bzip2, Bzip2)
+            .register(CompressionMethod::Lzma, Lzma)
+            .register(CompressionMethod::Xz, Xz);
+        #[cfg(feature = "zstd")]
+        codecs.register(CompressionMethod::Zstd, Zstd);
+        codecs
+    }
+}
+
+impl fmt::Debug for Codecs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut methods: Vec<u16> = self.decompressors.keys().copied().collect();
+        methods.sort_unstable();
+        f.debug_struct("Codecs").field("methods", &methods).finish()
+    }
+}
+
+struct Deflate;
+
+impl Decompressor for Deflate {
+    fn decoder<'a>(
+        &self,
+        _: &ZipFileEntry,
+        input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(flate2::read::DeflateDecoder::new(input)))
+    }
+}
+
+struct Bzip2;
+
+impl Decompressor for Bzip2 {
+    fn decoder<'a>(
+        &self,
+        _: &ZipFileEntry,
+        input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(bzip2::read::BzDecoder::new(input)))
+    }
+}
+
+struct Lzma;
+
+impl Decompressor for Lzma {
+    fn decoder<'a>(
+        &self,
+        entry: &ZipFileEntry,
+        mut input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        // LZMA data of a ZIP entry:
+        // [1 byte]   LZMA SDK major version
+        // [1 byte]   LZMA SDK minor version
+        // [2 bytes]  Properties size, 5
+        // [5 bytes]  Properties: the lc, lp and pb byte and the dictionary size
+        // [variable] Raw LZMA stream
+        let mut header: [u8; 9] = [0u8; 9];
+        let bad_header = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad LZMA header in {}", entry.filename),
+            )
+        };
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => bad_header(),
+            _ => e,
+        })?;
+        let [_, _, 5, 0, props, a, b, c, d] = header else {
+            return Err(bad_header());
+        };
+        let size: u64 = match entry.flags & FLAG_LZMA_END_MARKER {
+            0 => entry.uncompressed_size as u64,
+            _ => u64::MAX,
+        };
+        // Don't let the header allocate a dictionary larger than the output
+        let dict_size: u32 = u32::from_le_bytes([a, b, c, d])
+            .min(entry.uncompressed_size.max(lzma_rust2::DICT_SIZE_MIN));
+        let decoder = lzma_rust2::LzmaReader::new_with_props(input, size, props, dict_size, None)?;
+        Ok(Box::new(decoder))
+    }
+}
+
+struct Xz;
+
+impl Decompressor for Xz {
+    fn decoder<'a>(
+        &self,
+        _: &ZipFileEntry,
+        input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(lzma_rust2::XzReader::new(input, false)))
+    }
+}
+
+#[cfg(feature = "zstd")]
+struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl Decompressor for Zstd {
+    fn decoder<'a>(
+        &self,
+        _: &ZipFileEntry,
+        input: Box<dyn Read + 'a>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::stream::read::Decoder::new(input)?))
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use crate::writer::{EntryMetadata, NewEntry, ZipWriter};
+    use crate::zip::{ExtractOptions, ZipArchive};
+
+    /// Stores data reversed, as a stand-in for a real codec.
+    struct Reversed;
+
+    impl Decompressor for Reversed {
+        fn decoder<'a>(
+            &self,
+            _: &ZipFileEntry,
+            mut input: Box<dyn Read + 'a>,
+        ) -> io::Result<Box<dyn Read + 'a>> {
+            let mut data: Vec<u8> = vec![];
+            input.read_to_end(&mut data)?;
+            data.reverse();
+            Ok(Box::new(io::Cursor::new(data)))
+        }
+    }
+
+    #[test]
+    fn test_builtin_methods() -> io::Result<()> {
+        // Written by Python's zipfile with bzip2, LZMA and Deflate
+        let test_path = get_test_file_path("test_methods.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let mut sink: MemorySink = MemorySink::new();
+        archive.extract_to(&mut sink)?;
+        let entries = sink.into_entries();
+        assert_eq!(entries.len(), 3);
+        for (name, data) in &entries {
+            assert_eq!(data, &b"xpack ".repeat(100), "{}", name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_decompressor() -> io::Result<()> {
+        let custom: CompressionMethod = CompressionMethod::Unknown(0x8000);
+        let data: &[u8] = b"reversed";
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        let job = NewEntry::Copy {
+            entry: ZipFileEntry {
+                filename: "r.txt".to_string(),
+                compression_method: custom.id(),
+                crc32: crc32fast::hash(data),
+                uncompressed_size: data.len() as u32,
+                ..Default::default()
+            },
+            data: data.iter().rev().copied().collect(),
+            metadata: EntryMetadata::default(),
+        };
+        writer.add_parallel([move || Ok(job)], 1)?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+        let mut archive = ZipArchive::new(io::Cursor::new(bytes))?;
+        let entry: ZipFileEntry = archive.entries().next().unwrap()?;
+        assert_eq!(
+            Codecs::builtin().unsupported_reason(&entry).as_deref(),
+            Some("unsupported compression method 32768")
+        );
+
+        let mut codecs: Codecs = Codecs::default();
+        codecs.register(custom, Reversed);
+        assert_eq!(codecs.unsupported_reason(&entry), None);
+        archive.set_options(ExtractOptions {
+            codecs,
+            ..Default::default()
+        });
+        assert_eq!(archive.by_name("r.txt")?.read_to_vec()?.unwrap(), data);
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Reading of RAR archives through the UnRAR library.
+//!
+//! Enabled by the `rar` feature, which builds the UnRAR sources bundled
+//! with the `unrar` crate and so needs a C++ compiler. Both RAR4 and RAR5
+//! archives are read, multi-volume ones included when the first volume is
+//! opened. UnRAR only reads archives by path, so unlike the other readers
+//! this one can't read from standard input or a URL.
+
+use crate::sink::Sink;
+use crate::writer::{S_IFDIR, S_IFREG};
+use crate::zip::{dos_datetime, Deadline, EntryLimits, ExtractOptions, SkipLog, ZipFileEntry};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unrar::error::{Code, UnrarError};
+use unrar::{Archive, CursorBeforeHeader, FileHeader, OpenArchive, Process};
+
+// Mask of the file type bits of a Unix mode
+const S_IFMT: u32 = 0o170000;
+
+/// A single file or directory of a [`RarArchive`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RarEntry {
+    pub name: String,
+    pub is_directory: bool,
+    pub is_encrypted: bool,
+    pub size: u64,
+    pub crc32: u32,
+    pub modified: Option<SystemTime>,
+    /// Unix mode for archives made on Unix, Windows attributes otherwise
+    pub attributes: u32,
+}
+
+impl RarEntry {
+    fn from_header(header: &FileHeader) -> RarEntry {
+        RarEntry {
+            name: header.filename.to_string_lossy().replace('\\', "/"),
+            is_directory: header.is_directory(),
+            is_encrypted: header.is_encrypted(),
+            size: header.unpacked_size,
+            // UnRAR leaves the CRC-32 of the previous file in directory headers
+            crc32: match header.is_directory() {
+                true => 0,
+                false => header.file_crc,
+            },
+            modified: dos_datetime((header.file_time >> 16) as u16, header.file_time as u16),
+            attributes: header.file_attr,
+        }
+    }
+
+    /// Returns the Unix mode, file type included, if the archive was made on
+    /// Unix. UnRAR doesn't say which system made it, but Windows attributes
+    /// never have the file type bits of a mode set.
+    pub fn unix_mode(&self) -> Option<u32> {
+        Some(self.attributes).filter(|attributes| attributes & S_IFMT != 0)
+    }
+
+    /// Describes the entry in the form sinks expect, like
+    /// [`TarHeader::to_entry`](crate::tar::TarHeader::to_entry) does.
+    pub fn to_entry(&self) -> io::Result<ZipFileEntry> {
+        let size: u32 = u32::try_from(self.size).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is too large to extract", self.name),
+            )
+        })?;
+        let mut filename: String = self.name.clone();
+        if self.is_directory && !filename.ends_with('/') {
+            filename.push('/');
+        }
+        let mode: u32 = self.unix_mode().unwrap_or(match self.is_directory {
+            true => S_IFDIR | 0o755,
+            false => S_IFREG | 0o644,
+        });
+        Ok(ZipFileEntry {
+            filename,
+            crc32: self.crc32,
+            compressed_size: size,
+            uncompressed_size: size,
+            modified: self.modified,
+            external_attributes: mode << 16,
+            ..Default::default()
+        })
+    }
+}
+
+fn rar_error(e: UnrarError, path: &Path) -> io::Error {
+    let kind: io::ErrorKind = match e.code {
+        Code::EOpen => io::ErrorKind::NotFound,
+        Code::MissingPassword | Code::BadPassword => io::ErrorKind::Unsupported,
+        Code::NoMemory => io::ErrorKind::OutOfMemory,
+        Code::BadData | Code::BadArchive | Code::UnknownFormat | Code::EndArchive => {
+            io::ErrorKind::InvalidData
+        }
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {}", path.display(), e))
+}
+
+/// Reads a RAR archive by path.
+pub struct RarArchive {
+    path: PathBuf,
+    read_only: bool,
+    options: ExtractOptions,
+}
+
+impl RarArchive {
+    /// Opens the archive at `path`, failing if it isn't a RAR archive.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<RarArchive> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        Archive::new(&path)
+            .open_for_listing()
+            .map_err(|e| rar_error(e, &path))?;
+        Ok(RarArchive {
+            path,
+            read_only: false,
+            options: ExtractOptions::default(),
+        })
+    }
+
+    /// Opens an archive that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> io::Result<RarArchive> {
+        Ok(RarArchive {
+            read_only: true,
+            ..RarArchive::open(path)?
+        })
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Lists the files and directories in archive order.
+    pub fn entries(&self) -> io::Result<Vec<RarEntry>> {
+        let archive = Archive::new(&self.path)
+            .open_for_listing()
+            .map_err(|e| self.error(e))?;
+        archive
+            .map(|header| match header {
+                Ok(header) => Ok(RarEntry::from_header(&header)),
+                Err(e) => Err(self.error(e)),
+            })
+            .collect()
+    }
+
+    /// Decodes the first file named `name`.
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let mut archive: OpenArchive<Process, CursorBeforeHeader> = self.open_for_processing()?;
+        while let Some(header) = archive.read_header().map_err(|e| self.error(e))? {
+            let entry: RarEntry = RarEntry::from_header(header.entry());
+            if entry.name != name || entry.is_directory {
+                archive = header.skip().map_err(|e| self.error(e))?;
+                continue;
+            }
+            let limits: EntryLimits =
+                self.options
+                    .entry_limits(total_deadline, &ZipFileEntry::default(), 0);
+            check_entry(&entry, limits)?;
+            let (data, _) = header.read().map_err(|e| self.error(e))?;
+            return Ok(data);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No entry named {} in archive", name),
+        ))
+    }
+
+    /// Decodes every entry and hands it to `sink`. Encrypted files are
+    /// handled as [`ExtractOptions::unsupported`] says.
+    pub fn extract_to<S: Sink>(&self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let mut written: u64 = 0;
+        let mut skipped: SkipLog = SkipLog::new(self.options.unsupported);
+        let mut archive: OpenArchive<Process, CursorBeforeHeader> = self.open_for_processing()?;
+        while let Some(header) = archive.read_header().map_err(|e| self.error(e))? {
+            let entry: RarEntry = RarEntry::from_header(header.entry());
+            if entry.is_directory {
+                sink.write_entry(&entry.to_entry()?, &[])?;
+                archive = header.skip().map_err(|e| self.error(e))?;
+                continue;
+            }
+            if entry.is_encrypted {
+                skipped.skip(sink, &entry.to_entry()?, "encrypted".to_string())?;
+                archive = header.skip().map_err(|e| self.error(e))?;
+                continue;
+            }
+            let limits: EntryLimits =
+                self.options
+                    .entry_limits(total_deadline, &ZipFileEntry::default(), written);
+            check_entry(&entry, limits)?;
+            let (data, next) = header.read().map_err(|e| self.error(e))?;
+            written += data.len() as u64;
+            sink.write_entry(&entry.to_entry()?, &data)?;
+            archive = next;
+        }
+        skipped.finish()
+    }
+
+    fn open_for_processing(&self) -> io::Result<OpenArchive<Process, CursorBeforeHeader>> {
+        Archive::new(&self.path)
+            .open_for_processing()
+            .map_err(|e| self.error(e))
+    }
+
+    fn error(&self, e: UnrarError) -> io::Error {
+        rar_error(e, &self.path)
+    }
+}
+
+/// Checks the limits and encryption of a file before decoding it. UnRAR
+/// decodes whole files into memory, so the recorded size is what's checked.
+fn check_entry(entry: &RarEntry, limits: EntryLimits) -> io::Result<()> {
+    limits.check_time(&entry.name)?;
+    limits.check_size(&entry.name, entry.size)?;
+    if entry.is_encrypted {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} is encrypted", entry.name),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_rar_list_and_extract() -> io::Result<()> {
+        for filename in ["test_rar4.rar", "test_rar5.rar"] {
+            let archive: RarArchive = RarArchive::open_readonly(get_test_file_path(filename))?;
+            let entries: Vec<RarEntry> = archive.entries()?;
+            let names: Vec<(&str, bool, u64)> = entries
+                .iter()
+                .map(|entry| (entry.name.as_str(), entry.is_directory, entry.size))
+                .collect();
+            assert_eq!(
+                names,
+                [
+                    ("dir", true, 0),
+                    ("dir/test1.txt", false, 14),
+                    ("test2.txt", false, 14)
+                ],
+                "{}",
+                filename
+            );
+            assert_eq!(entries[2].unix_mode(), Some(0o100600));
+            assert_eq!(
+                entries[2].modified,
+                Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            );
+            assert_eq!(archive.read("test2.txt")?, b"this is test2\n");
+
+            let mut sink: MemorySink = MemorySink::new();
+            archive.extract_to(&mut sink)?;
+            let files = sink.into_entries();
+            assert_eq!(files.len(), 2);
+            assert_eq!(files["dir/test1.txt"], b"this is test1\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rar_errors() -> io::Result<()> {
+        let mut archive: RarArchive = RarArchive::open(get_test_file_path("test_rar4.rar"))?;
+        assert_eq!(
+            archive.read("missing.txt").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        archive.set_options(ExtractOptions {
+            max_entry_size: Some(10),
+            ..Default::default()
+        });
+        assert!(archive.extract_to(&mut MemorySink::new()).is_err());
+
+        let err: io::Error = RarArchive::open(get_test_file_path("test_single.zip"))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+//! Extraction of archives nested in other archives, see [`RecursiveSink`].
+
+use crate::ar::ArArchive;
+use crate::cpio::CpioArchive;
+use crate::error::{Limit, SkippedEntry, XpackError};
+use crate::format::{read_head, ArchiveFormat};
+use crate::sevenz::SevenZipArchive;
+use crate::sink::{Sink, SpecialFile};
+use crate::stream::ZipStreamReader;
+use crate::tar::TarArchive;
+use crate::zip::{ExtractOptions, ZipArchive, ZipFileEntry};
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Cursor, Read};
+use std::time::Instant;
+
+/// How many levels of nested archives are unpacked unless told otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 4;
+
+/// Bytes written at every level together when no total size limit is
+/// given: unpacking nested archives without one would let a few kilobytes
+/// of archives of archives fill the disk.
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 4 << 30;
+
+/// Unpacks entries that are archives themselves, recognized by their
+/// leading bytes, up to `max_depth` levels down. The entries of an archive
+/// named `a.zip` go below `a.zip.d/`, next to it. The size and time limits
+/// apply to every level together, so a nested bomb stops where a flat one
+/// would; the total size is capped at [`DEFAULT_MAX_TOTAL_SIZE`] when the
+/// options set no limit.
+///
+/// Extract the outer archive into this sink with the same options, then
+/// pass the result to [`RecursiveSink::finish`].
+pub struct RecursiveSink<S> {
+    inner: S,
+    max_depth: usize,
+    read_only: bool,
+    options: ExtractOptions,
+    started: Instant,
+    // Bytes written at every level, for the total size limit
+    written: u64,
+    // Set when the inner sink fails, which ends the extraction however
+    // deep it happens
+    sink_failed: bool,
+    // Entries of nested archives skipped with `UnsupportedPolicy::Collect`
+    skipped: Vec<SkippedEntry>,
+}
+
+impl<S: Sink> RecursiveSink<S> {
+    /// Wraps `inner`, unpacking nested archives with `options`, opened
+    /// read-only if `read_only` is set. A `max_depth` of 0 passes entries
+    /// on as they are.
+    pub fn new(
+        inner: S,
+        max_depth: usize,
+        read_only: bool,
+        options: &ExtractOptions,
+    ) -> RecursiveSink<S> {
+        let mut options: ExtractOptions = options.clone();
+        if max_depth > 0 {
+            options.max_total_size = options.max_total_size.or(Some(DEFAULT_MAX_TOTAL_SIZE));
+        }
+        RecursiveSink {
+            inner,
+            max_depth,
+            read_only,
+            options,
+            started: Instant::now(),
+            written: 0,
+            sink_failed: false,
+            skipped: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Ends the extraction of the outer archive with `result`, adding the
+    /// entries of nested archives skipped with
+    /// [`UnsupportedPolicy::Collect`](crate::zip::UnsupportedPolicy::Collect)
+    /// to the ones of the outer archive.
+    pub fn finish(&mut self, result: io::Result<()>) -> io::Result<()> {
+        if self.skipped.is_empty() {
+            return result;
+        }
+        let mut skipped: Vec<SkippedEntry> = match &result {
+            Ok(()) => vec![],
+            Err(e) => match XpackError::from_io(e) {
+                Some(XpackError::SkippedEntries { skipped }) => skipped.clone(),
+                _ => return result,
+            },
+        };
+        skipped.append(&mut self.skipped);
+        Err(XpackError::SkippedEntries { skipped }.into())
+    }
+
+    /// Writes an entry of the archive `depth` levels down, whose entries
+    /// are named below `prefix`, and unpacks it if it's an archive.
+    fn unpack(
+        &mut self,
+        entry: &ZipFileEntry,
+        data: &[u8],
+        prefix: &str,
+        depth: usize,
+    ) -> io::Result<()> {
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: format!("{}{}", prefix, entry.filename),
+            ..entry.clone()
+        };
+        let written: u64 = self.written + data.len() as u64;
+        if let Some(max) = self.options.max_total_size.filter(|&max| written > max) {
+            return Err(XpackError::LimitExceeded {
+                entry: entry.filename,
+                limit: Limit::TotalSize(max),
+            }
+            .into());
+        }
+        if let Err(e) = self.inner.write_entry(&entry, data) {
+            self.sink_failed = true;
+            return Err(e);
+        }
+        self.written = written;
+        if depth >= self.max_depth || entry.filename.ends_with('/') {
+            return Ok(());
+        }
+        let Some(archive) = NestedArchive::detect(&entry.filename, data) else {
+            return Ok(());
+        };
+
+        let nested_prefix: String = format!("{}.d/", entry.filename);
+        let options: ExtractOptions = ExtractOptions {
+            timeout: self
+                .options
+                .timeout
+                .map(|limit| limit.saturating_sub(self.started.elapsed())),
+            max_total_size: self
+                .options
+                .max_total_size
+                .map(|max| max.saturating_sub(self.written)),
+            ..self.options.clone()
+        };
+        let read_only: bool = self.read_only;
+        let mut nested = NestedSink {
+            root: self,
+            prefix: nested_prefix.clone(),
+            depth: depth + 1,
+        };
+        let Err(e) = archive.extract_to(read_only, options, &mut nested) else {
+            return Ok(());
+        };
+        if self.sink_failed {
+            return Err(e);
+        }
+        // Report limits as they were given, not as what was left of them
+        // for this archive, and name entries by their whole path
+        let nested_name = |name: &str| match name.starts_with(&nested_prefix) {
+            true => name.to_string(),
+            false => format!("{}{}", nested_prefix, name),
+        };
+        match XpackError::from_io(&e) {
+            Some(XpackError::LimitExceeded {
+                entry,
+                limit: Limit::TotalSize(_),
+            }) => Err(XpackError::LimitExceeded {
+                entry: nested_name(entry),
+                limit: Limit::TotalSize(self.options.max_total_size.unwrap_or_default()),
+            }
+            .into()),
+            Some(XpackError::Timeout {
+                entry,
+                limit,
+                per_entry,
+            }) => Err(XpackError::Timeout {
+                entry: nested_name(entry),
+                limit: match per_entry {
+                    true => *limit,
+                    false => self.options.timeout.unwrap_or(*limit),
+                },
+                per_entry: *per_entry,
+            }
+            .into()),
+            // The rest of the nested archive was extracted
+            Some(XpackError::SkippedEntries { skipped }) => {
+                let skipped = skipped.iter().map(|skipped| SkippedEntry {
+                    entry: nested_name(&skipped.entry),
+                    reason: skipped.reason.clone(),
+                });
+                self.skipped.extend(skipped);
+                Ok(())
+            }
+            Some(_) => Err(e),
+            // A damaged nested archive is left as it is
+            None => {
+                eprintln!("Can't unpack {}: {}", entry.filename, e);
+                Ok(())
+            }
+        }
+    }
+
+    fn write_special_below(
+        &mut self,
+        entry: &ZipFileEntry,
+        special: &SpecialFile,
+        prefix: &str,
+    ) -> io::Result<()> {
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: format!("{}{}", prefix, entry.filename),
+            ..entry.clone()
+        };
+        let special: SpecialFile = match special {
+            SpecialFile::HardLink { target } => SpecialFile::HardLink {
+                target: format!("{}{}", prefix, target),
+            },
+            other => other.clone(),
+        };
+        self.inner.write_special(&entry, &special).inspect_err(|_| {
+            self.sink_failed = true;
+        })
+    }
+}
+
+impl<S: Sink> Sink for RecursiveSink<S> {
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        self.unpack(entry, data, "", 0)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        self.write_special_below(entry, special, "")
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        self.inner.skip_entry(entry, reason)
+    }
+}
+
+/// The sink the entries of a nested archive go to, which hands them back
+/// to the [`RecursiveSink`] with their names below the archive's.
+struct NestedSink<'a, S> {
+    root: &'a mut RecursiveSink<S>,
+    prefix: String,
+    depth: usize,
+}
+
+impl<S: Sink> Sink for NestedSink<'_, S> {
+    fn is_read_only(&self) -> bool {
+        self.root.is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        self.root.unpack(entry, data, &self.prefix, self.depth)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        self.root.write_special_below(entry, special, &self.prefix)
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: format!("{}{}", self.prefix, entry.filename),
+            ..entry.clone()
+        };
+        self.root.inner.skip_entry(&entry, reason)
+    }
+}
+
+/// An archive found in the data of an extracted entry, by how it's read.
+enum NestedArchive {
+    Zip(Cursor<Vec<u8>>),
+    /// A ZIP archive in a gzip stream, which can only be read front to back
+    ZipStream(Box<dyn Read>),
+    Tar(Box<dyn Read>),
+    SevenZip(Cursor<Vec<u8>>),
+    Cpio(Box<dyn Read>),
+    Ar(Box<dyn Read>),
+}
+
+impl NestedArchive {
+    /// Recognizes an archive in `data`, the contents of the entry `name`.
+    /// Gzip data only counts when it holds an archive.
+    fn detect(name: &str, data: &[u8]) -> Option<NestedArchive> {
+        let format: ArchiveFormat = ArchiveFormat::detect(data)?;
+        let data: Cursor<Vec<u8>> = Cursor::new(data.to_vec());
+        match format {
+            ArchiveFormat::Zip => Some(NestedArchive::Zip(data)),
+            ArchiveFormat::SevenZip => Some(NestedArchive::SevenZip(data)),
+            ArchiveFormat::Tar => Some(NestedArchive::Tar(Box::new(data))),
+            ArchiveFormat::Cpio => Some(NestedArchive::Cpio(Box::new(data))),
+            ArchiveFormat::Ar => Some(NestedArchive::Ar(Box::new(data))),
+            ArchiveFormat::Gzip => {
+                let mut decoder: MultiGzDecoder<Cursor<Vec<u8>>> = MultiGzDecoder::new(data);
+                let head: Vec<u8> = read_head(&mut decoder).ok()?;
+                let format: ArchiveFormat = ArchiveFormat::detect(&head)?;
+                // Put the sniffed bytes back in front of the rest
+                let mut stream: Box<dyn Read> = Box::new(Cursor::new(head).chain(decoder));
+                match format {
+                    ArchiveFormat::Zip => Some(NestedArchive::ZipStream(stream)),
+                    ArchiveFormat::Tar => Some(NestedArchive::Tar(stream)),
+                    ArchiveFormat::Cpio => Some(NestedArchive::Cpio(stream)),
+                    ArchiveFormat::Ar => Some(NestedArchive::Ar(stream)),
+                    ArchiveFormat::SevenZip => {
+                        let mut data: Vec<u8> = vec![];
+                        stream.read_to_end(&mut data).ok()?;
+                        Some(NestedArchive::SevenZip(Cursor::new(data)))
+                    }
+                    ArchiveFormat::Gzip | ArchiveFormat::Rar => None,
+                }
+            }
+            ArchiveFormat::Rar => {
+                eprintln!(
+                    "Not unpacking {}: RAR archives can only be read from a file",
+                    name
+                );
+                None
+            }
+        }
+    }
+
+    fn extract_to<S: Sink>(
+        self,
+        read_only: bool,
+        options: ExtractOptions,
+        sink: &mut S,
+    ) -> io::Result<()> {
+        match self {
+            NestedArchive::Zip(data) => {
+                let mut archive: ZipArchive<Cursor<Vec<u8>>> = if read_only {
+                    ZipArchive::new_readonly(data)?
+                } else {
+                    ZipArchive::new(data)?
+                };
+                archive.set_options(options);
+                archive.extract_to(sink)
+            }
+            NestedArchive::ZipStream(reader) => {
+                let mut reader: ZipStreamReader<Box<dyn Read>> = if read_only {
+                    ZipStreamReader::new_readonly(reader)
+                } else {
+                    ZipStreamReader::new(reader)
+                };
+                reader.set_options(options);
+                reader.extract_to(sink)
+            }
+            NestedArchive::Tar(reader) => {
+                let mut archive: TarArchive<Box<dyn Read>> = if read_only {
+                    TarArchive::new_readonly(reader)
+                } else {
+                    TarArchive::new(reader)
+                };
+                archive.set_options(options);
+                archive.extract_to(sink)
+            }
+            NestedArchive::SevenZip(data) => {
+                let mut archive: SevenZipArchive<Cursor<Vec<u8>>> = if read_only {
+                    SevenZipArchive::new_readonly(data)?
+                } else {
+                    SevenZipArchive::new(data)?
+                };
+                archive.set_options(options);
+                archive.extract_to(sink)
+            }
+            NestedArchive::Cpio(reader) => {
+                let mut archive: CpioArchive<Box<dyn Read>> = if read_only {
+                    CpioArchive::new_readonly(reader)
+                } else {
+                    CpioArchive::new(reader)
+                };
+                archive.set_options(options);
+                archive.extract_to(sink)
+            }
+            NestedArchive::Ar(reader) => {
+                let mut archive: ArArchive<Box<dyn Read>> = if read_only {
+                    ArArchive::new_readonly(reader)
+                } else {
+                    ArArchive::new(reader)
+                };
+                archive.set_options(options);
+                archive.extract_to(sink)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::{MemorySink, NullSink};
+    use crate::writer::{EntryMetadata, ZipWriter};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// A ZIP archive holding `entries`.
+    fn zip(entries: &[(&str, &[u8])]) -> io::Result<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.add_file(name, data, &EntryMetadata::default())?;
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Archives `data` as `name` in `levels` archives, each holding the
+    /// one before as `level<N>.zip`.
+    fn nest(name: &str, data: &[u8], levels: usize) -> io::Result<Vec<u8>> {
+        let mut archive: Vec<u8> = zip(&[(name, data)])?;
+        for level in (1..levels).rev() {
+            archive = zip(&[(&format!("level{}.zip", level), &archive)])?;
+        }
+        Ok(archive)
+    }
+
+    fn extract(
+        archive: Vec<u8>,
+        max_depth: usize,
+        options: ExtractOptions,
+    ) -> io::Result<HashMap<String, Vec<u8>>> {
+        let mut sink = RecursiveSink::new(MemorySink::new(), max_depth, true, &options);
+        let mut outer: ZipArchive<Cursor<Vec<u8>>> =
+            ZipArchive::new_readonly(Cursor::new(archive))?;
+        outer.set_options(options);
+        let result: io::Result<()> = outer.extract_to(&mut sink);
+        sink.finish(result)?;
+        Ok(sink.into_inner().into_entries())
+    }
+
+    #[test]
+    fn test_unpacks_nested_archives() -> io::Result<()> {
+        let inner: Vec<u8> = zip(&[("a.txt", b"nested"), ("dir/b.txt", b"deeper")])?;
+        let outer: Vec<u8> = zip(&[("inner.zip", &inner), ("c.txt", b"outer")])?;
+        let entries = extract(outer, DEFAULT_MAX_DEPTH, ExtractOptions::default())?;
+        assert_eq!(entries["inner.zip.d/a.txt"], b"nested");
+        assert_eq!(entries["inner.zip.d/dir/b.txt"], b"deeper");
+        assert_eq!(entries["inner.zip"], inner);
+        assert_eq!(entries["c.txt"], b"outer");
+        Ok(())
+    }
+
+    #[test]
+    fn test_depth_cap() -> io::Result<()> {
+        let archive: Vec<u8> = nest("leaf.txt", b"leaf", 4)?;
+        let entries = extract(archive.clone(), 3, ExtractOptions::default())?;
+        let leaf: &str = "level1.zip.d/level2.zip.d/level3.zip.d/leaf.txt";
+        assert_eq!(entries[leaf], b"leaf");
+
+        // The archive at the cap is written but not unpacked
+        let entries = extract(archive.clone(), 2, ExtractOptions::default())?;
+        assert!(entries.contains_key("level1.zip.d/level2.zip.d/level3.zip"));
+        assert!(!entries.contains_key(leaf));
+
+        let entries = extract(archive, 0, ExtractOptions::default())?;
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["level1.zip"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_size_across_levels() -> io::Result<()> {
+        let data: Vec<u8> = vec![b'x'; 1000];
+        let archive: Vec<u8> = nest("big.bin", &data, 3)?;
+        // Every level fits on its own, but not all of them together
+        let options: ExtractOptions = ExtractOptions {
+            max_total_size: Some(archive.len() as u64 + 1000),
+            ..Default::default()
+        };
+        let err = extract(archive.clone(), DEFAULT_MAX_DEPTH, options).unwrap_err();
+        match XpackError::from_io(&err) {
+            Some(XpackError::LimitExceeded { entry, limit }) => {
+                assert!(entry.starts_with("level1.zip.d/"), "{}", entry);
+                assert_eq!(*limit, Limit::TotalSize(archive.len() as u64 + 1000));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let options: ExtractOptions = ExtractOptions {
+            max_total_size: Some(archive.len() as u64 * 3 + 1000),
+            ..Default::default()
+        };
+        let entries = extract(archive, DEFAULT_MAX_DEPTH, options)?;
+        assert_eq!(entries["level1.zip.d/level2.zip.d/big.bin"], data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_total_size() {
+        let sink = RecursiveSink::new(NullSink, 1, true, &ExtractOptions::default());
+        assert_eq!(sink.options.max_total_size, Some(DEFAULT_MAX_TOTAL_SIZE));
+        let sink = RecursiveSink::new(NullSink, 0, true, &ExtractOptions::default());
+        assert_eq!(sink.options.max_total_size, None);
+    }
+
+    #[test]
+    fn test_timeout_across_levels() -> io::Result<()> {
+        let inner: Vec<u8> = zip(&[("a.txt", b"nested")])?;
+        let limit: Duration = Duration::from_millis(20);
+        let options: ExtractOptions = ExtractOptions {
+            timeout: Some(limit),
+            ..Default::default()
+        };
+        let mut sink = RecursiveSink::new(MemorySink::new(), 1, true, &options);
+        // The outer archive used up the time, so nothing is left for the
+        // nested one
+        std::thread::sleep(limit);
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: "inner.zip".to_string(),
+            ..Default::default()
+        };
+        let err = sink.write_entry(&entry, &inner).unwrap_err();
+        match XpackError::from_io(&err) {
+            Some(XpackError::Timeout {
+                entry,
+                limit: reported,
+                per_entry,
+            }) => {
+                assert_eq!(entry, "inner.zip.d/a.txt");
+                assert_eq!(*reported, limit);
+                assert!(!per_entry);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Salvaging entries from damaged archives.
+//!
+//! When the end of central directory record or the central directory is
+//! missing (a truncated download) or corrupt, the local file headers are
+//! still there. Salvaging scans for their signatures and decodes every entry
+//! that can be read on its own, taking sizes from the local header or from a
+//! trailing data descriptor.
+
+use crate::sink::Sink;
+use crate::stream::ZipStreamReader;
+use crate::zip::{ZipFileEntry, LOCAL_FILE_HEADER_SIGNATURE};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// What a salvage run recovered.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Names of the entries handed to the sink
+    pub recovered: Vec<String>,
+    /// Offset of each local header that couldn't be decoded, with the reason
+    pub failed: Vec<(u64, String)>,
+}
+
+/// Scans `reader` for local file headers and hands every entry that decodes
+/// and passes its CRC check to `sink`.
+///
+/// Errors from reading `reader` or from the sink abort the run; damaged
+/// entries are recorded in the report and scanning resumes right after
+/// their signature.
+pub fn salvage<R: Read + Seek, S: Sink>(reader: &mut R, sink: &mut S) -> io::Result<RepairReport> {
+    let mut report: RepairReport = RepairReport::default();
+    let mut offset: u64 = 0;
+    while let Some(found) = find_local_header(reader, offset)? {
+        reader.seek(SeekFrom::Start(found))?;
+        let mut entry_reader = ZipStreamReader::new(&mut *reader);
+        match entry_reader.next_entry() {
+            Ok(Some((entry, Some(data)))) => {
+                offset = found + entry_reader.position();
+                sink.write_entry(&entry, &data)?;
+                report.recovered.push(entry.filename);
+            }
+            Ok(Some((entry, None))) => {
+                offset = found + entry_reader.position();
+                report.failed.push((found, unsupported(&entry)));
+            }
+            Ok(None) => offset = found + 1,
+            Err(e) => {
+                report.failed.push((found, e.to_string()));
+                offset = found + 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn unsupported(entry: &ZipFileEntry) -> String {
+    format!(
+        "{} uses unsupported compression method {}",
+        entry.filename, entry.compression_method
+    )
+}
+
+/// Returns the offset of the first local file header signature at or after
+/// `from`, reading in chunks that overlap by the signature length.
+fn find_local_header<R: Read + Seek>(reader: &mut R, from: u64) -> io::Result<Option<u64>> {
+    let signature: [u8; 4] = LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes();
+    let mut chunk: Vec<u8> = vec![0u8; 64 * 1024];
+    let mut start: u64 = from;
+    loop {
+        reader.seek(SeekFrom::Start(start))?;
+        let mut filled: usize = 0;
+        while filled < chunk.len() {
+            let read: usize = reader.read(&mut chunk[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        if let Some(pos) = chunk[..filled].windows(4).position(|w| w == signature) {
+            return Ok(Some(start + pos as u64));
+        }
+        if filled < chunk.len() {
+            return Ok(None);
+        }
+        start += (filled - signature.len() + 1) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::NullSink;
+    use crate::test_util::get_test_file_path;
+    use std::fs::File;
+
+    #[test]
+    fn test_salvage_truncated_archive() -> io::Result<()> {
+        // Three entries, cut off in the middle of the third one's data
+        let mut f = File::open(get_test_file_path("test_truncated.zip"))?;
+        let report: RepairReport = salvage(&mut f, &mut NullSink)?;
+        assert_eq!(report.recovered, ["a.txt", "b.txt"]);
+        assert_eq!(report.failed.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_salvage_skips_garbage() -> io::Result<()> {
+        // Leading junk, a stray signature and descriptor-only entries
+        let mut data: Vec<u8> = b"junk PK\x03\x04 junk".to_vec();
+        data.extend(std::fs::read(get_test_file_path("test_multiple.zip"))?);
+        let report: RepairReport = salvage(&mut io::Cursor::new(data), &mut NullSink)?;
+        assert_eq!(report.recovered, ["test1.txt", "test2.txt"]);
+        Ok(())
+    }
+}
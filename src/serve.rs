@@ -0,0 +1,436 @@
+//! Answering queries about archives from a long-running process.
+//!
+//! Opening a ZIP archive means finding and parsing its central directory,
+//! which costs a tool asking about the same archives thousands of times more
+//! than the answers do. A [`Server`] keeps every archive it's asked about
+//! open with its entries parsed, and answers requests read one JSON object
+//! per line, over a Unix socket with [`Server::serve`] or from any reader
+//! with [`Server::handle`]. An archive whose size or modification time
+//! changed since it was opened is opened again.
+//!
+//! Requests name an operation and, by path, the archive it's about:
+//!
+//! ```text
+//! {"op":"list","archive":"/data/a.zip"}
+//! {"op":"cat","archive":"/data/a.zip","entry":"docs/readme.txt"}
+//! {"op":"extract","archive":"/data/a.zip","to":"/tmp/out"}
+//! {"op":"extract","archive":"/data/a.zip","entry":"docs/readme.txt","to":"/tmp/out"}
+//! {"op":"close","archive":"/data/a.zip"}
+//! ```
+//!
+//! Every request is answered by a line with `"status":"ok"`, or with
+//! `"status":"error"` and the message in `"error"`. `list` first sends one
+//! line per entry, as [`entry_object`] describes it; `cat` answers with the
+//! decoded data in base64 under `"data"`, and `extract` with the numbers of
+//! entries written and skipped. `extract` never replaces a file that already
+//! exists, it skips the entry instead.
+//!
+//! Every client is trusted as much as the user running the server: requests
+//! name any path that user can read, and `extract` writes wherever it can
+//! create files. The `xpack serve --socket` command therefore makes the
+//! socket accessible to its owner only; a server handed a listener
+//! elsewhere should restrict it the same way, or run read-only.
+
+use crate::error::XpackError;
+use crate::json::{entry_object, parse_object, JsonObject, JsonValue};
+use crate::sink::{DiskSink, OverwritePolicy, Sink};
+use crate::zip::{Entry, ExtractOptions, ZipArchive, ZipFileEntry};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use std::io::BufReader;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+/// How often [`Server::serve`] checks for cancellation while no client
+/// connects.
+#[cfg(unix)]
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+/// Keeps archives open and answers requests about them, see the module
+/// documentation for the protocol.
+///
+/// Requests about different archives are answered in parallel; those about
+/// the same archive wait for each other.
+#[derive(Default)]
+pub struct Server {
+    // Archives are opened holding only their own slot, so opening a large
+    // one delays the requests about it and no others
+    archives: Mutex<HashMap<PathBuf, Arc<Slot>>>,
+    options: ExtractOptions,
+    read_only: bool,
+}
+
+/// Where the archive at a path is kept, empty until it has been opened.
+type Slot = Mutex<Option<Arc<OpenArchive>>>;
+
+/// An archive kept open, with the size and modification time of its file
+/// when it was opened.
+struct OpenArchive {
+    archive: Mutex<ZipArchive<File>>,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// Applies `options` to every archive opened. Cancelling
+    /// [`ExtractOptions::cancel`] also stops [`Server::serve`].
+    pub fn with_options(mut self, options: ExtractOptions) -> Server {
+        self.options = options;
+        self
+    }
+
+    /// Opens archives read-only and refuses `extract` requests.
+    pub fn with_read_only(mut self, read_only: bool) -> Server {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Number of archives kept open. Waits for those being opened.
+    pub fn open_archives(&self) -> usize {
+        let slots: Vec<Arc<Slot>> = lock(&self.archives).values().cloned().collect();
+        slots.iter().filter(|slot| lock(slot).is_some()).count()
+    }
+
+    /// Answers the clients connecting to `listener`, each on its own
+    /// thread, until [`ExtractOptions::cancel`] is cancelled.
+    #[cfg(unix)]
+    pub fn serve(self, listener: UnixListener) -> io::Result<()> {
+        let server: Arc<Server> = Arc::new(self);
+        // Without a token to check, waiting for clients can block
+        listener.set_nonblocking(server.options.cancel.is_some())?;
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    let input: BufReader<_> = BufReader::new(stream.try_clone()?);
+                    let server: Arc<Server> = Arc::clone(&server);
+                    thread::spawn(move || {
+                        if let Err(e) = server.handle(input, stream) {
+                            eprintln!("Dropping client: {}", e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if server.is_cancelled() {
+                        return Ok(());
+                    }
+                    thread::sleep(ACCEPT_POLL);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Answers the requests read from `input`, one per line, on `output`
+    /// until `input` ends. A failed request is answered with its error;
+    /// only failing to read or write ends the conversation.
+    pub fn handle<R: BufRead, W: Write>(&self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            let line: String = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let answer: JsonObject = match self.answer(&line, &mut output) {
+                Ok(answer) => answer,
+                Err(e) => JsonObject::new()
+                    .string("status", "error")
+                    .string("error", &e.to_string()),
+            };
+            writeln!(output, "{}", answer.finish())?;
+            output.flush()?;
+            if self.is_cancelled() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Carries out one request, writing the lines that come before its
+    /// status line to `output`, and returns the status line.
+    fn answer<W: Write>(&self, line: &str, output: &mut W) -> io::Result<JsonObject> {
+        let request: HashMap<String, JsonValue> = parse_object(line)?;
+        let op: &str = field(&request, "op")?;
+        let path: &str = field(&request, "archive")?;
+        let ok: JsonObject = JsonObject::new().string("status", "ok");
+        match op {
+            "list" => {
+                let open: Arc<OpenArchive> = self.open(path)?;
+                let archive = lock(&open.archive);
+                let mut count: u64 = 0;
+                for entry in archive.entries() {
+                    writeln!(output, "{}", entry_object(&entry?).finish())?;
+                    count += 1;
+                }
+                Ok(ok.number("entries", count))
+            }
+            "cat" => {
+                let name: &str = field(&request, "entry")?;
+                let open: Arc<OpenArchive> = self.open(path)?;
+                let archive = lock(&open.archive);
+                let entry: Entry<'_, File> = archive.by_name(name)?;
+                let data: Vec<u8> = read_entry(&entry)?;
+                Ok(ok
+                    .number("size", data.len() as u64)
+                    .string("data", &base64(&data)))
+            }
+            "extract" => {
+                if self.read_only {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "Extracting can't run read-only",
+                    ));
+                }
+                let mut sink: DiskSink =
+                    DiskSink::new(field(&request, "to")?).with_overwrite(OverwritePolicy::Skip);
+                let open: Arc<OpenArchive> = self.open(path)?;
+                let archive = lock(&open.archive);
+                match optional_field(&request, "entry")? {
+                    Some(name) => {
+                        let entry: Entry<'_, File> = archive.by_name(name)?;
+                        let data: Vec<u8> = read_entry(&entry)?;
+                        sink.write_entry(entry.metadata(), &data)?;
+                    }
+                    None => archive.extract_to(&mut sink)?,
+                }
+                let skipped: usize = sink
+                    .outcomes()
+                    .iter()
+                    .filter(|(_, outcome)| outcome.skip_reason().is_some())
+                    .count();
+                Ok(ok
+                    .number("entries", (sink.outcomes().len() - skipped) as u64)
+                    .number("skipped", skipped as u64))
+            }
+            "close" => {
+                let path: PathBuf = fs::canonicalize(path)?;
+                let slot: Option<Arc<Slot>> = lock(&self.archives).remove(&path);
+                let closed: bool = slot.is_some_and(|slot| lock(&slot).is_some());
+                Ok(ok.number("closed", closed as u64))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown op {}", op),
+            )),
+        }
+    }
+
+    /// Returns the archive at `path`, opening it unless it's open already
+    /// and its file unchanged since.
+    fn open(&self, path: &str) -> io::Result<Arc<OpenArchive>> {
+        // Different spellings of a path share the open archive
+        let path: PathBuf = fs::canonicalize(path)?;
+        let metadata: fs::Metadata = fs::metadata(&path)?;
+        let (size, modified) = (metadata.len(), metadata.modified().ok());
+        let slot: Arc<Slot> = Arc::clone(lock(&self.archives).entry(path.clone()).or_default());
+        let mut kept = lock(&slot);
+        if let Some(open) = kept.as_ref() {
+            if open.size == size && open.modified == modified {
+                return Ok(Arc::clone(open));
+            }
+        }
+        let file: File = File::open(&path)?;
+        let mut archive: ZipArchive<File> = if self.read_only {
+            ZipArchive::new_readonly(file)?
+        } else {
+            ZipArchive::new(file)?
+        };
+        archive.set_options(self.options.clone());
+        archive.preload()?;
+        eprintln!("Opened {}", path.display());
+        let open: Arc<OpenArchive> = Arc::new(OpenArchive {
+            archive: Mutex::new(archive),
+            size,
+            modified,
+        });
+        *kept = Some(Arc::clone(&open));
+        Ok(open)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        (self.options.cancel.as_ref()).is_some_and(|cancel| cancel.is_cancelled())
+    }
+}
+
+/// Locks `mutex`, also when another request panicked holding it: archives
+/// are only read through it, so they stay usable.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn field<'a>(request: &'a HashMap<String, JsonValue>, key: &str) -> io::Result<&'a str> {
+    optional_field(request, key)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Request has no \"{}\"", key),
+        )
+    })
+}
+
+fn optional_field<'a>(
+    request: &'a HashMap<String, JsonValue>,
+    key: &str,
+) -> io::Result<Option<&'a str>> {
+    match request.get(key) {
+        Some(JsonValue::String(value)) => Ok(Some(value)),
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("\"{}\" must be a string", key),
+        )),
+    }
+}
+
+fn read_entry(entry: &Entry<'_, File>) -> io::Result<Vec<u8>> {
+    let metadata: &ZipFileEntry = entry.metadata();
+    if let Some(reason) = metadata.unsupported_reason() {
+        return Err(XpackError::unsupported(metadata, reason).into());
+    }
+    entry
+        .read_to_vec()?
+        .ok_or_else(|| XpackError::unsupported(metadata, "invalid local header".to_string()).into())
+}
+
+/// Encodes `data` in standard base64, with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded: String = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits: u32 = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use std::path::Path;
+
+    fn ask(server: &Server, requests: &[String]) -> io::Result<Vec<HashMap<String, JsonValue>>> {
+        let mut output: Vec<u8> = vec![];
+        server.handle(requests.join("\n").as_bytes(), &mut output)?;
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(parse_object)
+            .collect()
+    }
+
+    fn request(op: &str, archive: &Path) -> JsonObject {
+        JsonObject::new()
+            .string("op", op)
+            .string("archive", &archive.to_string_lossy())
+    }
+
+    #[test]
+    fn test_requests() -> io::Result<()> {
+        let server: Server = Server::new();
+        let archive: PathBuf = get_test_file_path("test_multiple.zip");
+        let answers = ask(
+            &server,
+            &[
+                request("list", &archive).finish(),
+                request("cat", &archive)
+                    .string("entry", "test2.txt")
+                    .finish(),
+                request("cat", &archive)
+                    .string("entry", "missing.txt")
+                    .finish(),
+                String::from("{\"op\":\"list\""),
+                request("close", &archive).finish(),
+            ],
+        )?;
+        let string = |value: &str| JsonValue::String(value.to_string());
+
+        assert_eq!(answers[0]["name"], string("test1.txt"));
+        assert_eq!(answers[1]["name"], string("test2.txt"));
+        assert_eq!(answers[2]["status"], string("ok"));
+        assert_eq!(answers[2]["entries"], JsonValue::Number(2));
+        assert_eq!(answers[3]["data"], string(&base64(b"this is test2\n")));
+        assert_eq!(answers[4]["status"], string("error"));
+        assert_eq!(
+            answers[4]["error"],
+            string("No entry named missing.txt in archive")
+        );
+        assert_eq!(answers[5]["status"], string("error"));
+        assert_eq!(answers[6]["closed"], JsonValue::Number(1));
+        assert_eq!(answers.len(), 7);
+        assert_eq!(server.open_archives(), 0);
+
+        // Answers come from the archive kept open, and extracting writes
+        ask(&server, &[request("list", &archive).finish()])?;
+        assert_eq!(server.open_archives(), 1);
+        let out_dir: PathBuf = std::env::temp_dir().join("xpack_test_serve");
+        let _ = fs::remove_dir_all(&out_dir);
+        let answers = ask(
+            &server,
+            &[request("extract", &archive)
+                .string("entry", "test1.txt")
+                .string("to", &out_dir.to_string_lossy())
+                .finish()],
+        )?;
+        assert_eq!(answers[0]["entries"], JsonValue::Number(1));
+        assert_eq!(fs::read(out_dir.join("test1.txt"))?, b"this is test1\n");
+
+        // Files already there are kept
+        fs::write(out_dir.join("test1.txt"), b"kept")?;
+        let answers = ask(
+            &server,
+            &[request("extract", &archive)
+                .string("to", &out_dir.to_string_lossy())
+                .finish()],
+        )?;
+        assert_eq!(answers[0]["entries"], JsonValue::Number(1));
+        assert_eq!(answers[0]["skipped"], JsonValue::Number(1));
+        assert_eq!(fs::read(out_dir.join("test1.txt"))?, b"kept");
+        assert_eq!(fs::read(out_dir.join("test2.txt"))?, b"this is test2\n");
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_opening_blocks_only_its_archive() -> io::Result<()> {
+        let server: Server = Server::new();
+        let slow: PathBuf = fs::canonicalize(get_test_file_path("test_single.zip"))?;
+        let slot: Arc<Slot> = Arc::clone(lock(&server.archives).entry(slow).or_default());
+        // Holding the slot stands in for a large archive being opened
+        let _opening = lock(&slot);
+        let archive: PathBuf = get_test_file_path("test_multiple.zip");
+        let answers = ask(&server, &[request("list", &archive).finish()])?;
+        assert_eq!(answers[2]["entries"], JsonValue::Number(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar\xff"), "Zm9vYmFy/w==");
+    }
+}
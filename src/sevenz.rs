@@ -0,0 +1,1102 @@
+//! Reading of 7z archives.
+//!
+//! A 7z archive starts with a 32-byte signature header that points at the
+//! real header at the end of the archive. The header describes the packed
+//! streams, the folders that decode them, each a chain of coders, and the
+//! files, whose data a folder produces back to back. A solid archive packs
+//! many files into one folder, which then has to be decoded as a whole. The
+//! header itself is usually packed too, as an encoded header that is
+//! decoded like any folder and parsed again.
+//!
+//! Folders made of a single Copy, LZMA, LZMA2 or Deflate coder are
+//! supported. Encrypted archives and filter chains such as BCJ aren't.
+
+use crate::sink::Sink;
+use crate::writer::{S_IFDIR, S_IFREG};
+use crate::zip::{Deadline, EntryLimits, ExtractOptions, LimitReader, ZipFileEntry};
+use flate2::read::DeflateDecoder;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The six bytes every 7z archive starts with.
+pub const SIGNATURE: &[u8; 6] = b"7z\xBC\xAF\x27\x1C";
+
+// Packed stream offsets count from the end of the signature header
+const SIGNATURE_HEADER_SIZE: u64 = 32;
+
+// Largest header accepted, packed or decoded, so that a crafted size can't
+// make us allocate everything
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+
+// An encoded header decodes to another header, which could in theory be
+// encoded again; real archives never nest more than once
+const MAX_HEADER_NESTING: usize = 4;
+
+// Property IDs of the header
+const ID_END: u8 = 0x00;
+const ID_HEADER: u8 = 0x01;
+const ID_ARCHIVE_PROPERTIES: u8 = 0x02;
+const ID_ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+const ID_MAIN_STREAMS_INFO: u8 = 0x04;
+const ID_FILES_INFO: u8 = 0x05;
+const ID_PACK_INFO: u8 = 0x06;
+const ID_UNPACK_INFO: u8 = 0x07;
+const ID_SUBSTREAMS_INFO: u8 = 0x08;
+const ID_SIZE: u8 = 0x09;
+const ID_CRC: u8 = 0x0A;
+const ID_FOLDER: u8 = 0x0B;
+const ID_CODERS_UNPACK_SIZE: u8 = 0x0C;
+const ID_NUM_UNPACK_STREAM: u8 = 0x0D;
+const ID_EMPTY_STREAM: u8 = 0x0E;
+const ID_EMPTY_FILE: u8 = 0x0F;
+const ID_ANTI: u8 = 0x10;
+const ID_NAME: u8 = 0x11;
+const ID_MTIME: u8 = 0x14;
+const ID_WIN_ATTRIBUTES: u8 = 0x15;
+const ID_ENCODED_HEADER: u8 = 0x17;
+
+// Coder method IDs
+const METHOD_COPY: &[u8] = &[0x00];
+const METHOD_LZMA: &[u8] = &[0x03, 0x01, 0x01];
+const METHOD_LZMA2: &[u8] = &[0x21];
+const METHOD_DEFLATE: &[u8] = &[0x04, 0x01, 0x08];
+const METHOD_AES: &[u8] = &[0x06, 0xF1, 0x07, 0x01];
+
+// Windows attribute bits
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+// Set by p7zip and 7-Zip on Unix when the upper half holds a Unix mode
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+// Seconds from 1601-01-01, where FILETIME counts from, to the Unix epoch
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+fn bad_header(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Bad 7z header: {}", what),
+    )
+}
+
+/// Bounds-checked reading of header fields.
+struct HeaderReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> HeaderReader<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<&'a [u8]> {
+        let len: usize = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.data.len())
+            .ok_or_else(|| bad_header("truncated"))?;
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a variable-length number. The leading one bits of the first
+    /// byte count the little-endian bytes that follow, and the bits left in
+    /// the first byte go on top of them.
+    fn number(&mut self) -> io::Result<u64> {
+        let first: u8 = self.byte()?;
+        let mut value: u64 = 0;
+        for i in 0..8 {
+            let mask: u8 = 0x80 >> i;
+            if first & mask == 0 {
+                let high: u64 = (first & (mask - 1)) as u64;
+                return Ok(value | high << (8 * i));
+            }
+            value |= (self.byte()? as u64) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    /// Reads a number of items. Each item takes at least a byte of what
+    /// follows, so a count beyond that is rejected before anything is
+    /// allocated for it.
+    fn count(&mut self) -> io::Result<usize> {
+        usize::try_from(self.number()?)
+            .ok()
+            .filter(|&count| count <= self.data.len())
+            .ok_or_else(|| bad_header("count larger than the header"))
+    }
+
+    fn expect(&mut self, id: u8) -> io::Result<()> {
+        match self.byte()? {
+            found if found == id => Ok(()),
+            found => Err(bad_header(&format!(
+                "expected property {:#04x}, found {:#04x}",
+                id, found
+            ))),
+        }
+    }
+
+    /// Reads a bit field of `len` bits, most significant bit first.
+    fn bits(&mut self, len: usize) -> io::Result<Vec<bool>> {
+        let bytes: &[u8] = self.bytes(len.div_ceil(8) as u64)?;
+        Ok((0..len)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+
+    /// Reads which of `len` items are defined: a byte that is non-zero when
+    /// all are, or else a bit field.
+    fn defined(&mut self, len: usize) -> io::Result<Vec<bool>> {
+        match self.byte()? {
+            0 => self.bits(len),
+            _ => Ok(vec![true; len]),
+        }
+    }
+
+    /// Reads the CRC-32s of `len` streams, some of which may be missing.
+    fn digests(&mut self, len: usize) -> io::Result<Vec<Option<u32>>> {
+        self.defined(len)?
+            .into_iter()
+            .map(|defined| match defined {
+                true => self.u32().map(Some),
+                false => Ok(None),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Coder {
+    method: Vec<u8>,
+    properties: Vec<u8>,
+    in_streams: usize,
+    out_streams: usize,
+}
+
+/// A chain of coders turning packed streams into one unpacked stream.
+#[derive(Debug, Clone, Default)]
+struct Folder {
+    coders: Vec<Coder>,
+    packed_streams: usize,
+    // Output stream of the chain that isn't bound to another coder's input
+    main_out: usize,
+    // Sizes of every coder output, main one included
+    unpack_sizes: Vec<u64>,
+    crc32: Option<u32>,
+    // Where the packed data starts in the archive, and its size
+    pack_offset: u64,
+    pack_size: u64,
+}
+
+impl Folder {
+    fn unpack_size(&self) -> u64 {
+        self.unpack_sizes.get(self.main_out).copied().unwrap_or(0)
+    }
+}
+
+/// Where a file's data is in the output of a folder.
+#[derive(Debug, Clone, PartialEq)]
+struct Substream {
+    folder: usize,
+    offset: u64,
+    size: u64,
+    crc32: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct StreamsInfo {
+    folders: Vec<Folder>,
+    streams: Vec<Substream>,
+}
+
+/// A single file or directory of a [`SevenZipArchive`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SevenZipEntry {
+    pub name: String,
+    pub is_directory: bool,
+    pub size: u64,
+    /// Size of the packed data, when the entry is alone in its folder. The
+    /// files of a solid archive share their packed data.
+    pub packed_size: Option<u64>,
+    pub crc32: Option<u32>,
+    pub modified: Option<SystemTime>,
+    /// Windows attributes; with bit 15 set, the upper half is a Unix mode
+    pub attributes: Option<u32>,
+    stream: Option<Substream>,
+}
+
+impl SevenZipEntry {
+    /// Returns the Unix mode, file type included, if the archive recorded
+    /// one.
+    pub fn unix_mode(&self) -> Option<u32> {
+        self.attributes
+            .filter(|attributes| attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0)
+            .map(|attributes| attributes >> 16)
+    }
+
+    /// Describes the entry in the form sinks expect, like
+    /// [`TarHeader::to_entry`](crate::tar::TarHeader::to_entry) does.
+    pub fn to_entry(&self, data: &[u8]) -> io::Result<ZipFileEntry> {
+        let size: u32 = u32::try_from(self.size).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is too large to extract", self.name),
+            )
+        })?;
+        let mut filename: String = self.name.clone();
+        if self.is_directory && !filename.ends_with('/') {
+            filename.push('/');
+        }
+        let mode: u32 = self.unix_mode().unwrap_or(match self.is_directory {
+            true => S_IFDIR | 0o755,
+            false => S_IFREG | 0o644,
+        });
+        Ok(ZipFileEntry {
+            filename,
+            crc32: self.crc32.unwrap_or_else(|| crc32fast::hash(data)),
+            compressed_size: self
+                .packed_size
+                .map_or(size, |packed| u32::try_from(packed).unwrap_or(u32::MAX)),
+            uncompressed_size: size,
+            modified: self.modified,
+            // MS-DOS attributes in the low byte, the mode in the upper half
+            external_attributes: mode << 16 | self.attributes.unwrap_or(0) & 0xFF,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads a 7z archive from any seekable reader.
+pub struct SevenZipArchive<R: Read + Seek> {
+    reader: R,
+    folders: Vec<Folder>,
+    entries: Vec<SevenZipEntry>,
+    // Output of the folder decoded last, which the following files of a
+    // solid archive are read from
+    decoded: Option<(usize, Vec<u8>)>,
+    read_only: bool,
+    options: ExtractOptions,
+}
+
+impl<R: Read + Seek> SevenZipArchive<R> {
+    /// Reads the signature header and the header it points to.
+    pub fn new(mut reader: R) -> io::Result<SevenZipArchive<R>> {
+        // Signature header:
+        // [6 bytes] Signature
+        // [2 bytes] Format version
+        // [4 bytes] CRC-32 of the next 20 bytes
+        // [8 bytes] Offset of the header, from the end of this one
+        // [8 bytes] Size of the header
+        // [4 bytes] CRC-32 of the header
+        let mut start: [u8; 32] = [0u8; 32];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut start)?;
+        if &start[..6] != SIGNATURE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a 7z archive",
+            ));
+        }
+        if crc32fast::hash(&start[12..]) != u32::from_le_bytes(start[8..12].try_into().unwrap()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "7z signature header CRC mismatch",
+            ));
+        }
+        let mut fields: HeaderReader = HeaderReader { data: &start[12..] };
+        let header_offset: u64 = fields.u64()?;
+        let header_size: u64 = fields.u64()?;
+        let header_crc: u32 = fields.u32()?;
+
+        let mut archive: SevenZipArchive<R> = SevenZipArchive {
+            reader,
+            folders: vec![],
+            entries: vec![],
+            decoded: None,
+            read_only: false,
+            options: ExtractOptions::default(),
+        };
+        // An archive without files may have no header at all
+        if header_size == 0 {
+            return Ok(archive);
+        }
+        if header_size > MAX_HEADER_SIZE {
+            return Err(bad_header("too large"));
+        }
+        let position: u64 = SIGNATURE_HEADER_SIZE
+            .checked_add(header_offset)
+            .ok_or_else(|| bad_header("offset out of range"))?;
+        archive.reader.seek(SeekFrom::Start(position))?;
+        let mut header: Vec<u8> = vec![];
+        (&mut archive.reader)
+            .take(header_size)
+            .read_to_end(&mut header)?;
+        if (header.len() as u64) < header_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "7z archive ends in the middle of the header",
+            ));
+        }
+        if crc32fast::hash(&header) != header_crc {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "7z header CRC mismatch",
+            ));
+        }
+
+        for _ in 0..MAX_HEADER_NESTING {
+            let mut r: HeaderReader = HeaderReader { data: &header };
+            match r.byte()? {
+                ID_HEADER => {
+                    let (folders, entries) = read_header(&mut r)?;
+                    archive.folders = folders;
+                    archive.entries = entries;
+                    return Ok(archive);
+                }
+                ID_ENCODED_HEADER => {
+                    let info: StreamsInfo = read_streams_info(&mut r)?;
+                    let folder: &Folder = info
+                        .folders
+                        .first()
+                        .ok_or_else(|| bad_header("encoded header without a folder"))?;
+                    if folder.unpack_size() > MAX_HEADER_SIZE {
+                        return Err(bad_header("too large"));
+                    }
+                    header = decode_folder(
+                        &mut archive.reader,
+                        folder,
+                        "the 7z header",
+                        EntryLimits::default(),
+                    )?;
+                }
+                id => return Err(bad_header(&format!("unexpected property {:#04x}", id))),
+            }
+        }
+        Err(bad_header("too deeply encoded"))
+    }
+
+    /// Opens an archive that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn new_readonly(reader: R) -> io::Result<SevenZipArchive<R>> {
+        Ok(SevenZipArchive {
+            read_only: true,
+            ..SevenZipArchive::new(reader)?
+        })
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Files and directories in archive order.
+    pub fn entries(&self) -> &[SevenZipEntry] {
+        &self.entries
+    }
+
+    /// Decodes and CRC-checks the data of `entry`. In a solid archive this
+    /// decodes the whole folder the entry is in, which is kept for reading
+    /// the next entries of the same folder.
+    pub fn read(&mut self, entry: &SevenZipEntry) -> io::Result<Vec<u8>> {
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let limits: EntryLimits =
+            self.options
+                .entry_limits(total_deadline, &ZipFileEntry::default(), 0);
+        self.read_limited(entry, limits, 0)
+    }
+
+    /// Decodes every entry and hands it to `sink`.
+    pub fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        let mut written: u64 = 0;
+        for entry in self.entries.clone() {
+            let limits: EntryLimits =
+                self.options
+                    .entry_limits(total_deadline, &ZipFileEntry::default(), written);
+            let data: Vec<u8> = self.read_limited(&entry, limits, written)?;
+            written += data.len() as u64;
+            sink.write_entry(&entry.to_entry(&data)?, &data)?;
+        }
+        Ok(())
+    }
+
+    fn read_limited(
+        &mut self,
+        entry: &SevenZipEntry,
+        limits: EntryLimits,
+        written: u64,
+    ) -> io::Result<Vec<u8>> {
+        // Sizes are known up front, so the limits are checked before
+        // decoding anything
+        limits.check_time(&entry.name)?;
+        limits.check_size(&entry.name, entry.size)?;
+        let Some(stream) = &entry.stream else {
+            return Ok(vec![]);
+        };
+        if self.decoded.as_ref().map(|(folder, _)| *folder) != Some(stream.folder) {
+            let folder: &Folder = self
+                .folders
+                .get(stream.folder)
+                .ok_or_else(|| bad_header("folder out of range"))?;
+            // The whole folder is decoded, so the total size limit applies
+            // to all of it
+            let total: EntryLimits =
+                self.options
+                    .entry_limits(None, &ZipFileEntry::default(), written);
+            total.check_size(&entry.name, folder.unpack_size())?;
+            self.options
+                .check_ratio(&entry.name, folder.pack_size, folder.unpack_size())?;
+            self.decoded = None;
+            let data: Vec<u8> =
+                decode_folder(&mut self.reader, folder, &entry.name, limits.clone())?;
+            self.decoded = Some((stream.folder, data));
+        }
+        let (_, decoded) = self.decoded.as_ref().unwrap();
+        let data: &[u8] = usize::try_from(stream.offset)
+            .ok()
+            .zip(usize::try_from(stream.size).ok())
+            .and_then(|(offset, size)| decoded.get(offset..offset.checked_add(size)?))
+            .ok_or_else(|| bad_header("file data outside its folder"))?;
+        if let Some(crc32) = stream.crc32 {
+            if crc32fast::hash(data) != crc32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("CRC mismatch for {}", entry.name),
+                ));
+            }
+        }
+        Ok(data.to_vec())
+    }
+}
+
+/// Parses a plain header, after its property ID.
+fn read_header(r: &mut HeaderReader) -> io::Result<(Vec<Folder>, Vec<SevenZipEntry>)> {
+    let mut id: u8 = r.byte()?;
+    if id == ID_ARCHIVE_PROPERTIES {
+        while r.byte()? != ID_END {
+            let size: u64 = r.number()?;
+            r.bytes(size)?;
+        }
+        id = r.byte()?;
+    }
+    if id == ID_ADDITIONAL_STREAMS_INFO {
+        read_streams_info(r)?;
+        id = r.byte()?;
+    }
+    let mut info: StreamsInfo = StreamsInfo::default();
+    if id == ID_MAIN_STREAMS_INFO {
+        info = read_streams_info(r)?;
+        id = r.byte()?;
+    }
+    let mut entries: Vec<SevenZipEntry> = vec![];
+    if id == ID_FILES_INFO {
+        entries = read_files(r, &info)?;
+        id = r.byte()?;
+    }
+    if id != ID_END {
+        return Err(bad_header(&format!("unexpected property {:#04x}", id)));
+    }
+    Ok((info.folders, entries))
+}
+
+/// Parses the pack, unpack and substreams info, after the property ID
+/// introducing them.
+fn read_streams_info(r: &mut HeaderReader) -> io::Result<StreamsInfo> {
+    let mut pack_position: u64 = 0;
+    let mut pack_sizes: Vec<u64> = vec![];
+    let mut folders: Vec<Folder> = vec![];
+    let mut id: u8 = r.byte()?;
+
+    if id == ID_PACK_INFO {
+        pack_position = r.number()?;
+        let count: usize = r.count()?;
+        loop {
+            match r.byte()? {
+                ID_SIZE => {
+                    pack_sizes = (0..count).map(|_| r.number()).collect::<io::Result<_>>()?;
+                }
+                ID_CRC => {
+                    r.digests(count)?;
+                }
+                ID_END => break,
+                id => return Err(bad_header(&format!("unexpected property {:#04x}", id))),
+            }
+        }
+        id = r.byte()?;
+    }
+
+    if id == ID_UNPACK_INFO {
+        r.expect(ID_FOLDER)?;
+        let count: usize = r.count()?;
+        if r.byte()? != 0 {
+            return Err(bad_header("external folders"));
+        }
+        for _ in 0..count {
+            folders.push(read_folder(r)?);
+        }
+        r.expect(ID_CODERS_UNPACK_SIZE)?;
+        for folder in &mut folders {
+            let outputs: usize = folder.coders.iter().map(|coder| coder.out_streams).sum();
+            for _ in 0..outputs {
+                folder.unpack_sizes.push(r.number()?);
+            }
+        }
+        id = r.byte()?;
+        if id == ID_CRC {
+            for (folder, crc32) in folders.iter_mut().zip(r.digests(count)?) {
+                folder.crc32 = crc32;
+            }
+            id = r.byte()?;
+        }
+        if id != ID_END {
+            return Err(bad_header(&format!("unexpected property {:#04x}", id)));
+        }
+        id = r.byte()?;
+    }
+
+    // Packed streams follow each other, in folder order
+    let mut offset: Option<u64> = SIGNATURE_HEADER_SIZE.checked_add(pack_position);
+    let mut pack_index: usize = 0;
+    for folder in &mut folders {
+        let sizes: &[u64] = pack_sizes
+            .get(pack_index..pack_index + folder.packed_streams)
+            .ok_or_else(|| bad_header("more packed streams used than listed"))?;
+        let size: Option<u64> = sizes
+            .iter()
+            .try_fold(0u64, |total, &size| total.checked_add(size));
+        folder.pack_offset = offset.ok_or_else(|| bad_header("offset out of range"))?;
+        folder.pack_size = size.ok_or_else(|| bad_header("size out of range"))?;
+        offset = offset.and_then(|offset| offset.checked_add(folder.pack_size));
+        pack_index += folder.packed_streams;
+    }
+
+    let streams: Vec<Substream> = if id == ID_SUBSTREAMS_INFO {
+        let streams: Vec<Substream> = read_substreams(r, &folders)?;
+        id = r.byte()?;
+        streams
+    } else {
+        // Without substreams info, each folder holds a single file
+        folders
+            .iter()
+            .enumerate()
+            .map(|(i, folder)| Substream {
+                folder: i,
+                offset: 0,
+                size: folder.unpack_size(),
+                crc32: folder.crc32,
+            })
+            .collect()
+    };
+    if id != ID_END {
+        return Err(bad_header(&format!("unexpected property {:#04x}", id)));
+    }
+    Ok(StreamsInfo { folders, streams })
+}
+
+fn read_folder(r: &mut HeaderReader) -> io::Result<Folder> {
+    // Coder:
+    // [1 byte]   Flags: method ID size in bits 0-3, bit 4 set for more
+    //            than one input or output, bit 5 set when properties follow
+    // [n bytes]  Method ID
+    // [number]   Input and output stream counts, if bit 4 is set
+    // [number]   Properties size and properties, if bit 5 is set
+    let count: usize = r.count()?;
+    let mut coders: Vec<Coder> = vec![];
+    for _ in 0..count {
+        let flags: u8 = r.byte()?;
+        if flags & 0x80 != 0 {
+            return Err(bad_header("alternative coder methods"));
+        }
+        let method: Vec<u8> = r.bytes((flags & 0x0F) as u64)?.to_vec();
+        let (in_streams, out_streams) = match flags & 0x10 {
+            0 => (1, 1),
+            _ => (r.count()?, r.count()?),
+        };
+        let properties: Vec<u8> = match flags & 0x20 {
+            0 => vec![],
+            _ => {
+                let size: u64 = r.number()?;
+                r.bytes(size)?.to_vec()
+            }
+        };
+        coders.push(Coder {
+            method,
+            properties,
+            in_streams,
+            out_streams,
+        });
+    }
+
+    // Bind pairs connect every output but the main one to another coder's
+    // input; inputs left unconnected read packed streams
+    let inputs: usize = coders
+        .iter()
+        .fold(0, |total, coder| total.saturating_add(coder.in_streams));
+    let outputs: usize = coders
+        .iter()
+        .fold(0, |total, coder| total.saturating_add(coder.out_streams));
+    // Every output has its size listed further on
+    if outputs > r.data.len() {
+        return Err(bad_header("more coder outputs than the header holds"));
+    }
+    let bind_pairs: usize = outputs
+        .checked_sub(1)
+        .ok_or_else(|| bad_header("folder without outputs"))?;
+    let mut bound: Vec<bool> = vec![false; outputs];
+    for _ in 0..bind_pairs {
+        let _input: u64 = r.number()?;
+        let output: u64 = r.number()?;
+        if let Some(bound) = usize::try_from(output).ok().and_then(|i| bound.get_mut(i)) {
+            *bound = true;
+        }
+    }
+    let packed_streams: usize = inputs
+        .checked_sub(bind_pairs)
+        .ok_or_else(|| bad_header("more bind pairs than inputs"))?;
+    if packed_streams > 1 {
+        for _ in 0..packed_streams {
+            r.number()?;
+        }
+    }
+    Ok(Folder {
+        coders,
+        packed_streams,
+        main_out: bound.iter().position(|&bound| !bound).unwrap_or(0),
+        ..Default::default()
+    })
+}
+
+/// Splits the output of each folder into the files it holds.
+fn read_substreams(r: &mut HeaderReader, folders: &[Folder]) -> io::Result<Vec<Substream>> {
+    let mut counts: Vec<usize> = vec![1; folders.len()];
+    let mut id: u8 = r.byte()?;
+    if id == ID_NUM_UNPACK_STREAM {
+        for count in &mut counts {
+            *count = r.count()?;
+        }
+        id = r.byte()?;
+    }
+
+    // Sizes of all but the last file of each folder are listed, the last
+    // one gets the rest
+    let mut streams: Vec<Substream> = vec![];
+    for (i, (folder, &count)) in folders.iter().zip(&counts).enumerate() {
+        let total: u64 = folder.unpack_size();
+        let mut offset: u64 = 0;
+        for j in 0..count {
+            let size: u64 = if j + 1 == count {
+                total.saturating_sub(offset)
+            } else if id == ID_SIZE {
+                r.number()?
+            } else {
+                return Err(bad_header("missing file sizes"));
+            };
+            streams.push(Substream {
+                folder: i,
+                offset,
+                size,
+                crc32: None,
+            });
+            offset = offset
+                .checked_add(size)
+                .filter(|&offset| offset <= total)
+                .ok_or_else(|| bad_header("file sizes exceed their folder"))?;
+        }
+    }
+    if id == ID_SIZE {
+        id = r.byte()?;
+    }
+
+    // A folder holding a single file with a known CRC-32 covers that file,
+    // the CRC-32s of all other files are listed
+    let covered = |folder: &Folder, count: usize| count == 1 && folder.crc32.is_some();
+    let listed: usize = folders
+        .iter()
+        .zip(&counts)
+        .filter(|&(folder, &count)| !covered(folder, count))
+        .fold(0, |total, (_, &count)| total.saturating_add(count));
+    let mut digests: Vec<Option<u32>> = vec![];
+    if id == ID_CRC {
+        digests = r.digests(listed)?;
+        id = r.byte()?;
+    }
+    let mut digests = digests.into_iter();
+    for stream in &mut streams {
+        let folder: &Folder = &folders[stream.folder];
+        stream.crc32 = match covered(folder, counts[stream.folder]) {
+            true => folder.crc32,
+            false => digests.next().flatten(),
+        };
+    }
+    if id != ID_END {
+        return Err(bad_header(&format!("unexpected property {:#04x}", id)));
+    }
+    Ok(streams)
+}
+
+/// Parses the files info, matching files that have data with the streams
+/// in order.
+fn read_files(r: &mut HeaderReader, info: &StreamsInfo) -> io::Result<Vec<SevenZipEntry>> {
+    let count: usize = r.count()?;
+    let mut empty_stream: Vec<bool> = vec![false; count];
+    let mut empty_file: Vec<bool> = vec![];
+    let mut anti: Vec<bool> = vec![];
+    let mut names: Vec<String> = vec![];
+    let mut modified: Vec<Option<SystemTime>> = vec![None; count];
+    let mut attributes: Vec<Option<u32>> = vec![None; count];
+
+    // Properties:
+    // [1 byte]  Property ID, 0 after the last one
+    // [number]  Size
+    // [n bytes] Data
+    loop {
+        let id: u8 = r.byte()?;
+        if id == ID_END {
+            break;
+        }
+        let size: u64 = r.number()?;
+        let mut p: HeaderReader = HeaderReader {
+            data: r.bytes(size)?,
+        };
+        let empty_streams: usize = empty_stream.iter().filter(|&&empty| empty).count();
+        match id {
+            ID_EMPTY_STREAM => empty_stream = p.bits(count)?,
+            ID_EMPTY_FILE => empty_file = p.bits(empty_streams)?,
+            ID_ANTI => anti = p.bits(empty_streams)?,
+            ID_NAME => {
+                if p.byte()? != 0 {
+                    return Err(bad_header("external file names"));
+                }
+                // UTF-16LE, each name terminated by a NUL
+                let units: Vec<u16> = p
+                    .data
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect();
+                names = units
+                    .split(|&unit| unit == 0)
+                    .take(count)
+                    .map(String::from_utf16_lossy)
+                    .collect();
+            }
+            ID_MTIME => {
+                let defined: Vec<bool> = p.defined(count)?;
+                if p.byte()? != 0 {
+                    return Err(bad_header("external times"));
+                }
+                for (time, defined) in modified.iter_mut().zip(defined) {
+                    if defined {
+                        *time = filetime(p.u64()?);
+                    }
+                }
+            }
+            ID_WIN_ATTRIBUTES => {
+                let defined: Vec<bool> = p.defined(count)?;
+                if p.byte()? != 0 {
+                    return Err(bad_header("external attributes"));
+                }
+                for (value, defined) in attributes.iter_mut().zip(defined) {
+                    if defined {
+                        *value = Some(p.u32()?);
+                    }
+                }
+            }
+            // Creation and access times, comments, padding
+            _ => {}
+        }
+    }
+
+    let mut files_in_folder: Vec<usize> = vec![0; info.folders.len()];
+    for stream in &info.streams {
+        files_in_folder[stream.folder] += 1;
+    }
+    let mut streams = info.streams.iter();
+    let mut entries: Vec<SevenZipEntry> = vec![];
+    let mut empty_index: usize = 0;
+    for i in 0..count {
+        let name: String = names.get(i).cloned().unwrap_or_default();
+        let mut entry: SevenZipEntry = SevenZipEntry {
+            name,
+            is_directory: attributes[i]
+                .is_some_and(|attributes| attributes & FILE_ATTRIBUTE_DIRECTORY != 0),
+            size: 0,
+            packed_size: None,
+            crc32: None,
+            modified: modified[i],
+            attributes: attributes[i],
+            stream: None,
+        };
+        if empty_stream[i] {
+            // Anti items mark files deleted by an update, there's nothing
+            // to extract
+            if anti.get(empty_index).copied().unwrap_or(false) {
+                eprintln!("Skipping anti item {}", entry.name);
+                empty_index += 1;
+                continue;
+            }
+            if !empty_file.get(empty_index).copied().unwrap_or(false) {
+                entry.is_directory = true;
+            }
+            empty_index += 1;
+        } else {
+            let stream: &Substream = streams
+                .next()
+                .ok_or_else(|| bad_header("more files than streams"))?;
+            entry.size = stream.size;
+            entry.crc32 = stream.crc32;
+            if files_in_folder[stream.folder] == 1 {
+                entry.packed_size = Some(info.folders[stream.folder].pack_size);
+            }
+            entry.stream = Some(stream.clone());
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Converts a FILETIME, 100-nanosecond ticks since 1601, to a time.
+fn filetime(ticks: u64) -> Option<SystemTime> {
+    let since_1601: Duration =
+        Duration::from_secs(ticks / 10_000_000) + Duration::from_nanos(ticks % 10_000_000 * 100);
+    let offset: Duration = Duration::from_secs(FILETIME_UNIX_OFFSET);
+    match since_1601.checked_sub(offset) {
+        Some(since_epoch) => UNIX_EPOCH.checked_add(since_epoch),
+        None => UNIX_EPOCH.checked_sub(offset - since_1601),
+    }
+}
+
+/// Collects decoded data, failing once it grows past the size the header
+/// recorded.
+struct CappedWriter<'a> {
+    data: Vec<u8>,
+    limit: u64,
+    name: &'a str,
+}
+
+impl Write for CappedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() as u64 + buf.len() as u64 > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} decodes to more than its recorded size", self.name),
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the dictionary size to decode `unpack_size` bytes with. The
+/// decoders allocate the whole dictionary up front, and a dictionary larger
+/// than the output is never used, so a crafted size is cut down to that.
+fn dictionary_size(recorded: u32, unpack_size: u64) -> u32 {
+    let needed: u32 = u32::try_from(unpack_size).unwrap_or(u32::MAX);
+    recorded.min(needed.max(lzma_rust2::DICT_SIZE_MIN))
+}
+
+/// Decodes the output of a folder and checks its size and CRC-32. `name`
+/// names what's decoded in errors.
+fn decode_folder<R: Read + Seek>(
+    reader: &mut R,
+    folder: &Folder,
+    name: &str,
+    limits: EntryLimits,
+) -> io::Result<Vec<u8>> {
+    let coder: &Coder = match folder.coders.as_slice() {
+        [coder] if coder.in_streams == 1 && coder.out_streams == 1 => coder,
+        coders if coders.iter().any(|coder| coder.method == METHOD_AES) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is encrypted", name),
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} is packed with a chain of coders, which isn't supported",
+                    name
+                ),
+            ))
+        }
+    };
+    let unpack_size: u64 = folder.unpack_size();
+    reader.seek(SeekFrom::Start(folder.pack_offset))?;
+    // The output is capped by `CappedWriter` instead
+    let limits: EntryLimits = EntryLimits {
+        size_cap: None,
+        ..limits
+    };
+    let mut packed = BufReader::new(LimitReader::new(
+        reader.take(folder.pack_size),
+        limits,
+        name,
+    ));
+    let mut output: CappedWriter = CappedWriter {
+        data: vec![],
+        limit: unpack_size,
+        name,
+    };
+    match coder.method.as_slice() {
+        METHOD_COPY => {
+            io::copy(&mut packed, &mut output)?;
+        }
+        METHOD_LZMA => {
+            // Properties: the lc, lp and pb byte of a raw LZMA stream and
+            // the dictionary size
+            let Some(&[props, a, b, c, d]) = coder.properties.get(..5) else {
+                return Err(bad_header("LZMA properties too short"));
+            };
+            let dict_size: u32 = dictionary_size(u32::from_le_bytes([a, b, c, d]), unpack_size);
+            let mut decoder = lzma_rust2::LzmaReader::new_with_props(
+                packed,
+                unpack_size,
+                props,
+                dict_size,
+                None,
+            )?;
+            io::copy(&mut decoder, &mut output)?;
+        }
+        METHOD_LZMA2 => {
+            // Property: the dictionary size, 2 or 3 times a power of two
+            let bits: u32 = match coder.properties.first() {
+                Some(&bits) if bits <= 40 => bits as u32,
+                _ => return Err(bad_header("bad LZMA2 dictionary size")),
+            };
+            let recorded: u32 = match bits {
+                40 => u32::MAX,
+                _ => (2 | (bits & 1)) << (bits / 2 + 11),
+            };
+            let dict_size: u32 = dictionary_size(recorded, unpack_size);
+            let mut decoder = lzma_rust2::Lzma2Reader::new(packed, dict_size, None);
+            io::copy(&mut decoder, &mut output)?;
+        }
+        METHOD_DEFLATE => {
+            io::copy(&mut DeflateDecoder::new(packed), &mut output)?;
+        }
+        METHOD_AES => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is encrypted", name),
+            ))
+        }
+        method => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} uses unsupported 7z method {:02x?}", name, method),
+            ))
+        }
+    }
+
+    if output.data.len() as u64 != unpack_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} decodes to {} bytes instead of {}",
+                name,
+                output.data.len(),
+                unpack_size
+            ),
+        ));
+    }
+    if let Some(crc32) = folder.crc32 {
+        if crc32fast::hash(&output.data) != crc32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("CRC mismatch for {}", name),
+            ));
+        }
+    }
+    Ok(output.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::test_util::get_test_file_path;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sevenz_lzma2() -> io::Result<()> {
+        let file: File = File::open(get_test_file_path("test_lzma2.7z"))?;
+        let mut archive: SevenZipArchive<File> = SevenZipArchive::new(file)?;
+        let names: Vec<(&str, bool, u64)> = archive
+            .entries()
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.is_directory, entry.size))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("dir", true, 0),
+                ("dir/test1.txt", false, 14),
+                ("test2.txt", false, 14),
+                ("empty.txt", false, 0),
+            ]
+        );
+        let test2: SevenZipEntry = archive.entries()[2].clone();
+        assert_eq!(test2.unix_mode(), Some(0o100600));
+        assert_eq!(
+            test2.modified,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(archive.read(&test2)?, b"this is test2\n");
+
+        let mut sink: MemorySink = MemorySink::new();
+        archive.extract_to(&mut sink)?;
+        let files = sink.into_entries();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files["dir/test1.txt"], b"this is test1\n");
+        assert_eq!(files["empty.txt"], b"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sevenz_solid_lzma() -> io::Result<()> {
+        let data: Vec<u8> = std::fs::read(get_test_file_path("test_solid_lzma.7z"))?;
+        let mut archive: SevenZipArchive<Cursor<Vec<u8>>> =
+            SevenZipArchive::new(Cursor::new(data.clone()))?;
+        let entries: Vec<SevenZipEntry> = archive.entries().to_vec();
+        assert_eq!(entries.len(), 2);
+        // Both files share a folder, so neither has a packed size of its own
+        assert!(entries.iter().all(|entry| entry.packed_size.is_none()));
+        assert_eq!(archive.read(&entries[1])?, b"compressible ".repeat(100));
+        assert_eq!(archive.read(&entries[0])?, b"this is test1\n");
+
+        // The packed data starts right after the signature header
+        let mut corrupt: Vec<u8> = data.clone();
+        corrupt[40] ^= 0xFF;
+        let mut archive: SevenZipArchive<Cursor<Vec<u8>>> =
+            SevenZipArchive::new(Cursor::new(corrupt))?;
+        assert!(archive.extract_to(&mut MemorySink::new()).is_err());
+
+        let mut corrupt: Vec<u8> = data;
+        corrupt[12] ^= 0xFF;
+        let err: io::Error = SevenZipArchive::new(Cursor::new(corrupt)).err().unwrap();
+        assert_eq!(err.to_string(), "7z signature header CRC mismatch");
+        Ok(())
+    }
+}
@@ -0,0 +1,1469 @@
+//! Destinations for decoded archive entries.
+
+#[cfg(feature = "fs")]
+use crate::appledouble;
+use crate::error::{Limit, XpackError};
+#[cfg(feature = "fs")]
+use crate::vfs::{Filesystem, NodeInfo, NodeKind, OsFilesystem};
+use crate::zip::ZipFileEntry;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::ffi::OsString;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Component, Path, PathBuf};
+
+/// Receives the decoded contents of archive entries.
+pub trait Sink {
+    /// Returns true if this sink never writes to the filesystem, temporary
+    /// files included. Read-only archives only accept such sinks.
+    fn is_read_only(&self) -> bool;
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()>;
+
+    /// Receives an entry that is neither a regular file nor a directory.
+    /// Sinks that can't represent it skip it with a warning.
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        self.skip_entry(entry, special.kind())
+    }
+
+    /// Learns of an entry left out of the extraction and why, e.g. an
+    /// encrypted entry or a symbolic link. Sinks that keep no record of it
+    /// print a warning.
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        eprintln!("Skipping {} ({})", entry.filename, reason);
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        (**self).write_entry(entry, data)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        (**self).write_special(entry, special)
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        (**self).skip_entry(entry, reason)
+    }
+}
+
+/// A link or special file, as found in tar archives.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecialFile {
+    /// Another name for an entry earlier in the archive
+    HardLink {
+        target: String,
+    },
+    CharDevice {
+        major: u32,
+        minor: u32,
+        mode: u32,
+    },
+    BlockDevice {
+        major: u32,
+        minor: u32,
+        mode: u32,
+    },
+    Fifo {
+        mode: u32,
+    },
+}
+
+impl SpecialFile {
+    /// Names the kind of file, e.g. `"hard link"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SpecialFile::HardLink { .. } => "hard link",
+            SpecialFile::CharDevice { .. } => "character device",
+            SpecialFile::BlockDevice { .. } => "block device",
+            SpecialFile::Fifo { .. } => "FIFO",
+        }
+    }
+}
+
+/// What [`DiskSink`] does with hard links, device nodes and FIFOs.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SpecialFilePolicy {
+    /// Leave them out with a warning
+    #[default]
+    Skip,
+    /// Create them. Hard links must point to an entry that was extracted
+    /// before them, and device nodes usually require root.
+    Create,
+}
+
+/// Discards every entry. Used to list and test archives.
+pub struct NullSink;
+
+impl Sink for NullSink {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, _entry: &ZipFileEntry, _data: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects entries in memory, keyed by name, without touching the
+/// filesystem. Directory entries are skipped and the first entry with a
+/// given name wins, like [`ZipArchive::by_name`](crate::zip::ZipArchive::by_name).
+#[derive(Default)]
+pub struct MemorySink {
+    entries: HashMap<String, Vec<u8>>,
+    limit: Option<u64>,
+    total: u64,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+
+    /// Caps the total size of the collected data at `limit` bytes; writing
+    /// an entry past the cap fails.
+    pub fn with_limit(mut self, limit: Option<u64>) -> MemorySink {
+        self.limit = limit;
+        self
+    }
+
+    pub fn into_entries(self) -> HashMap<String, Vec<u8>> {
+        self.entries
+    }
+}
+
+impl Sink for MemorySink {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        if entry.filename.ends_with('/') || self.entries.contains_key(&entry.filename) {
+            return Ok(());
+        }
+        let total: u64 = self.total + data.len() as u64;
+        if let Some(limit) = self.limit.filter(|&limit| total > limit) {
+            return Err(XpackError::LimitExceeded {
+                entry: entry.filename.clone(),
+                limit: Limit::TotalSize(limit),
+            }
+            .into());
+        }
+        self.total = total;
+        self.entries.insert(entry.filename.clone(), data.to_vec());
+        Ok(())
+    }
+}
+
+/// Hands entries on to another sink under new names and permissions, the
+/// way [`DiskSink`] names the files it writes, for sinks that write
+/// archives. Entries with nothing left of their name are left out.
+pub struct RenameSink<S> {
+    inner: S,
+    strip_components: usize,
+    transform: Option<Box<Transform>>,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+}
+
+impl<S: Sink> RenameSink<S> {
+    pub fn new(inner: S) -> RenameSink<S> {
+        RenameSink {
+            inner,
+            strip_components: 0,
+            transform: None,
+            file_mode: None,
+            dir_mode: None,
+        }
+    }
+
+    /// Removes the first `count` components from entry names, see
+    /// [`DiskSink::with_strip_components`].
+    pub fn with_strip_components(mut self, count: usize) -> RenameSink<S> {
+        self.strip_components = count;
+        self
+    }
+
+    /// Renames entries with `transform` after leading components are
+    /// stripped, see [`DiskSink::with_transform`].
+    pub fn with_transform<F>(mut self, transform: F) -> RenameSink<S>
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Records these permissions for every file instead of its own.
+    pub fn with_file_mode(mut self, mode: Option<u32>) -> RenameSink<S> {
+        self.file_mode = mode;
+        self
+    }
+
+    /// Records these permissions for every directory instead of its own.
+    pub fn with_dir_mode(mut self, mode: Option<u32>) -> RenameSink<S> {
+        self.dir_mode = mode;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Returns the name `filename` is passed on under, or `None` if
+    /// nothing is left of it.
+    pub fn target_name(&self, filename: &str) -> Option<String> {
+        rename(filename, self.strip_components, self.transform.as_deref())
+    }
+
+    fn rename_entry(&self, entry: &ZipFileEntry) -> Option<ZipFileEntry> {
+        let mut renamed: ZipFileEntry = entry.clone();
+        renamed.filename = self.target_name(&entry.filename)?;
+        let mode: Option<u32> = if entry.is_symlink() {
+            None
+        } else if entry.is_dir() {
+            self.dir_mode
+        } else {
+            self.file_mode
+        };
+        // Replaces the permission bits of the Unix mode, keeping its type
+        if let Some(mode) = mode {
+            renamed.external_attributes =
+                (entry.external_attributes & !(0o7777 << 16)) | ((mode & 0o7777) << 16);
+        }
+        Some(renamed)
+    }
+}
+
+impl<S: Sink> Sink for RenameSink<S> {
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        match self.rename_entry(entry) {
+            Some(renamed) => self.inner.write_entry(&renamed, data),
+            None => Ok(()),
+        }
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        let Some(renamed) = self.rename_entry(entry) else {
+            return Ok(());
+        };
+        match special {
+            SpecialFile::HardLink { target } => match self.target_name(target) {
+                Some(target) => self
+                    .inner
+                    .write_special(&renamed, &SpecialFile::HardLink { target }),
+                None => self
+                    .inner
+                    .skip_entry(&renamed, &format!("links to {}, which is left out", target)),
+            },
+            _ => self.inner.write_special(&renamed, special),
+        }
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        self.inner.skip_entry(entry, reason)
+    }
+}
+
+/// Strips the first `strip_components` components from `filename` and
+/// applies `transform`, returning `None` if nothing is left of the name.
+fn rename(
+    filename: &str,
+    strip_components: usize,
+    transform: Option<&Transform>,
+) -> Option<String> {
+    let name: String = strip(filename, strip_components)?;
+    match transform {
+        Some(transform) => transform(&name).filter(|name| !name.is_empty()),
+        None => Some(name),
+    }
+}
+
+fn strip(filename: &str, count: usize) -> Option<String> {
+    if count == 0 {
+        return Some(filename.to_string());
+    }
+    let mut parts = filename
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".");
+    for _ in 0..count {
+        parts.next()?;
+    }
+    let mut name: String = parts.collect::<Vec<&str>>().join("/");
+    if name.is_empty() {
+        return None;
+    }
+    if filename.ends_with('/') {
+        name.push('/');
+    }
+    Some(name)
+}
+
+/// What [`DiskSink`] does when the file an entry maps to already exists.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverwritePolicy {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Keep the existing file and drop the entry
+    Skip,
+    /// Write the entry next to the existing file as `name (1).ext`, etc.
+    Rename,
+    /// Replace the existing file only if the entry was modified after it
+    Update,
+}
+
+/// Records what a [`DiskSink`] would do with each entry without writing
+/// anything, for previewing an extraction.
+#[cfg(feature = "fs")]
+pub struct DryRunSink {
+    disk: DiskSink,
+    actions: Vec<(String, Action)>,
+}
+
+#[cfg(feature = "fs")]
+impl DryRunSink {
+    pub fn new(disk: DiskSink) -> DryRunSink {
+        DryRunSink {
+            disk,
+            actions: vec![],
+        }
+    }
+
+    /// The sink whose actions are planned.
+    pub fn disk(&self) -> &DiskSink {
+        &self.disk
+    }
+
+    /// Entry names with the action planned for each, in archive order.
+    pub fn actions(&self) -> &[(String, Action)] {
+        &self.actions
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Sink for DryRunSink {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, _data: &[u8]) -> io::Result<()> {
+        let action: Action = self.disk.plan(entry)?;
+        self.actions.push((entry.filename.clone(), action));
+        Ok(())
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        if self.disk.special_files == SpecialFilePolicy::Skip {
+            return self.disk.write_special(entry, special);
+        }
+        self.write_entry(entry, &[])
+    }
+}
+
+/// Writes entries below an output directory, on disk or in another
+/// [`Filesystem`].
+///
+/// By default the output directory and the parent directories of entries
+/// are created as needed. Extracted files get the modification time
+/// recorded in the archive.
+///
+/// On Unix, files and the directories of directory entries get the
+/// permissions recorded in the archive without the setuid, setgid and
+/// sticky bits, filtered through the process umask like tar does.
+/// Directories keep owner read, write and search permission so their
+/// entries can be written. Entries without recorded permissions, and
+/// parent directories created on the way, get the defaults of
+/// `File::create` and `mkdir -p`: 0o666 and 0o777 filtered through the
+/// umask. [`DiskSink::with_file_mode`] and [`DiskSink::with_dir_mode`] set
+/// a mode for every file or directory instead, used as given.
+#[cfg(feature = "fs")]
+pub struct DiskSink {
+    path_to_unpack: PathBuf,
+    create_dirs: bool,
+    overwrite: OverwritePolicy,
+    preserve_ownership: bool,
+    special_files: SpecialFilePolicy,
+    strip_components: usize,
+    transform: Option<Box<Transform>>,
+    conflict_handler: Option<Box<ConflictHandler>>,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    umask: u32,
+    filesystem: Box<dyn Filesystem>,
+    keep_going: bool,
+    atomic: bool,
+    fsync: bool,
+    sparse: bool,
+    xattrs: bool,
+    // Extended attributes of AppleDouble companions whose file isn't
+    // written yet, by the path of the file
+    pending_xattrs: HashMap<PathBuf, Vec<(String, Vec<u8>)>>,
+    outcomes: Vec<(String, Outcome)>,
+}
+
+/// What became of an entry handed to a [`DiskSink`], see
+/// [`DiskSink::outcomes`].
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Handled as planned by [`DiskSink::plan`], which leaves it out with
+    /// [`Action::Skip`] and [`Action::Omit`]
+    Done(Action),
+    /// Left out for the reason given, e.g. a FIFO with
+    /// [`SpecialFilePolicy::Skip`]
+    Skipped(String),
+    /// Writing it failed with the error given
+    Failed(String),
+}
+
+#[cfg(feature = "fs")]
+impl Outcome {
+    /// Why the entry was left out, if it was.
+    pub fn skip_reason(&self) -> Option<&str> {
+        match self {
+            Outcome::Done(Action::Skip(_)) => Some("the file already exists"),
+            Outcome::Done(Action::Omit) => Some("nothing is left of its name"),
+            Outcome::Skipped(reason) => Some(reason),
+            Outcome::Done(_) | Outcome::Failed(_) => None,
+        }
+    }
+}
+
+/// Maps an entry name to the name to extract it under, `None` leaving the
+/// entry out. See [`DiskSink::with_transform`].
+pub type Transform = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// Decides what to do with an entry whose file exists, given the path of
+/// the file. See [`DiskSink::with_conflict_handler`].
+#[cfg(feature = "fs")]
+pub type ConflictHandler =
+    dyn Fn(&ZipFileEntry, &Path) -> io::Result<OverwritePolicy> + Send + Sync;
+
+#[cfg(feature = "fs")]
+impl DiskSink {
+    pub fn new<P: AsRef<Path>>(path_to_unpack: P) -> DiskSink {
+        DiskSink {
+            path_to_unpack: path_to_unpack.as_ref().to_path_buf(),
+            create_dirs: true,
+            overwrite: OverwritePolicy::default(),
+            preserve_ownership: false,
+            special_files: SpecialFilePolicy::default(),
+            strip_components: 0,
+            transform: None,
+            conflict_handler: None,
+            file_mode: None,
+            dir_mode: None,
+            umask: current_umask(),
+            filesystem: Box::new(OsFilesystem),
+            keep_going: false,
+            atomic: false,
+            fsync: false,
+            sparse: false,
+            xattrs: false,
+            pending_xattrs: HashMap::new(),
+            outcomes: vec![],
+        }
+    }
+
+    /// Controls whether an entry that fails to be written, e.g. for lack
+    /// of permission, ends the extraction. When disabled, the default, the
+    /// error is returned; when enabled it's only recorded in
+    /// [`DiskSink::outcomes`] and the following entries are written.
+    pub fn with_keep_going(mut self, keep_going: bool) -> DiskSink {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Entry names with what became of each, in the order received.
+    pub fn outcomes(&self) -> &[(String, Outcome)] {
+        &self.outcomes
+    }
+
+    /// Sets what happens to files that already exist, overwriting them by
+    /// default.
+    pub fn with_overwrite(mut self, overwrite: OverwritePolicy) -> DiskSink {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Controls whether extracted files and directories are given the owner
+    /// recorded in the entry's Unix extra field. Changing the owner usually
+    /// requires root; failing to is an error. Ignored outside Unix.
+    pub fn with_preserve_ownership(mut self, preserve_ownership: bool) -> DiskSink {
+        self.preserve_ownership = preserve_ownership;
+        self
+    }
+
+    /// Sets what happens to hard links, device nodes and FIFOs, skipping
+    /// them by default.
+    pub fn with_special_files(mut self, special_files: SpecialFilePolicy) -> DiskSink {
+        self.special_files = special_files;
+        self
+    }
+
+    /// Removes the first `count` components from entry names, like tar's
+    /// `--strip-components`, so an archive whose entries are all in one
+    /// top directory can be extracted without it. Entries with nothing
+    /// left of their name are omitted.
+    pub fn with_strip_components(mut self, count: usize) -> DiskSink {
+        self.strip_components = count;
+        self
+    }
+
+    /// Renames entries with `transform` before writing them, after leading
+    /// components are stripped. The new name is sanitized like any other;
+    /// returning `None` or an empty name leaves the entry out. Directory
+    /// names passed to `transform` end with `/`.
+    pub fn with_transform<F>(mut self, transform: F) -> DiskSink
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Asks `handler` what to do with each entry whose file exists, instead
+    /// of applying the overwrite policy, e.g. to prompt the user. The
+    /// handler is called by [`DiskSink::plan`], so once per conflicting
+    /// entry when extracting; remembering an answer for the following
+    /// entries is up to it.
+    pub fn with_conflict_handler<F>(mut self, handler: F) -> DiskSink
+    where
+        F: Fn(&ZipFileEntry, &Path) -> io::Result<OverwritePolicy> + Send + Sync + 'static,
+    {
+        self.conflict_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Gives every extracted file the permissions `mode`, whatever the
+    /// archive records. Ignored outside Unix.
+    pub fn with_file_mode(mut self, mode: Option<u32>) -> DiskSink {
+        self.file_mode = mode;
+        self
+    }
+
+    /// Gives the directories of directory entries the permissions `mode`,
+    /// whatever the archive records. Ignored outside Unix.
+    pub fn with_dir_mode(mut self, mode: Option<u32>) -> DiskSink {
+        self.dir_mode = mode;
+        self
+    }
+
+    /// Extracts to `filesystem` instead of the disk, e.g. a
+    /// [`MemoryFilesystem`](crate::vfs::MemoryFilesystem). Paths are still
+    /// below the output directory.
+    pub fn with_filesystem<F: Filesystem + 'static>(mut self, filesystem: F) -> DiskSink {
+        self.filesystem = Box::new(filesystem);
+        self
+    }
+
+    /// Controls whether missing directories are created. When disabled, a
+    /// missing output directory or entry parent directory is an error.
+    pub fn with_create_dirs(mut self, create_dirs: bool) -> DiskSink {
+        self.create_dirs = create_dirs;
+        self
+    }
+
+    /// Controls whether files are written to a temporary file next to
+    /// their path, named `.<name>.xpack-tmp`, and renamed into place once
+    /// complete. An interrupted extraction then leaves no half-written file
+    /// under an entry's name, only temporary files.
+    pub fn with_atomic(mut self, atomic: bool) -> DiskSink {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Controls whether each file is flushed to stable storage once
+    /// written, and with [`DiskSink::with_atomic`] its directory once the
+    /// file is renamed, so it survives a crash of the system.
+    pub fn with_fsync(mut self, fsync: bool) -> DiskSink {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Controls whether blocks of zeros in files, as in disk images, are
+    /// left as holes instead of written, making sparse files where the
+    /// filesystem supports them. See [`Filesystem::create_sparse_file`].
+    pub fn with_sparse(mut self, sparse: bool) -> DiskSink {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Controls whether extended attributes are restored: those of tar
+    /// entries, and for ZIP archives those in the AppleDouble companions
+    /// macOS stores as `__MACOSX/<dir>/._<name>`, which are applied to the
+    /// file they describe instead of being written. Attributes the
+    /// filesystem refuses, e.g. `security.*` ones without privileges, are
+    /// reported and left out.
+    pub fn with_xattrs(mut self, xattrs: bool) -> DiskSink {
+        self.xattrs = xattrs;
+        self
+    }
+
+    /// Maps an entry name to a path below the output directory.
+    ///
+    /// Leading slashes are dropped and names containing `..` are rejected, so
+    /// a crafted archive can't write outside the output directory.
+    pub fn output_path(&self, filename: &str) -> io::Result<PathBuf> {
+        let mut full_path: PathBuf = self.path_to_unpack.clone();
+        for component in Path::new(filename).components() {
+            match component {
+                Component::Normal(part) => full_path.push(part),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Entry path escapes the output directory: {}", filename),
+                    ));
+                }
+            }
+        }
+        Ok(full_path)
+    }
+
+    /// Returns the name `filename` is extracted under once leading
+    /// components are stripped and the transform applied, or `None` if
+    /// nothing is left of it.
+    pub fn target_name(&self, filename: &str) -> Option<String> {
+        rename(filename, self.strip_components, self.transform.as_deref())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.filesystem.create_dir(path, self.create_dirs)
+    }
+
+    fn is_kind(&self, path: &Path, kind: NodeKind) -> io::Result<bool> {
+        Ok(self.filesystem.metadata(path)?.map(|info| info.kind) == Some(kind))
+    }
+
+    /// Returns the permissions a file or directory extracted from `entry`
+    /// gets, or `None` to keep those it was created with.
+    pub fn permissions(&self, entry: &ZipFileEntry, is_dir: bool) -> Option<u32> {
+        let explicit: Option<u32> = if is_dir {
+            self.dir_mode
+        } else {
+            self.file_mode
+        };
+        if explicit.is_some() {
+            return explicit;
+        }
+        let mode: u32 = entry.unix_permissions()? & 0o777 & !self.umask;
+        Some(if is_dir { mode | 0o700 } else { mode })
+    }
+
+    fn restore_permissions(
+        &self,
+        path: &Path,
+        entry: &ZipFileEntry,
+        is_dir: bool,
+    ) -> io::Result<()> {
+        match self.permissions(entry, is_dir) {
+            Some(mode) => self.filesystem.set_permissions(path, mode),
+            None => Ok(()),
+        }
+    }
+
+    fn restore_owner(&self, path: &Path, entry: &ZipFileEntry) -> io::Result<()> {
+        if !self.preserve_ownership {
+            return Ok(());
+        }
+        match entry.unix_owner() {
+            Some((uid, gid)) => self.filesystem.set_owner(path, uid, gid),
+            None => Ok(()),
+        }
+    }
+
+    /// Works out what writing `entry` would do, applying the overwrite
+    /// policy, or the conflict handler's answer, to files that already
+    /// exist. Nothing is written.
+    pub fn plan(&self, entry: &ZipFileEntry) -> io::Result<Action> {
+        let Some(name) = self.target_name(&entry.filename) else {
+            return Ok(Action::Omit);
+        };
+        let path: PathBuf = self.output_path(&name)?;
+        if name.ends_with('/') {
+            return Ok(Action::CreateDir(path));
+        }
+        let Some(existing) = self.filesystem.metadata(&path)? else {
+            return Ok(Action::Create(path));
+        };
+        let policy: OverwritePolicy = match &self.conflict_handler {
+            Some(handler) => handler(entry, &path)?,
+            None => self.overwrite,
+        };
+        Ok(match policy {
+            OverwritePolicy::Overwrite => Action::Overwrite(path),
+            OverwritePolicy::Skip => Action::Skip(path),
+            OverwritePolicy::Rename => Action::Rename {
+                to: unused_path(self.filesystem.as_ref(), &path)?,
+                existing: path,
+            },
+            OverwritePolicy::Update => match (entry.modified, existing.modified) {
+                (Some(entry_time), Some(file_time)) if entry_time > file_time => {
+                    Action::Overwrite(path)
+                }
+                _ => Action::Skip(path),
+            },
+        })
+    }
+}
+
+/// What [`DiskSink`] does with an entry, see [`DiskSink::plan`].
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    CreateDir(PathBuf),
+    Create(PathBuf),
+    /// Replace the existing file
+    Overwrite(PathBuf),
+    /// Leave the existing file alone and write the entry to `to`
+    Rename {
+        existing: PathBuf,
+        to: PathBuf,
+    },
+    /// Leave the existing file alone and drop the entry
+    Skip(PathBuf),
+    /// Drop the entry, nothing being left of its name once leading
+    /// components are stripped or the transform applied
+    Omit,
+}
+
+#[cfg(feature = "fs")]
+impl Action {
+    /// Returns true if the entry collides with an existing file.
+    pub fn is_conflict(&self) -> bool {
+        matches!(
+            self,
+            Action::Overwrite(_) | Action::Rename { .. } | Action::Skip(_)
+        )
+    }
+}
+
+/// Returns the first of `name (1).ext`, `name (2).ext`, ... that doesn't
+/// exist next to `path`.
+#[cfg(feature = "fs")]
+fn unused_path(filesystem: &dyn Filesystem, path: &Path) -> io::Result<PathBuf> {
+    let stem: String = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension: String = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    for n in 1.. {
+        let candidate: PathBuf = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+        if filesystem.metadata(&candidate)?.is_none() {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(feature = "fs")]
+impl DiskSink {
+    fn extract_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<Outcome> {
+        let output: Option<NodeInfo> = self.filesystem.metadata(&self.path_to_unpack)?;
+        if output.is_none() {
+            if !self.create_dirs {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} doesn't exist", self.path_to_unpack.display()),
+                ));
+            }
+            self.create_dir(&self.path_to_unpack)?;
+        }
+
+        if self.xattrs {
+            if let Some(target) = appledouble::companion_target(&entry.filename) {
+                return self.apply_companion(&target, data);
+            }
+        }
+
+        let action: Action = self.plan(entry)?;
+        let full_path: PathBuf = match &action {
+            Action::CreateDir(path) => {
+                // Directories that exist already are left as they are
+                let xattrs: Vec<(String, Vec<u8>)> = self.take_xattrs(entry, path);
+                if !self.is_kind(path, NodeKind::Dir)? {
+                    self.create_dir(path)?;
+                    self.restore_xattrs(path, &xattrs);
+                    self.restore_permissions(path, entry, true)?;
+                } else {
+                    self.restore_xattrs(path, &xattrs);
+                }
+                self.restore_owner(path, entry)?;
+                return Ok(Outcome::Done(action));
+            }
+            Action::Skip(_) => {
+                eprintln!("Skipping {}, the file already exists", entry.filename);
+                return Ok(Outcome::Done(action));
+            }
+            Action::Omit => {
+                eprintln!("Skipping {}, nothing is left of its name", entry.filename);
+                return Ok(Outcome::Done(action));
+            }
+            Action::Create(path) | Action::Overwrite(path) | Action::Rename { to: path, .. } => {
+                path.clone()
+            }
+        };
+
+        if let Some(parent) = full_path.parent() {
+            if self.create_dirs && !self.is_kind(parent, NodeKind::Dir)? {
+                self.create_dir(parent)?;
+            }
+        }
+
+        let xattrs: Vec<(String, Vec<u8>)> = self.take_xattrs(entry, &full_path);
+        if self.atomic {
+            self.write_file_atomically(&full_path, entry, data, &xattrs)?;
+        } else {
+            self.write_file(&full_path, entry, data, &xattrs)?;
+        }
+
+        Ok(Outcome::Done(action))
+    }
+
+    /// Writes `data` to a file at `path` with the metadata of `entry` and
+    /// the extended attributes `xattrs`.
+    fn write_file(
+        &self,
+        path: &Path,
+        entry: &ZipFileEntry,
+        data: &[u8],
+        xattrs: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        if self.sparse {
+            self.filesystem.create_sparse_file(path, data)?;
+        } else {
+            self.filesystem.create_file(path, data)?;
+        }
+        self.restore_xattrs(path, xattrs);
+        if let Some(modified) = entry.modified {
+            self.filesystem.set_times(path, modified)?;
+        }
+        self.restore_permissions(path, entry, false)?;
+        self.restore_owner(path, entry)?;
+        if self.fsync {
+            self.filesystem.sync(path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the file to a temporary path and renames it to `path` once
+    /// it's complete, removing the temporary file if anything fails.
+    fn write_file_atomically(
+        &self,
+        path: &Path,
+        entry: &ZipFileEntry,
+        data: &[u8],
+        xattrs: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        let mut name: OsString = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(".xpack-tmp");
+        let temporary: PathBuf = path.with_file_name(name);
+        let result: io::Result<()> = self
+            .write_file(&temporary, entry, data, xattrs)
+            .and_then(|_| self.filesystem.rename(&temporary, path));
+        if let Err(e) = result {
+            let _ = self.filesystem.remove_file(&temporary);
+            return Err(e);
+        }
+        match path.parent() {
+            Some(parent) if self.fsync => self.filesystem.sync(parent),
+            _ => Ok(()),
+        }
+    }
+
+    /// The extended attributes to give the file or directory written at
+    /// `path` for `entry`: its own and those of an AppleDouble companion
+    /// seen before it.
+    fn take_xattrs(&mut self, entry: &ZipFileEntry, path: &Path) -> Vec<(String, Vec<u8>)> {
+        if !self.xattrs {
+            return vec![];
+        }
+        let mut xattrs: Vec<(String, Vec<u8>)> = entry.xattrs.clone();
+        xattrs.extend(self.pending_xattrs.remove(path).unwrap_or_default());
+        xattrs
+    }
+
+    /// Sets extended attributes, reporting those that can't be set rather
+    /// than failing the entry, as tar does.
+    fn restore_xattrs(&self, path: &Path, xattrs: &[(String, Vec<u8>)]) {
+        for (name, value) in xattrs {
+            if let Err(e) = self.filesystem.set_xattr(path, name, value) {
+                eprintln!("Can't set {} on {}: {}", name, path.display(), e);
+            }
+        }
+    }
+
+    /// Applies the extended attributes in the AppleDouble file `data` to
+    /// the entry named `target`, now if it's been written or once it is.
+    fn apply_companion(&mut self, target: &str, data: &[u8]) -> io::Result<Outcome> {
+        let xattrs: Vec<(String, Vec<u8>)> = appledouble::parse(data)?;
+        let reason: String = format!("extended attributes of {}", target);
+        let Some(name) = self.target_name(target) else {
+            return Ok(Outcome::Skipped(reason));
+        };
+        let path: PathBuf = self.output_path(&name)?;
+        if self.filesystem.metadata(&path)?.is_some() {
+            self.restore_xattrs(&path, &xattrs);
+        } else {
+            self.pending_xattrs.entry(path).or_default().extend(xattrs);
+        }
+        eprintln!("Restoring {}", reason);
+        Ok(Outcome::Skipped(reason))
+    }
+
+    fn extract_special(
+        &mut self,
+        entry: &ZipFileEntry,
+        special: &SpecialFile,
+    ) -> io::Result<Outcome> {
+        if self.special_files == SpecialFilePolicy::Skip {
+            eprintln!("Skipping {} ({})", entry.filename, special.kind());
+            return Ok(Outcome::Skipped(special.kind().to_string()));
+        }
+        let action: Action = self.plan(entry)?;
+        let full_path: PathBuf = match &action {
+            Action::Skip(_) | Action::CreateDir(_) => {
+                eprintln!("Skipping {}, the file already exists", entry.filename);
+                return Ok(Outcome::Skipped("the file already exists".to_string()));
+            }
+            Action::Omit => {
+                eprintln!("Skipping {}, nothing is left of its name", entry.filename);
+                return Ok(Outcome::Done(action));
+            }
+            Action::Overwrite(path) => {
+                // Links and nodes can't be created over an existing file
+                self.filesystem.remove_file(path)?;
+                path.clone()
+            }
+            Action::Create(path) | Action::Rename { to: path, .. } => path.clone(),
+        };
+        if let Some(parent) = full_path.parent() {
+            if self.create_dirs && !self.is_kind(parent, NodeKind::Dir)? {
+                self.create_dir(parent)?;
+            }
+        }
+
+        match special {
+            SpecialFile::HardLink { target } => {
+                let target_path: Option<PathBuf> = match self.target_name(target) {
+                    Some(name) => Some(self.output_path(&name)?),
+                    None => None,
+                };
+                let target_path: Option<PathBuf> = match target_path {
+                    Some(path) if self.is_kind(&path, NodeKind::File)? => Some(path),
+                    _ => None,
+                };
+                let Some(target_path) = target_path else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "{} links to {}, which wasn't extracted",
+                            entry.filename, target
+                        ),
+                    ));
+                };
+                self.filesystem.hard_link(&target_path, &full_path)?;
+                // The link shares the target's metadata, already restored
+                eprintln!(
+                    "Linked {} to {}",
+                    full_path.display(),
+                    target_path.display()
+                );
+                return Ok(Outcome::Done(action));
+            }
+            SpecialFile::CharDevice { .. }
+            | SpecialFile::BlockDevice { .. }
+            | SpecialFile::Fifo { .. } => self.filesystem.make_node(&full_path, special)?,
+        }
+        // Setting the modification time would mean opening the node, which
+        // opens the device or blocks on the FIFO, so it's left alone
+        self.restore_owner(&full_path, entry)?;
+        Ok(Outcome::Done(action))
+    }
+
+    /// Records the outcome of an entry, returning the error it failed with
+    /// unless [`DiskSink::with_keep_going`] is set.
+    fn record(&mut self, entry: &ZipFileEntry, outcome: io::Result<Outcome>) -> io::Result<()> {
+        let (outcome, result) = match outcome {
+            Ok(outcome) => (outcome, Ok(())),
+            Err(e) if self.keep_going => {
+                eprintln!("Failed to extract {}: {}", entry.filename, e);
+                (Outcome::Failed(e.to_string()), Ok(()))
+            }
+            Err(e) => (Outcome::Failed(e.to_string()), Err(e)),
+        };
+        self.outcomes.push((entry.filename.clone(), outcome));
+        result
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Sink for DiskSink {
+    fn is_read_only(&self) -> bool {
+        self.filesystem.is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        let outcome: io::Result<Outcome> = self.extract_entry(entry, data);
+        self.record(entry, outcome)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        let outcome: io::Result<Outcome> = self.extract_special(entry, special);
+        self.record(entry, outcome)
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        eprintln!("Skipping {} ({})", entry.filename, reason);
+        self.record(entry, Ok(Outcome::Skipped(reason.to_string())))
+    }
+}
+
+/// Reads the process umask. There's no call to read it without setting it,
+/// so it's set and set back right away.
+#[cfg(all(feature = "fs", unix))]
+fn current_umask() -> u32 {
+    // SAFETY: umask can't fail and only affects files created meanwhile
+    unsafe {
+        let umask: libc::mode_t = libc::umask(0o022);
+        libc::umask(umask);
+        umask as u32
+    }
+}
+
+#[cfg(all(feature = "fs", not(unix)))]
+fn current_umask() -> u32 {
+    0
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(filename: &str) -> ZipFileEntry {
+        ZipFileEntry {
+            filename: filename.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_output_path_sanitization() -> io::Result<()> {
+        let sink = DiskSink::new("out");
+        assert_eq!(sink.output_path("a/b.txt")?, Path::new("out/a/b.txt"));
+        assert_eq!(
+            sink.output_path("/etc/passwd")?,
+            Path::new("out/etc/passwd")
+        );
+        assert!(sink.output_path("../escape.txt").is_err());
+        assert!(sink.output_path("a/../../escape.txt").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_creates_missing_directories() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_mkdir");
+        let _ = fs::remove_dir_all(&out_dir);
+        let mut sink = DiskSink::new(out_dir.join("nested"));
+        sink.write_entry(&entry("dir/"), b"")?;
+        sink.write_entry(&entry("a/b/c.txt"), b"hello")?;
+
+        assert!(out_dir.join("nested/dir").is_dir());
+        assert_eq!(fs::read(out_dir.join("nested/a/b/c.txt"))?, b"hello");
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_overwrite_policies() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_overwrite");
+        let _ = fs::remove_dir_all(&out_dir);
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        let dated = |secs: u64| ZipFileEntry {
+            modified: Some(at(secs)),
+            ..entry("a.txt")
+        };
+        DiskSink::new(&out_dir).write_entry(&dated(1000), b"original")?;
+        let read = |name: &str| fs::read_to_string(out_dir.join(name));
+
+        let sink = |policy| DiskSink::new(&out_dir).with_overwrite(policy);
+        sink(OverwritePolicy::Skip).write_entry(&dated(2000), b"skipped")?;
+        assert_eq!(read("a.txt")?, "original");
+        sink(OverwritePolicy::Rename).write_entry(&dated(2000), b"renamed")?;
+        sink(OverwritePolicy::Rename).write_entry(&dated(2000), b"renamed again")?;
+        assert_eq!(read("a (1).txt")?, "renamed");
+        assert_eq!(read("a (2).txt")?, "renamed again");
+        sink(OverwritePolicy::Update).write_entry(&dated(500), b"older")?;
+        assert_eq!(read("a.txt")?, "original");
+        sink(OverwritePolicy::Update).write_entry(&dated(3000), b"newer")?;
+        assert_eq!(read("a.txt")?, "newer");
+        assert_eq!(fs::metadata(out_dir.join("a.txt"))?.modified()?, at(3000));
+        sink(OverwritePolicy::Overwrite).write_entry(&dated(100), b"replaced")?;
+        assert_eq!(read("a.txt")?, "replaced");
+
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_ownership() -> io::Result<()> {
+        use crate::extra::ExtraField;
+        use std::os::unix::fs::MetadataExt;
+
+        let out_dir = std::env::temp_dir().join("xpack_test_ownership");
+        let _ = fs::remove_dir_all(&out_dir);
+        fs::create_dir_all(&out_dir)?;
+        // Only root can give files away, anyone can keep their own
+        let own: fs::Metadata = fs::metadata(&out_dir)?;
+        let (uid, gid) = match own.uid() {
+            0 => (1234, 5678),
+            uid => (uid, own.gid()),
+        };
+        let owned = ZipFileEntry {
+            extra_fields: vec![ExtraField::Unix { uid, gid }],
+            ..entry("owned.txt")
+        };
+        DiskSink::new(&out_dir)
+            .with_preserve_ownership(true)
+            .write_entry(&owned, b"data")?;
+        let metadata: fs::Metadata = fs::metadata(out_dir.join("owned.txt"))?;
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_permissions() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let out_dir = std::env::temp_dir().join("xpack_test_permissions");
+        let _ = fs::remove_dir_all(&out_dir);
+        let with_mode = |name: &str, mode: u32| ZipFileEntry {
+            external_attributes: mode << 16,
+            ..entry(name)
+        };
+        let mode = |path: &str| -> io::Result<u32> {
+            Ok(fs::metadata(out_dir.join(path))?.permissions().mode() & 0o7777)
+        };
+        let mut sink = DiskSink::new(&out_dir);
+        let umask: u32 = sink.umask;
+        sink.write_entry(&with_mode("script.sh", 0o104775), b"#!/bin/sh")?;
+        sink.write_entry(&with_mode("locked/", 0o040555), b"")?;
+        sink.write_entry(&with_mode("locked/file", 0o100444), b"data")?;
+        assert_eq!(mode("script.sh")?, 0o775 & !umask);
+        assert_eq!(mode("locked")?, 0o555 & !umask | 0o700);
+        assert_eq!(mode("locked/file")?, 0o444 & !umask);
+
+        let mut sink = DiskSink::new(&out_dir)
+            .with_overwrite(OverwritePolicy::Overwrite)
+            .with_file_mode(Some(0o640))
+            .with_dir_mode(Some(0o750));
+        assert_eq!(sink.permissions(&entry("plain"), false), Some(0o640));
+        sink.write_entry(&with_mode("script.sh", 0o100777), b"#!/bin/sh")?;
+        sink.write_entry(&entry("new/"), b"")?;
+        assert_eq!(mode("script.sh")?, 0o640);
+        assert_eq!(mode("new")?, 0o750);
+        assert_eq!(
+            DiskSink::new(&out_dir).permissions(&entry("plain"), false),
+            None
+        );
+
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_dry_run");
+        let _ = fs::remove_dir_all(&out_dir);
+        fs::create_dir_all(&out_dir)?;
+        fs::write(out_dir.join("exists.txt"), b"keep")?;
+
+        let disk = DiskSink::new(&out_dir).with_overwrite(OverwritePolicy::Rename);
+        let mut sink = DryRunSink::new(disk);
+        sink.write_entry(&entry("new/a.txt"), b"data")?;
+        sink.write_entry(&entry("exists.txt"), b"data")?;
+        let actions: Vec<Action> = sink.actions().iter().map(|(_, a)| a.clone()).collect();
+        assert_eq!(
+            actions,
+            [
+                Action::Create(out_dir.join("new/a.txt")),
+                Action::Rename {
+                    existing: out_dir.join("exists.txt"),
+                    to: out_dir.join("exists (1).txt"),
+                },
+            ]
+        );
+        assert!(!out_dir.join("new").exists());
+        assert_eq!(fs::read(out_dir.join("exists.txt"))?, b"keep");
+
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_components() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_strip");
+        let _ = fs::remove_dir_all(&out_dir);
+        let mut sink = DiskSink::new(&out_dir).with_strip_components(1);
+        assert_eq!(
+            sink.target_name("./repo-main//src/"),
+            Some("src/".to_string())
+        );
+        assert_eq!(sink.plan(&entry("repo-main/"))?, Action::Omit);
+        sink.write_entry(&entry("repo-main/"), b"")?;
+        sink.write_entry(&entry("repo-main/src/lib.rs"), b"code")?;
+        sink.write_entry(&entry("top.txt"), b"omitted")?;
+
+        assert_eq!(fs::read(out_dir.join("src/lib.rs"))?, b"code");
+        assert!(!out_dir.join("repo-main").exists());
+        assert!(!out_dir.join("top.txt").exists());
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_transform");
+        let _ = fs::remove_dir_all(&out_dir);
+        // Flattens the tree and leaves out everything but text files
+        let mut sink = DiskSink::new(&out_dir)
+            .with_strip_components(1)
+            .with_transform(|name| match name.rsplit_once('/') {
+                _ if !name.ends_with(".txt") => None,
+                Some((_, base)) => Some(base.to_string()),
+                None => Some(name.to_string()),
+            });
+        sink.write_entry(&entry("top/a/"), b"")?;
+        sink.write_entry(&entry("top/a/b/deep.txt"), b"deep")?;
+        sink.write_entry(&entry("top/a/image.png"), b"png")?;
+        assert_eq!(sink.plan(&entry("top/a/image.png"))?, Action::Omit);
+
+        let names: Vec<_> = fs::read_dir(&out_dir)?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["deep.txt"]);
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_sink() -> io::Result<()> {
+        let mut sink = RenameSink::new(MemorySink::new())
+            .with_strip_components(1)
+            .with_transform(|name| Some(name.replace("docs/", "manual/")))
+            .with_file_mode(Some(0o600));
+        sink.write_entry(&entry("top/"), b"")?;
+        sink.write_entry(&entry("top/docs/a.txt"), b"a")?;
+        sink.write_entry(&entry("omitted.txt"), b"omitted")?;
+
+        // The permissions change, the file type stays
+        let mut script: ZipFileEntry = entry("top/run.sh");
+        script.external_attributes = 0o100755 << 16;
+        let renamed: Option<ZipFileEntry> = sink.rename_entry(&script);
+        assert_eq!(
+            renamed.as_ref().map(|e| e.filename.as_str()),
+            Some("run.sh")
+        );
+        assert_eq!(renamed.map(|e| e.external_attributes >> 16), Some(0o100600));
+
+        let entries: HashMap<String, Vec<u8>> = sink.into_inner().into_entries();
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            [("manual/a.txt".to_string(), b"a".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_writes() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_atomic_writes");
+        let _ = fs::remove_dir_all(&out_dir);
+        DiskSink::new(&out_dir).write_entry(&entry("a.txt"), b"original")?;
+        let sink = || {
+            DiskSink::new(&out_dir)
+                .with_overwrite(OverwritePolicy::Overwrite)
+                .with_atomic(true)
+                .with_fsync(true)
+        };
+
+        // A failed write leaves the old file whole
+        fs::create_dir(out_dir.join(".a.txt.xpack-tmp"))?;
+        assert!(sink().write_entry(&entry("a.txt"), b"new").is_err());
+        assert_eq!(fs::read(out_dir.join("a.txt"))?, b"original");
+        fs::remove_dir(out_dir.join(".a.txt.xpack-tmp"))?;
+
+        sink().write_entry(&entry("a.txt"), b"new")?;
+        assert_eq!(fs::read(out_dir.join("a.txt"))?, b"new");
+        let names: Vec<_> = fs::read_dir(&out_dir)?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.txt"]);
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_xattrs() -> io::Result<()> {
+        use crate::vfs::MemoryFilesystem;
+
+        let filesystem: MemoryFilesystem = MemoryFilesystem::new();
+        let mut sink = DiskSink::new("out")
+            .with_filesystem(filesystem.clone())
+            .with_xattrs(true);
+        let quarantine = || -> Vec<(String, Vec<u8>)> {
+            vec![(
+                String::from("com.apple.quarantine"),
+                b"0081;Safari;".to_vec(),
+            )]
+        };
+        // Companions before and after their file, and tar attributes
+        let companion: Vec<u8> = appledouble::encode(&quarantine());
+        sink.write_entry(&entry("__MACOSX/docs/._a.txt"), &companion)?;
+        sink.write_entry(&entry("docs/"), b"")?;
+        sink.write_entry(&entry("docs/a.txt"), b"a")?;
+        sink.write_entry(&entry("b.txt"), b"b")?;
+        sink.write_entry(&entry("__MACOSX/._b.txt"), &companion)?;
+        let labeled: ZipFileEntry = ZipFileEntry {
+            xattrs: quarantine(),
+            ..entry("c.txt")
+        };
+        sink.write_entry(&labeled, b"c")?;
+
+        assert_eq!(
+            filesystem.paths(),
+            [
+                "out",
+                "out/b.txt",
+                "out/c.txt",
+                "out/docs",
+                "out/docs/a.txt"
+            ]
+            .map(PathBuf::from)
+        );
+        for path in ["out/b.txt", "out/c.txt", "out/docs/a.txt"] {
+            let xattrs: Vec<(String, Vec<u8>)> =
+                filesystem.get(path).unwrap().xattrs.into_iter().collect();
+            assert_eq!(xattrs, quarantine(), "{}", path);
+        }
+        assert_eq!(
+            sink.outcomes()[0].1.skip_reason(),
+            Some("extended attributes of docs/a.txt")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_handler() -> io::Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let out_dir = std::env::temp_dir().join("xpack_test_conflict_handler");
+        let _ = fs::remove_dir_all(&out_dir);
+        DiskSink::new(&out_dir).write_entry(&entry("a.txt"), b"original")?;
+
+        // Answers rename, then skip, and isn't asked about new files
+        let asked: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let counter: Arc<AtomicUsize> = Arc::clone(&asked);
+        let mut sink = DiskSink::new(&out_dir).with_conflict_handler(move |_, path| {
+            assert!(path.ends_with("a.txt"));
+            Ok(match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => OverwritePolicy::Rename,
+                _ => OverwritePolicy::Skip,
+            })
+        });
+        sink.write_entry(&entry("a.txt"), b"renamed")?;
+        sink.write_entry(&entry("a.txt"), b"skipped")?;
+        sink.write_entry(&entry("b.txt"), b"new")?;
+
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+        assert_eq!(fs::read(out_dir.join("a.txt"))?, b"original");
+        assert_eq!(fs::read(out_dir.join("a (1).txt"))?, b"renamed");
+        assert_eq!(fs::read(out_dir.join("b.txt"))?, b"new");
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_outcomes() -> io::Result<()> {
+        let out_dir = std::env::temp_dir().join("xpack_test_outcomes");
+        let _ = fs::remove_dir_all(&out_dir);
+        let mut sink = DiskSink::new(&out_dir)
+            .with_overwrite(OverwritePolicy::Skip)
+            .with_keep_going(true);
+        sink.write_entry(&entry("a.txt"), b"data")?;
+        sink.write_entry(&entry("a.txt"), b"again")?;
+        // A file can't be the parent of another, which fails only this entry
+        sink.write_entry(&entry("a.txt/c.txt"), b"data")?;
+        let fifo: SpecialFile = SpecialFile::Fifo { mode: 0o644 };
+        sink.write_special(&entry("pipe"), &fifo)?;
+        sink.skip_entry(&entry("secret.txt"), "encrypted")?;
+        sink.write_entry(&entry("b.txt"), b"data")?;
+
+        let outcomes: &[(String, Outcome)] = sink.outcomes();
+        assert_eq!(
+            outcomes[0].1,
+            Outcome::Done(Action::Create(out_dir.join("a.txt")))
+        );
+        assert_eq!(outcomes[1].1.skip_reason(), Some("the file already exists"));
+        assert!(matches!(outcomes[2].1, Outcome::Failed(_)));
+        assert_eq!(outcomes[3].1, Outcome::Skipped("FIFO".to_string()));
+        assert_eq!(outcomes[4].1, Outcome::Skipped("encrypted".to_string()));
+        assert_eq!(outcomes[5].0, "b.txt");
+        assert_eq!(fs::read(out_dir.join("b.txt"))?, b"data");
+
+        // Without keep going, the failure is returned and still recorded
+        let mut sink = DiskSink::new(&out_dir);
+        assert!(sink.write_entry(&entry("a.txt/c.txt"), b"data").is_err());
+        assert!(matches!(sink.outcomes()[0].1, Outcome::Failed(_)));
+        fs::remove_dir_all(&out_dir)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_sink_limit() -> io::Result<()> {
+        let mut sink = MemorySink::new().with_limit(Some(8));
+        sink.write_entry(&entry("dir/"), b"")?;
+        sink.write_entry(&entry("a.txt"), b"hello")?;
+        sink.write_entry(&entry("a.txt"), b"ignored")?;
+        assert!(sink.write_entry(&entry("b.txt"), b"world").is_err());
+
+        let entries: HashMap<String, Vec<u8>> = sink.into_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries["a.txt"], b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_mkdir_requires_existing_output() {
+        let out_dir = std::env::temp_dir().join("xpack_test_no_mkdir");
+        let mut sink = DiskSink::new(&out_dir).with_create_dirs(false);
+        let err = sink.write_entry(&entry("a.txt"), b"hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!out_dir.exists());
+    }
+}
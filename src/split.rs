@@ -0,0 +1,152 @@
+//! Reading of split (multi-disk) ZIP archives.
+//!
+//! `zip -s` writes an archive as `name.z01`, `name.z02`, ... and a final
+//! `name.zip` holding the end of central directory record. Offsets in the
+//! archive are relative to the start of the part (disk) they point into, so
+//! besides stitching the parts together the reader remembers where each
+//! part starts.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Presents the parts of a split archive as one continuous file.
+pub struct SplitReader {
+    parts: Vec<File>,
+    // Offset of each part in the combined file, plus the total length
+    starts: Vec<u64>,
+    position: u64,
+}
+
+impl SplitReader {
+    /// Opens the split archive whose last part is at `path`, e.g.
+    /// `archive.zip`, collecting `archive.z01`, `archive.z02`, ... from the
+    /// same directory.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SplitReader> {
+        let path: &Path = path.as_ref();
+        let mut paths: Vec<PathBuf> = vec![];
+        loop {
+            let part: PathBuf = path.with_extension(format!("z{:02}", paths.len() + 1));
+            if !part.exists() {
+                break;
+            }
+            paths.push(part);
+        }
+        paths.push(path.to_path_buf());
+
+        let mut parts: Vec<File> = vec![];
+        for part in &paths {
+            parts.push(File::open(part)?);
+        }
+        SplitReader::new(parts)
+    }
+
+    /// Combines already opened parts, first part first.
+    pub fn new(parts: Vec<File>) -> io::Result<SplitReader> {
+        let mut starts: Vec<u64> = vec![0];
+        for part in &parts {
+            let end: u64 = starts[starts.len() - 1] + part.metadata()?.len();
+            starts.push(end);
+        }
+        Ok(SplitReader {
+            parts,
+            starts,
+            position: 0,
+        })
+    }
+
+    /// Offset in the combined file at which each disk starts.
+    pub fn disk_starts(&self) -> &[u64] {
+        &self.starts[..self.parts.len()]
+    }
+
+    fn len(&self) -> u64 {
+        self.starts[self.parts.len()]
+    }
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        // Last part starting at or before the position
+        let index: usize = self.starts.partition_point(|&start| start <= self.position) - 1;
+        let offset: u64 = self.position - self.starts[index];
+        let available: u64 = self.starts[index + 1] - self.position;
+        let wanted: usize = min_len(buf.len(), available);
+
+        let part: &mut File = &mut self.parts[index];
+        part.seek(SeekFrom::Start(offset))?;
+        let read: usize = part.read(&mut buf[..wanted])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SplitReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target: Option<u64> = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        match target {
+            Some(target) => {
+                self.position = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative position",
+            )),
+        }
+    }
+}
+
+fn min_len(len: usize, available: u64) -> usize {
+    usize::try_from(available).map_or(len, |available| len.min(available))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::XpackError;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+
+    #[test]
+    fn test_reads_across_parts() -> io::Result<()> {
+        let mut reader = SplitReader::open(get_test_file_path("test_split.zip"))?;
+        assert_eq!(reader.disk_starts(), [0, 65536]);
+
+        let mut combined: Vec<u8> = vec![];
+        reader.read_to_end(&mut combined)?;
+        let mut expected: Vec<u8> = std::fs::read(get_test_file_path("test_split.z01"))?;
+        expected.extend(std::fs::read(get_test_file_path("test_split.zip"))?);
+        assert_eq!(combined, expected);
+
+        reader.seek(SeekFrom::Start(65530))?;
+        let mut straddling: [u8; 12] = [0u8; 12];
+        reader.read_exact(&mut straddling)?;
+        assert_eq!(straddling[..], expected[65530..65542]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_archive() -> io::Result<()> {
+        let path = get_test_file_path("test_split.zip");
+        let err = ZipArchive::open(path.to_str().unwrap()).err().unwrap();
+        assert!(matches!(
+            XpackError::from_io(&err),
+            Some(XpackError::SplitArchive { disks: 2 })
+        ));
+
+        let archive = ZipArchive::open_split(path.to_str().unwrap())?;
+        let data: Vec<u8> = archive.by_name("random.bin")?.read_to_vec()?.unwrap();
+        assert_eq!(data.len(), 70000);
+        let notes: Vec<u8> = archive.by_name("notes.txt")?.read_to_vec()?.unwrap();
+        assert_eq!(notes, "split archive notes\n".repeat(50).as_bytes());
+        archive.test()
+    }
+}
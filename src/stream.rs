@@ -0,0 +1,411 @@
+//! Forward-only reading of ZIP archives from non-seekable sources.
+//!
+//! [`ZipArchive`](crate::zip::ZipArchive) starts at the central directory at
+//! the end of the archive, which a pipe can't seek to. This reader instead
+//! walks the local file headers in the order they appear and stops at the
+//! first central directory record.
+
+use crate::encoding::decode_filename;
+use crate::error::XpackError;
+use crate::extra::{parse_extra_fields, ExtraField};
+use crate::sink::Sink;
+use crate::zip::{
+    decode_entry_data, dos_datetime, inflate, read_data_descriptor, verify_crc, CompressionMethod,
+    DataDescriptor, Deadline, Encryption, EntryLimits, ExtractOptions, SkipLog, ZipFileEntry,
+    DATA_DESCRIPTOR_SIGNATURE, FLAG_DATA_DESCRIPTOR, LOCAL_FILE_HEADER_SIGNATURE,
+};
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Reads a ZIP archive from a stream such as stdin or a network socket.
+///
+/// Entries whose sizes are only known from a trailing data descriptor
+/// (general purpose bit 3) are supported when they are deflated, since the
+/// deflate stream marks its own end, or stored with a signed descriptor, which
+/// is located by scanning and confirmed by its sizes and CRC-32.
+pub struct ZipStreamReader<R: Read> {
+    reader: BufReader<R>,
+    // Number of bytes consumed from the stream, i.e. the archive offset
+    position: u64,
+    read_only: bool,
+    options: ExtractOptions,
+    total_deadline: Option<Deadline>,
+    // Bytes decompressed so far, for the total size limit
+    written: u64,
+    done: bool,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    pub fn new(reader: R) -> ZipStreamReader<R> {
+        ZipStreamReader {
+            reader: BufReader::with_capacity(64 * 1024, reader),
+            position: 0,
+            read_only: false,
+            options: ExtractOptions::default(),
+            total_deadline: None,
+            written: 0,
+            done: false,
+        }
+    }
+
+    /// Creates a reader that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn new_readonly(reader: R) -> ZipStreamReader<R> {
+        ZipStreamReader {
+            read_only: true,
+            ..ZipStreamReader::new(reader)
+        }
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Number of bytes of the archive consumed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads the next entry and its decoded data, or `None` once the central
+    /// directory (or the end of the stream) is reached. The data is `None`
+    /// for entries that can't be decoded, see
+    /// [`ZipFileEntry::unsupported_reason`].
+    pub fn next_entry(&mut self) -> io::Result<Option<(ZipFileEntry, Option<Vec<u8>>)>> {
+        if self.done {
+            return Ok(None);
+        }
+        if self.total_deadline.is_none() {
+            self.total_deadline = self
+                .options
+                .timeout
+                .map(|limit| Deadline::after(limit, false));
+        }
+
+        // Local File Header:
+        // [4 bytes]  Signature
+        // [2 bytes]  Version needed
+        // [2 bytes]  General purpose bit flag
+        // [2 bytes]  Compression method
+        // [2 bytes]  Last modified time
+        // [2 bytes]  Last modified date
+        // [4 bytes]  CRC-32
+        // [4 bytes]  Compressed size
+        // [4 bytes]  Uncompressed size
+        // [2 bytes]  Filename length
+        // [2 bytes]  Extra field length
+        // [variable] Filename
+        // [variable] Extra field
+        let mut local_header: [u8; 30] = [0u8; 30];
+        if self.reader.read_exact(&mut local_header[0..4]).is_err()
+            || local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes()
+        {
+            self.done = true;
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut local_header[4..30])?;
+
+        let flags: u16 = u16::from_le_bytes(local_header[6..8].try_into().unwrap());
+        let name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
+        let extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+        let mut filename_buf: Vec<u8> = vec![0u8; name_length as usize];
+        self.reader.read_exact(&mut filename_buf)?;
+        let mut extra_buf: Vec<u8> = vec![0u8; extra_length as usize];
+        self.reader.read_exact(&mut extra_buf)?;
+
+        let mut entry: ZipFileEntry = ZipFileEntry {
+            filename: decode_filename(&filename_buf, flags, &extra_buf).into_owned(),
+            version_needed: u16::from_le_bytes(local_header[4..6].try_into().unwrap()),
+            flags,
+            compression_method: u16::from_le_bytes(local_header[8..10].try_into().unwrap()),
+            crc32: u32::from_le_bytes(local_header[14..18].try_into().unwrap()),
+            compressed_size: u32::from_le_bytes(local_header[18..22].try_into().unwrap()),
+            uncompressed_size: u32::from_le_bytes(local_header[22..26].try_into().unwrap()),
+            file_offset: self.position,
+            modified: dos_datetime(
+                u16::from_le_bytes(local_header[12..14].try_into().unwrap()),
+                u16::from_le_bytes(local_header[10..12].try_into().unwrap()),
+            ),
+            extra_fields: parse_extra_fields(&extra_buf),
+            // Only recorded in the central directory
+            version_made_by: 0,
+            external_attributes: 0,
+            internal_attributes: 0,
+            comment: String::new(),
+            xattrs: vec![],
+        };
+        self.position += 30 + name_length as u64 + extra_length as u64;
+
+        let limits: EntryLimits =
+            self.options
+                .entry_limits(self.total_deadline, &entry, self.written);
+        limits.check_time(&entry.filename)?;
+
+        if flags & FLAG_DATA_DESCRIPTOR == 0 {
+            // Grow the buffer as data arrives rather than trusting the header
+            // with a large allocation up front
+            let mut compressed_data_buf: Vec<u8> = vec![];
+            (&mut self.reader)
+                .take(entry.sizes().1)
+                .read_to_end(&mut compressed_data_buf)?;
+            if compressed_data_buf.len() != entry.sizes().1 as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Archive ends in the middle of {}", entry.filename),
+                ));
+            }
+            self.position += entry.sizes().1;
+            if self.options.codecs.unsupported_reason(&entry).is_some() {
+                return Ok(Some((entry, None)));
+            }
+            let data: Option<Vec<u8>> = decode_entry_data(
+                &entry,
+                Cow::Owned(compressed_data_buf),
+                limits,
+                &self.options.codecs,
+            )?
+            .map(Cow::into_owned);
+            self.written += data.as_ref().map_or(0, |data| data.len() as u64);
+            return Ok(Some((entry, data)));
+        }
+
+        let reason: Option<String> = self.options.codecs.unsupported_reason(&entry);
+        let data: Vec<u8> = match (entry.method(), reason) {
+            (CompressionMethod::Stored, None) => {
+                self.read_stored_until_descriptor(&mut entry, limits)?
+            }
+            (CompressionMethod::Deflated, None) => {
+                self.inflate_until_descriptor(&mut entry, limits)?
+            }
+            (_, Some(reason)) if entry.encryption() != Encryption::None => {
+                return Err(XpackError::unsupported(&entry, reason).into())
+            }
+            (method, reason) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "Can't find the end of {}, whose size is unknown: {}",
+                        entry.filename,
+                        reason.unwrap_or_else(|| format!(
+                            "compression method {} has no end marker",
+                            method.id()
+                        ))
+                    ),
+                ))
+            }
+        };
+        if data.len() != entry.sizes().0 as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Data descriptor does not match entry data",
+            ));
+        }
+        verify_crc(&entry, &data)?;
+        self.written += data.len() as u64;
+        Ok(Some((entry, Some(data))))
+    }
+
+    /// Decodes every remaining entry and hands it to `sink`. Entries that
+    /// can't be decoded are handled as [`ExtractOptions::unsupported`]
+    /// says.
+    pub fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        let mut skipped: SkipLog = SkipLog::new(self.options.unsupported);
+        while let Some((entry, data)) = self.next_entry()? {
+            match data {
+                Some(data) => sink.write_entry(&entry, &data)?,
+                None => {
+                    let reason: String = self
+                        .options
+                        .codecs
+                        .unsupported_reason(&entry)
+                        .unwrap_or_default();
+                    skipped.skip(sink, &entry, reason)?;
+                }
+            }
+        }
+        skipped.finish()
+    }
+
+    fn inflate_until_descriptor(
+        &mut self,
+        entry: &mut ZipFileEntry,
+        limits: EntryLimits,
+    ) -> io::Result<Vec<u8>> {
+        // The bufread decoder only consumes the bytes of the deflate stream,
+        // leaving the reader positioned at the data descriptor
+        let mut decoder = flate2::bufread::DeflateDecoder::new(&mut self.reader);
+        let data: Vec<u8> = inflate(&mut decoder, entry, limits)?;
+        let compressed_size: u64 = decoder.total_in();
+        self.position += compressed_size;
+        self.options
+            .check_ratio(&entry.filename, compressed_size, data.len() as u64)?;
+
+        // The descriptor is 12 to 24 bytes long depending on its signature
+        // and on whether it has Zip64 sizes
+        let mut counted: CountingReader<&mut BufReader<R>> = CountingReader {
+            inner: &mut self.reader,
+            count: 0,
+        };
+        let descriptor: DataDescriptor = read_data_descriptor(&mut counted, is_zip64(entry))?;
+        self.position += counted.count;
+        if descriptor.compressed_size != compressed_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Data descriptor does not match entry data",
+            ));
+        }
+        apply_descriptor(entry, &descriptor);
+        Ok(data)
+    }
+
+    fn read_stored_until_descriptor(
+        &mut self,
+        entry: &mut ZipFileEntry,
+        limits: EntryLimits,
+    ) -> io::Result<Vec<u8>> {
+        // Stored data has no end marker, so look for a signed descriptor whose
+        // sizes match the bytes read so far and whose CRC matches the data
+        let signature: [u8; 4] = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes();
+        let zip64: bool = is_zip64(entry);
+        let descriptor_len: usize = if zip64 { 24 } else { 16 };
+        let mut data: Vec<u8> = vec![];
+        loop {
+            let available: &[u8] = self.reader.fill_buf()?;
+            if available.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("No data descriptor found for {}", entry.filename),
+                ));
+            }
+            let byte: u8 = available[0];
+            self.reader.consume(1);
+            data.push(byte);
+
+            let len: usize = data.len();
+            // Allow for the descriptor bytes read along with the data
+            limits.check_size(&entry.filename, len.saturating_sub(descriptor_len) as u64)?;
+            if len < descriptor_len || data[len - descriptor_len..][..4] != signature {
+                continue;
+            }
+            let candidate: usize = len - descriptor_len;
+            let fields: &[u8] = &data[candidate + 4..];
+            let descriptor: DataDescriptor = read_data_descriptor(&mut &fields[..], zip64)?;
+            if descriptor.compressed_size == candidate as u64
+                && descriptor.uncompressed_size == candidate as u64
+                && descriptor.crc32 == crc32fast::hash(&data[..candidate])
+            {
+                data.truncate(candidate);
+                self.position += len as u64;
+                apply_descriptor(entry, &descriptor);
+                return Ok(data);
+            }
+        }
+    }
+}
+
+/// Whether the local header of `entry` has a Zip64 extra field, which gives
+/// its data descriptor 8-byte sizes.
+fn is_zip64(entry: &ZipFileEntry) -> bool {
+    (entry.extra_fields.iter()).any(|field| matches!(field, ExtraField::Zip64 { .. }))
+}
+
+/// Fills in the CRC-32 and sizes of `entry` from its data descriptor. Zip64
+/// sizes go in the Zip64 extra field, behind saturated header fields, where
+/// [`ZipFileEntry::sizes`] finds them.
+fn apply_descriptor(entry: &mut ZipFileEntry, descriptor: &DataDescriptor) {
+    entry.crc32 = descriptor.crc32;
+    for field in &mut entry.extra_fields {
+        if let ExtraField::Zip64 { values, .. } = field {
+            *values = vec![descriptor.uncompressed_size, descriptor.compressed_size];
+            entry.compressed_size = u32::MAX;
+            entry.uncompressed_size = u32::MAX;
+            return;
+        }
+    }
+    entry.compressed_size = descriptor.compressed_size as u32;
+    entry.uncompressed_size = descriptor.uncompressed_size as u32;
+}
+
+struct CountingReader<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read: usize = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_test_file_path;
+    use crate::zip::ZipArchive;
+    use std::fs::{self, File};
+
+    /// Reads every entry of a fixture through a reader that can't seek.
+    fn stream_entries(filename: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let bytes: Vec<u8> = fs::read(get_test_file_path(filename))?;
+        let mut reader = ZipStreamReader::new(&bytes[..]);
+        let mut entries: Vec<(String, Vec<u8>)> = vec![];
+        while let Some((entry, data)) = reader.next_entry()? {
+            entries.push((entry.filename, data.unwrap()));
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_stream_matches_central_directory() -> io::Result<()> {
+        for filename in [
+            "test_multiple.zip",
+            "test_descriptor_nosig.zip",
+            "test_encodings.zip",
+            "test_stored_descriptor.zip",
+            "test_zip64.zip",
+            "test_zip64_local.zip",
+            "test_zip64_offsets.zip",
+            "test_zip64_descriptor.zip",
+        ] {
+            let path = get_test_file_path(filename);
+            let archive = ZipArchive::new(File::open(&path)?)?;
+            let mut expected: Vec<(String, Vec<u8>)> = vec![];
+            for entry in archive.entries() {
+                let entry = entry?;
+                let data = archive.by_name(&entry.filename)?.read_to_vec()?.unwrap();
+                expected.push((entry.filename, data));
+            }
+            assert_eq!(stream_entries(filename)?, expected, "{}", filename);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_size_limit() -> io::Result<()> {
+        let bytes: Vec<u8> = fs::read(get_test_file_path("test_bomb.zip"))?;
+        let mut reader = ZipStreamReader::new(&bytes[..]);
+        reader.set_options(ExtractOptions {
+            max_total_size: Some(1 << 20),
+            ..Default::default()
+        });
+        assert!(reader.next_entry()?.is_some());
+        let err = reader.next_entry().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stored_descriptor_skips_fake_signature() -> io::Result<()> {
+        let entries = stream_entries("test_stored_descriptor.zip")?;
+        assert_eq!(entries[1].0, "b.bin");
+        assert_eq!(entries[1].1, b"PK\x07\x08 not a descriptor\n");
+        Ok(())
+    }
+}
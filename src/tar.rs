@@ -0,0 +1,1066 @@
+//! Reading and writing of tar archives.
+//!
+//! A tar archive is a sequence of 512-byte header blocks, each followed by
+//! the entry data padded to a block boundary, and ends with two zero blocks.
+//! Besides plain ustar headers this reads the GNU long name records (`L`,
+//! `K`) and POSIX pax extended headers (`x`, `g`) that carry names and sizes
+//! which don't fit the fixed-width fields, or extended attributes as
+//! `SCHILY.xattr.<name>` records like GNU tar and bsdtar write them.
+//! [`TarWriter`] writes ustar headers, preceded by a pax extended header
+//! when something doesn't fit or the entry has extended attributes.
+
+use crate::extra::ExtraField;
+use crate::sink::{Sink, SpecialFile};
+use crate::writer::{EntryMetadata, S_IFDIR, S_IFREG};
+use crate::zip::{Deadline, EntryLimits, ExtractOptions, ZipFileEntry};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+const BLOCK_SIZE: u64 = 512;
+
+// Prefix of the pax records carrying extended attributes
+const XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Pax records by key. Values are bytes, as extended attributes needn't be
+/// text.
+type PaxRecords = HashMap<String, Vec<u8>>;
+
+/// The type of a tar entry, from the header's typeflag byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TarEntryKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Other(u8),
+}
+
+impl TarEntryKind {
+    fn from_typeflag(typeflag: u8) -> TarEntryKind {
+        match typeflag {
+            // '7' is a contiguous file, which readers treat as a regular one
+            b'0' | b'\0' | b'7' => TarEntryKind::File,
+            b'1' => TarEntryKind::HardLink,
+            b'2' => TarEntryKind::Symlink,
+            b'3' => TarEntryKind::CharDevice,
+            b'4' => TarEntryKind::BlockDevice,
+            b'5' => TarEntryKind::Directory,
+            b'6' => TarEntryKind::Fifo,
+            other => TarEntryKind::Other(other),
+        }
+    }
+
+    fn typeflag(self) -> u8 {
+        match self {
+            TarEntryKind::File => b'0',
+            TarEntryKind::HardLink => b'1',
+            TarEntryKind::Symlink => b'2',
+            TarEntryKind::CharDevice => b'3',
+            TarEntryKind::BlockDevice => b'4',
+            TarEntryKind::Directory => b'5',
+            TarEntryKind::Fifo => b'6',
+            TarEntryKind::Other(typeflag) => typeflag,
+        }
+    }
+}
+
+/// The metadata of a single tar entry, with any long name or pax records
+/// already applied.
+#[derive(Debug, Clone)]
+pub struct TarHeader {
+    pub path: String,
+    pub link_name: String,
+    pub kind: TarEntryKind,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub mtime: u64,
+    /// Device numbers of character and block devices
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    /// Offset of the header block in the archive
+    pub offset: u64,
+    /// Extended attributes, names with values, in name order
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl TarHeader {
+    /// Describes the entry in the form sinks expect. Directory names get a
+    /// trailing slash, which is how sinks recognize them, the owner is
+    /// recorded as a Unix extra field and the mode as external attributes.
+    pub fn to_entry(&self, data: &[u8]) -> io::Result<ZipFileEntry> {
+        let size: u32 = u32::try_from(self.size).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is too large to extract", self.path),
+            )
+        })?;
+        let mut filename: String = self.path.clone();
+        let file_type: u32 = if self.kind == TarEntryKind::Directory {
+            S_IFDIR
+        } else {
+            S_IFREG
+        };
+        if self.kind == TarEntryKind::Directory && !filename.ends_with('/') {
+            filename.push('/');
+        }
+        Ok(ZipFileEntry {
+            filename,
+            crc32: crc32fast::hash(data),
+            compressed_size: size,
+            uncompressed_size: size,
+            file_offset: self.offset,
+            modified: UNIX_EPOCH.checked_add(Duration::from_secs(self.mtime)),
+            extra_fields: match (u32::try_from(self.uid), u32::try_from(self.gid)) {
+                (Ok(uid), Ok(gid)) => vec![ExtraField::Unix { uid, gid }],
+                _ => vec![],
+            },
+            external_attributes: (file_type | self.mode & 0o7777) << 16,
+            xattrs: self.xattrs.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Describes a hard link, device or FIFO entry.
+    fn special_file(&self) -> SpecialFile {
+        let (major, minor, mode) = (self.dev_major, self.dev_minor, self.mode);
+        match self.kind {
+            TarEntryKind::HardLink => SpecialFile::HardLink {
+                target: self.link_name.clone(),
+            },
+            TarEntryKind::CharDevice => SpecialFile::CharDevice { major, minor, mode },
+            TarEntryKind::BlockDevice => SpecialFile::BlockDevice { major, minor, mode },
+            _ => SpecialFile::Fifo { mode },
+        }
+    }
+}
+
+/// Returns true if `block` looks like the first header of a tar archive:
+/// either it carries the ustar magic or, for old v7 archives, its checksum
+/// is valid.
+pub fn is_tar_header(block: &[u8]) -> bool {
+    let Ok(block) = <&[u8; 512]>::try_from(block.get(..512).unwrap_or_default()) else {
+        return false;
+    };
+    if &block[257..262] == b"ustar" {
+        return true;
+    }
+    block.iter().any(|&b| b != 0) && verify_checksum(block).is_ok()
+}
+
+/// Reads a tar archive front to back from any reader.
+pub struct TarArchive<R: Read> {
+    reader: R,
+    position: u64,
+    // Unread data of the current entry, followed by its block padding
+    data_left: u64,
+    padding: u64,
+    current: Option<TarHeader>,
+    // Records of pax global headers, which apply to every following entry
+    globals: PaxRecords,
+    read_only: bool,
+    options: ExtractOptions,
+    total_deadline: Option<Deadline>,
+    // Bytes of entry data read so far, for the total size limit
+    written: u64,
+    done: bool,
+}
+
+impl<R: Read> TarArchive<R> {
+    pub fn new(reader: R) -> TarArchive<R> {
+        TarArchive {
+            reader,
+            position: 0,
+            data_left: 0,
+            padding: 0,
+            current: None,
+            globals: HashMap::new(),
+            read_only: false,
+            options: ExtractOptions::default(),
+            total_deadline: None,
+            written: 0,
+            done: false,
+        }
+    }
+
+    /// Creates an archive that only extracts into read-only sinks, see
+    /// [`ZipArchive::open_readonly`](crate::zip::ZipArchive::open_readonly).
+    pub fn new_readonly(reader: R) -> TarArchive<R> {
+        TarArchive {
+            read_only: true,
+            ..TarArchive::new(reader)
+        }
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Advances to the next entry, skipping whatever is left of the data of
+    /// the current one. Returns `None` at the end of the archive.
+    pub fn next_header(&mut self) -> io::Result<Option<TarHeader>> {
+        self.skip(self.data_left.saturating_add(self.padding))?;
+        self.data_left = 0;
+        self.padding = 0;
+        self.current = None;
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut long_name: Option<String> = None;
+        let mut long_link: Option<String> = None;
+        let mut pax: PaxRecords = HashMap::new();
+        loop {
+            // Header block:
+            // [100 bytes] Name
+            // [8 bytes]   Mode (octal)
+            // [8 bytes]   Owner id (octal)
+            // [8 bytes]   Group id (octal)
+            // [12 bytes]  Size (octal, or base-256 in GNU archives)
+            // [12 bytes]  Modification time (octal)
+            // [8 bytes]   Checksum
+            // [1 byte]    Typeflag
+            // [100 bytes] Link name
+            // [6 bytes]   Magic ("ustar\0" for POSIX, "ustar " for GNU)
+            // [2 bytes]   Version
+            // [32 bytes]  Owner name
+            // [32 bytes]  Group name
+            // [8 bytes]   Device major
+            // [8 bytes]   Device minor
+            // [155 bytes] Name prefix (POSIX only)
+            // [12 bytes]  Padding
+            let offset: u64 = self.position;
+            let mut block: [u8; 512] = [0u8; 512];
+            if !self.read_block(&mut block)? || block.iter().all(|&b| b == 0) {
+                // A zero block marks the end; the second one is often missing
+                self.done = true;
+                return Ok(None);
+            }
+            verify_checksum(&block)?;
+
+            let typeflag: u8 = block[156];
+            let size: u64 = match pax_text(&pax, "size")? {
+                Some(size) => parse_pax_number(size)?,
+                None => parse_number(&block[124..136])?,
+            };
+            match typeflag {
+                b'L' => long_name = Some(decode_name(&self.read_record(size)?)),
+                b'K' => long_link = Some(decode_name(&self.read_record(size)?)),
+                b'x' => pax.extend(parse_pax_records(&self.read_record(size)?)?),
+                b'g' => {
+                    let records = parse_pax_records(&self.read_record(size)?)?;
+                    self.globals.extend(records);
+                }
+                _ => {
+                    let mut records: PaxRecords = self.globals.clone();
+                    records.extend(pax);
+                    let header: TarHeader = build_header(
+                        &block,
+                        offset,
+                        size,
+                        long_name.take(),
+                        long_link.take(),
+                        &records,
+                    )?;
+                    self.data_left = size;
+                    self.padding = padding(size);
+                    self.current = Some(header.clone());
+                    return Ok(Some(header));
+                }
+            }
+        }
+    }
+
+    /// Reads the data of the entry last returned by
+    /// [`next_header`](TarArchive::next_header).
+    pub fn read_data(&mut self) -> io::Result<Vec<u8>> {
+        let name: String = self
+            .current
+            .as_ref()
+            .map(|header| header.path.clone())
+            .unwrap_or_default();
+        // Tar data isn't compressed, so the size limits can be checked
+        // before reading anything
+        let limits: EntryLimits =
+            self.options
+                .entry_limits(self.total_deadline, &ZipFileEntry::default(), self.written);
+        limits.check_time(&name)?;
+        limits.check_size(&name, self.data_left)?;
+        // Grow the buffer as data arrives rather than trusting the header
+        // with a large allocation up front
+        let mut data: Vec<u8> = vec![];
+        (&mut self.reader)
+            .take(self.data_left)
+            .read_to_end(&mut data)?;
+        if (data.len() as u64) < self.data_left {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Tar archive ends in the middle of {}", name),
+            ));
+        }
+        self.position += self.data_left;
+        self.written += self.data_left;
+        self.data_left = 0;
+        Ok(data)
+    }
+
+    /// Reads every remaining entry and hands files and directories to
+    /// `sink`, and hard links, devices and FIFOs to
+    /// [`Sink::write_special`]. Symbolic links are skipped, see
+    /// [`Sink::skip_entry`].
+    pub fn extract_to<S: Sink>(&mut self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        self.total_deadline = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        while let Some(header) = self.next_header()? {
+            match header.kind {
+                TarEntryKind::File | TarEntryKind::Directory => {
+                    let data: Vec<u8> = self.read_data()?;
+                    sink.write_entry(&header.to_entry(&data)?, &data)?;
+                }
+                TarEntryKind::HardLink
+                | TarEntryKind::CharDevice
+                | TarEntryKind::BlockDevice
+                | TarEntryKind::Fifo => {
+                    sink.write_special(&header.to_entry(&[])?, &header.special_file())?;
+                }
+                TarEntryKind::Symlink => {
+                    sink.skip_entry(&header.to_entry(&[])?, "symbolic link")?;
+                }
+                TarEntryKind::Other(typeflag) => {
+                    let reason: String = format!("type flag {}", typeflag as char);
+                    sink.skip_entry(&header.to_entry(&[])?, &reason)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a whole block, or returns false if the archive ends first.
+    fn read_block(&mut self, block: &mut [u8; 512]) -> io::Result<bool> {
+        let mut filled: usize = 0;
+        while filled < block.len() {
+            match self.reader.read(&mut block[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.position += filled as u64;
+        match filled {
+            0 => Ok(false),
+            512 => Ok(true),
+            _ => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Tar archive ends in the middle of a header",
+            )),
+        }
+    }
+
+    /// Reads the data of a long name or pax record including its padding.
+    fn read_record(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        let mut data: Vec<u8> = vec![];
+        (&mut self.reader).take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Tar archive ends in the middle of a record",
+            ));
+        }
+        self.position += size;
+        self.skip(padding(size))?;
+        Ok(data)
+    }
+
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped: u64 = io::copy(&mut (&mut self.reader).take(count), &mut io::sink())?;
+        self.position += skipped;
+        if skipped < count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Tar archive ends in the middle of an entry",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Writes a tar archive to any writer.
+pub struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> TarWriter<W> {
+        TarWriter { out }
+    }
+
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        metadata: &EntryMetadata,
+    ) -> io::Result<()> {
+        let header: TarHeader = new_header(name, TarEntryKind::File, data.len() as u64, metadata);
+        self.append(&header, data)
+    }
+
+    /// Adds a directory entry; a trailing `/` is appended to `name` if
+    /// missing.
+    pub fn add_directory(&mut self, name: &str, metadata: &EntryMetadata) -> io::Result<()> {
+        let name: String = if name.ends_with('/') {
+            name.to_string()
+        } else {
+            format!("{}/", name)
+        };
+        let header: TarHeader = new_header(&name, TarEntryKind::Directory, 0, metadata);
+        self.append(&header, &[])
+    }
+
+    /// Writes an entry described by `header`, whose `offset` is ignored,
+    /// followed by `data`. Pax records are added for the name, link name,
+    /// size, owner and time when they don't fit the header fields.
+    pub fn append(&mut self, header: &TarHeader, data: &[u8]) -> io::Result<()> {
+        let (block, records) = encode_header(header);
+        if !records.is_empty() || !header.xattrs.is_empty() {
+            // Named like GNU tar names them, for readers that don't know
+            // pax and extract the records as a file
+            let mut pax: Vec<u8> = vec![];
+            for (key, value) in &records {
+                pax.extend(pax_record(key, value.as_bytes()));
+            }
+            for (name, value) in &header.xattrs {
+                pax.extend(pax_record(&format!("{}{}", XATTR_PREFIX, name), value));
+            }
+            let pax_header: TarHeader = TarHeader {
+                path: String::from("././@PaxHeader"),
+                kind: TarEntryKind::Other(b'x'),
+                size: pax.len() as u64,
+                mode: 0o644,
+                ..header.clone()
+            };
+            self.write_block_and_data(&encode_header(&pax_header).0, &pax)?;
+        }
+        self.write_block_and_data(&block, data)
+    }
+
+    /// Writes the two zero blocks ending the archive and returns the
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_block_and_data(&mut self, block: &[u8; 512], data: &[u8]) -> io::Result<()> {
+        self.out.write_all(block)?;
+        self.out.write_all(data)?;
+        self.out
+            .write_all(&[0u8; BLOCK_SIZE as usize][..padding(data.len() as u64) as usize])
+    }
+}
+
+/// Adds every decoded entry, and hard links, devices and FIFOs, with the
+/// metadata recorded for them.
+impl<W: Write> Sink for TarWriter<W> {
+    // Nothing is written but the archive itself
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        let metadata: EntryMetadata = EntryMetadata::from_entry(entry);
+        let (kind, size) = if entry.filename.ends_with('/') {
+            (TarEntryKind::Directory, 0)
+        } else {
+            (TarEntryKind::File, data.len() as u64)
+        };
+        let mut header: TarHeader = new_header(&entry.filename, kind, size, &metadata);
+        header.xattrs = entry.xattrs.clone();
+        self.append(&header, data)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        let metadata: EntryMetadata = EntryMetadata::from_entry(entry);
+        let mut header: TarHeader = new_header(&entry.filename, TarEntryKind::File, 0, &metadata);
+        header.xattrs = entry.xattrs.clone();
+        (header.kind, header.mode, header.dev_major, header.dev_minor) = match special {
+            SpecialFile::HardLink { target } => {
+                header.link_name = target.clone();
+                (TarEntryKind::HardLink, header.mode, 0, 0)
+            }
+            SpecialFile::CharDevice { major, minor, mode } => {
+                (TarEntryKind::CharDevice, *mode, *major, *minor)
+            }
+            SpecialFile::BlockDevice { major, minor, mode } => {
+                (TarEntryKind::BlockDevice, *mode, *major, *minor)
+            }
+            SpecialFile::Fifo { mode } => (TarEntryKind::Fifo, *mode, 0, 0),
+        };
+        self.append(&header, &[])
+    }
+}
+
+fn new_header(name: &str, kind: TarEntryKind, size: u64, metadata: &EntryMetadata) -> TarHeader {
+    let default_mode: u32 = if kind == TarEntryKind::Directory {
+        0o755
+    } else {
+        0o644
+    };
+    let (uid, gid) = metadata.owner.unwrap_or((0, 0));
+    TarHeader {
+        path: name.to_string(),
+        link_name: String::new(),
+        kind,
+        size,
+        mode: metadata.permissions.unwrap_or(default_mode),
+        uid: uid as u64,
+        gid: gid as u64,
+        mtime: metadata
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_secs()),
+        dev_major: 0,
+        dev_minor: 0,
+        offset: 0,
+        xattrs: vec![],
+    }
+}
+
+/// Encodes `header` as a ustar header block, along with the pax records
+/// needed for the values that don't fit.
+fn encode_header(header: &TarHeader) -> ([u8; 512], Vec<(&'static str, String)>) {
+    let mut block: [u8; 512] = [0u8; 512];
+    let mut records: Vec<(&'static str, String)> = vec![];
+
+    let path: &[u8] = header.path.as_bytes();
+    if path.len() <= 100 {
+        block[0..path.len()].copy_from_slice(path);
+    } else if let Some((prefix, name)) = split_path(path) {
+        block[0..name.len()].copy_from_slice(name);
+        block[345..345 + prefix.len()].copy_from_slice(prefix);
+    } else {
+        records.push(("path", header.path.clone()));
+        block[0..100].copy_from_slice(&path[..100]);
+    }
+    let link: &[u8] = header.link_name.as_bytes();
+    if link.len() > 100 {
+        records.push(("linkpath", header.link_name.clone()));
+    }
+    block[157..157 + link.len().min(100)].copy_from_slice(&link[..link.len().min(100)]);
+
+    let mut number = |range: std::ops::Range<usize>, key: &'static str, value: u64| {
+        let digits: usize = range.len() - 1;
+        if value >> (3 * digits) == 0 {
+            write_octal(&mut block[range], value);
+        } else {
+            records.push((key, value.to_string()));
+        }
+    };
+    number(108..116, "uid", header.uid);
+    number(116..124, "gid", header.gid);
+    number(124..136, "size", header.size);
+    number(136..148, "mtime", header.mtime);
+    write_octal(&mut block[100..108], (header.mode & 0o7777) as u64);
+    write_octal(&mut block[329..337], (header.dev_major & 0o7777777) as u64);
+    write_octal(&mut block[337..345], (header.dev_minor & 0o7777777) as u64);
+    block[156] = header.kind.typeflag();
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // Checksum, summed with the field itself as spaces: six octal digits,
+    // a NUL and a space
+    block[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = block.iter().map(|&b| b as u64).sum();
+    write_octal(&mut block[148..155], checksum);
+    (block, records)
+}
+
+/// Splits a path too long for the name field into a ustar prefix of up to
+/// 155 bytes and a name of up to 100, at a `/`.
+fn split_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let trimmed: &[u8] = path.strip_suffix(b"/").unwrap_or(path);
+    (1..trimmed.len())
+        .rev()
+        .filter(|&i| trimmed[i] == b'/')
+        .map(|i| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)
+}
+
+/// Writes `value` as zero-padded octal digits filling all but the last byte
+/// of `field`, which is left NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits: usize = field.len() - 1;
+    let text: String = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+}
+
+/// Formats a pax record, whose leading length counts the whole record,
+/// its own digits included.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let base: usize = key.len() + value.len() + 3;
+    let mut length: usize = base + base.to_string().len();
+    if length.to_string().len() > base.to_string().len() {
+        length += 1;
+    }
+    let mut record: Vec<u8> = format!("{} {}=", length, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+fn build_header(
+    block: &[u8; 512],
+    offset: u64,
+    size: u64,
+    long_name: Option<String>,
+    long_link: Option<String>,
+    pax: &PaxRecords,
+) -> io::Result<TarHeader> {
+    let path: String = match (pax_text(pax, "path")?, long_name) {
+        (Some(path), _) => path.to_string(),
+        (None, Some(name)) => name,
+        (None, None) => {
+            let name: String = decode_name(&block[0..100]);
+            // Only POSIX ustar has a prefix, GNU stores other fields there
+            let prefix: String = if &block[257..263] == b"ustar\0" {
+                decode_name(&block[345..500])
+            } else {
+                String::new()
+            };
+            if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            }
+        }
+    };
+    let link_name: String = match (pax_text(pax, "linkpath")?, long_link) {
+        (Some(link), _) => link.to_string(),
+        (None, Some(link)) => link,
+        (None, None) => decode_name(&block[157..257]),
+    };
+    let mut xattrs: Vec<(String, Vec<u8>)> = pax
+        .iter()
+        .filter_map(|(key, value)| {
+            Some((key.strip_prefix(XATTR_PREFIX)?.to_string(), value.clone()))
+        })
+        .collect();
+    xattrs.sort();
+    let kind: TarEntryKind = match TarEntryKind::from_typeflag(block[156]) {
+        // Pre-POSIX archives mark directories with a trailing slash only
+        TarEntryKind::File if path.ends_with('/') => TarEntryKind::Directory,
+        kind => kind,
+    };
+
+    Ok(TarHeader {
+        path,
+        link_name,
+        kind,
+        size,
+        mode: parse_number(&block[100..108])? as u32,
+        uid: match pax_text(pax, "uid")? {
+            Some(uid) => parse_pax_number(uid)?,
+            None => parse_number(&block[108..116])?,
+        },
+        gid: match pax_text(pax, "gid")? {
+            Some(gid) => parse_pax_number(gid)?,
+            None => parse_number(&block[116..124])?,
+        },
+        mtime: match pax_text(pax, "mtime")? {
+            Some(mtime) => parse_pax_number(mtime)?,
+            None => parse_number(&block[136..148])?,
+        },
+        dev_major: parse_number(&block[329..337])? as u32,
+        dev_minor: parse_number(&block[337..345])? as u32,
+        offset,
+        xattrs,
+    })
+}
+
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+/// Decodes a NUL-terminated name field. Tar doesn't record an encoding;
+/// modern tools write UTF-8.
+fn decode_name(field: &[u8]) -> String {
+    let end: usize = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Parses a numeric header field: octal digits padded with spaces or NULs,
+/// or a big-endian base-256 number when the high bit of the first byte is
+/// set (GNU, for values that don't fit in octal).
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        if field[0] & 0x40 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Negative number in tar header",
+            ));
+        }
+        let mut value: u64 = (field[0] & 0x3f) as u64;
+        for &byte in &field[1..] {
+            value = value
+                .checked_mul(256)
+                .map(|v| v + byte as u64)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Number in tar header too large")
+                })?;
+        }
+        return Ok(value);
+    }
+
+    let digits: &[u8] = field
+        .split(|&b| b == 0)
+        .next()
+        .unwrap_or_default()
+        .trim_ascii();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, 8).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid number in tar header"))
+}
+
+/// Parses a decimal pax value, dropping any fractional part of timestamps.
+fn parse_pax_number(value: &str) -> io::Result<u64> {
+    let integer: &str = value.split('.').next().unwrap_or_default();
+    integer
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid number in pax header"))
+}
+
+/// Returns the value of a pax record that must be text, like names and
+/// numbers.
+fn pax_text<'a>(pax: &'a PaxRecords, key: &str) -> io::Result<Option<&'a str>> {
+    match pax.get(key) {
+        Some(value) => match std::str::from_utf8(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {} in pax header", key),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses pax extended header records, each of the form
+/// `<length> <key>=<value>\n` where length counts the whole record.
+fn parse_pax_records(data: &[u8]) -> io::Result<PaxRecords> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid pax header record");
+    let mut records: PaxRecords = HashMap::new();
+    let mut rest: &[u8] = data;
+    while !rest.is_empty() && rest[0] != 0 {
+        let space: usize = rest.iter().position(|&b| b == b' ').ok_or_else(invalid)?;
+        let length: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(invalid)?;
+        if length <= space + 1 || length > rest.len() || rest[length - 1] != b'\n' {
+            return Err(invalid());
+        }
+        let record: &[u8] = &rest[space + 1..length - 1];
+        let equals: usize = record.iter().position(|&b| b == b'=').ok_or_else(invalid)?;
+        let key: &str = std::str::from_utf8(&record[..equals]).map_err(|_| invalid())?;
+        records.insert(key.to_string(), record[equals + 1..].to_vec());
+        rest = &rest[length..];
+    }
+    Ok(records)
+}
+
+/// Checks the header checksum: the sum of all header bytes with the checksum
+/// field itself counted as spaces. Some old tools summed signed bytes, so
+/// that sum is accepted too.
+fn verify_checksum(block: &[u8; 512]) -> io::Result<()> {
+    let stored: u64 = parse_number(&block[148..156])?;
+    let mut unsigned: u64 = 0;
+    let mut signed: i64 = 0;
+    for (i, &byte) in block.iter().enumerate() {
+        let byte: u8 = if (148..156).contains(&i) { b' ' } else { byte };
+        unsigned += byte as u64;
+        signed += byte as i8 as i64;
+    }
+    if stored == unsigned || stored as i64 == signed {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Tar header checksum mismatch",
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::sink::DiskSink;
+    use crate::test_util::get_test_file_path;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    fn read_headers(filename: &str) -> io::Result<Vec<(String, TarEntryKind)>> {
+        let mut archive = TarArchive::new(File::open(get_test_file_path(filename))?);
+        let mut headers: Vec<(String, TarEntryKind)> = vec![];
+        while let Some(header) = archive.next_header()? {
+            headers.push((header.path, header.kind));
+        }
+        Ok(headers)
+    }
+
+    #[test]
+    fn test_tar_formats() -> io::Result<()> {
+        let long_name: String = format!("d/{}.txt", "x".repeat(120));
+        let common = vec![
+            ("dir/".to_string(), TarEntryKind::Directory),
+            ("dir/test1.txt".to_string(), TarEntryKind::File),
+            ("link".to_string(), TarEntryKind::Symlink),
+        ];
+        assert_eq!(read_headers("test_ustar.tar")?, common);
+
+        let mut gnu = common.clone();
+        gnu.push((long_name.clone(), TarEntryKind::File));
+        assert_eq!(read_headers("test_gnu.tar")?, gnu);
+
+        let mut pax = gnu.clone();
+        pax.push(("ünïcode.txt".to_string(), TarEntryKind::File));
+        assert_eq!(read_headers("test_pax.tar")?, pax);
+
+        let block: Vec<u8> = fs::read(get_test_file_path("test_pax.tar"))?;
+        assert!(is_tar_header(&block));
+        assert!(!is_tar_header(&fs::read(get_test_file_path(
+            "test_single.zip"
+        ))?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_extract() -> io::Result<()> {
+        let output = get_test_file_path("tar_extract_out");
+        let mut archive = TarArchive::new(File::open(get_test_file_path("test_pax.tar"))?);
+        archive.extract_to(&mut DiskSink::new(&output))?;
+
+        let text = fs::read_to_string(output.join("dir/test1.txt"))?;
+        let unicode = fs::read_to_string(output.join("ünïcode.txt"))?;
+        let linked = output.join("link").exists();
+        fs::remove_dir_all(&output)?;
+        assert_eq!(text, "this is test1\n");
+        assert_eq!(unicode, "unicode\n");
+        assert!(!linked);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_special_files() -> io::Result<()> {
+        use crate::sink::SpecialFilePolicy;
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let mut archive = TarArchive::new(File::open(get_test_file_path("test_special.tar"))?);
+        let mut headers: Vec<TarHeader> = vec![];
+        while let Some(header) = archive.next_header()? {
+            headers.push(header);
+        }
+        assert_eq!(
+            headers[1].special_file(),
+            SpecialFile::HardLink {
+                target: "a.txt".to_string()
+            }
+        );
+        assert_eq!(
+            headers[3].special_file(),
+            SpecialFile::CharDevice {
+                major: 1,
+                minor: 3,
+                mode: 0o666
+            }
+        );
+
+        // Device nodes need root, so only the link and the FIFO are created
+        let output = get_test_file_path("tar_special_out");
+        let _ = fs::remove_dir_all(&output);
+        let mut sink = DiskSink::new(&output).with_special_files(SpecialFilePolicy::Create);
+        let mut archive = TarArchive::new(File::open(get_test_file_path("test_special.tar"))?);
+        for _ in 0..3 {
+            let header: TarHeader = archive.next_header()?.unwrap();
+            let data: Vec<u8> = archive.read_data()?;
+            match header.kind {
+                TarEntryKind::File => sink.write_entry(&header.to_entry(&data)?, &data)?,
+                _ => sink.write_special(&header.to_entry(&data)?, &header.special_file())?,
+            }
+        }
+        let linked: bool =
+            fs::metadata(output.join("a.txt"))?.ino() == fs::metadata(output.join("b.txt"))?.ino();
+        let fifo: bool = fs::symlink_metadata(output.join("pipe"))?
+            .file_type()
+            .is_fifo();
+        fs::remove_dir_all(&output)?;
+        assert!(linked);
+        assert!(fifo);
+
+        // Skipped by default, without leaving empty files behind
+        let mut archive = TarArchive::new(File::open(get_test_file_path("test_special.tar"))?);
+        archive.extract_to(&mut DiskSink::new(&output))?;
+        let names: Vec<_> = fs::read_dir(&output)?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        fs::remove_dir_all(&output)?;
+        assert_eq!(names, ["a.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_writer_round_trip() -> io::Result<()> {
+        let metadata: EntryMetadata = EntryMetadata {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            permissions: Some(0o750),
+            // Too large for the octal field, so it goes in a pax record
+            owner: Some((1000, 0o10000000)),
+        };
+        // Fits once split into prefix and name, and doesn't fit at all
+        let split: String = format!("{}/{}", "p".repeat(120), "n".repeat(90));
+        let long: String = format!("{}.txt", "l".repeat(300));
+        let mut writer = TarWriter::new(vec![]);
+        writer.add_directory("dir", &metadata)?;
+        writer.add_file(&split, b"split", &metadata)?;
+        writer.add_file(&long, b"long", &EntryMetadata::default())?;
+        let link: ZipFileEntry = ZipFileEntry {
+            filename: String::from("link"),
+            ..Default::default()
+        };
+        writer.write_special(
+            &link,
+            &SpecialFile::HardLink {
+                target: long.clone(),
+            },
+        )?;
+        let bytes: Vec<u8> = writer.finish()?;
+        assert_eq!(bytes.len() % 512, 0);
+
+        let mut archive = TarArchive::new(&bytes[..]);
+        let dir: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!(
+            (dir.path.as_str(), dir.kind),
+            ("dir/", TarEntryKind::Directory)
+        );
+        assert_eq!((dir.mode, dir.uid, dir.gid), (0o750, 1000, 0o10000000));
+        assert_eq!(dir.mtime, 1_700_000_000);
+        assert_eq!(archive.next_header()?.unwrap().path, split);
+        assert_eq!(archive.read_data()?, b"split");
+        let header: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!((header.path, header.mode), (long.clone(), 0o644));
+        assert_eq!(archive.read_data()?, b"long");
+        let header: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!(
+            header.special_file(),
+            SpecialFile::HardLink { target: long }
+        );
+        assert!(archive.next_header()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_xattrs() -> io::Result<()> {
+        let xattrs: Vec<(String, Vec<u8>)> = vec![
+            (
+                String::from("security.selinux"),
+                b"system_u:object_r:bin_t:s0\0".to_vec(),
+            ),
+            // Binary, with what separates pax records and their keys
+            (String::from("user.binary"), vec![0xff, b'=', b'\n', 0]),
+        ];
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: String::from("labeled"),
+            xattrs: xattrs.clone(),
+            ..Default::default()
+        };
+        let mut writer = TarWriter::new(vec![]);
+        writer.write_entry(&entry, b"data")?;
+        let bytes: Vec<u8> = writer.finish()?;
+
+        let mut archive = TarArchive::new(&bytes[..]);
+        let header: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!(header.path, "labeled");
+        assert_eq!(header.xattrs, xattrs);
+        assert_eq!(archive.read_data()?, b"data");
+        assert_eq!(header.to_entry(b"data")?.xattrs, xattrs);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_zip_to_tar() -> io::Result<()> {
+        let path: PathBuf = get_test_file_path("test_multiple.zip");
+        let archive = crate::zip::ZipArchive::open_readonly(path.to_str().unwrap())?;
+        let mut writer = TarWriter::new(vec![]);
+        archive.extract_to(&mut writer)?;
+        let bytes: Vec<u8> = writer.finish()?;
+
+        let mut archive = TarArchive::new(&bytes[..]);
+        let header: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!(header.path, "test1.txt");
+        assert_eq!((header.mode, header.uid, header.gid), (0o664, 1000, 1000));
+        assert_eq!(header.mtime, 1731603432);
+        assert_eq!(archive.read_data()?, b"this is test1\n");
+        assert_eq!(archive.next_header()?.unwrap().path, "test2.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_huge_header_fields() -> io::Result<()> {
+        // The dir/test1.txt header with a base-256 field far beyond the data
+        // that follows or the range of SystemTime
+        let archive: Vec<u8> = fs::read(get_test_file_path("test_ustar.tar"))?;
+        let with_field = |range: std::ops::Range<usize>| -> Vec<u8> {
+            let mut archive: Vec<u8> = archive.clone();
+            let block: &mut [u8] = &mut archive[512..1024];
+            // Base-256: a flag byte then big-endian u64::MAX
+            block[range.clone()].fill(0);
+            block[range.start] = 0x80;
+            block[range.end - 8..range.end].fill(0xff);
+            block[148..156].copy_from_slice(b"        ");
+            let checksum: u64 = block.iter().map(|&b| b as u64).sum();
+            write_octal(&mut block[148..155], checksum);
+            archive
+        };
+
+        let mtime: Vec<u8> = with_field(136..148);
+        let mut archive = TarArchive::new(&mtime[512..]);
+        let header: TarHeader = archive.next_header()?.unwrap();
+        assert_eq!(header.to_entry(&[])?.modified, None);
+        assert_eq!(archive.read_data()?, b"this is test1\n");
+
+        let size: Vec<u8> = with_field(124..136);
+        let mut archive = TarArchive::new(&size[512..1024]);
+        assert_eq!(archive.next_header()?.unwrap().size, u64::MAX);
+        assert!(archive.read_data().is_err());
+        assert!(archive.next_header().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_number() -> io::Result<()> {
+        assert_eq!(parse_number(b"0000644\0")?, 0o644);
+        assert_eq!(parse_number(b"     17 ")?, 0o17);
+        assert_eq!(parse_number(b"\0\0\0\0")?, 0);
+        assert_eq!(parse_number(b"\x80\0\0\0\0\0\0\x02\0\0\0\0")?, 1 << 33);
+        assert!(parse_number(b"12x").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by the unit tests of every module.
+
+use std::path::PathBuf;
+
+/// Path of `filename` among the fixtures in `test_files`.
+pub(crate) fn get_test_file_path(filename: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("test_files");
+    path.push(filename);
+    path
+}
@@ -0,0 +1,261 @@
+//! Bandwidth limits and I/O priority, so extracting a huge archive on a
+//! shared machine doesn't starve other workloads.
+//!
+//! A [`RateLimiter`] holds a budget of bytes per second that can be shared
+//! by several readers and writers, across threads. [`Throttled`] applies it
+//! to anything that reads or writes, e.g. the file an archive is read from,
+//! and [`ThrottledFilesystem`] to the files a
+//! [`DiskSink`](crate::sink::DiskSink) writes.
+//!
+//! Limiters need a clock, which wasm32-unknown-unknown doesn't have; don't
+//! use them there.
+
+use crate::sink::SpecialFile;
+use crate::vfs::{Filesystem, NodeInfo};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Reads and writes through `Throttled` are split into pieces of at most
+// this much, so a large buffer doesn't go through in one burst followed by
+// a long pause
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Holds everything going through it to a number of bytes per second.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    // When the bytes let through so far are paid for. Never earlier than
+    // the last call, so idle time doesn't build up credit for a burst
+    next: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    /// A limiter letting `bytes_per_second` through, at least 1.
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        RateLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            next: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Accounts for `bytes` having gone through, sleeping until the budget
+    /// allows them.
+    pub fn consume(&self, bytes: u64) {
+        let cost: Duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let now: Instant = Instant::now();
+        let due: Instant = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let due: Instant = next.map_or(now, |next| next.max(now)) + cost;
+            *next = Some(due);
+            due
+        };
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+/// A reader or writer whose transfers are held to the rate of a
+/// [`RateLimiter`], or passed through untouched without one.
+pub struct Throttled<T> {
+    inner: T,
+    limiter: Option<RateLimiter>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiter: Option<RateLimiter>) -> Throttled<T> {
+        Throttled { inner, limiter }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limiter) = &self.limiter else {
+            return self.inner.read(buf);
+        };
+        let length: usize = buf.len().min(CHUNK_SIZE);
+        let read: usize = self.inner.read(&mut buf[..length])?;
+        limiter.consume(read as u64);
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(limiter) = &self.limiter else {
+            return self.inner.write(buf);
+        };
+        let written: usize = self.inner.write(&buf[..buf.len().min(CHUNK_SIZE)])?;
+        limiter.consume(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Seek> Seek for Throttled<S> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+/// A [`Filesystem`] whose file contents are held to the rate of a
+/// [`RateLimiter`].
+///
+/// Files are handed to the filesystem whole, so the limit holds on average
+/// over files rather than within each: a file is written once the budget
+/// has room for all of it.
+pub struct ThrottledFilesystem<F> {
+    inner: F,
+    limiter: RateLimiter,
+}
+
+impl<F: Filesystem> ThrottledFilesystem<F> {
+    pub fn new(inner: F, limiter: RateLimiter) -> ThrottledFilesystem<F> {
+        ThrottledFilesystem { inner, limiter }
+    }
+}
+
+impl<F: Filesystem> Filesystem for ThrottledFilesystem<F> {
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Option<NodeInfo>> {
+        self.inner.metadata(path)
+    }
+
+    fn create_dir(&self, path: &Path, recursive: bool) -> io::Result<()> {
+        self.inner.create_dir(path, recursive)
+    }
+
+    fn create_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.limiter.consume(data.len() as u64);
+        self.inner.create_file(path, data)
+    }
+
+    fn create_sparse_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        // Holes aren't written, so they don't count
+        let written: usize = data
+            .chunks(4096)
+            .filter(|block| block.iter().any(|&byte| byte != 0))
+            .map(<[u8]>::len)
+            .sum();
+        self.limiter.consume(written as u64);
+        self.inner.create_sparse_file(path, data)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        self.inner.sync(path)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        self.inner.symlink(target, path)
+    }
+
+    fn hard_link(&self, target: &Path, path: &Path) -> io::Result<()> {
+        self.inner.hard_link(target, path)
+    }
+
+    fn make_node(&self, path: &Path, special: &SpecialFile) -> io::Result<()> {
+        self.inner.make_node(path, special)
+    }
+
+    fn set_times(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        self.inner.set_times(path, modified)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.inner.set_permissions(path, mode)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        self.inner.set_owner(path, uid, gid)
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        self.inner.set_xattr(path, name, value)
+    }
+}
+
+/// Asks the OS to give the disk I/O of this process idle priority, so it
+/// only gets the disk when nothing else wants it. Only supported on Linux,
+/// through `ioprio_set(2)`.
+pub fn lower_io_priority() -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        // SAFETY: ioprio_set only reads its integer arguments; 0 is the
+        // calling process
+        let result: libc::c_long = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Lowering the I/O priority is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFilesystem;
+
+    #[test]
+    fn test_rate_limit() -> io::Result<()> {
+        // 100 KiB at 1 MiB/s, shared between a reader and a filesystem
+        let limiter: RateLimiter = RateLimiter::new(1024 * 1024);
+        let start: Instant = Instant::now();
+        let mut reader = Throttled::new(&[1u8; 50 * 1024][..], Some(limiter.clone()));
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        let filesystem = ThrottledFilesystem::new(MemoryFilesystem::new(), limiter);
+        filesystem.create_file(Path::new("a"), &data)?;
+        assert!(start.elapsed() >= Duration::from_millis(95));
+        assert_eq!(data.len(), 50 * 1024);
+
+        // Without a limiter nothing waits
+        let start: Instant = Instant::now();
+        let mut writer = Throttled::new(vec![], None);
+        writer.write_all(&[0; 1 << 20])?;
+        assert!(start.elapsed() < Duration::from_millis(95));
+        assert_eq!(writer.into_inner().len(), 1 << 20);
+        Ok(())
+    }
+}
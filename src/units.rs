@@ -0,0 +1,76 @@
+//! Formatting and parsing of sizes for people.
+//!
+//! Output is the same whatever the locale: binary prefixes, one decimal
+//! and a `.` separator, so listings can be compared and parsed anywhere.
+
+const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Formats a byte count with the largest binary prefix that leaves at least
+/// one unit, e.g. `512 B`, `1.5 KiB` or `2.0 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value: f64 = bytes as f64 / 1024.0;
+    let mut unit: usize = 0;
+    // Round first, so 1023.96 KiB shows as 1.0 MiB rather than 1024.0 KiB
+    while (value * 10.0).round() / 10.0 >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Parses a byte count such as `512`, `64K`, `10M` or `1.5GiB`. Suffixes
+/// are binary multiples, case-insensitive, with optional `iB` or `B`.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let lower: String = size.trim().to_ascii_lowercase();
+    let number: &str = lower
+        .strip_suffix("ib")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+    let (number, shift): (&str, u32) = match number.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => {
+            let unit: usize = UNITS
+                .iter()
+                .position(|unit| unit.starts_with(c.to_ascii_uppercase()))?;
+            (&number[..i], 10 * (unit as u32 + 1))
+        }
+        _ => (number, 0),
+    };
+    if let Ok(value) = number.parse::<u64>() {
+        return value.checked_mul(1 << shift);
+    }
+    let value: f64 = number.parse().ok()?;
+    let bytes: f64 = value * (1u64 << shift) as f64;
+    (value.is_finite() && value >= 0.0 && bytes < u64::MAX as f64).then_some(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(format_size(5 << 30), "5.0 GiB");
+        assert_eq!(format_size(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64K"), Some(64 << 10));
+        assert_eq!(parse_size("10mb"), Some(10 << 20));
+        assert_eq!(parse_size("1.5GiB"), Some(3 << 29));
+        assert_eq!(parse_size("2E"), Some(2 << 60));
+        assert_eq!(parse_size("16E"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("10X"), None);
+        assert_eq!(parse_size(""), None);
+    }
+}
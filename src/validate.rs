@@ -0,0 +1,217 @@
+//! Post-extraction checks that entry contents match their extension.
+//!
+//! CRC-32 only proves the data survived compression; these validators add a
+//! cheap forensics signal by comparing leading bytes with the format the
+//! entry name claims to be.
+
+use crate::sink::{Sink, SpecialFile};
+use crate::zip::ZipFileEntry;
+use std::io;
+use std::path::Path;
+
+/// Checks entries whose extension it claims.
+pub trait Validator {
+    /// Lowercase extensions, without the dot, this validator applies to.
+    fn extensions(&self) -> &[&str];
+
+    /// Returns a description of the problem if `data` isn't a valid file of
+    /// this kind.
+    fn validate(&self, data: &[u8]) -> Option<String>;
+}
+
+/// Validates that data starts with one of a set of magic byte sequences.
+pub struct MagicValidator {
+    format: &'static str,
+    extensions: &'static [&'static str],
+    magics: &'static [&'static [u8]],
+}
+
+impl MagicValidator {
+    pub const fn new(
+        format: &'static str,
+        extensions: &'static [&'static str],
+        magics: &'static [&'static [u8]],
+    ) -> MagicValidator {
+        MagicValidator {
+            format,
+            extensions,
+            magics,
+        }
+    }
+}
+
+impl Validator for MagicValidator {
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn validate(&self, data: &[u8]) -> Option<String> {
+        if self.magics.iter().any(|magic| data.starts_with(magic)) {
+            None
+        } else {
+            Some(format!("content is not {}", self.format))
+        }
+    }
+}
+
+// Local file header, or end of central directory for an empty archive
+pub const ZIP_VALIDATOR: MagicValidator =
+    MagicValidator::new("a zip archive", &["zip"], &[b"PK\x03\x04", b"PK\x05\x06"]);
+pub const GZIP_VALIDATOR: MagicValidator =
+    MagicValidator::new("gzip data", &["gz", "tgz"], &[b"\x1f\x8b"]);
+pub const PNG_VALIDATOR: MagicValidator =
+    MagicValidator::new("a PNG image", &["png"], &[b"\x89PNG\r\n\x1a\n"]);
+
+/// An entry whose contents didn't match its extension.
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub filename: String,
+    pub reason: String,
+}
+
+/// A set of validators, looked up by entry extension.
+pub struct Validators {
+    validators: Vec<Box<dyn Validator>>,
+}
+
+impl Validators {
+    pub fn new() -> Validators {
+        Validators { validators: vec![] }
+    }
+
+    /// Returns a set with the zip, gzip and PNG validators registered.
+    pub fn with_builtins() -> Validators {
+        let mut validators: Validators = Validators::new();
+        validators.register(ZIP_VALIDATOR);
+        validators.register(GZIP_VALIDATOR);
+        validators.register(PNG_VALIDATOR);
+        validators
+    }
+
+    pub fn register<V: Validator + 'static>(&mut self, validator: V) {
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Runs every validator registered for the extension of `filename`.
+    pub fn check(&self, filename: &str, data: &[u8]) -> Option<Mismatch> {
+        let extension: String = Path::new(filename)
+            .extension()?
+            .to_str()?
+            .to_ascii_lowercase();
+        self.validators
+            .iter()
+            .filter(|v| v.extensions().contains(&extension.as_str()))
+            .find_map(|v| v.validate(data))
+            .map(|reason| Mismatch {
+                filename: filename.to_string(),
+                reason,
+            })
+    }
+}
+
+impl Default for Validators {
+    fn default() -> Validators {
+        Validators::new()
+    }
+}
+
+/// Wraps another sink, validating every entry before passing it on.
+pub struct ValidatingSink<'a, S: Sink> {
+    inner: S,
+    validators: &'a Validators,
+    mismatches: Vec<Mismatch>,
+}
+
+impl<'a, S: Sink> ValidatingSink<'a, S> {
+    pub fn new(inner: S, validators: &'a Validators) -> ValidatingSink<'a, S> {
+        ValidatingSink {
+            inner,
+            validators,
+            mismatches: vec![],
+        }
+    }
+
+    pub fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Sink> Sink for ValidatingSink<'_, S> {
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        if let Some(mismatch) = self.validators.check(&entry.filename, data) {
+            self.mismatches.push(mismatch);
+        }
+        self.inner.write_entry(entry, data)
+    }
+
+    fn write_special(&mut self, entry: &ZipFileEntry, special: &SpecialFile) -> io::Result<()> {
+        self.inner.write_special(entry, special)
+    }
+
+    fn skip_entry(&mut self, entry: &ZipFileEntry, reason: &str) -> io::Result<()> {
+        self.inner.skip_entry(entry, reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::NullSink;
+
+    #[test]
+    fn test_builtin_validators() {
+        let validators = Validators::with_builtins();
+        assert_eq!(validators.check("a.png", b"\x89PNG\r\n\x1a\n...."), None);
+        assert_eq!(validators.check("a.TGZ", b"\x1f\x8b\x08"), None);
+        assert_eq!(validators.check("notes.txt", b"anything"), None);
+        assert_eq!(
+            validators.check("nested.zip", b"MZ\x90\x00"),
+            Some(Mismatch {
+                filename: "nested.zip".to_string(),
+                reason: "content is not a zip archive".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_validating_sink_collects_mismatches() -> io::Result<()> {
+        struct TextValidator;
+        impl Validator for TextValidator {
+            fn extensions(&self) -> &[&str] {
+                &["txt"]
+            }
+            fn validate(&self, data: &[u8]) -> Option<String> {
+                std::str::from_utf8(data)
+                    .err()
+                    .map(|_| "not UTF-8".to_string())
+            }
+        }
+
+        let mut validators = Validators::with_builtins();
+        validators.register(TextValidator);
+        let mut sink = ValidatingSink::new(NullSink, &validators);
+        let entry = |name: &str| ZipFileEntry {
+            filename: name.to_string(),
+            ..Default::default()
+        };
+        sink.write_entry(&entry("ok.txt"), b"hello")?;
+        sink.write_entry(&entry("bad.txt"), b"\xff\xfe")?;
+        sink.write_entry(&entry("image.png"), b"GIF89a")?;
+
+        let names: Vec<&str> = sink
+            .mismatches()
+            .iter()
+            .map(|m| m.filename.as_str())
+            .collect();
+        assert_eq!(names, ["bad.txt", "image.png"]);
+        Ok(())
+    }
+}
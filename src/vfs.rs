@@ -0,0 +1,811 @@
+//! Filesystems that [`DiskSink`](crate::sink::DiskSink) extracts to.
+//!
+//! [`OsFilesystem`] is the real disk. [`MemoryFilesystem`] keeps everything
+//! in memory, for tests and for embedders that want to look at or forward
+//! the extracted tree without touching the disk. Other implementations can
+//! redirect extraction into object storage or a sandbox.
+
+use crate::sink::SpecialFile;
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::fs::{self, DirBuilder, File};
+use std::io;
+#[cfg(feature = "fs")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+// Granularity of the holes of sparse files, the block size of most
+// filesystems
+#[cfg(feature = "fs")]
+const SPARSE_BLOCK_SIZE: usize = 4096;
+
+/// What a path is, as far as extraction cares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    File,
+    Dir,
+    Symlink,
+    /// A device node, FIFO or socket
+    Other,
+}
+
+/// What [`Filesystem::metadata`] reports about a path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeInfo {
+    pub kind: NodeKind,
+    pub modified: Option<SystemTime>,
+}
+
+/// The operations extraction needs from a filesystem. Paths are those
+/// [`DiskSink`](crate::sink::DiskSink) builds below its output directory;
+/// symbolic links aren't followed.
+pub trait Filesystem: Send + Sync {
+    /// Returns true if this filesystem never writes to the disk, so sinks
+    /// using it are accepted by read-only archives.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Describes `path`, or returns `None` if nothing is there.
+    fn metadata(&self, path: &Path) -> io::Result<Option<NodeInfo>>;
+
+    /// Creates a directory, and its missing parents if `recursive` is set.
+    fn create_dir(&self, path: &Path, recursive: bool) -> io::Result<()>;
+
+    /// Creates or truncates the file at `path` and writes `data` to it. A
+    /// failed write doesn't leave a truncated file behind.
+    fn create_file(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Like [`Filesystem::create_file`], but may leave runs of zeros in
+    /// `data` as holes instead of writing them, where the filesystem
+    /// supports sparse files. Writes every byte by default.
+    fn create_sparse_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.create_file(path, data)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Moves the file at `from` to `to`, replacing any file there in one
+    /// step.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Flushes the file or directory at `path` to stable storage. Does
+    /// nothing by default, for filesystems without such a thing.
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`.
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()>;
+
+    /// Creates `path` as another name for the file at `target`.
+    fn hard_link(&self, target: &Path, path: &Path) -> io::Result<()>;
+
+    /// Creates a device node or FIFO.
+    fn make_node(&self, path: &Path, special: &SpecialFile) -> io::Result<()>;
+
+    /// Sets the modification time of a file.
+    fn set_times(&self, path: &Path, modified: SystemTime) -> io::Result<()>;
+
+    /// Sets the Unix permission bits of a file or directory.
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Sets the Unix owner and group of a file or directory.
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()>;
+
+    /// Sets an extended attribute of a file or directory. Unsupported by
+    /// default.
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let _ = (path, value);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't set extended attribute {} here", name),
+        ))
+    }
+}
+
+/// The real filesystem. Permissions, owners, symbolic links and special
+/// files are unsupported outside Unix: permissions are ignored, the rest
+/// fail.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFilesystem;
+
+#[cfg(feature = "fs")]
+impl Filesystem for OsFilesystem {
+    fn metadata(&self, path: &Path) -> io::Result<Option<NodeInfo>> {
+        let metadata: fs::Metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let file_type: fs::FileType = metadata.file_type();
+        let kind: NodeKind = if file_type.is_file() {
+            NodeKind::File
+        } else if file_type.is_dir() {
+            NodeKind::Dir
+        } else if file_type.is_symlink() {
+            NodeKind::Symlink
+        } else {
+            NodeKind::Other
+        };
+        Ok(Some(NodeInfo {
+            kind,
+            modified: metadata.modified().ok(),
+        }))
+    }
+
+    fn create_dir(&self, path: &Path, recursive: bool) -> io::Result<()> {
+        DirBuilder::new().recursive(recursive).create(path)
+    }
+
+    fn create_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file: File = File::create(path)?;
+        if let Err(e) = file.write_all(data).and_then(|_| file.flush()) {
+            // Don't leave a truncated file behind
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn create_sparse_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file: File = File::create(path)?;
+        if let Err(e) = write_sparse(&mut file, data) {
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        // Directories can only be opened, and need syncing, on Unix
+        #[cfg(not(unix))]
+        if fs::metadata(path)?.is_dir() {
+            return Ok(());
+        }
+        File::open(path)?.sync_all()
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, path);
+        #[cfg(not(unix))]
+        {
+            let _ = (target, path);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Can't create a symbolic link on this platform",
+            ))
+        }
+    }
+
+    fn hard_link(&self, target: &Path, path: &Path) -> io::Result<()> {
+        fs::hard_link(target, path)
+    }
+
+    fn make_node(&self, path: &Path, special: &SpecialFile) -> io::Result<()> {
+        make_node(path, special)
+    }
+
+    fn set_times(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = (path, mode);
+        Ok(())
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        #[cfg(unix)]
+        return std::os::unix::fs::chown(path, Some(uid), Some(gid));
+        #[cfg(not(unix))]
+        {
+            let _ = (path, uid, gid);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Can't change owners on this platform",
+            ))
+        }
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        xattr::set(path, name, value)
+    }
+}
+
+/// Reads the extended attributes of the file or directory at `path`, in
+/// name order, without following symbolic links. Empty on platforms and
+/// filesystems without extended attributes.
+#[cfg(feature = "fs")]
+pub fn read_xattrs(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    xattr::read(path)
+}
+
+/// Extended attributes through the `l*xattr(2)` calls of Linux and the
+/// `*xattr(2)` calls of macOS with `XATTR_NOFOLLOW`.
+#[cfg(all(
+    feature = "fs",
+    any(target_os = "linux", target_os = "android", target_os = "macos")
+))]
+mod xattr {
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    pub fn read(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let c_path: CString = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: c_path is NUL-terminated and the buffer holds size bytes
+        let names: Vec<u8> = match read_sized(|buffer, size| unsafe {
+            list(c_path.as_ptr(), buffer.cast(), size)
+        }) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut xattrs: Vec<(String, Vec<u8>)> = vec![];
+        for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+            let Ok(text) = std::str::from_utf8(name) else {
+                continue;
+            };
+            let c_name: CString = CString::new(name)?;
+            // SAFETY: as above, and c_name is NUL-terminated too
+            let value: Vec<u8> = read_sized(|buffer, size| unsafe {
+                get(c_path.as_ptr(), c_name.as_ptr(), buffer, size)
+            })?;
+            xattrs.push((text.to_string(), value));
+        }
+        xattrs.sort();
+        Ok(xattrs)
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let c_path: CString = CString::new(path.as_os_str().as_bytes())?;
+        let c_name: CString = CString::new(name)?;
+        // SAFETY: both strings are NUL-terminated and value is valid for
+        // its length
+        if unsafe { set_raw(&c_path, &c_name, value) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Calls `call` once to learn the size of the result and again to read
+    /// it, starting over if it grew in between.
+    fn read_sized<F>(call: F) -> io::Result<Vec<u8>>
+    where
+        F: Fn(*mut libc::c_void, usize) -> isize,
+    {
+        loop {
+            let size: isize = call(ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer: Vec<u8> = vec![0u8; size as usize];
+            let read: isize = call(buffer.as_mut_ptr().cast(), buffer.len());
+            if read >= 0 {
+                buffer.truncate(read as usize);
+                return Ok(buffer);
+            }
+            let e: io::Error = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ERANGE) {
+                return Err(e);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    unsafe fn list(path: *const libc::c_char, buffer: *mut libc::c_char, size: usize) -> isize {
+        libc::llistxattr(path, buffer, size)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    unsafe fn get(
+        path: *const libc::c_char,
+        name: *const libc::c_char,
+        buffer: *mut libc::c_void,
+        size: usize,
+    ) -> isize {
+        libc::lgetxattr(path, name, buffer, size)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    unsafe fn set_raw(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn list(path: *const libc::c_char, buffer: *mut libc::c_char, size: usize) -> isize {
+        libc::listxattr(path, buffer, size, libc::XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn get(
+        path: *const libc::c_char,
+        name: *const libc::c_char,
+        buffer: *mut libc::c_void,
+        size: usize,
+    ) -> isize {
+        libc::getxattr(path, name, buffer, size, 0, libc::XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn set_raw(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+}
+
+#[cfg(all(
+    feature = "fs",
+    not(any(target_os = "linux", target_os = "android", target_os = "macos"))
+))]
+mod xattr {
+    use std::io;
+    use std::path::Path;
+
+    pub fn read(_path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(vec![])
+    }
+
+    pub fn set(_path: &Path, name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't set extended attribute {} on this platform", name),
+        ))
+    }
+}
+
+/// Writes `data` to the empty `file`, seeking over blocks of zeros so the
+/// filesystem can leave holes there. Holes are allocated by block, so
+/// shorter runs of zeros are written.
+#[cfg(feature = "fs")]
+fn write_sparse(file: &mut File, data: &[u8]) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut hole: u64 = 0;
+    for block in data.chunks(SPARSE_BLOCK_SIZE) {
+        if block.iter().all(|&byte| byte == 0) {
+            hole += block.len() as u64;
+            continue;
+        }
+        if hole > 0 {
+            file.seek(SeekFrom::Current(hole as i64))?;
+            hole = 0;
+        }
+        file.write_all(block)?;
+    }
+    // Trailing zeros are a hole too, but still count in the length
+    file.set_len(data.len() as u64)?;
+    file.flush()
+}
+
+/// Creates a device node or FIFO with `mknod(2)`.
+#[cfg(all(feature = "fs", unix))]
+fn make_node(path: &Path, special: &SpecialFile) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let (file_type, mode, device): (libc::mode_t, u32, libc::dev_t) = match *special {
+        SpecialFile::CharDevice { major, minor, mode } => {
+            (libc::S_IFCHR, mode, libc::makedev(major, minor))
+        }
+        SpecialFile::BlockDevice { major, minor, mode } => {
+            (libc::S_IFBLK, mode, libc::makedev(major, minor))
+        }
+        SpecialFile::Fifo { mode } => (libc::S_IFIFO, mode, 0),
+        SpecialFile::HardLink { .. } => unreachable!("hard links aren't nodes"),
+    };
+    let c_path: CString = CString::new(path.as_os_str().as_bytes())?;
+    let permissions: libc::mode_t = (mode & 0o7777) as libc::mode_t;
+    // SAFETY: c_path is a valid NUL-terminated string for the whole call
+    if unsafe { libc::mknod(c_path.as_ptr(), file_type | permissions, device) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "fs", not(unix)))]
+fn make_node(_path: &Path, special: &SpecialFile) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Can't create a {} on this platform", special.kind()),
+    ))
+}
+
+/// What a path of a [`MemoryFilesystem`] holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryNode {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+    /// A device node or FIFO
+    Special(SpecialFile),
+}
+
+/// A path of a [`MemoryFilesystem`] with its metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryEntry {
+    pub node: MemoryNode,
+    pub modified: Option<SystemTime>,
+    pub mode: Option<u32>,
+    pub owner: Option<(u32, u32)>,
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+/// A filesystem kept in memory. Clones share the same tree, so a clone can
+/// be handed to a [`DiskSink`](crate::sink::DiskSink) and the result read
+/// from the original afterwards. Paths are compared component by component
+/// and every path without a parent is an existing root. Hard links copy the
+/// data of their target.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFilesystem {
+    entries: Arc<Mutex<BTreeMap<PathBuf, MemoryEntry>>>,
+}
+
+impl MemoryFilesystem {
+    pub fn new() -> MemoryFilesystem {
+        MemoryFilesystem::default()
+    }
+
+    /// Returns the entry at `path`.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<MemoryEntry> {
+        self.lock().get(path.as_ref()).cloned()
+    }
+
+    /// Returns the contents of the file at `path`.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> Option<Vec<u8>> {
+        match self.get(path)?.node {
+            MemoryNode::File(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns every path, directories included, in order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.lock().keys().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+        // A panic while holding the lock can't leave the map half updated
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a node at `path`, whose parent must be a directory.
+    fn insert(&self, path: &Path, node: MemoryNode) -> io::Result<()> {
+        let mut entries = self.lock();
+        check_parent(&entries, path)?;
+        entries.insert(
+            path.to_path_buf(),
+            MemoryEntry {
+                node,
+                modified: None,
+                mode: None,
+                owner: None,
+                xattrs: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn update<F: FnOnce(&mut MemoryEntry)>(&self, path: &Path, update: F) -> io::Result<()> {
+        match self.lock().get_mut(path) {
+            Some(entry) => {
+                update(entry);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} doesn't exist", path.display()),
+    )
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+fn check_parent(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        None => Ok(()),
+        Some(parent) if parent.as_os_str().is_empty() => Ok(()),
+        Some(parent) => match entries.get(parent).map(|entry| &entry.node) {
+            Some(MemoryNode::Dir) => Ok(()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a directory", parent.display()),
+            )),
+            None => Err(not_found(parent)),
+        },
+    }
+}
+
+impl Filesystem for MemoryFilesystem {
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Option<NodeInfo>> {
+        Ok(self.lock().get(path).map(|entry| NodeInfo {
+            kind: match entry.node {
+                MemoryNode::File(_) => NodeKind::File,
+                MemoryNode::Dir => NodeKind::Dir,
+                MemoryNode::Symlink(_) => NodeKind::Symlink,
+                MemoryNode::Special(_) => NodeKind::Other,
+            },
+            modified: entry.modified,
+        }))
+    }
+
+    fn create_dir(&self, path: &Path, recursive: bool) -> io::Result<()> {
+        if !recursive {
+            if self.lock().contains_key(path) {
+                return Err(already_exists(path));
+            }
+            return self.insert(path, MemoryNode::Dir);
+        }
+        // Parents first, like `mkdir -p`
+        let mut ancestors: Vec<&Path> = path
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty())
+            .collect();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            match self.lock().get(ancestor).map(|entry| &entry.node) {
+                Some(MemoryNode::Dir) => continue,
+                Some(_) => return Err(already_exists(ancestor)),
+                None => {}
+            }
+            self.insert(ancestor, MemoryNode::Dir)?;
+        }
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        match self.lock().get_mut(path) {
+            Some(MemoryEntry {
+                node: node @ MemoryNode::File(_),
+                ..
+            }) => {
+                // Truncating keeps the metadata, as on disk
+                *node = MemoryNode::File(data.to_vec());
+                return Ok(());
+            }
+            Some(_) => return Err(already_exists(path)),
+            None => {}
+        }
+        self.insert(path, MemoryNode::File(data.to_vec()))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.lock();
+        match entries.get(path).map(|entry| &entry.node) {
+            Some(MemoryNode::Dir) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a directory", path.display()),
+            )),
+            Some(_) => {
+                entries.remove(path);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.lock();
+        match entries.get(to).map(|entry| &entry.node) {
+            Some(MemoryNode::Dir) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is a directory", to.display()),
+                ))
+            }
+            Some(_) => {}
+            None => check_parent(&entries, to)?,
+        }
+        match entries.get(from).map(|entry| &entry.node) {
+            // Moving a directory would leave its children behind
+            Some(MemoryNode::Dir) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Can't move directory {}", from.display()),
+                ))
+            }
+            Some(_) => {}
+            None => return Err(not_found(from)),
+        }
+        let entry: MemoryEntry = entries.remove(from).ok_or_else(|| not_found(from))?;
+        entries.insert(to.to_path_buf(), entry);
+        Ok(())
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        if self.lock().contains_key(path) {
+            return Err(already_exists(path));
+        }
+        self.insert(path, MemoryNode::Symlink(target.to_path_buf()))
+    }
+
+    fn hard_link(&self, target: &Path, path: &Path) -> io::Result<()> {
+        let Some(entry) = self.get(target) else {
+            return Err(not_found(target));
+        };
+        if self.lock().contains_key(path) {
+            return Err(already_exists(path));
+        }
+        check_parent(&self.lock(), path)?;
+        self.lock().insert(path.to_path_buf(), entry);
+        Ok(())
+    }
+
+    fn make_node(&self, path: &Path, special: &SpecialFile) -> io::Result<()> {
+        if self.lock().contains_key(path) {
+            return Err(already_exists(path));
+        }
+        self.insert(path, MemoryNode::Special(special.clone()))
+    }
+
+    fn set_times(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        self.update(path, |entry| entry.modified = Some(modified))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.update(path, |entry| entry.mode = Some(mode))
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        self.update(path, |entry| entry.owner = Some((uid, gid)))
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        self.update(path, |entry| {
+            entry.xattrs.insert(name.to_string(), value.to_vec());
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::test_util::get_test_file_path;
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_extract_to_memory_filesystem() -> io::Result<()> {
+        use crate::sink::{DiskSink, SpecialFilePolicy};
+        use crate::tar::TarArchive;
+        use crate::zip::ZipArchive;
+
+        let filesystem: MemoryFilesystem = MemoryFilesystem::new();
+        let mut sink = DiskSink::new("out")
+            .with_filesystem(filesystem.clone())
+            .with_special_files(SpecialFilePolicy::Create);
+        let archive = ZipArchive::new(File::open(get_test_file_path("test_multiple.zip"))?)?;
+        archive.extract_to(&mut sink)?;
+        // Devices need no privileges in memory
+        let mut archive = TarArchive::new(File::open(get_test_file_path("test_special.tar"))?);
+        archive.extract_to(&mut sink)?;
+
+        assert_eq!(
+            filesystem.paths(),
+            [
+                "out",
+                "out/a.txt",
+                "out/b.txt",
+                "out/null",
+                "out/pipe",
+                "out/test1.txt",
+                "out/test2.txt"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(filesystem.read("out/b.txt"), filesystem.read("out/a.txt"));
+        let null: MemoryEntry = filesystem.get("out/null").unwrap();
+        assert!(matches!(
+            null.node,
+            MemoryNode::Special(SpecialFile::CharDevice {
+                major: 1,
+                minor: 3,
+                ..
+            })
+        ));
+        assert!(filesystem.get("out/test1.txt").unwrap().modified.is_some());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_sparse_file() -> io::Result<()> {
+        let path: PathBuf = std::env::temp_dir().join("xpack_test_sparse_file");
+        // Data at both ends of 4 MiB of zeros, and zeros at the end
+        let mut data: Vec<u8> = vec![0u8; 4 << 20];
+        data[..5].copy_from_slice(b"start");
+        data[2 << 20] = 1;
+        OsFilesystem.create_sparse_file(&path, &data)?;
+        assert_eq!(fs::read(&path)?, data);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Counted in 512-byte units. Assumes the temporary directory
+            // supports holes, as tmpfs, ext4 and APFS do
+            assert!(fs::metadata(&path)?.blocks() * 512 < 1 << 20);
+        }
+        fs::remove_file(&path)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_filesystem_operations() -> io::Result<()> {
+        let filesystem: MemoryFilesystem = MemoryFilesystem::new();
+        let path = Path::new("a/b/c.txt");
+        assert!(filesystem.create_file(path, b"data").is_err());
+        assert!(filesystem.create_dir(Path::new("a/b"), false).is_err());
+        filesystem.create_dir(Path::new("a/b"), true)?;
+        filesystem.create_file(path, b"data")?;
+        filesystem.set_permissions(path, 0o600)?;
+        filesystem.create_file(path, b"new")?;
+        assert_eq!(filesystem.read(path), Some(b"new".to_vec()));
+        assert_eq!(filesystem.get(path).unwrap().mode, Some(0o600));
+
+        filesystem.symlink(Path::new("c.txt"), Path::new("a/b/link"))?;
+        assert_eq!(
+            filesystem.metadata(Path::new("a/b/link"))?.unwrap().kind,
+            NodeKind::Symlink
+        );
+        assert!(filesystem
+            .create_dir(Path::new("a/b/c.txt/d"), true)
+            .is_err());
+        assert!(filesystem.remove_file(Path::new("a/b")).is_err());
+        filesystem.rename(path, Path::new("a/c.txt"))?;
+        assert_eq!(filesystem.metadata(path)?, None);
+        assert_eq!(filesystem.read("a/c.txt"), Some(b"new".to_vec()));
+        assert!(filesystem.rename(path, Path::new("a/d.txt")).is_err());
+        filesystem.remove_file(Path::new("a/c.txt"))?;
+        Ok(())
+    }
+}
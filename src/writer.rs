@@ -0,0 +1,1208 @@
+//! Creation of ZIP archives.
+//!
+//! Entries are compressed in memory, so every local header carries the
+//! final sizes and CRC-32 and no data descriptors are needed. Archives are
+//! limited to what fits without Zip64: 65535 entries and 4 GB.
+
+use flate2::write::DeflateEncoder;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::extra::{ExtraField, AES_EXTRA_ID, EXTENDED_TIMESTAMP_EXTRA_ID, UNIX_EXTRA_ID};
+use crate::sink::Sink;
+use crate::zip::{
+    ZipFileEntry, CENTRAL_DIR_SIGNATURE, DATA_DESCRIPTOR_SIGNATURE, END_CENTRAL_DIR_SIGNATURE,
+    FLAG_DATA_DESCRIPTOR, FLAG_ENCRYPTED, LOCAL_FILE_HEADER_SIGNATURE,
+};
+
+// General purpose bit 11: the filename is UTF-8
+const FLAG_UTF8: u16 = 0x0800;
+// General purpose bits 1 and 2 of deflated entries: the level used, as
+// maximum (-8, -9), fast (-2) or super fast (-1) compression
+const FLAG_DEFLATE_MAXIMUM: u16 = 0x0002;
+const FLAG_DEFLATE_FAST: u16 = 0x0004;
+const FLAG_DEFLATE_SUPER_FAST: u16 = 0x0006;
+
+// Version made by: Unix (3) in the upper byte, so the upper half of the
+// external attributes is read as a Unix mode, and spec version 2.0
+const VERSION_MADE_BY: u16 = (3 << 8) | 20;
+
+// File type bits of a Unix mode
+pub(crate) const S_IFREG: u32 = 0o100000;
+pub(crate) const S_IFDIR: u32 = 0o040000;
+// MS-DOS directory attribute, in the low byte of the external attributes
+pub(crate) const DOS_DIRECTORY: u32 = 0x10;
+
+// Extra field of zipalign, padding the local header so the data of a stored
+// entry starts at a multiple of the alignment
+const ALIGNMENT_EXTRA_ID: u16 = 0xd935;
+
+/// Extensions of formats that are compressed already. Deflating them again
+/// costs time and saves next to nothing.
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg", "m4a",
+    "mkv", "mov", "mp3", "mp4", "odp", "ods", "odt", "ogg", "png", "pptx", "rar", "tgz", "webm",
+    "webp", "woff", "woff2", "xlsx", "xz", "zip", "zst",
+];
+
+/// Conventions of file formats built on ZIP, see
+/// [`ZipWriter::with_profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// EPUB and OpenDocument: a `mimetype` entry first, stored and without
+    /// extra fields, so readers find the media type at a fixed offset
+    Epub,
+    /// Android packages: stored entries aligned on 4 bytes and shared
+    /// libraries on 4096, like `zipalign -p`, and `resources.arsc` stored
+    Apk,
+    /// Java archives: the `META-INF/` directory and its `MANIFEST.MF` first
+    Jar,
+}
+
+impl Profile {
+    /// Where an entry named `name` goes among those the format wants
+    /// first, lower first, or past them all. Sorting entries on it, stably,
+    /// gives the order the format expects.
+    pub fn rank(self, name: &str) -> usize {
+        let first: &[&str] = match self {
+            Profile::Epub => &["mimetype"],
+            Profile::Apk => &[],
+            Profile::Jar => &["META-INF", "META-INF/MANIFEST.MF"],
+        };
+        let name: &str = name.trim_end_matches('/');
+        first
+            .iter()
+            .position(|first| *first == name)
+            .unwrap_or(first.len())
+    }
+
+    /// Alignment of the data of a stored entry named `name`, if any.
+    fn alignment(self, name: &str) -> Option<u64> {
+        match self {
+            Profile::Apk if name.ends_with('/') => None,
+            Profile::Apk if name.ends_with(".so") => Some(4096),
+            Profile::Apk => Some(4),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Store,
+    Deflate,
+    /// Zstandard, method 93. Needs the `zstd` feature.
+    Zstd,
+}
+
+impl Method {
+    /// Compression method number recorded in the headers.
+    pub fn id(self) -> u16 {
+        match self {
+            Method::Store => 0,
+            Method::Deflate => 8,
+            Method::Zstd => 93,
+        }
+    }
+}
+
+/// A compression method and level. A level of 0 stores entries whatever
+/// the method, like `zip -0`; no level uses the method's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub method: Method,
+    pub level: Option<u32>,
+}
+
+impl Compression {
+    pub fn new(method: Method, level: Option<u32>) -> Compression {
+        Compression { method, level }
+    }
+
+    pub fn stored() -> Compression {
+        Compression::new(Method::Store, None)
+    }
+
+    /// Compresses `data`, or returns `None` when it should be stored.
+    fn compress(self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if self.level == Some(0) {
+            return Ok(None);
+        }
+        match self.method {
+            Method::Store => Ok(None),
+            Method::Deflate => {
+                let level: flate2::Compression =
+                    self.level.map_or(flate2::Compression::default(), |level| {
+                        flate2::Compression::new(level.min(9))
+                    });
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                Ok(Some(encoder.finish()?))
+            }
+            #[cfg(feature = "zstd")]
+            Method::Zstd => {
+                let level: i32 = self.level.map_or(0, |level| level as i32);
+                Ok(Some(zstd::bulk::compress(data, level)?))
+            }
+            #[cfg(not(feature = "zstd"))]
+            Method::Zstd => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Zstandard compression needs the zstd feature",
+            )),
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new(Method::Deflate, None)
+    }
+}
+
+/// Parses `METHOD[:LEVEL]`, e.g. `store`, `deflate:9` or `zstd`.
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        let (method, level) = match s.split_once(':') {
+            Some((method, level)) => (method, Some(level)),
+            None => (s, None),
+        };
+        let method: Method = match method {
+            "store" => Method::Store,
+            "deflate" => Method::Deflate,
+            "zstd" => Method::Zstd,
+            other => return Err(format!("unknown compression method {}", other)),
+        };
+        let level: Option<u32> = match level {
+            Some(level) => match level.parse::<u32>() {
+                Ok(level) if level <= 9 => Some(level),
+                _ => return Err(format!("level must be 0 to 9, not {}", level)),
+            },
+            None => None,
+        };
+        Ok(Compression::new(method, level))
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let method: &str = match self.method {
+            Method::Store => "store",
+            Method::Deflate => "deflate",
+            Method::Zstd => "zstd",
+        };
+        match self.level {
+            Some(level) => write!(f, "{}:{}", method, level),
+            None => write!(f, "{}", method),
+        }
+    }
+}
+
+/// Filesystem metadata recorded with an entry. Whatever is missing is left
+/// out of the archive or takes a default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EntryMetadata {
+    /// Entries modified before 1980, or without a time, are dated
+    /// 1980-01-01
+    pub modified: Option<SystemTime>,
+    /// Unix permission bits, 0644 for files and 0755 for directories by
+    /// default
+    pub permissions: Option<u32>,
+    /// User and group IDs, recorded in an Info-ZIP Unix extra field
+    pub owner: Option<(u32, u32)>,
+}
+
+impl EntryMetadata {
+    /// The metadata recorded for `entry` in the archive it was read from.
+    pub fn from_entry(entry: &ZipFileEntry) -> EntryMetadata {
+        EntryMetadata {
+            modified: entry.precise_modified(),
+            permissions: entry.unix_permissions(),
+            owner: entry.unix_owner(),
+        }
+    }
+}
+
+/// A file or directory to add with [`ZipWriter::add_parallel`].
+pub enum NewEntry {
+    File {
+        name: String,
+        data: Vec<u8>,
+        metadata: EntryMetadata,
+    },
+    Directory {
+        name: String,
+        metadata: EntryMetadata,
+    },
+    /// An entry of another archive with its data as read by
+    /// [`Entry::read_raw`](crate::zip::Entry::read_raw), copied without
+    /// recompressing it
+    Copy {
+        entry: ZipFileEntry,
+        data: Vec<u8>,
+        metadata: EntryMetadata,
+    },
+}
+
+/// How entries are encoded, shared by the threads compressing them.
+#[derive(Clone)]
+struct Settings {
+    compression: Compression,
+    store_compressed: bool,
+    rules: Vec<(String, Compression)>,
+    reproducible: bool,
+    profile: Option<Profile>,
+}
+
+impl Settings {
+    fn canonical(&self, metadata: &EntryMetadata, directory: bool) -> EntryMetadata {
+        if !self.reproducible {
+            return *metadata;
+        }
+        let executable: bool = metadata.permissions.unwrap_or(0) & 0o111 != 0;
+        EntryMetadata {
+            modified: None,
+            permissions: Some(if directory || executable {
+                0o755
+            } else {
+                0o644
+            }),
+            owner: Some((0, 0)),
+        }
+    }
+
+    fn compression_for(&self, name: &str) -> Compression {
+        match (self.profile, name) {
+            (Some(Profile::Epub), "mimetype") | (Some(Profile::Apk), "resources.arsc") => {
+                return Compression::stored()
+            }
+            _ => {}
+        }
+        if let Some((_, compression)) = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, name))
+        {
+            return *compression;
+        }
+        if self.store_compressed && is_compressed_extension(name) {
+            return Compression::stored();
+        }
+        self.compression
+    }
+
+    /// Compresses a file entry, leaving only the writing to do.
+    fn prepare_file<'a>(
+        &self,
+        name: String,
+        data: Cow<'a, [u8]>,
+        metadata: &EntryMetadata,
+    ) -> io::Result<PreparedEntry<'a>> {
+        let compression: Compression = self.compression_for(&name);
+        let compressed: Option<Vec<u8>> = compression
+            .compress(&data)?
+            // Incompressible data would only grow
+            .filter(|compressed| compressed.len() < data.len());
+        let crc32: u32 = crc32fast::hash(&data);
+        let uncompressed_size: u32 = to_u32(data.len() as u64, &name)?;
+        let (method, body): (u16, Cow<'a, [u8]>) = match compressed {
+            Some(compressed) => (compression.method.id(), Cow::Owned(compressed)),
+            None => (Method::Store.id(), data),
+        };
+        let flags: u16 = match (method, compression.level) {
+            (8, Some(8..)) => FLAG_DEFLATE_MAXIMUM,
+            (8, Some(2)) => FLAG_DEFLATE_FAST,
+            (8, Some(1)) => FLAG_DEFLATE_SUPER_FAST,
+            _ => 0,
+        };
+        let metadata: EntryMetadata = self.canonical(metadata, false);
+        let mode: u32 = S_IFREG | metadata.permissions.unwrap_or(0o644) & 0o7777;
+        // Readers look for the media type right after the name
+        let extra: Vec<u8> = match (self.profile, name.as_str()) {
+            (Some(Profile::Epub), "mimetype") => vec![],
+            _ => extra_fields(&metadata),
+        };
+        Ok(PreparedEntry {
+            compressed_size: to_u32(body.len() as u64, &name)?,
+            name,
+            flags,
+            method,
+            modified: metadata.modified,
+            crc32,
+            uncompressed_size,
+            external_attributes: mode << 16,
+            extra,
+            body,
+        })
+    }
+
+    fn prepare_directory(&self, name: &str, metadata: &EntryMetadata) -> PreparedEntry<'static> {
+        let name: String = if name.ends_with('/') {
+            name.to_string()
+        } else {
+            format!("{}/", name)
+        };
+        let metadata: EntryMetadata = self.canonical(metadata, true);
+        let mode: u32 = S_IFDIR | metadata.permissions.unwrap_or(0o755) & 0o7777;
+        PreparedEntry {
+            name,
+            flags: 0,
+            method: Method::Store.id(),
+            modified: metadata.modified,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            external_attributes: (mode << 16) | DOS_DIRECTORY,
+            extra: extra_fields(&metadata),
+            body: Cow::Borrowed(&[]),
+        }
+    }
+
+    fn prepare_copy(
+        &self,
+        entry: ZipFileEntry,
+        data: Vec<u8>,
+        metadata: &EntryMetadata,
+    ) -> io::Result<PreparedEntry<'static>> {
+        if entry.filename.ends_with('/') {
+            return Ok(self.prepare_directory(&entry.filename, metadata));
+        }
+        let metadata: EntryMetadata = self.canonical(metadata, false);
+        let mode: u32 = S_IFREG | metadata.permissions.unwrap_or(0o644) & 0o7777;
+        // Sizes and CRC-32 go in the local header, so a data descriptor is
+        // only kept for encrypted entries, whose password check may depend
+        // on it
+        let encrypted: bool = entry.flags & FLAG_ENCRYPTED != 0;
+        let mut flags: u16 = entry.flags & !FLAG_UTF8;
+        if !encrypted {
+            flags &= !FLAG_DATA_DESCRIPTOR;
+        }
+        let extra: Vec<u8> = [extra_fields(&metadata), aes_extra_field(&entry)].concat();
+        Ok(PreparedEntry {
+            compressed_size: to_u32(data.len() as u64, &entry.filename)?,
+            name: entry.filename,
+            flags,
+            method: entry.compression_method,
+            modified: metadata.modified,
+            crc32: entry.crc32,
+            uncompressed_size: entry.uncompressed_size,
+            external_attributes: mode << 16,
+            extra,
+            body: Cow::Owned(data),
+        })
+    }
+
+    fn prepare(&self, entry: NewEntry) -> io::Result<PreparedEntry<'static>> {
+        match entry {
+            NewEntry::File {
+                name,
+                data,
+                metadata,
+            } => self.prepare_file(name, Cow::Owned(data), &metadata),
+            NewEntry::Directory { name, metadata } => Ok(self.prepare_directory(&name, &metadata)),
+            NewEntry::Copy {
+                entry,
+                data,
+                metadata,
+            } => self.prepare_copy(entry, data, &metadata),
+        }
+    }
+}
+
+/// Writes a ZIP archive to `out`, entry by entry. Nothing is valid until
+/// [`ZipWriter::finish`] writes the central directory.
+pub struct ZipWriter<W: Write> {
+    out: W,
+    // Bytes written to `out` so far, i.e. the offset of the next header
+    offset: u64,
+    central_directory: Vec<u8>,
+    entry_count: usize,
+    settings: Settings,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Entries are deflated with the default level, except those whose
+    /// extension is in [`COMPRESSED_EXTENSIONS`], which are stored.
+    pub fn new(out: W) -> ZipWriter<W> {
+        ZipWriter {
+            out,
+            offset: 0,
+            central_directory: vec![],
+            entry_count: 0,
+            settings: Settings {
+                compression: Compression::default(),
+                store_compressed: true,
+                rules: vec![],
+                reproducible: false,
+                profile: None,
+            },
+        }
+    }
+
+    /// Compression of entries that no rule applies to.
+    pub fn with_compression(mut self, compression: Compression) -> ZipWriter<W> {
+        self.settings.compression = compression;
+        self
+    }
+
+    /// Whether to store entries whose extension says they're compressed
+    /// already instead of compressing them again.
+    pub fn with_store_compressed(mut self, store_compressed: bool) -> ZipWriter<W> {
+        self.settings.store_compressed = store_compressed;
+        self
+    }
+
+    /// Compresses entries matching the glob `pattern` with `compression`.
+    /// The first matching rule wins over the defaults. See [`glob_match`].
+    pub fn with_rule(mut self, pattern: &str, compression: Compression) -> ZipWriter<W> {
+        self.settings.rules.push((pattern.to_string(), compression));
+        self
+    }
+
+    /// Replaces the metadata of every entry with canonical values, so that
+    /// the same entries added in the same order give byte-identical
+    /// archives: no modification time (1980-01-01 and no extended
+    /// timestamp), permissions 0755 for directories and executables and
+    /// 0644 otherwise, and owner 0:0.
+    pub fn with_reproducible(mut self, reproducible: bool) -> ZipWriter<W> {
+        self.settings.reproducible = reproducible;
+        self
+    }
+
+    /// Follows the conventions of `profile` for the entries added: which
+    /// ones are stored, their extra fields and the alignment of their data.
+    /// Entries are written in the order they are added, see
+    /// [`Profile::rank`] for the order the format expects.
+    pub fn with_profile(mut self, profile: Option<Profile>) -> ZipWriter<W> {
+        self.settings.profile = profile;
+        self
+    }
+
+    /// Returns how an entry named `name` would be compressed.
+    pub fn compression_for(&self, name: &str) -> Compression {
+        self.settings.compression_for(name)
+    }
+
+    /// Adds a file entry named `name` holding `data`.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        metadata: &EntryMetadata,
+    ) -> io::Result<()> {
+        let entry: PreparedEntry =
+            self.settings
+                .prepare_file(name.to_string(), Cow::Borrowed(data), metadata)?;
+        self.write_entry(&entry)
+    }
+
+    /// Adds a directory entry; a trailing `/` is appended to `name` if
+    /// missing.
+    pub fn add_directory(&mut self, name: &str, metadata: &EntryMetadata) -> io::Result<()> {
+        let entry: PreparedEntry = self.settings.prepare_directory(name, metadata);
+        self.write_entry(&entry)
+    }
+
+    /// Adds the entries returned by `jobs`, in order, running the jobs and
+    /// compressing their results on `threads` threads. Jobs typically read
+    /// a file. Only a few entries per thread are held in memory at once:
+    /// those being compressed and those waiting for an earlier one to be
+    /// written. The first error stops the remaining jobs.
+    pub fn add_parallel<I, F>(&mut self, jobs: I, threads: usize) -> io::Result<()>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> io::Result<NewEntry> + Send,
+    {
+        let settings: Settings = self.settings.clone();
+        // wasm32-unknown-unknown has no threads
+        if threads <= 1 || cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            for job in jobs {
+                let entry: PreparedEntry = settings.prepare(job()?)?;
+                self.write_entry(&entry)?;
+            }
+            return Ok(());
+        }
+
+        let (job_sender, job_receiver) = mpsc::channel::<(usize, F)>();
+        let (result_sender, result_receiver) =
+            mpsc::channel::<(usize, io::Result<PreparedEntry<'static>>)>();
+        let job_receiver: Mutex<mpsc::Receiver<(usize, F)>> = Mutex::new(job_receiver);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let result_sender = result_sender.clone();
+                let (job_receiver, settings) = (&job_receiver, &settings);
+                scope.spawn(move || loop {
+                    let job: Option<(usize, F)> = job_receiver.lock().unwrap().recv().ok();
+                    let Some((index, job)) = job else {
+                        break;
+                    };
+                    let entry: io::Result<PreparedEntry> = job().and_then(|e| settings.prepare(e));
+                    if result_sender.send((index, entry)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_sender);
+            // Owned by this closure, so that returning, early on an error
+            // too, closes the queue and stops the threads once the jobs
+            // already sent are done
+            let job_sender: mpsc::Sender<(usize, F)> = job_sender;
+
+            // Entries finished ahead of the next one to write, by index
+            let mut pending: BTreeMap<usize, PreparedEntry> = BTreeMap::new();
+            let mut jobs = jobs.into_iter().enumerate();
+            let (mut sent, mut written): (usize, usize) = (0, 0);
+            loop {
+                // Keep every thread busy with a job in reserve, and no more
+                while sent - written < threads * 2 {
+                    let Some(job) = jobs.next() else {
+                        break;
+                    };
+                    job_sender.send(job).unwrap();
+                    sent += 1;
+                }
+                if written == sent {
+                    return Ok(());
+                }
+                let (index, entry) = result_receiver.recv().unwrap();
+                pending.insert(index, entry?);
+                while let Some(entry) = pending.remove(&written) {
+                    self.write_entry(&entry)?;
+                    written += 1;
+                }
+            }
+        })
+    }
+
+    fn write_entry(&mut self, entry: &PreparedEntry<'_>) -> io::Result<()> {
+        if self.entry_count == u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "More than 65535 entries need Zip64, which isn't supported",
+            ));
+        }
+        let local_offset: u32 = to_u32(self.offset, &entry.name)?;
+        let name: &[u8] = entry.name.as_bytes();
+        let flags: u16 = if entry.name.is_ascii() {
+            entry.flags
+        } else {
+            entry.flags | FLAG_UTF8
+        };
+        let (date, time) = to_dos_datetime(entry.modified);
+
+        // Fields shared by the local header and the central directory record:
+        // [2 bytes]  Version needed to extract
+        // [2 bytes]  General purpose bit flag
+        // [2 bytes]  Compression method
+        // [2 bytes]  Last mod file time
+        // [2 bytes]  Last mod file date
+        // [4 bytes]  CRC-32
+        // [4 bytes]  Compressed size
+        // [4 bytes]  Uncompressed size
+        // [2 bytes]  File name length
+        let version_needed: u16 = match entry.method {
+            0 | 8 => 20,
+            9 => 21,
+            12 => 46,
+            _ => 63,
+        };
+        let mut common: Vec<u8> = Vec::with_capacity(24);
+        common.extend_from_slice(&version_needed.to_le_bytes());
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&entry.method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&entry.crc32.to_le_bytes());
+        common.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        common.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        // Local file header:
+        // [4 bytes]  Signature
+        // [24 bytes] Shared fields
+        // [2 bytes]  Extra field length
+        // [variable] File name
+        // [variable] Extra field, with any alignment padding, which only
+        //            goes here as it is what moves the data
+        let local_extra: Vec<u8> = match self.settings.profile {
+            Some(profile) if entry.method == Method::Store.id() => {
+                let data_start: u64 = self.offset + 30 + name.len() as u64;
+                let alignment: Option<u64> = profile.alignment(&entry.name);
+                [
+                    entry.extra.clone(),
+                    alignment_padding(data_start, &entry.extra, alignment),
+                ]
+                .concat()
+            }
+            _ => entry.extra.clone(),
+        };
+        let mut local: Vec<u8> = Vec::with_capacity(30 + name.len() + local_extra.len());
+        local.extend_from_slice(&LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        local.extend_from_slice(&common);
+        local.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        local.extend_from_slice(name);
+        local.extend_from_slice(&local_extra);
+        self.out.write_all(&local)?;
+        self.out.write_all(&entry.body)?;
+        self.offset += (local.len() + entry.body.len()) as u64;
+        if flags & FLAG_DATA_DESCRIPTOR != 0 {
+            // [4 bytes]  Signature
+            // [4 bytes]  CRC-32
+            // [4 bytes]  Compressed size
+            // [4 bytes]  Uncompressed size
+            let mut descriptor: Vec<u8> = Vec::with_capacity(16);
+            descriptor.extend_from_slice(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes());
+            descriptor.extend_from_slice(&entry.crc32.to_le_bytes());
+            descriptor.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            descriptor.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+            self.out.write_all(&descriptor)?;
+            self.offset += descriptor.len() as u64;
+        }
+
+        // Central directory record:
+        // [4 bytes]  Signature
+        // [2 bytes]  Version made by
+        // [24 bytes] Shared fields
+        // [2 bytes]  Extra field length
+        // [2 bytes]  File comment length
+        // [2 bytes]  Disk number start
+        // [2 bytes]  Internal file attributes
+        // [4 bytes]  External file attributes
+        // [4 bytes]  Relative offset of local header
+        // [variable] File name
+        // [variable] Extra field
+        let record: &mut Vec<u8> = &mut self.central_directory;
+        record.extend_from_slice(&CENTRAL_DIR_SIGNATURE.to_le_bytes());
+        record.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        record.extend_from_slice(&common);
+        record.extend_from_slice(&(entry.extra.len() as u16).to_le_bytes());
+        record.extend_from_slice(&[0u8; 6]);
+        record.extend_from_slice(&entry.external_attributes.to_le_bytes());
+        record.extend_from_slice(&local_offset.to_le_bytes());
+        record.extend_from_slice(name);
+        record.extend_from_slice(&entry.extra);
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let directory_offset: u32 = to_u32(self.offset, "the central directory")?;
+        let directory_size: u32 =
+            to_u32(self.central_directory.len() as u64, "the central directory")?;
+        self.out.write_all(&self.central_directory)?;
+
+        // [4 bytes]  Signature
+        // [2 bytes]  Number of this disk
+        // [2 bytes]  Disk where the central directory starts
+        // [2 bytes]  Number of central directory records on this disk
+        // [2 bytes]  Total number of central directory records
+        // [4 bytes]  Size of the central directory
+        // [4 bytes]  Offset of the central directory
+        // [2 bytes]  Comment length
+        let count: [u8; 2] = (self.entry_count as u16).to_le_bytes();
+        let mut end: Vec<u8> = Vec::with_capacity(22);
+        end.extend_from_slice(&END_CENTRAL_DIR_SIGNATURE.to_le_bytes());
+        end.extend_from_slice(&[0u8; 4]);
+        end.extend_from_slice(&count);
+        end.extend_from_slice(&count);
+        end.extend_from_slice(&directory_size.to_le_bytes());
+        end.extend_from_slice(&directory_offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Adds every decoded entry, recompressing it with the writer's settings,
+/// which converts archives entry by entry. Links and special files are
+/// skipped, ZIP has no place for them.
+impl<W: Write> Sink for ZipWriter<W> {
+    // Nothing is written but the archive itself
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+        let metadata: EntryMetadata = EntryMetadata::from_entry(entry);
+        if entry.filename.ends_with('/') {
+            self.add_directory(&entry.filename, &metadata)
+        } else {
+            self.add_file(&entry.filename, data, &metadata)
+        }
+    }
+}
+
+/// An entry ready to be written: compressed, with its header fields.
+struct PreparedEntry<'a> {
+    name: String,
+    flags: u16,
+    method: u16,
+    modified: Option<SystemTime>,
+    crc32: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    external_attributes: u32,
+    extra: Vec<u8>,
+    body: Cow<'a, [u8]>,
+}
+
+/// Encodes a zipalign extra field making the data of an entry, whose local
+/// header has the fixed fields and name up to `data_start` followed by
+/// `extra`, start at a multiple of `alignment`. Empty without alignment.
+fn alignment_padding(data_start: u64, extra: &[u8], alignment: Option<u64>) -> Vec<u8> {
+    let Some(alignment) = alignment else {
+        return vec![];
+    };
+    // [2 bytes]  Header ID
+    // [2 bytes]  Data size
+    // [2 bytes]  Alignment
+    // [variable] Zeros
+    let unpadded: u64 = data_start + extra.len() as u64 + 6;
+    let padding: u64 = (alignment - unpadded % alignment) % alignment;
+    let mut field: Vec<u8> = Vec::with_capacity(6 + padding as usize);
+    field.extend_from_slice(&ALIGNMENT_EXTRA_ID.to_le_bytes());
+    field.extend_from_slice(&(2 + padding as u16).to_le_bytes());
+    field.extend_from_slice(&(alignment as u16).to_le_bytes());
+    field.resize(6 + padding as usize, 0);
+    field
+}
+
+/// Encodes the extra fields recording `metadata`: an extended timestamp
+/// with the modification time and the owner in a Unix extra field. Both
+/// headers get the same blocks.
+fn extra_fields(metadata: &EntryMetadata) -> Vec<u8> {
+    let mut extra: Vec<u8> = vec![];
+    let mtime: Option<i32> = metadata
+        .modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since| i32::try_from(since.as_secs()).ok());
+    if let Some(mtime) = mtime {
+        // [1 byte]   Flags, modification time only
+        // [4 bytes]  Modification time
+        extra.extend_from_slice(&EXTENDED_TIMESTAMP_EXTRA_ID.to_le_bytes());
+        extra.extend_from_slice(&5u16.to_le_bytes());
+        extra.push(1);
+        extra.extend_from_slice(&mtime.to_le_bytes());
+    }
+    if let Some((uid, gid)) = metadata.owner {
+        // [1 byte]   Version
+        // [1 byte]   UID size, then the UID
+        // [1 byte]   GID size, then the GID
+        extra.extend_from_slice(&UNIX_EXTRA_ID.to_le_bytes());
+        extra.extend_from_slice(&11u16.to_le_bytes());
+        extra.extend_from_slice(&[1, 4]);
+        extra.extend_from_slice(&uid.to_le_bytes());
+        extra.push(4);
+        extra.extend_from_slice(&gid.to_le_bytes());
+    }
+    extra
+}
+
+/// Re-encodes the WinZip AES extra field of a copied entry, without which
+/// its data can't be decrypted.
+fn aes_extra_field(entry: &ZipFileEntry) -> Vec<u8> {
+    let mut extra: Vec<u8> = vec![];
+    for field in &entry.extra_fields {
+        let ExtraField::Aes {
+            vendor_version,
+            strength,
+            compression_method,
+        } = field
+        else {
+            continue;
+        };
+        // [2 bytes]  Vendor version
+        // [2 bytes]  Vendor ID ("AE")
+        // [1 byte]   Key strength
+        // [2 bytes]  Actual compression method
+        extra.extend_from_slice(&AES_EXTRA_ID.to_le_bytes());
+        extra.extend_from_slice(&7u16.to_le_bytes());
+        extra.extend_from_slice(&vendor_version.to_le_bytes());
+        extra.extend_from_slice(b"AE");
+        extra.push(*strength);
+        extra.extend_from_slice(&compression_method.to_le_bytes());
+    }
+    extra
+}
+
+fn to_u32(value: u64, what: &str) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} is past 4 GB, which needs Zip64", what),
+        )
+    })
+}
+
+fn is_compressed_extension(name: &str) -> bool {
+    let Some((_, extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    COMPRESSED_EXTENSIONS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(extension))
+}
+
+/// Matches `name` against a glob: `*` matches within a path component, `**`
+/// across components and `?` a single character. Patterns without a `/`
+/// are matched against the last component only, so `*.png` matches
+/// `images/logo.png`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let name: &str = if pattern.contains('/') {
+        name
+    } else {
+        name.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        ['*', '*', rest @ ..] => (0..=name.len()).any(|skip| match_from(rest, &name[skip..])),
+        ['*', rest @ ..] => {
+            let component: usize = name.iter().position(|&c| c == '/').unwrap_or(name.len());
+            (0..=component).any(|skip| match_from(rest, &name[skip..]))
+        }
+        ['?', rest @ ..] => matches!(name, [c, ..] if *c != '/') && match_from(rest, &name[1..]),
+        [c, rest @ ..] => name.first() == Some(c) && match_from(rest, &name[1..]),
+    }
+}
+
+/// Converts `time` to an MS-DOS date and time, the inverse of
+/// [`dos_datetime`](crate::zip::dos_datetime). Odd seconds are rounded
+/// down; times outside 1980 to 2107 are clamped.
+pub fn to_dos_datetime(time: Option<SystemTime>) -> (u16, u16) {
+    // 1980-01-01 00:00:00
+    const DOS_EPOCH: (u16, u16) = ((1 << 5) | 1, 0);
+    let Some(secs) = time
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs() as i64)
+    else {
+        return DOS_EPOCH;
+    };
+
+    // Civil date of a day count since 1970-01-01, the inverse of the
+    // computation in `dos_datetime`
+    let days: i64 = secs / 86400 + 719468;
+    let era: i64 = days / 146097;
+    let day_of_era: i64 = days - era * 146097;
+    let year_of_era: i64 =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month: i64 = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year: i64 = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        return DOS_EPOCH;
+    }
+    if year > 2107 {
+        return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29);
+    }
+    let seconds_of_day: i64 = secs % 86400;
+    let date: u16 = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time: u16 = (((seconds_of_day / 3600) << 11)
+        | ((seconds_of_day / 60 % 60) << 5)
+        | (seconds_of_day % 60 / 2)) as u16;
+    (date, time)
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::zip::{dos_datetime, ZipArchive};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_and_read_back() -> io::Result<()> {
+        let text: Vec<u8> = b"compressible ".repeat(100);
+        let modified: SystemTime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()))
+            .with_compression(Compression::new(Method::Deflate, Some(9)))
+            .with_rule("logs/**", Compression::stored());
+        let metadata: EntryMetadata = EntryMetadata {
+            modified: Some(modified),
+            permissions: Some(0o600),
+            owner: Some((1000, 100)),
+        };
+        writer.add_directory("logs", &metadata)?;
+        writer.add_file("logs/today.log", &text, &metadata)?;
+        writer.add_file("notes.txt", &text, &metadata)?;
+        writer.add_file("image.PNG", &text, &EntryMetadata::default())?;
+        writer.add_file("tiny.txt", b"x", &EntryMetadata::default())?;
+        writer.add_file("caf\u{e9}.txt", b"utf-8 name", &EntryMetadata::default())?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+
+        let archive = ZipArchive::new(Cursor::new(bytes))?;
+        let mut methods: Vec<(String, u16)> = vec![];
+        for entry in archive.entries() {
+            let entry = entry?;
+            methods.push((entry.filename.clone(), entry.compression_method));
+        }
+        assert_eq!(
+            methods,
+            [
+                (String::from("logs/"), 0),
+                (String::from("logs/today.log"), 0),
+                (String::from("notes.txt"), 8),
+                (String::from("image.PNG"), 0),
+                // Deflating a single byte makes it bigger
+                (String::from("tiny.txt"), 0),
+                (String::from("caf\u{e9}.txt"), 0),
+            ]
+        );
+        let notes = archive.by_name("notes.txt")?;
+        assert_eq!(notes.metadata().modified, Some(modified));
+        assert_eq!(notes.metadata().unix_owner(), Some((1000, 100)));
+        assert_eq!(notes.read_to_vec()?.unwrap(), text);
+        assert_eq!(
+            archive.by_name("caf\u{e9}.txt")?.read_to_vec()?.unwrap(),
+            b"utf-8 name"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_round_trip() -> io::Result<()> {
+        let text: Vec<u8> = b"zstandard ".repeat(100);
+        let mut writer =
+            ZipWriter::new(Cursor::new(Vec::new())).with_compression("zstd:9".parse().unwrap());
+        writer.add_file("a.txt", &text, &EntryMetadata::default())?;
+        let archive = ZipArchive::new(Cursor::new(writer.finish()?.into_inner()))?;
+        let entry = archive.by_name("a.txt")?;
+        assert_eq!(entry.metadata().compression_method, 93);
+        assert_eq!(entry.read_to_vec()?.unwrap(), text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reproducible_ignores_metadata() -> io::Result<()> {
+        let create = |metadata: EntryMetadata| -> io::Result<Vec<u8>> {
+            let mut writer = ZipWriter::new(Vec::new()).with_reproducible(true);
+            writer.add_directory("bin", &metadata)?;
+            writer.add_file("bin/run", b"#!/bin/sh\n", &metadata)?;
+            writer.finish()
+        };
+        let first: Vec<u8> = create(EntryMetadata {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            permissions: Some(0o700),
+            owner: Some((1000, 1000)),
+        })?;
+        let second: Vec<u8> = create(EntryMetadata {
+            modified: Some(SystemTime::now()),
+            permissions: Some(0o751),
+            owner: Some((0, 20)),
+        })?;
+        assert_eq!(first, second);
+
+        let archive = ZipArchive::new(Cursor::new(first))?;
+        let entry = archive.by_name("bin/run")?;
+        assert_eq!(entry.metadata().modified, dos_datetime(0x21, 0));
+        assert_eq!(entry.metadata().unix_owner(), Some((0, 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_keeps_compressed_data() -> io::Result<()> {
+        let path: String = format!(
+            "{}/test_files/test_multiple.zip",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let source = ZipArchive::open_readonly(&path)?;
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for entry in source.entries() {
+            let entry: ZipFileEntry = entry?;
+            let data: Vec<u8> = source.read_raw(&entry)?;
+            let metadata: EntryMetadata = EntryMetadata::from_entry(&entry);
+            writer.add_parallel(
+                [move || {
+                    Ok(NewEntry::Copy {
+                        entry,
+                        data,
+                        metadata,
+                    })
+                }],
+                1,
+            )?;
+        }
+        let archive = ZipArchive::new(Cursor::new(writer.finish()?.into_inner()))?;
+        for name in ["test1.txt", "test2.txt"] {
+            let (old, new) = (source.by_name(name)?, archive.by_name(name)?);
+            assert_eq!(new.read_raw()?, old.read_raw()?);
+            assert_eq!(new.metadata().crc32, old.metadata().crc32);
+            assert_eq!(new.read_to_vec()?, old.read_to_vec()?);
+            assert_eq!(
+                new.metadata().precise_modified(),
+                old.metadata().precise_modified()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_keeps_encryption() -> io::Result<()> {
+        let aes: ExtraField = ExtraField::Aes {
+            vendor_version: 2,
+            strength: 3,
+            compression_method: 8,
+        };
+        // Encrypted data is copied as it is, whatever it holds
+        let entry: ZipFileEntry = ZipFileEntry {
+            filename: String::from("secret.txt"),
+            flags: FLAG_ENCRYPTED | FLAG_DATA_DESCRIPTOR,
+            compression_method: 99,
+            uncompressed_size: 100,
+            extra_fields: vec![aes.clone()],
+            ..Default::default()
+        };
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let data: Vec<u8> = vec![0xa5; 40];
+        let job = || {
+            Ok(NewEntry::Copy {
+                entry,
+                data,
+                metadata: EntryMetadata::default(),
+            })
+        };
+        writer.add_parallel([job], 1)?;
+        let archive = ZipArchive::new(Cursor::new(writer.finish()?.into_inner()))?;
+        let copied = archive.by_name("secret.txt")?;
+        assert_eq!(copied.metadata().compression_method, 99);
+        assert_eq!(copied.metadata().flags & FLAG_ENCRYPTED, FLAG_ENCRYPTED);
+        assert!(copied.metadata().extra_fields.contains(&aes));
+        assert_eq!(copied.read_raw()?, [0xa5; 40]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_parallel_keeps_order() -> io::Result<()> {
+        let create = |threads: usize| -> io::Result<Vec<u8>> {
+            let jobs = (0..50).map(|i| {
+                move || {
+                    Ok(NewEntry::File {
+                        name: format!("file{}.txt", i),
+                        data: format!("contents of {} ", i).repeat(i * 10).into_bytes(),
+                        metadata: EntryMetadata::default(),
+                    })
+                }
+            });
+            let mut writer = ZipWriter::new(Vec::new());
+            writer.add_parallel(jobs, threads)?;
+            writer.finish()
+        };
+        assert_eq!(create(4)?, create(1)?);
+
+        let mut writer = ZipWriter::new(Vec::new());
+        let jobs = (0..50).map(|i| {
+            move || match i {
+                20 => Err(io::Error::new(io::ErrorKind::NotFound, "missing")),
+                _ => Ok(NewEntry::Directory {
+                    name: format!("dir{}", i),
+                    metadata: EntryMetadata::default(),
+                }),
+            }
+        });
+        let err = writer.add_parallel(jobs, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.png", "images/logo.png"));
+        assert!(!glob_match("*.png", "logo.png.txt"));
+        assert!(glob_match("images/*.png", "images/logo.png"));
+        assert!(!glob_match("images/*.png", "images/icons/logo.png"));
+        assert!(glob_match("images/**.png", "images/icons/logo.png"));
+        assert!(glob_match("log?.txt", "log1.txt"));
+        assert!(!glob_match("log?.txt", "log10.txt"));
+    }
+
+    #[test]
+    fn test_to_dos_datetime() {
+        for secs in [315_532_800u64, 951_782_400, 1_600_000_000, 4_102_444_798] {
+            let time: SystemTime = UNIX_EPOCH + Duration::from_secs(secs);
+            let (date, dos_time) = to_dos_datetime(Some(time));
+            assert_eq!(dos_datetime(date, dos_time), Some(time), "{}", secs);
+        }
+        assert_eq!(to_dos_datetime(Some(UNIX_EPOCH)), to_dos_datetime(None));
+        assert_eq!(
+            "deflate:9".parse(),
+            Ok(Compression::new(Method::Deflate, Some(9)))
+        );
+        assert!("deflate:10".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_profiles() -> io::Result<()> {
+        let metadata: EntryMetadata = EntryMetadata {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            ..Default::default()
+        };
+        let mut names: Vec<&str> = vec!["OEBPS/", "OEBPS/book.xhtml", "mimetype"];
+        names.sort_by_key(|name| Profile::Epub.rank(name));
+        assert_eq!(names, ["mimetype", "OEBPS/", "OEBPS/book.xhtml"]);
+        let mut names: Vec<&str> = vec!["a.class", "META-INF/MANIFEST.MF", "META-INF/"];
+        names.sort_by_key(|name| Profile::Jar.rank(name));
+        assert_eq!(names, ["META-INF/", "META-INF/MANIFEST.MF", "a.class"]);
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).with_profile(Some(Profile::Epub));
+        writer.add_file("mimetype", b"application/epub+zip", &metadata)?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+        // The media type is at offset 38, right after the name
+        assert_eq!(&bytes[26..30], &[8, 0, 0, 0]);
+        assert_eq!(&bytes[38..58], b"application/epub+zip");
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()))
+            .with_compression(Compression::stored())
+            .with_profile(Some(Profile::Apk));
+        writer.add_file("resources.arsc", &b"table ".repeat(100), &metadata)?;
+        writer.add_directory("lib", &metadata)?;
+        writer.add_file("a.txt", b"odd", &metadata)?;
+        writer.add_file("lib/libx.so", b"elf", &metadata)?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+        let archive = ZipArchive::new(Cursor::new(bytes.clone()))?;
+        for entry in archive.entries() {
+            let entry = entry?;
+            assert_eq!(entry.compression_method, 0, "{}", entry.filename);
+            let header: &[u8] = &bytes[entry.file_offset as usize..];
+            let name_length = u16::from_le_bytes([header[26], header[27]]) as u64;
+            let extra_length = u16::from_le_bytes([header[28], header[29]]) as u64;
+            let data_start: u64 = entry.file_offset + 30 + name_length + extra_length;
+            let alignment: u64 = match entry.filename.as_str() {
+                "lib/" => 1,
+                "lib/libx.so" => 4096,
+                _ => 4,
+            };
+            assert_eq!(data_start % alignment, 0, "{}", entry.filename);
+        }
+        let archive = ZipArchive::new(Cursor::new(bytes))?;
+        assert_eq!(
+            archive.by_name("lib/libx.so")?.read_to_vec()?.unwrap(),
+            b"elf"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,3316 @@
+//! ZIP archive parsing and entry decoding.
+
+use crate::cancel::CancellationToken;
+use crate::diff::normalize_name;
+use crate::encoding::{decode_comment, decode_filename, FLAG_UTF8};
+use crate::error::{Limit, SkippedEntry, XpackError};
+use crate::extra::{find_extra_field, parse_extra_fields, ExtraField, ZIP64_EXTRA_ID};
+use crate::index::{self, Fingerprint};
+use crate::method::Codecs;
+use crate::sink::{MemorySink, NullSink, Sink};
+#[cfg(feature = "fs")]
+use crate::split::SplitReader;
+use crate::writer::{DOS_DIRECTORY, S_IFDIR};
+use std::io::{self, BufReader, SeekFrom};
+use std::{
+    borrow::Cow,
+    cell::{OnceCell, RefCell},
+    cmp::min,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::File,
+    io::{Read, Seek, Write},
+    ops::ControlFlow,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const LOCAL_FILE_HEADER_SIGNATURE: i32 = 0x04034b50;
+pub(crate) const CENTRAL_DIR_SIGNATURE: i32 = 0x02014b50;
+pub(crate) const END_CENTRAL_DIR_SIGNATURE: i32 = 0x06054b50;
+const ZIP64_END_CENTRAL_DIR_SIGNATURE: i32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: i32 = 0x07064b50;
+pub(crate) const DATA_DESCRIPTOR_SIGNATURE: i32 = 0x08074b50;
+
+// General purpose bit 3: CRC-32 and sizes are zero in the local header and
+// the real values follow the compressed data in a data descriptor.
+pub(crate) const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+// General purpose bit 0: the entry is encrypted
+pub(crate) const FLAG_ENCRYPTED: u16 = 0x0001;
+// General purpose bit 5: the data is a PKWARE patch against another file
+const FLAG_PATCHED_DATA: u16 = 0x0020;
+// General purpose bit 6: PKWARE strong encryption, along with bit 0
+const FLAG_STRONG_ENCRYPTION: u16 = 0x0040;
+// Method recorded for WinZip AES entries, the real one is in the extra field
+const AES_METHOD: u16 = 99;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+// Fixed size of the end of central directory record and the furthest from
+// the end of the file it can start (maximal comment length is 65535)
+const EOCD_SIZE: usize = 22;
+pub(crate) const EOCD_MAX_SEARCH: u64 = EOCD_SIZE as u64 + u16::MAX as u64;
+
+// Fixed sizes of the Zip64 end of central directory record, without
+// extensible data, and of the locator between it and the classic record
+const ZIP64_EOCD_SIZE: usize = 56;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+
+// Buffer of the reader shared by an archive's entries. Larger buffers speed
+// up sequential reads no further and slow down random access by name
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+// Most memory reserved for decompressed data before any of it is decoded,
+// since recorded sizes can't be trusted
+const MAX_CAPACITY_HINT: u64 = 16 * 1024 * 1024;
+
+pub(crate) struct EndCentralDirectory {
+    disk_num: u16,
+    start_disk: u16,
+    dir_size: u64,
+    dir_offset: u64,
+    comment_length: u16,
+    // Where the Zip64 record that the saturated fields were taken from
+    // starts, which is where the central directory ends
+    zip64_position: Option<u64>,
+}
+
+impl EndCentralDirectory {
+    /// Checks that a record found at `position` accounts for exactly the
+    /// rest of the file and that its central directory lies before it.
+    fn is_consistent(&self, position: u64, file_size: u64) -> bool {
+        self.ends_at(position, file_size)
+            && self
+                .dir_offset
+                .checked_add(self.dir_size)
+                .is_some_and(|end| end <= self.directory_end(position))
+    }
+
+    /// Where the central directory ends for a record found at `position`:
+    /// right before the record, or before its Zip64 record.
+    pub(crate) fn directory_end(&self, position: u64) -> u64 {
+        self.zip64_position.unwrap_or(position)
+    }
+
+    fn is_saturated(&self) -> bool {
+        self.disk_num == u16::MAX
+            || self.start_disk == u16::MAX
+            || self.dir_size == u32::MAX as u64
+            || self.dir_offset == u32::MAX as u64
+    }
+
+    /// Takes the saturated fields of a record found at `pos` in `buf` from
+    /// the Zip64 record in front of it, if there is one.
+    ///
+    /// Writers put the Zip64 record right before its locator. The offset
+    /// the locator records is left alone, since prepended data would shift
+    /// it.
+    fn read_zip64(&mut self, buf: &[u8], buf_start: u64, pos: usize) {
+        // Zip64 End of Central Directory Record:
+        // [Signature (4 bytes)]
+        // [Record Size (8 bytes)]
+        // [Version Made By (2 bytes)]
+        // [Version Needed (2 bytes)]
+        // [Disk Number (4 bytes)]
+        // [Start Disk (4 bytes)]
+        // [Disk Entries (8 bytes)]
+        // [Total Entries (8 bytes)]
+        // [Directory Size (8 bytes)]
+        // [Directory Offset (8 bytes)]
+        // Zip64 End of Central Directory Locator:
+        // [Signature (4 bytes)]
+        // [Record Disk (4 bytes)]
+        // [Record Offset (8 bytes)]
+        // [Total Disks (4 bytes)]
+        let Some(start) = pos.checked_sub(ZIP64_LOCATOR_SIZE + ZIP64_EOCD_SIZE) else {
+            return;
+        };
+        let record: &[u8] = &buf[start..start + ZIP64_EOCD_SIZE];
+        let locator: &[u8] = &buf[start + ZIP64_EOCD_SIZE..pos];
+        if record[0..4] != ZIP64_END_CENTRAL_DIR_SIGNATURE.to_le_bytes()
+            || locator[0..4] != ZIP64_LOCATOR_SIGNATURE.to_le_bytes()
+        {
+            eprintln!("Saturated end of central directory record without a Zip64 record");
+            return;
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(record[i..i + 8].try_into().unwrap());
+        if self.disk_num == u16::MAX {
+            self.disk_num = u32_at(16).try_into().unwrap_or(u16::MAX);
+        }
+        if self.start_disk == u16::MAX {
+            self.start_disk = u32_at(20).try_into().unwrap_or(u16::MAX);
+        }
+        if self.dir_size == u32::MAX as u64 {
+            self.dir_size = u64_at(40);
+        }
+        if self.dir_offset == u32::MAX as u64 {
+            self.dir_offset = u64_at(48);
+        }
+        self.zip64_position = Some(buf_start + start as u64);
+    }
+
+    fn ends_at(&self, position: u64, file_size: u64) -> bool {
+        position + EOCD_SIZE as u64 + self.comment_length as u64 == file_size
+    }
+
+    fn is_split(&self) -> bool {
+        self.disk_num != 0 || self.start_disk != 0
+    }
+
+    /// Offset of the central directory as recorded, resolved through the
+    /// disk it starts on.
+    pub(crate) fn recorded_offset(&self, disk_starts: &[u64]) -> u64 {
+        let disk_start: u64 = disk_starts
+            .get(self.start_disk as usize)
+            .copied()
+            .unwrap_or(0);
+        disk_start.saturating_add(self.dir_offset)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct DataDescriptor {
+    pub(crate) crc32: u32,
+    pub(crate) compressed_size: u64,
+    pub(crate) uncompressed_size: u64,
+}
+
+/// A field on which an entry's local header, or its data descriptor,
+/// disagrees with the central directory, see
+/// [`ZipArchive::check_local_headers`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderMismatch {
+    pub filename: String,
+    /// `"signature"`, `"filename"`, `"compression method"`, `"CRC-32"`,
+    /// `"compressed size"` or `"uncompressed size"`
+    pub field: &'static str,
+    pub local: String,
+    pub central: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ZipFileEntry {
+    pub filename: String,
+    /// Host system in the upper byte (0 MS-DOS, 3 Unix, 19 macOS) and
+    /// version of the specification in the lower one. Zero when read as a
+    /// stream.
+    pub version_made_by: u16,
+    /// Version of the specification needed to extract, e.g. 20 for 2.0
+    pub version_needed: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub compression_method: u16,
+    pub file_offset: u64,
+    /// Last modification time, if the archive records a valid one
+    pub modified: Option<SystemTime>,
+    /// Extra fields of the central directory record, or of the local header
+    /// when the archive is read as a stream
+    pub extra_fields: Vec<ExtraField>,
+    /// Host-specific attributes from the central directory: MS-DOS
+    /// attributes in the low byte and, for archives made on Unix, the mode
+    /// in the upper half. Zero when read as a stream.
+    pub external_attributes: u32,
+    /// Bit 0 is set for text files. Zero when read as a stream.
+    pub internal_attributes: u16,
+    /// Entry comment from the central directory, empty when read as a stream
+    pub comment: String,
+    /// Extended attributes, names with values, from the pax records of tar
+    /// entries. ZIP archives carry them in AppleDouble companion entries
+    /// instead, see [`appledouble`](crate::appledouble).
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+/// Compression method of an entry, see [`ZipFileEntry::method`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    Stored,
+    Deflated,
+    Deflate64,
+    Bzip2,
+    Lzma,
+    Zstd,
+    Xz,
+    Ppmd,
+    Unknown(u16),
+}
+
+impl CompressionMethod {
+    pub fn from_id(id: u16) -> CompressionMethod {
+        match id {
+            0 => CompressionMethod::Stored,
+            8 => CompressionMethod::Deflated,
+            9 => CompressionMethod::Deflate64,
+            12 => CompressionMethod::Bzip2,
+            14 => CompressionMethod::Lzma,
+            93 => CompressionMethod::Zstd,
+            95 => CompressionMethod::Xz,
+            98 => CompressionMethod::Ppmd,
+            other => CompressionMethod::Unknown(other),
+        }
+    }
+
+    /// The method's ID in the compression method field
+    pub fn id(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+            CompressionMethod::Deflate64 => 9,
+            CompressionMethod::Bzip2 => 12,
+            CompressionMethod::Lzma => 14,
+            CompressionMethod::Zstd => 93,
+            CompressionMethod::Xz => 95,
+            CompressionMethod::Ppmd => 98,
+            CompressionMethod::Unknown(id) => id,
+        }
+    }
+
+    /// Lowercase name as shown in listings, or the ID of unknown methods
+    pub fn name(self) -> String {
+        match self {
+            CompressionMethod::Stored => String::from("stored"),
+            CompressionMethod::Deflated => String::from("deflated"),
+            CompressionMethod::Deflate64 => String::from("deflate64"),
+            CompressionMethod::Bzip2 => String::from("bzip2"),
+            CompressionMethod::Lzma => String::from("lzma"),
+            CompressionMethod::Zstd => String::from("zstd"),
+            CompressionMethod::Xz => String::from("xz"),
+            CompressionMethod::Ppmd => String::from("ppmd"),
+            CompressionMethod::Unknown(id) => id.to_string(),
+        }
+    }
+}
+
+/// How an entry is encrypted, see [`ZipFileEntry::encryption`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    None,
+    /// The traditional PKWARE stream cipher
+    ZipCrypto,
+    /// WinZip AES, `strength` 1, 2 and 3 for 128, 192 and 256-bit keys
+    Aes {
+        strength: u8,
+    },
+    /// PKWARE strong encryption
+    Strong,
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encryption::None => write!(f, "no encryption"),
+            Encryption::ZipCrypto => write!(f, "ZipCrypto"),
+            Encryption::Aes { strength } => write!(f, "AES-{}", 64 + 64 * *strength as u16),
+            Encryption::Strong => write!(f, "PKWARE strong encryption"),
+        }
+    }
+}
+
+impl ZipFileEntry {
+    /// The compression method. For AES-encrypted entries, whose method
+    /// field reads 99, this is the method of the data once decrypted.
+    pub fn method(&self) -> CompressionMethod {
+        let id: u16 = match self.compression_method {
+            AES_METHOD => self
+                .extra_fields
+                .iter()
+                .find_map(|field| match field {
+                    ExtraField::Aes {
+                        compression_method, ..
+                    } => Some(*compression_method),
+                    _ => None,
+                })
+                .unwrap_or(AES_METHOD),
+            id => id,
+        };
+        CompressionMethod::from_id(id)
+    }
+
+    pub fn encryption(&self) -> Encryption {
+        if self.flags & FLAG_ENCRYPTED == 0 {
+            return Encryption::None;
+        }
+        if self.flags & FLAG_STRONG_ENCRYPTION != 0 {
+            return Encryption::Strong;
+        }
+        self.extra_fields
+            .iter()
+            .find_map(|field| match field {
+                ExtraField::Aes { strength, .. } => Some(Encryption::Aes {
+                    strength: *strength,
+                }),
+                _ => None,
+            })
+            .unwrap_or(Encryption::ZipCrypto)
+    }
+
+    /// Whether the data is a PKWARE patch, which only rebuilds the file
+    /// when applied to the one it was made against.
+    pub fn is_patched(&self) -> bool {
+        self.flags & FLAG_PATCHED_DATA != 0
+    }
+
+    /// Whether the language encoding flag marks the name and comment as
+    /// UTF-8.
+    pub fn has_utf8_name(&self) -> bool {
+        self.flags & FLAG_UTF8 != 0
+    }
+
+    /// Whether the entry is a directory: its name ends with `/`, or its
+    /// MS-DOS attributes or Unix mode say so.
+    pub fn is_dir(&self) -> bool {
+        self.filename.ends_with('/')
+            || self.external_attributes & DOS_DIRECTORY != 0
+            || (self.external_attributes >> 16) & S_IFMT == S_IFDIR
+    }
+
+    /// Whether the Unix mode marks the entry as a symbolic link, whose data
+    /// is then the link target.
+    pub fn is_symlink(&self) -> bool {
+        (self.external_attributes >> 16) & S_IFMT == S_IFLNK
+    }
+
+    /// Owner user and group IDs recorded in the Info-ZIP new Unix extra
+    /// field, if any.
+    pub fn unix_owner(&self) -> Option<(u32, u32)> {
+        self.extra_fields.iter().find_map(|field| match field {
+            ExtraField::Unix { uid, gid } => Some((*uid, *gid)),
+            _ => None,
+        })
+    }
+
+    /// Unix permission bits from the external attributes, if recorded.
+    pub fn unix_permissions(&self) -> Option<u32> {
+        match self.external_attributes >> 16 {
+            0 => None,
+            mode => Some(mode & 0o7777),
+        }
+    }
+
+    /// Uncompressed and compressed sizes, taken from the Zip64 extra field
+    /// when the 32-bit header fields are saturated.
+    pub fn sizes(&self) -> (u64, u64) {
+        let mut zip64 = self
+            .extra_fields
+            .iter()
+            .find_map(|field| match field {
+                ExtraField::Zip64 { values, .. } => Some(values.iter().copied()),
+                _ => None,
+            })
+            .into_iter()
+            .flatten();
+        // The extra field only holds the values whose field is saturated,
+        // in this order
+        let mut size = |field: u32| match field {
+            u32::MAX => zip64.next().unwrap_or(field as u64),
+            field => field as u64,
+        };
+        let uncompressed: u64 = size(self.uncompressed_size);
+        (uncompressed, size(self.compressed_size))
+    }
+
+    /// Modification time from the extended timestamp extra field, to the
+    /// second, or else from the MS-DOS fields, to two seconds.
+    pub fn precise_modified(&self) -> Option<SystemTime> {
+        let extended: Option<i32> = self.extra_fields.iter().find_map(|field| match field {
+            ExtraField::ExtendedTimestamp { modified, .. } => *modified,
+            _ => None,
+        });
+        match extended {
+            Some(secs) if secs >= 0 => Some(UNIX_EPOCH + Duration::from_secs(secs as u64)),
+            _ => self.modified,
+        }
+    }
+
+    /// Why the entry can't be decoded with the built-in codecs, if it
+    /// can't, see [`Codecs::unsupported_reason`].
+    pub fn unsupported_reason(&self) -> Option<String> {
+        Codecs::builtin().unsupported_reason(self)
+    }
+}
+
+/// Where the central directory of an archive was found, see
+/// [`read_end_central_dir`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CentralDirectory {
+    /// Offset of the first central directory record in the file.
+    pub offset: u64,
+    /// Number of bytes in front of the archive proper, e.g. the stub of a
+    /// self-extracting executable. Offsets recorded in the archive are off
+    /// by this much and have to be corrected before seeking.
+    pub prefix_len: u64,
+    /// Offset at which each disk of a split archive starts, empty for
+    /// archives in a single file.
+    pub disk_starts: Vec<u64>,
+}
+
+impl CentralDirectory {
+    /// Maps an `offset` recorded relative to `disk` to a reader position.
+    pub fn resolve(&self, disk: u32, offset: u64) -> u64 {
+        let disk_start: u64 = self.disk_starts.get(disk as usize).copied().unwrap_or(0);
+        disk_start
+            .saturating_add(offset)
+            .saturating_add(self.prefix_len)
+    }
+}
+
+/// An opened ZIP archive.
+///
+/// Opening only locates the central directory; records are parsed lazily
+/// by [`ZipArchive::entries`], and the name index used by
+/// [`ZipArchive::by_name`] is built on first lookup.
+///
+/// Archives opened with [`ZipArchive::open_readonly`] refuse to extract into
+/// any sink that writes to the filesystem, so listing and testing them is
+/// guaranteed to leave no trace on disk.
+pub struct ZipArchive<R = File> {
+    // Shared by entries and the central directory iterator, which move it
+    // to the position they need before every read, see `seek_to`
+    reader: RefCell<BufReader<R>>,
+    // Contents of the file when opened with `open_mmap`, read instead of
+    // `reader`
+    #[cfg(feature = "mmap")]
+    map: Option<memmap2::Mmap>,
+    directory: CentralDirectory,
+    // Filename to entry, first occurrence wins
+    index: OnceCell<HashMap<String, ZipFileEntry>>,
+    // Entries loaded by `load_index`, yielded instead of parsing records
+    loaded: Option<Vec<ZipFileEntry>>,
+    read_only: bool,
+    options: ExtractOptions,
+}
+
+/// Limits applied while decoding entries, see [`ZipArchive::set_options`].
+///
+/// The time limits need a clock, which wasm32-unknown-unknown doesn't have;
+/// leave them unset there.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Maximal duration of a whole [`ZipArchive::extract_to`] call.
+    pub timeout: Option<Duration>,
+    /// Maximal duration of decoding a single entry.
+    pub entry_timeout: Option<Duration>,
+    /// Maximal number of bytes a single entry may decompress to.
+    pub max_entry_size: Option<u64>,
+    /// Maximal number of bytes decompressed by a whole extraction.
+    pub max_total_size: Option<u64>,
+    /// Maximal ratio of decompressed to compressed size of an entry.
+    pub max_ratio: Option<f64>,
+    /// What [`ZipArchive::extract_to`] does with entries named like an
+    /// earlier one.
+    pub duplicates: DuplicatePolicy,
+    /// What extraction does with entries that can't be decoded.
+    pub unsupported: UnsupportedPolicy,
+    /// Decompressors for the compression methods of entries.
+    pub codecs: Codecs,
+    /// Bytes of compressed data [`ZipArchive::extract_to`] may read ahead
+    /// of the entry being decompressed. When set, entries are decompressed
+    /// on a background thread while the next ones are read, which pays off
+    /// when reading is slow, e.g. from a spinning disk or a network
+    /// filesystem. Ignored for memory-mapped archives and on targets
+    /// without threads.
+    pub readahead: Option<usize>,
+    /// Stops the extraction with [`XpackError::Cancelled`] once cancelled,
+    /// see [`crate::cancel`].
+    pub cancel: Option<CancellationToken>,
+}
+
+/// What to do with entries whose name, once normalized, is the same as an
+/// earlier entry's, see [`resolve_duplicates`].
+///
+/// Archives may legally hold several entries of the same name, and which
+/// one a reader picks differs between tools, which attackers use to show
+/// scanners one file and users another. Duplicate directories are harmless
+/// and always merged.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DuplicatePolicy {
+    /// Keep the first entry, like [`ZipArchive::by_name`]
+    #[default]
+    FirstWins,
+    /// Keep the last entry, in the place of the first
+    LastWins,
+    /// Fail with [`XpackError::DuplicateEntry`]
+    Error,
+    /// Keep every entry, renaming later ones `name (1).ext`, etc.
+    Rename,
+}
+
+/// What to do with entries that can't be decoded, such as encrypted ones
+/// or ones using an unsupported compression method.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnsupportedPolicy {
+    /// Fail with [`XpackError::Unsupported`] at the first one
+    Error,
+    /// Skip them, telling the sink, see [`Sink::skip_entry`]. Encrypted
+    /// entries fail with [`XpackError::PasswordRequired`] instead
+    #[default]
+    Warn,
+    /// Skip them, and once every other entry is extracted fail with
+    /// [`XpackError::SkippedEntries`] listing them all
+    Collect,
+}
+
+/// The entries skipped by a single extraction, see [`UnsupportedPolicy`].
+pub(crate) struct SkipLog {
+    policy: UnsupportedPolicy,
+    skipped: Vec<SkippedEntry>,
+}
+
+impl SkipLog {
+    pub(crate) fn new(policy: UnsupportedPolicy) -> SkipLog {
+        SkipLog {
+            policy,
+            skipped: vec![],
+        }
+    }
+
+    /// Skips `entry` for `reason`, telling `sink`, unless the policy is to
+    /// fail.
+    pub(crate) fn skip<S: Sink>(
+        &mut self,
+        sink: &mut S,
+        entry: &ZipFileEntry,
+        reason: String,
+    ) -> io::Result<()> {
+        match self.policy {
+            UnsupportedPolicy::Error => Err(XpackError::unsupported(entry, reason).into()),
+            UnsupportedPolicy::Warn if entry.encryption() != Encryption::None => {
+                Err(XpackError::unsupported(entry, reason).into())
+            }
+            UnsupportedPolicy::Warn => sink.skip_entry(entry, &reason),
+            UnsupportedPolicy::Collect => {
+                sink.skip_entry(entry, &reason)?;
+                self.skipped.push(SkippedEntry {
+                    entry: entry.filename.clone(),
+                    reason,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Ends the extraction, failing if entries were collected.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.skipped.is_empty() {
+            true => Ok(()),
+            false => Err(XpackError::SkippedEntries {
+                skipped: self.skipped,
+            }
+            .into()),
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub(crate) fn entry_deadline(&self) -> Option<Deadline> {
+        self.entry_timeout.map(|limit| Deadline::after(limit, true))
+    }
+
+    /// Limits for decoding `entry` after `written` bytes were already
+    /// decompressed by the same extraction.
+    pub(crate) fn entry_limits(
+        &self,
+        total_deadline: Option<Deadline>,
+        entry: &ZipFileEntry,
+        written: u64,
+    ) -> EntryLimits {
+        let mut caps: Vec<SizeCap> = vec![];
+        if let Some(max) = self.max_entry_size {
+            caps.push(SizeCap {
+                bytes: max,
+                limit: Limit::EntrySize(max),
+            });
+        }
+        if let Some(max) = self.max_total_size {
+            caps.push(SizeCap {
+                bytes: max.saturating_sub(written),
+                limit: Limit::TotalSize(max),
+            });
+        }
+        // With a data descriptor the compressed size may not be known yet,
+        // the ratio is then checked once it is, see `check_ratio`
+        if let Some(ratio) = self.max_ratio.filter(|_| entry.compressed_size != 0) {
+            caps.push(SizeCap {
+                bytes: (entry.sizes().1 as f64 * ratio) as u64,
+                limit: Limit::Ratio(ratio),
+            });
+        }
+        EntryLimits {
+            deadline: Deadline::earliest(total_deadline, self.entry_deadline()),
+            size_cap: caps.into_iter().min_by_key(|cap| cap.bytes),
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    /// Checks the compression ratio of an entry whose compressed size was
+    /// only known after decoding it.
+    pub(crate) fn check_ratio(
+        &self,
+        entry: &str,
+        compressed: u64,
+        decompressed: u64,
+    ) -> io::Result<()> {
+        match self.max_ratio {
+            Some(ratio) if decompressed as f64 > compressed.max(1) as f64 * ratio => {
+                Err(XpackError::LimitExceeded {
+                    entry: entry.to_string(),
+                    limit: Limit::Ratio(ratio),
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A single entry of an opened [`ZipArchive`].
+pub struct Entry<'a, R = File> {
+    archive: &'a ZipArchive<R>,
+    metadata: &'a ZipFileEntry,
+}
+
+impl<'a, R: Read + Seek> Entry<'a, R> {
+    pub fn name(&self) -> &'a str {
+        &self.metadata.filename
+    }
+
+    pub fn metadata(&self) -> &'a ZipFileEntry {
+        self.metadata
+    }
+
+    /// Reads the entry's data as stored in the archive, without
+    /// decompressing or checking it, e.g. to copy it to another archive.
+    pub fn read_raw(&self) -> io::Result<Vec<u8>> {
+        self.archive.read_raw(self.metadata)
+    }
+
+    /// Decodes the entry into memory. Returns `Ok(None)` when the entry
+    /// uses an unsupported compression method.
+    pub fn read_to_vec(&self) -> io::Result<Option<Vec<u8>>> {
+        self.archive.read(self.metadata)
+    }
+
+    /// Returns a reader decoding the entry as it is read, see
+    /// [`EntryReader`].
+    pub fn reader(&self) -> io::Result<EntryReader<'a, R>> {
+        EntryReader::new(self.archive, self.metadata)
+    }
+}
+
+/// Reads the decoded data of an entry straight from the archive, without
+/// holding it all in memory, see [`Entry::reader`].
+///
+/// Seeking in stored entries is cheap. Compressed entries can't be entered
+/// in the middle, so seeking forward decodes and drops the data in between
+/// and seeking backward starts over from the beginning of the entry.
+///
+/// The reader never yields more than the recorded uncompressed size, and
+/// fails with [`io::ErrorKind::InvalidData`] if the data turns out longer or
+/// shorter. The CRC is checked once the data has been read to the end from
+/// the start, in order.
+pub struct EntryReader<'a, R = File> {
+    archive: &'a ZipArchive<R>,
+    entry: &'a ZipFileEntry,
+    // Offset of the entry's data in the archive, past the local header
+    data_start: u64,
+    // None for stored entries, which are read directly
+    decoder: Option<Box<dyn Read + 'a>>,
+    position: u64,
+    // CRC-32 of the data up to `checked`, computed while the data is read
+    // in order
+    hasher: crc32fast::Hasher,
+    checked: u64,
+}
+
+impl<'a, R: Read + Seek> EntryReader<'a, R> {
+    fn new(archive: &'a ZipArchive<R>, entry: &'a ZipFileEntry) -> io::Result<EntryReader<'a, R>> {
+        if let Some(reason) = archive.options.codecs.unsupported_reason(entry) {
+            return Err(XpackError::unsupported(entry, reason).into());
+        }
+        let data_start: u64 = {
+            let mut reader = archive.reader.borrow_mut();
+            if seek_to_data(&mut *reader, entry)?.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid local file header for {}", entry.filename),
+                ));
+            }
+            reader.stream_position()?
+        };
+        let mut entry_reader: EntryReader<'a, R> = EntryReader {
+            archive,
+            entry,
+            data_start,
+            decoder: None,
+            position: 0,
+            hasher: crc32fast::Hasher::new(),
+            checked: 0,
+        };
+        entry_reader.start_decoder()?;
+        Ok(entry_reader)
+    }
+
+    /// Sets up decoding from the beginning of the entry, if it is
+    /// compressed.
+    fn start_decoder(&mut self) -> io::Result<()> {
+        let method: CompressionMethod = self.entry.method();
+        let Some(decompressor) = self.archive.options.codecs.get(method) else {
+            return Ok(());
+        };
+        let compressed: ArchiveSection<'a, R> = ArchiveSection {
+            reader: &self.archive.reader,
+            position: self.data_start,
+            remaining: self.entry.sizes().1,
+        };
+        self.decoder = Some(decompressor.decoder(self.entry, Box::new(compressed))?);
+        self.position = 0;
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.entry.sizes().0
+    }
+}
+
+impl<R: Read + Seek> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left: u64 = self.size().saturating_sub(self.position);
+        let read: usize = match &mut self.decoder {
+            Some(decoder) => decoder.read(buf)?,
+            None => {
+                let wanted: usize = min(buf.len() as u64, left) as usize;
+                let mut reader = self.archive.reader.borrow_mut();
+                seek_to(&mut *reader, self.data_start + self.position)?;
+                reader.read(&mut buf[..wanted])?
+            }
+        };
+        if read as u64 > left || (read == 0 && left > 0 && !buf.is_empty()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Data of {} doesn't match its recorded size",
+                    self.entry.filename
+                ),
+            ));
+        }
+
+        if self.checked == self.position && read > 0 {
+            self.hasher.update(&buf[..read]);
+            self.checked += read as u64;
+            if self.checked == self.size() && self.hasher.clone().finalize() != self.entry.crc32 {
+                eprintln!("CRC mismatch for {}", self.entry.filename);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "CRC mismatch"));
+            }
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for EntryReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target: Option<u64> = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative position",
+            ));
+        };
+        if self.decoder.is_none() {
+            self.position = target;
+            return Ok(target);
+        }
+
+        if target < self.position {
+            eprintln!("Seeking back in {}, decoding it again", self.entry.filename);
+            self.start_decoder()?;
+        }
+        // Decode and drop the data up to the target, stopping at the end
+        let distance: u64 = min(target, self.size()) - self.position;
+        io::copy(&mut self.by_ref().take(distance), &mut io::sink())?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+/// The compressed data of an entry, read through the reader shared by the
+/// archive, see [`EntryReader`].
+struct ArchiveSection<'a, R> {
+    reader: &'a RefCell<BufReader<R>>,
+    position: u64,
+    remaining: u64,
+}
+
+impl<R: Read + Seek> Read for ArchiveSection<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let wanted: usize = min(buf.len() as u64, self.remaining) as usize;
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let mut reader = self.reader.borrow_mut();
+        seek_to(&mut *reader, self.position)?;
+        let read: usize = reader.read(&mut buf[..wanted])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ends in the middle of an entry",
+            ));
+        }
+        self.position += read as u64;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl ZipArchive<File> {
+    /// Opens the archive at `path` for reading and extraction.
+    pub fn open(path: &str) -> io::Result<ZipArchive<File>> {
+        ZipArchive::new(File::open(path)?)
+    }
+
+    /// Opens the archive at `path` in read-only mode: only sinks reporting
+    /// [`Sink::is_read_only`] are accepted by [`ZipArchive::extract_to`].
+    pub fn open_readonly(path: &str) -> io::Result<ZipArchive<File>> {
+        ZipArchive::new_readonly(File::open(path)?)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ZipArchive<File> {
+    /// Opens the archive at `path` through a memory map. The central
+    /// directory and entry data are read straight from the map, and stored
+    /// entries reach sinks without being copied. Falls back to reading the
+    /// file like [`ZipArchive::open`] when it can't be mapped.
+    ///
+    /// The file must not be modified while the archive is open.
+    pub fn open_mmap(path: &str) -> io::Result<ZipArchive<File>> {
+        ZipArchive::mmap_with_mode(path, false)
+    }
+
+    /// Opens the archive at `path` through a memory map in read-only mode,
+    /// see [`ZipArchive::open_mmap`] and [`ZipArchive::open_readonly`].
+    pub fn open_mmap_readonly(path: &str) -> io::Result<ZipArchive<File>> {
+        ZipArchive::mmap_with_mode(path, true)
+    }
+
+    fn mmap_with_mode(path: &str, read_only: bool) -> io::Result<ZipArchive<File>> {
+        let file: File = File::open(path)?;
+        // SAFETY: the map is only ever read, and changing the file while it
+        // is mapped is documented as unsupported
+        let map: Option<memmap2::Mmap> = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(map) => Some(map),
+            Err(e) => {
+                eprintln!("Can't map {}, reading it instead: {}", path, e);
+                None
+            }
+        };
+        let mut archive: ZipArchive<File> = ZipArchive::with_mode(file, read_only, &[])?;
+        archive.map = map;
+        Ok(archive)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl ZipArchive<SplitReader> {
+    /// Opens a split archive given the path of its last part, see
+    /// [`SplitReader::open`].
+    pub fn open_split(path: &str) -> io::Result<ZipArchive<SplitReader>> {
+        ZipArchive::split_with_mode(SplitReader::open(path)?, false)
+    }
+
+    /// Opens a split archive in read-only mode, see
+    /// [`ZipArchive::open_readonly`].
+    pub fn open_split_readonly(path: &str) -> io::Result<ZipArchive<SplitReader>> {
+        ZipArchive::split_with_mode(SplitReader::open(path)?, true)
+    }
+
+    fn split_with_mode(
+        reader: SplitReader,
+        read_only: bool,
+    ) -> io::Result<ZipArchive<SplitReader>> {
+        let disk_starts: Vec<u64> = reader.disk_starts().to_vec();
+        ZipArchive::with_mode(reader, read_only, &disk_starts)
+    }
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    /// Reads the archive from `reader`, e.g. a `File` or `Cursor<Vec<u8>>`.
+    pub fn new(reader: R) -> io::Result<ZipArchive<R>> {
+        ZipArchive::with_mode(reader, false, &[])
+    }
+
+    /// Reads the archive from `reader` in read-only mode, see
+    /// [`ZipArchive::open_readonly`].
+    pub fn new_readonly(reader: R) -> io::Result<ZipArchive<R>> {
+        ZipArchive::with_mode(reader, true, &[])
+    }
+
+    fn with_mode(mut reader: R, read_only: bool, disk_starts: &[u64]) -> io::Result<ZipArchive<R>> {
+        let location: Option<CentralDirectory> =
+            read_end_central_dir_on_disks(&mut reader, disk_starts)?;
+        let Some(directory) = location else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "End of central directory record not found",
+            ));
+        };
+        Ok(ZipArchive {
+            reader: RefCell::new(BufReader::with_capacity(READ_BUFFER_SIZE, reader)),
+            #[cfg(feature = "mmap")]
+            map: None,
+            directory,
+            index: OnceCell::new(),
+            loaded: None,
+            read_only,
+            options: ExtractOptions::default(),
+        })
+    }
+
+    /// Returns an iterator parsing one central directory record per step.
+    pub fn entries(&self) -> Entries<'_, R> {
+        Entries {
+            archive: self,
+            offset: self.directory.offset,
+            buffers: RecordBuffers::new(),
+            loaded: self.loaded.as_deref().map(|entries| entries.iter()),
+            done: false,
+        }
+    }
+
+    /// Writes the parsed central directory to `out`, for
+    /// [`ZipArchive::load_index`] to read back on later opens of the same
+    /// archive. See [`crate::index`] for the format.
+    pub fn write_index<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let fingerprint: Fingerprint = self.fingerprint()?;
+        let entries: Vec<ZipFileEntry> = self.entries().collect::<io::Result<_>>()?;
+        out.write_all(&index::encode(fingerprint, &entries))
+    }
+
+    /// Loads an index written by [`ZipArchive::write_index`], after which
+    /// [`ZipArchive::entries`] and everything built on it skip parsing the
+    /// central directory.
+    ///
+    /// The central directory is still read once to check that the index was
+    /// written for this archive as it is now; an index that doesn't match is
+    /// rejected with [`io::ErrorKind::InvalidData`] and leaves the archive
+    /// unchanged.
+    pub fn load_index<Rd: Read>(&mut self, input: &mut Rd) -> io::Result<()> {
+        let mut data: Vec<u8> = vec![];
+        input.read_to_end(&mut data)?;
+        let (fingerprint, entries) = index::decode(&data)?;
+        if fingerprint != self.fingerprint()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Index doesn't match the archive",
+            ));
+        }
+        self.loaded = Some(entries);
+        self.index = OnceCell::new();
+        Ok(())
+    }
+
+    /// Parses the central directory once and keeps the entries, after which
+    /// [`ZipArchive::entries`] yields them without parsing records, as
+    /// after [`ZipArchive::load_index`]. For archives kept open and queried
+    /// many times over.
+    pub fn preload(&mut self) -> io::Result<()> {
+        if self.loaded.is_none() {
+            let entries: Vec<ZipFileEntry> = self.entries().collect::<io::Result<_>>()?;
+            self.loaded = Some(entries);
+        }
+        Ok(())
+    }
+
+    // The size of the archive, where its central directory starts and the
+    // CRC-32 of everything from there to the end, which covers the central
+    // directory and the end records
+    fn fingerprint(&self) -> io::Result<Fingerprint> {
+        let mut hasher: crc32fast::Hasher = crc32fast::Hasher::new();
+        let archive_size: u64;
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            hasher.update(
+                map.get(self.directory.offset as usize..)
+                    .unwrap_or_default(),
+            );
+            return Ok(Fingerprint {
+                archive_size: map.len() as u64,
+                directory_offset: self.directory.offset,
+                directory_crc32: hasher.finalize(),
+            });
+        }
+        {
+            let mut reader = self.reader.borrow_mut();
+            reader.seek(SeekFrom::Start(self.directory.offset))?;
+            let mut buffer: Vec<u8> = vec![0; READ_BUFFER_SIZE];
+            loop {
+                let read: usize = reader.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            archive_size = reader.stream_position()?;
+        }
+        Ok(Fingerprint {
+            archive_size,
+            directory_offset: self.directory.offset,
+            directory_crc32: hasher.finalize(),
+        })
+    }
+
+    /// Looks up an entry by its exact decoded name in constant time.
+    ///
+    /// The first call parses the whole central directory to build the index.
+    pub fn by_name(&self, name: &str) -> io::Result<Entry<'_, R>> {
+        if self.index.get().is_none() {
+            let mut index: HashMap<String, ZipFileEntry> = HashMap::new();
+            for entry in self.entries() {
+                let entry: ZipFileEntry = entry?;
+                index.entry(entry.filename.clone()).or_insert(entry);
+            }
+            let _ = self.index.set(index);
+        }
+
+        match self.index.get().and_then(|index| index.get(name)) {
+            Some(metadata) => Ok(Entry {
+                archive: self,
+                metadata,
+            }),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No entry named {} in archive", name),
+            )),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_options(&mut self, options: ExtractOptions) {
+        self.options = options;
+    }
+
+    /// Decodes every entry and hands it to `sink`.
+    ///
+    /// Entries that can't be decoded, being encrypted, compressed with an
+    /// unsupported method or missing their local header, are handled as
+    /// [`ExtractOptions::unsupported`] says; other errors from decoding or
+    /// from the sink abort the extraction.
+    pub fn extract_to<S: Sink>(&self, sink: &mut S) -> io::Result<()> {
+        if self.read_only && !sink.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Archive was opened read-only and the sink writes to the filesystem",
+            ));
+        }
+        let total_deadline: Option<Deadline> = self
+            .options
+            .timeout
+            .map(|limit| Deadline::after(limit, false));
+        // Reading the whole directory first keeps the reader moving forward
+        // through the entry data instead of jumping back and forth between
+        // the two, which would throw its buffer away every time
+        let entries: Vec<ZipFileEntry> = resolve_duplicates(
+            self.entries().collect::<io::Result<_>>()?,
+            self.options.duplicates,
+        )?;
+        let mut skipped: SkipLog = SkipLog::new(self.options.unsupported);
+        #[cfg(feature = "mmap")]
+        let mapped: bool = self.map.is_some();
+        #[cfg(not(feature = "mmap"))]
+        let mapped: bool = false;
+        match self.options.readahead {
+            // wasm32-unknown-unknown has no threads
+            Some(readahead)
+                if !mapped && !cfg!(all(target_arch = "wasm32", target_os = "unknown")) =>
+            {
+                self.extract_reading_ahead(sink, entries, total_deadline, readahead, &mut skipped)?
+            }
+            _ => {
+                let mut written: u64 = 0;
+                for entry in entries {
+                    if let Some(reason) = self.options.codecs.unsupported_reason(&entry) {
+                        skipped.skip(sink, &entry, reason)?;
+                        continue;
+                    }
+                    let limits: EntryLimits =
+                        self.options.entry_limits(total_deadline, &entry, written);
+                    let data: Option<Cow<'_, [u8]>> = self.read_data(&entry, limits)?;
+                    match data {
+                        Some(data) => {
+                            written += data.len() as u64;
+                            sink.write_entry(&entry, &data)?;
+                        }
+                        None => skipped.skip(sink, &entry, "invalid local header".to_string())?,
+                    }
+                }
+            }
+        }
+        skipped.finish()
+    }
+
+    /// Extracts `entries` reading their compressed data up to `readahead`
+    /// bytes ahead of a background thread decompressing them, see
+    /// [`ExtractOptions::readahead`]. Entries reach `sink` in order.
+    fn extract_reading_ahead<S: Sink>(
+        &self,
+        sink: &mut S,
+        entries: Vec<ZipFileEntry>,
+        total_deadline: Option<Deadline>,
+        readahead: usize,
+        skipped: &mut SkipLog,
+    ) -> io::Result<()> {
+        // Entries read and not yet handed to the sink, in order: those being
+        // decompressed, with their compressed size, and those to skip
+        enum Pending {
+            Decoding(ZipFileEntry, u64),
+            Skip(ZipFileEntry, String),
+        }
+
+        let options: &ExtractOptions = &self.options;
+        let (job_sender, job_receiver) = mpsc::channel::<(ZipFileEntry, Vec<u8>)>();
+        let (result_sender, result_receiver) = mpsc::channel::<io::Result<Option<Vec<u8>>>>();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                // Entries are decompressed in order, so the thread knows how
+                // much has been written for the total size limit
+                let mut written: u64 = 0;
+                for (entry, compressed_data_buf) in job_receiver {
+                    let limits: EntryLimits = options.entry_limits(total_deadline, &entry, written);
+                    let data: io::Result<Option<Vec<u8>>> = limits
+                        .check_time(&entry.filename)
+                        .and_then(|()| {
+                            decode_entry_data(
+                                &entry,
+                                Cow::Owned(compressed_data_buf),
+                                limits,
+                                &options.codecs,
+                            )
+                        })
+                        .map(|data| data.map(Cow::into_owned));
+                    if let Ok(Some(data)) = &data {
+                        written += data.len() as u64;
+                    }
+                    if result_sender.send(data).is_err() {
+                        break;
+                    }
+                }
+            });
+            // Owned by this closure, so that returning, early on an error
+            // too, stops the thread once the entry it decodes is done
+            let job_sender: mpsc::Sender<(ZipFileEntry, Vec<u8>)> = job_sender;
+
+            let mut pending: VecDeque<Pending> = VecDeque::new();
+            let mut in_flight: u64 = 0;
+            let mut deliver =
+                |pending: &mut VecDeque<Pending>, in_flight: &mut u64| match pending.pop_front() {
+                    Some(Pending::Decoding(entry, size)) => {
+                        *in_flight -= size;
+                        match result_receiver.recv().unwrap()? {
+                            Some(data) => sink.write_entry(&entry, &data),
+                            None => skipped.skip(sink, &entry, "invalid local header".to_string()),
+                        }
+                    }
+                    Some(Pending::Skip(entry, reason)) => skipped.skip(sink, &entry, reason),
+                    None => Ok(()),
+                };
+            for entry in entries {
+                if let Some(reason) = options.codecs.unsupported_reason(&entry) {
+                    pending.push_back(Pending::Skip(entry, reason));
+                    continue;
+                }
+                let size: u64 = entry.sizes().1;
+                while in_flight > 0 && in_flight + size > readahead as u64 {
+                    deliver(&mut pending, &mut in_flight)?;
+                }
+                match read_compressed_data(&mut *self.reader.borrow_mut(), &entry)? {
+                    Some(compressed_data_buf) => {
+                        job_sender
+                            .send((entry.clone(), compressed_data_buf))
+                            .unwrap();
+                        pending.push_back(Pending::Decoding(entry, size));
+                        in_flight += size;
+                    }
+                    None => {
+                        pending.push_back(Pending::Skip(entry, "invalid local header".to_string()))
+                    }
+                }
+            }
+            while !pending.is_empty() {
+                deliver(&mut pending, &mut in_flight)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Decodes every entry into memory, keyed by name, without touching the
+    /// filesystem. Directory entries are left out.
+    ///
+    /// Extraction fails as soon as the data would exceed
+    /// [`ExtractOptions::max_total_size`]; the sizes recorded in the central
+    /// directory are checked before anything is decoded.
+    pub fn extract_all_to_memory(&self) -> io::Result<HashMap<String, Vec<u8>>> {
+        let limit: Option<u64> = self.options.max_total_size;
+        if let Some(limit) = limit {
+            let mut declared: u64 = 0;
+            for entry in self.entries() {
+                declared += entry?.sizes().0;
+            }
+            if declared > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Archive declares {} bytes of data, more than the cap of {} bytes",
+                        declared, limit
+                    ),
+                ));
+            }
+        }
+        let mut sink: MemorySink = MemorySink::new().with_limit(limit);
+        self.extract_to(&mut sink)?;
+        Ok(sink.into_entries())
+    }
+
+    /// Decodes and CRC-checks every entry without writing anything.
+    pub fn test(&self) -> io::Result<()> {
+        self.extract_to(&mut NullSink)
+    }
+
+    /// Cross-checks the local header of every entry against its central
+    /// directory record: filename, compression method, CRC-32 and sizes,
+    /// the last three from the data descriptor when the entry has one.
+    /// Readers trust one copy or the other, so disagreements often mean the
+    /// archive was tampered with or corrupted.
+    pub fn check_local_headers(&self) -> io::Result<Vec<HeaderMismatch>> {
+        let mut mismatches: Vec<HeaderMismatch> = vec![];
+        for entry in self.entries() {
+            let entry: ZipFileEntry = entry?;
+            check_local_header(&mut *self.reader.borrow_mut(), &entry, &mut mismatches)?;
+        }
+        Ok(mismatches)
+    }
+
+    /// Decodes `entry` into memory, see [`Entry::read_to_vec`].
+    pub fn read(&self, entry: &ZipFileEntry) -> io::Result<Option<Vec<u8>>> {
+        let limits: EntryLimits = self.options.entry_limits(
+            self.options
+                .timeout
+                .map(|limit| Deadline::after(limit, false)),
+            entry,
+            0,
+        );
+        let data: Option<Cow<'_, [u8]>> = self.read_data(entry, limits)?;
+        Ok(data.map(Cow::into_owned))
+    }
+
+    /// Reads the data of `entry` as stored, see [`Entry::read_raw`].
+    pub fn read_raw(&self, entry: &ZipFileEntry) -> io::Result<Vec<u8>> {
+        let mut reader = self.reader.borrow_mut();
+        match read_compressed_data(&mut *reader, entry)? {
+            Some(data) => Ok(data),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid local file header for {}", entry.filename),
+            )),
+        }
+    }
+
+    /// Decodes `entry`, borrowing stored data from the memory map if there
+    /// is one.
+    fn read_data(
+        &self,
+        entry: &ZipFileEntry,
+        limits: EntryLimits,
+    ) -> io::Result<Option<Cow<'_, [u8]>>> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            return read_mapped_entry(map, entry, limits, &self.options.codecs);
+        }
+        let data: Option<Vec<u8>> = read_entry_data(
+            &mut *self.reader.borrow_mut(),
+            entry,
+            limits,
+            &self.options.codecs,
+        )?;
+        Ok(data.map(Cow::Owned))
+    }
+}
+
+/// Iterator over the entries of a [`ZipArchive`], see
+/// [`ZipArchive::entries`].
+pub struct Entries<'a, R> {
+    archive: &'a ZipArchive<R>,
+    // Position of the next record in the central directory
+    offset: u64,
+    buffers: RecordBuffers,
+    // Entries of a loaded index, see `ZipArchive::load_index`
+    loaded: Option<std::slice::Iter<'a, ZipFileEntry>>,
+    done: bool,
+}
+
+impl<R: Read + Seek> Entries<'_, R> {
+    fn read_next(&mut self) -> io::Result<Option<ZipFileEntry>> {
+        if let Some(loaded) = &mut self.loaded {
+            return Ok(loaded.next().cloned());
+        }
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.archive.map {
+            let mut rest: &[u8] = map.get(self.offset as usize..).unwrap_or_default();
+            let entry: Option<ZipFileEntry> = self
+                .buffers
+                .read_record(&mut rest, &self.archive.directory)?
+                .map(|record| record.to_entry());
+            self.offset = (map.len() - rest.len()) as u64;
+            return Ok(entry);
+        }
+        let mut reader = self.archive.reader.borrow_mut();
+        seek_to(&mut *reader, self.offset)?;
+        let entry: Option<ZipFileEntry> = self
+            .buffers
+            .read_record(&mut *reader, &self.archive.directory)?
+            .map(|record| record.to_entry());
+        self.offset = reader.stream_position()?;
+        Ok(entry)
+    }
+}
+
+impl<R: Read + Seek> Iterator for Entries<'_, R> {
+    type Item = io::Result<ZipFileEntry>;
+
+    fn next(&mut self) -> Option<io::Result<ZipFileEntry>> {
+        if self.done {
+            return None;
+        }
+        let next: io::Result<Option<ZipFileEntry>> = self.read_next();
+        if !matches!(next, Ok(Some(_))) {
+            self.done = true;
+        }
+        next.transpose()
+    }
+}
+
+/// Finds the end of central directory record and returns where the central
+/// directory starts, or `None` if there is no valid record.
+pub fn read_end_central_dir<R: Read + Seek>(f: &mut R) -> io::Result<Option<CentralDirectory>> {
+    read_end_central_dir_on_disks(f, &[])
+}
+
+/// Like [`read_end_central_dir`] for the combined parts of a split archive,
+/// where `disk_starts` holds the offset at which each part starts. Without
+/// it, finding a record of a split archive fails with
+/// [`XpackError::SplitArchive`].
+pub fn read_end_central_dir_on_disks<R: Read + Seek>(
+    f: &mut R,
+    disk_starts: &[u64],
+) -> io::Result<Option<CentralDirectory>> {
+    f.seek(SeekFrom::End(0))?;
+    let file_size: u64 = f.stream_position()?;
+
+    // The record is 22 bytes followed by a comment of up to 65535 bytes, so
+    // the signature can only be found within the last 64 KB + 22 bytes
+    let search_size: u64 = min(EOCD_MAX_SEARCH, file_size);
+    f.seek(SeekFrom::End(-(search_size as i64)))?;
+    let mut buf: Vec<u8> = vec![0; search_size as usize];
+    f.read_exact(&mut buf)?;
+
+    match find_end_central_dir(&buf, file_size, disk_starts)? {
+        Some((record, position)) => {
+            locate_central_directory(f, &record, position, disk_starts).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Searches `buf`, the last bytes of a file of `file_size` bytes, for the
+/// end of central directory record. Returns the record and its position.
+pub(crate) fn find_end_central_dir(
+    buf: &[u8],
+    file_size: u64,
+    disk_starts: &[u64],
+) -> io::Result<Option<(EndCentralDirectory, u64)>> {
+    let buf_start: u64 = file_size - buf.len() as u64;
+    let signature_bytes: [u8; 4] = END_CENTRAL_DIR_SIGNATURE.to_le_bytes();
+
+    // End of Central Directory Record:
+    // [Signature (4 bytes)]
+    // [Disk Number (2 bytes)]
+    // [Start Disk (2 bytes)]
+    // [Disk Entries (2 bytes)]
+    // [Total Entries (2 bytes)]
+    // [Directory Size (4 bytes)]
+    // [Directory Offset (4 bytes)]
+    // [Comment Length (2 bytes)]
+    // [Optional Comment (variable)]
+    //
+    // Signature bytes may also appear inside the comment or compressed data,
+    // so search backwards and accept the first candidate that is consistent.
+    let Some(last) = buf.len().checked_sub(EOCD_SIZE) else {
+        return Ok(None);
+    };
+    for pos in (0..=last).rev() {
+        if buf[pos..pos + 4] != signature_bytes {
+            continue;
+        }
+
+        let record_bytes: &[u8] = &buf[pos + 4..pos + EOCD_SIZE]; // 18 bytes after signature
+        let mut end_central_dir: EndCentralDirectory = EndCentralDirectory {
+            disk_num: u16::from_le_bytes(record_bytes[0..2].try_into().unwrap()),
+            start_disk: u16::from_le_bytes(record_bytes[2..4].try_into().unwrap()),
+            dir_size: u32::from_le_bytes(record_bytes[8..12].try_into().unwrap()) as u64,
+            dir_offset: u32::from_le_bytes(record_bytes[12..16].try_into().unwrap()) as u64,
+            comment_length: u16::from_le_bytes(record_bytes[16..18].try_into().unwrap()),
+            zip64_position: None,
+        };
+        if end_central_dir.is_saturated() {
+            end_central_dir.read_zip64(buf, buf_start, pos);
+        }
+
+        let position: u64 = buf_start + pos as u64;
+        if end_central_dir.is_split()
+            && disk_starts.is_empty()
+            && end_central_dir.ends_at(position, file_size)
+        {
+            return Err(XpackError::SplitArchive {
+                disks: end_central_dir.disk_num.saturating_add(1),
+            }
+            .into());
+        }
+        if end_central_dir.is_consistent(position, file_size) {
+            return Ok(Some((end_central_dir, position)));
+        }
+        eprintln!(
+            "Skipping inconsistent EOCD candidate at {}",
+            buf_start + pos as u64
+        );
+    }
+
+    Ok(None)
+}
+
+/// Works out where the central directory described by the record found at
+/// `position` really starts.
+///
+/// Data prepended to an archive (self-extractor stubs, shell script
+/// launchers) shifts everything without updating the recorded offsets. The
+/// directory ends right where the end of central directory record starts,
+/// so when no directory is found at the recorded offset the difference
+/// between the two is the length of the prepended data.
+fn locate_central_directory<R: Read + Seek>(
+    f: &mut R,
+    record: &EndCentralDirectory,
+    position: u64,
+    disk_starts: &[u64],
+) -> io::Result<CentralDirectory> {
+    let mut signature: [u8; 4] = [0u8; 4];
+    f.seek(SeekFrom::Start(record.recorded_offset(disk_starts)))?;
+    f.read_exact(&mut signature)?;
+    Ok(central_directory_at(
+        record,
+        position,
+        disk_starts,
+        signature,
+    ))
+}
+
+/// Decides where the central directory starts given the `signature` found
+/// at its recorded offset, see [`locate_central_directory`].
+pub(crate) fn central_directory_at(
+    record: &EndCentralDirectory,
+    position: u64,
+    disk_starts: &[u64],
+    signature: [u8; 4],
+) -> CentralDirectory {
+    let recorded: u64 = record.recorded_offset(disk_starts);
+    // Consistent records have their directory before them
+    let actual: Option<u64> = record.directory_end(position).checked_sub(record.dir_size);
+    let prefix_len: Option<u64> = actual.and_then(|actual| actual.checked_sub(recorded));
+    match (actual, prefix_len) {
+        (Some(actual), Some(prefix_len))
+            if record.dir_size != 0 && signature != CENTRAL_DIR_SIGNATURE.to_le_bytes() =>
+        {
+            eprintln!(
+                "Central directory found at {} instead of {}, correcting offsets",
+                actual, recorded
+            );
+            CentralDirectory {
+                offset: actual,
+                prefix_len,
+                disk_starts: disk_starts.to_vec(),
+            }
+        }
+        _ => CentralDirectory {
+            offset: recorded,
+            prefix_len: 0,
+            disk_starts: disk_starts.to_vec(),
+        },
+    }
+}
+
+pub fn read_central_directory<R: Read + Seek>(
+    reader: &mut R,
+    location: Option<CentralDirectory>,
+) -> io::Result<Option<Vec<ZipFileEntry>>> {
+    let Some(location) = location else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "End of central directory record not found",
+        ));
+    };
+    let mut reader: BufReader<&mut R> = BufReader::with_capacity(64 * 1024, reader);
+    reader.seek(SeekFrom::Start(location.offset))?;
+    let mut buffers: RecordBuffers = RecordBuffers::new();
+    let mut file_entries: Vec<ZipFileEntry> = vec![];
+    while let Some(record) = buffers.read_record(&mut reader, &location)? {
+        file_entries.push(record.to_entry());
+    }
+    Ok(Some(file_entries))
+}
+
+/// A central directory record borrowed from the buffers of the reader that
+/// parsed it. The filename is only valid until the next record is read,
+/// which is what keeps walking the directory allocation-free per record.
+#[derive(Debug)]
+pub struct CentralRecord<'a> {
+    pub filename: &'a [u8],
+    pub extra: &'a [u8],
+    pub comment: &'a [u8],
+    pub version_made_by: u16,
+    pub version_needed: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub compression_method: u16,
+    pub internal_attributes: u16,
+    pub external_attributes: u32,
+    /// Offset of the local header, corrected for any prepended data
+    pub file_offset: u64,
+    /// MS-DOS modification time and date
+    pub last_mod_time: u16,
+    pub last_mod_date: u16,
+}
+
+impl CentralRecord<'_> {
+    /// Returns the filename decoded as described in [`decode_filename`].
+    pub fn decoded_filename(&self) -> Cow<'_, str> {
+        decode_filename(self.filename, self.flags, self.extra)
+    }
+
+    pub fn to_entry(&self) -> ZipFileEntry {
+        ZipFileEntry {
+            filename: self.decoded_filename().into_owned(),
+            version_made_by: self.version_made_by,
+            version_needed: self.version_needed,
+            flags: self.flags,
+            crc32: self.crc32,
+            compressed_size: self.compressed_size,
+            uncompressed_size: self.uncompressed_size,
+            compression_method: self.compression_method,
+            file_offset: self.file_offset,
+            modified: dos_datetime(self.last_mod_date, self.last_mod_time),
+            extra_fields: parse_extra_fields(self.extra),
+            internal_attributes: self.internal_attributes,
+            external_attributes: self.external_attributes,
+            comment: decode_comment(self.comment, self.flags).into_owned(),
+            xattrs: vec![],
+        }
+    }
+}
+
+/// Applies `policy` to entries whose names are the same once normalized
+/// with [`normalize_name`], returning the entries to extract in order.
+pub fn resolve_duplicates(
+    entries: Vec<ZipFileEntry>,
+    policy: DuplicatePolicy,
+) -> io::Result<Vec<ZipFileEntry>> {
+    // Renamed entries mustn't take the name of a later entry either
+    let mut names: HashSet<String> = match policy {
+        DuplicatePolicy::Rename => entries
+            .iter()
+            .map(|e| normalize_name(&e.filename))
+            .collect(),
+        _ => HashSet::new(),
+    };
+    let mut kept: Vec<ZipFileEntry> = vec![];
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for mut entry in entries {
+        let name: String = normalize_name(&entry.filename);
+        if let Some(&earlier) = seen.get(&name) {
+            match policy {
+                _ if entry.filename.ends_with('/') => continue,
+                DuplicatePolicy::FirstWins => {
+                    eprintln!("Skipping duplicate entry {}", entry.filename);
+                    continue;
+                }
+                DuplicatePolicy::LastWins => {
+                    eprintln!("Replacing {} with a later entry", entry.filename);
+                    kept[earlier] = entry;
+                    continue;
+                }
+                DuplicatePolicy::Error => {
+                    return Err(XpackError::DuplicateEntry {
+                        entry: entry.filename,
+                    }
+                    .into())
+                }
+                DuplicatePolicy::Rename => {
+                    let renamed: String = numbered_name(&entry.filename, |name| {
+                        names.contains(&normalize_name(name))
+                    });
+                    eprintln!("Renaming duplicate entry {} to {}", entry.filename, renamed);
+                    names.insert(normalize_name(&renamed));
+                    entry.filename = renamed;
+                }
+            }
+        }
+        seen.insert(normalize_name(&entry.filename), kept.len());
+        kept.push(entry);
+    }
+    Ok(kept)
+}
+
+/// Returns the first of `name (1).ext`, `name (2).ext`, ... for which
+/// `is_taken` is false, numbering the last component of `name`.
+pub fn numbered_name<F: Fn(&str) -> bool>(name: &str, is_taken: F) -> String {
+    let (directory, file) = match name.rfind('/') {
+        Some(slash) => name.split_at(slash + 1),
+        None => ("", name),
+    };
+    // A leading dot starts a hidden file's name, not an extension
+    let (stem, extension) = match file.rfind('.') {
+        Some(dot) if dot > 0 => file.split_at(dot),
+        _ => (file, ""),
+    };
+    (1..)
+        .map(|n| format!("{}{} ({}){}", directory, stem, n, extension))
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
+
+/// Converts an MS-DOS date and time to a `SystemTime`.
+///
+/// DOS timestamps carry no time zone; like most tools this takes them as
+/// UTC. Returns `None` for invalid dates such as the all-zero timestamp.
+/// ```text
+/// Date: [7 bits] Year - 1980  [4 bits] Month  [5 bits] Day
+/// Time: [5 bits] Hour  [6 bits] Minute  [5 bits] Second / 2
+/// ```
+pub fn dos_datetime(date: u16, time: u16) -> Option<SystemTime> {
+    let year: i64 = 1980 + (date >> 9) as i64;
+    let month: i64 = ((date >> 5) & 0x0f) as i64;
+    let day: i64 = (date & 0x1f) as i64;
+    let hour: u64 = (time >> 11) as u64;
+    let minute: u64 = ((time >> 5) & 0x3f) as u64;
+    let second: u64 = ((time & 0x1f) * 2) as u64;
+    if !(1..=12).contains(&month) || day == 0 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Days since 1970-01-01 of a proleptic Gregorian date, shifting the
+    // year to start in March so the leap day comes last
+    let y: i64 = if month <= 2 { year - 1 } else { year };
+    let era: i64 = y / 400;
+    let year_of_era: i64 = y - era * 400;
+    let day_of_year: i64 = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era: i64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days: u64 = (era * 146097 + day_of_era - 719468) as u64;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+/// Buffers for parsing central directory records, reused from one record
+/// to the next.
+pub(crate) struct RecordBuffers {
+    header: [u8; 46],
+    filename: Vec<u8>,
+    extra: Vec<u8>,
+    comment: Vec<u8>,
+}
+
+impl RecordBuffers {
+    pub(crate) fn new() -> RecordBuffers {
+        RecordBuffers {
+            header: [0u8; 46],
+            filename: vec![],
+            extra: vec![],
+            comment: vec![],
+        }
+    }
+
+    /// Reads the record at the current position of `reader`, or returns
+    /// `None` if the signature there isn't a central directory signature.
+    /// The local header offset is resolved through `directory`.
+    pub(crate) fn read_record<R: Read>(
+        &mut self,
+        reader: &mut R,
+        directory: &CentralDirectory,
+    ) -> io::Result<Option<CentralRecord<'_>>> {
+        // Central Directory Header:
+        // [4 bytes]  Signature
+        // [2 bytes]  Version made by
+        // [2 bytes]  Version needed
+        // [2 bytes]  General purpose bit flag
+        // [2 bytes]  Compression method
+        // [2 bytes]  Last modified time
+        // [2 bytes]  Last modified date
+        // [4 bytes]  CRC-32
+        // [4 bytes]  Compressed size
+        // [4 bytes]  Uncompressed size
+        // [2 bytes]  Filename length
+        // [2 bytes]  Extra field length
+        // [2 bytes]  File comment length
+        // [2 bytes]  Disk number start
+        // [2 bytes]  Internal file attributes
+        // [4 bytes]  External file attributes
+        // [4 bytes]  Local header offset
+        // [variable] Filename
+        // [variable] Extra field
+        // [variable] File comment
+        let header: &mut [u8; 46] = &mut self.header;
+        if reader.read_exact(header).is_err() || header[0..4] != CENTRAL_DIR_SIGNATURE.to_le_bytes()
+        {
+            return Ok(None);
+        }
+
+        let filename_length: u16 = u16::from_le_bytes(header[28..30].try_into().unwrap());
+        let extra_length: u16 = u16::from_le_bytes(header[30..32].try_into().unwrap());
+        let comment_length: u16 = u16::from_le_bytes(header[32..34].try_into().unwrap());
+
+        self.filename.resize(filename_length as usize, 0);
+        reader.read_exact(&mut self.filename)?;
+        // The extra field may carry a Unicode copy of the filename
+        self.extra.resize(extra_length as usize, 0);
+        reader.read_exact(&mut self.extra)?;
+        self.comment.resize(comment_length as usize, 0);
+        reader.read_exact(&mut self.comment)?;
+
+        let header: &[u8; 46] = &self.header;
+        let compressed_size: u32 = u32::from_le_bytes(header[20..24].try_into().unwrap());
+        let uncompressed_size: u32 = u32::from_le_bytes(header[24..28].try_into().unwrap());
+        let (disk, offset) = zip64_location(
+            &self.extra,
+            [uncompressed_size, compressed_size],
+            u16::from_le_bytes(header[34..36].try_into().unwrap()),
+            u32::from_le_bytes(header[42..46].try_into().unwrap()),
+        );
+        Ok(Some(CentralRecord {
+            filename: &self.filename,
+            extra: &self.extra,
+            comment: &self.comment,
+            version_made_by: u16::from_le_bytes(header[4..6].try_into().unwrap()),
+            version_needed: u16::from_le_bytes(header[6..8].try_into().unwrap()),
+            flags: u16::from_le_bytes(header[8..10].try_into().unwrap()),
+            compression_method: u16::from_le_bytes(header[10..12].try_into().unwrap()),
+            last_mod_time: u16::from_le_bytes(header[12..14].try_into().unwrap()),
+            last_mod_date: u16::from_le_bytes(header[14..16].try_into().unwrap()),
+            crc32: u32::from_le_bytes(header[16..20].try_into().unwrap()),
+            compressed_size,
+            uncompressed_size,
+            internal_attributes: u16::from_le_bytes(header[36..38].try_into().unwrap()),
+            external_attributes: u32::from_le_bytes(header[38..42].try_into().unwrap()),
+            file_offset: directory.resolve(disk, offset),
+        }))
+    }
+}
+
+/// Takes the disk number and local header offset of a central directory
+/// record from its Zip64 extra field when their header fields are
+/// saturated. The extra field holds the saturated `sizes` first, then the
+/// offset, then the disk number, each only if saturated.
+fn zip64_location(extra: &[u8], sizes: [u32; 2], disk: u16, offset: u32) -> (u32, u64) {
+    let mut values: &[u8] = find_extra_field(extra, ZIP64_EXTRA_ID).unwrap_or_default();
+    let saturated_sizes: usize = sizes.iter().filter(|&&size| size == u32::MAX).count();
+    values = values.get(saturated_sizes * 8..).unwrap_or_default();
+    let offset: u64 = match (offset, values.get(..8)) {
+        (u32::MAX, Some(value)) => {
+            values = &values[8..];
+            u64::from_le_bytes(value.try_into().unwrap())
+        }
+        (offset, _) => offset as u64,
+    };
+    let disk: u32 = match (disk, values.get(..4)) {
+        (u16::MAX, Some(value)) => u32::from_le_bytes(value.try_into().unwrap()),
+        (disk, _) => disk as u32,
+    };
+    (disk, offset)
+}
+
+/// Streams the central directory of the archive read from `reader`, calling
+/// `visit` for each record until the directory ends or `visit` breaks.
+///
+/// Memory use is constant regardless of the number of entries: records are
+/// read through a single buffered reader and variable fields share buffers.
+pub fn walk_central_directory<R, F>(mut reader: R, mut visit: F) -> io::Result<()>
+where
+    R: Read + Seek,
+    F: FnMut(&CentralRecord<'_>) -> ControlFlow<()>,
+{
+    let location: Option<CentralDirectory> = read_end_central_dir(&mut reader)?;
+    let Some(location) = location else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "End of central directory record not found",
+        ));
+    };
+
+    let mut reader: BufReader<R> = BufReader::with_capacity(64 * 1024, reader);
+    reader.seek(SeekFrom::Start(location.offset))?;
+    let mut buffers: RecordBuffers = RecordBuffers::new();
+    while let Some(record) = buffers.read_record(&mut reader, &location)? {
+        if visit(&record).is_break() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// A point in time after which decoding an entry fails with
+/// [`XpackError::Timeout`].
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    // None when the limit reaches past what an `Instant` can hold, so the
+    // deadline never passes
+    at: Option<Instant>,
+    limit: Duration,
+    per_entry: bool,
+}
+
+impl Deadline {
+    /// A deadline `limit` from now. `per_entry` only affects reporting.
+    pub fn after(limit: Duration, per_entry: bool) -> Deadline {
+        Deadline {
+            at: Instant::now().checked_add(limit),
+            limit,
+            per_entry,
+        }
+    }
+
+    /// Returns whichever of the two deadlines expires first.
+    pub fn earliest(a: Option<Deadline>, b: Option<Deadline>) -> Option<Deadline> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(match (a.at, b.at) {
+                (Some(a_at), Some(b_at)) if b_at < a_at => b,
+                (None, Some(_)) => b,
+                _ => a,
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Time left until the deadline passes, `None` if it never does.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    fn has_passed(&self) -> bool {
+        self.at.is_some_and(|at| Instant::now() >= at)
+    }
+}
+
+pub(crate) fn check_deadline(deadline: Option<Deadline>, entry: &str) -> io::Result<()> {
+    match deadline {
+        Some(deadline) if deadline.has_passed() => Err(XpackError::Timeout {
+            entry: entry.to_string(),
+            limit: deadline.limit,
+            per_entry: deadline.per_entry,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// An upper bound on the decompressed size of an entry and the option it
+/// comes from.
+#[derive(Debug, Clone, Copy)]
+pub struct SizeCap {
+    pub bytes: u64,
+    pub limit: Limit,
+}
+
+/// Limits enforced while decoding a single entry, see
+/// [`ExtractOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct EntryLimits {
+    pub deadline: Option<Deadline>,
+    pub size_cap: Option<SizeCap>,
+    pub cancel: Option<CancellationToken>,
+}
+
+impl EntryLimits {
+    /// Fails if the extraction was cancelled or the deadline has passed.
+    pub(crate) fn check_time(&self, entry: &str) -> io::Result<()> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(XpackError::Cancelled {
+                entry: entry.to_string(),
+            }
+            .into());
+        }
+        check_deadline(self.deadline, entry)
+    }
+
+    /// Fails if `size` decompressed bytes exceed the size cap.
+    pub(crate) fn check_size(&self, entry: &str, size: u64) -> io::Result<()> {
+        match self.size_cap {
+            Some(cap) if size > cap.bytes => Err(XpackError::LimitExceeded {
+                entry: entry.to_string(),
+                limit: cap.limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks the deadline and cancellation before every read and counts the
+/// output, so a decompressor spinning on a crafted stream or expanding a
+/// bomb is stopped between output chunks.
+pub(crate) struct LimitReader<'a, R: Read> {
+    inner: R,
+    limits: EntryLimits,
+    entry: &'a str,
+    produced: u64,
+}
+
+impl<'a, R: Read> LimitReader<'a, R> {
+    pub(crate) fn new(inner: R, limits: EntryLimits, entry: &'a str) -> LimitReader<'a, R> {
+        LimitReader {
+            inner,
+            limits,
+            entry,
+            produced: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.limits.check_time(self.entry)?;
+        let read: usize = self.inner.read(buf)?;
+        self.produced += read as u64;
+        self.limits.check_size(self.entry, self.produced)?;
+        Ok(read)
+    }
+}
+
+/// Reads, decompresses and CRC-checks the data of a single entry.
+///
+/// Returns `Ok(None)` when the entry can't be decoded (bad local header or
+/// unsupported compression method).
+pub fn read_entry_data<R: Read + Seek>(
+    f: &mut R,
+    entry: &ZipFileEntry,
+    limits: EntryLimits,
+    codecs: &Codecs,
+) -> io::Result<Option<Vec<u8>>> {
+    limits.check_time(&entry.filename)?;
+
+    let Some(compressed_data_buf) = read_compressed_data(f, entry)? else {
+        return Ok(None);
+    };
+    let data: Option<Cow<'_, [u8]>> =
+        decode_entry_data(entry, Cow::Owned(compressed_data_buf), limits, codecs)?;
+    Ok(data.map(Cow::into_owned))
+}
+
+/// Reads the compressed data of `entry`, checking the data descriptor if
+/// there is one. Returns `Ok(None)` for a bad local header.
+fn read_compressed_data<R: Read + Seek>(
+    f: &mut R,
+    entry: &ZipFileEntry,
+) -> io::Result<Option<Vec<u8>>> {
+    let Some((local_flags, zip64)) = seek_to_data(f, entry)? else {
+        return Ok(None);
+    };
+
+    // Grow the buffer as data arrives rather than trusting the recorded size
+    // with a large allocation up front
+    let mut compressed_data_buf: Vec<u8> = vec![];
+    f.take(entry.sizes().1)
+        .read_to_end(&mut compressed_data_buf)?;
+    if compressed_data_buf.len() != entry.sizes().1 as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Archive ends in the middle of {}", entry.filename),
+        ));
+    }
+
+    if (local_flags | entry.flags) & FLAG_DATA_DESCRIPTOR != 0 {
+        check_data_descriptor(f, entry, zip64)?;
+    }
+    Ok(Some(compressed_data_buf))
+}
+
+/// Moves `f` past the local header of `entry`, to its data, and returns the
+/// flags of the local header and whether it has a Zip64 extra field, which
+/// gives its data descriptor 8-byte sizes. Returns `Ok(None)` for a bad
+/// local header.
+fn seek_to_data<R: Read + Seek>(
+    f: &mut R,
+    entry: &ZipFileEntry,
+) -> io::Result<Option<(u16, bool)>> {
+    seek_to(f, entry.file_offset)?;
+
+    // Read and verify local file header
+    let mut local_header: [u8; 30] = [0u8; 30];
+    f.read_exact(&mut local_header)?;
+
+    // Check signature
+    if local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
+        eprintln!("Invalid local file header signature");
+        return Ok(None);
+    }
+
+    let local_flags: u16 = u16::from_le_bytes(local_header[6..8].try_into().unwrap());
+    let local_name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
+    let local_extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+
+    skip(f, local_name_length as u64)?;
+    let mut extra_buf: Vec<u8> = vec![0u8; local_extra_length as usize];
+    f.read_exact(&mut extra_buf)?;
+    let zip64: bool = find_extra_field(&extra_buf, ZIP64_EXTRA_ID).is_some();
+    Ok(Some((local_flags, zip64)))
+}
+
+/// Compares the local header of `entry`, and its data descriptor if it has
+/// one, with the central directory record, adding what differs to
+/// `mismatches`.
+fn check_local_header<R: Read + Seek>(
+    f: &mut R,
+    entry: &ZipFileEntry,
+    mismatches: &mut Vec<HeaderMismatch>,
+) -> io::Result<()> {
+    let mut mismatch = |field: &'static str, local: String, central: String| {
+        if local != central {
+            mismatches.push(HeaderMismatch {
+                filename: entry.filename.clone(),
+                field,
+                local,
+                central,
+            });
+        }
+    };
+
+    seek_to(f, entry.file_offset)?;
+    let mut local_header: [u8; 30] = [0u8; 30];
+    f.read_exact(&mut local_header)?;
+    if local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
+        mismatch(
+            "signature",
+            format!("{:02x?}", &local_header[0..4]),
+            format!("{:02x?}", LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes()),
+        );
+        return Ok(());
+    }
+
+    let local_flags: u16 = u16::from_le_bytes(local_header[6..8].try_into().unwrap());
+    let local_name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
+    let local_extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+    let mut filename_buf: Vec<u8> = vec![0u8; local_name_length as usize];
+    f.read_exact(&mut filename_buf)?;
+    let mut extra_buf: Vec<u8> = vec![0u8; local_extra_length as usize];
+    f.read_exact(&mut extra_buf)?;
+
+    mismatch(
+        "filename",
+        decode_filename(&filename_buf, local_flags, &extra_buf).into_owned(),
+        entry.filename.clone(),
+    );
+    mismatch(
+        "compression method",
+        u16::from_le_bytes(local_header[8..10].try_into().unwrap()).to_string(),
+        entry.compression_method.to_string(),
+    );
+
+    // With bit 3 set the local header carries zeros and the data descriptor
+    // after the data has the values
+    let local: DataDescriptor = if (local_flags | entry.flags) & FLAG_DATA_DESCRIPTOR != 0 {
+        skip(f, entry.sizes().1)?;
+        read_data_descriptor(f, find_extra_field(&extra_buf, ZIP64_EXTRA_ID).is_some())?
+    } else {
+        // Either header may keep its sizes in a Zip64 extra field
+        let (uncompressed_size, compressed_size) = ZipFileEntry {
+            compressed_size: u32::from_le_bytes(local_header[18..22].try_into().unwrap()),
+            uncompressed_size: u32::from_le_bytes(local_header[22..26].try_into().unwrap()),
+            extra_fields: parse_extra_fields(&extra_buf),
+            ..Default::default()
+        }
+        .sizes();
+        DataDescriptor {
+            crc32: u32::from_le_bytes(local_header[14..18].try_into().unwrap()),
+            compressed_size,
+            uncompressed_size,
+        }
+    };
+    mismatch(
+        "CRC-32",
+        format!("{:08x}", local.crc32),
+        format!("{:08x}", entry.crc32),
+    );
+    mismatch(
+        "compressed size",
+        local.compressed_size.to_string(),
+        entry.sizes().1.to_string(),
+    );
+    mismatch(
+        "uncompressed size",
+        local.uncompressed_size.to_string(),
+        entry.sizes().0.to_string(),
+    );
+    Ok(())
+}
+
+/// Like [`read_entry_data`] for an archive held in memory: the data is
+/// sliced out of `archive` and stored entries are returned without copying.
+#[cfg(feature = "mmap")]
+fn read_mapped_entry<'a>(
+    archive: &'a [u8],
+    entry: &ZipFileEntry,
+    limits: EntryLimits,
+    codecs: &Codecs,
+) -> io::Result<Option<Cow<'a, [u8]>>> {
+    limits.check_time(&entry.filename)?;
+    let truncated = || {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Archive ends in the middle of {}", entry.filename),
+        )
+    };
+
+    // Slicing step by step keeps offsets from overflowing
+    let start: usize = usize::try_from(entry.file_offset).unwrap_or(usize::MAX);
+    let rest: &[u8] = archive.get(start..).ok_or_else(truncated)?;
+    let local_header: &[u8] = rest.get(..30).ok_or_else(truncated)?;
+    if local_header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
+        eprintln!("Invalid local file header signature");
+        return Ok(None);
+    }
+    let local_flags: u16 = u16::from_le_bytes(local_header[6..8].try_into().unwrap());
+    let local_name_length: u16 = u16::from_le_bytes(local_header[26..28].try_into().unwrap());
+    let local_extra_length: u16 = u16::from_le_bytes(local_header[28..30].try_into().unwrap());
+
+    let rest: &[u8] = rest
+        .get(30 + local_name_length as usize..)
+        .ok_or_else(truncated)?;
+    let extra: &[u8] = rest
+        .get(..local_extra_length as usize)
+        .ok_or_else(truncated)?;
+    let rest: &[u8] = &rest[extra.len()..];
+    let data: &[u8] = rest.get(..entry.sizes().1 as usize).ok_or_else(truncated)?;
+    if (local_flags | entry.flags) & FLAG_DATA_DESCRIPTOR != 0 {
+        let zip64: bool = find_extra_field(extra, ZIP64_EXTRA_ID).is_some();
+        check_data_descriptor(&mut &rest[data.len()..], entry, zip64)?;
+    }
+
+    decode_entry_data(entry, Cow::Borrowed(data), limits, codecs)
+}
+
+/// Reads the data descriptor following the data of `entry`, with 8-byte
+/// sizes if `zip64`, see [`read_data_descriptor`].
+///
+/// With bit 3 set the local header carries zeros, so the data descriptor
+/// is the only local source of truth. It must agree with the central
+/// directory, otherwise the archive has been tampered with or truncated.
+pub(crate) fn check_data_descriptor<R: Read>(
+    reader: &mut R,
+    entry: &ZipFileEntry,
+    zip64: bool,
+) -> io::Result<()> {
+    let descriptor: DataDescriptor = read_data_descriptor(reader, zip64)?;
+    let (uncompressed_size, compressed_size) = entry.sizes();
+    if descriptor.crc32 != entry.crc32
+        || descriptor.compressed_size != compressed_size
+        || descriptor.uncompressed_size != uncompressed_size
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Data descriptor does not match central directory",
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes the compressed data of `entry` with the decompressor `codecs`
+/// has for its method and checks its CRC. Returns `Ok(None)` for entries
+/// the codecs can't decode. Stored data is returned as is, without copying
+/// it.
+pub(crate) fn decode_entry_data<'a>(
+    entry: &ZipFileEntry,
+    compressed_data_buf: Cow<'a, [u8]>,
+    limits: EntryLimits,
+    codecs: &Codecs,
+) -> io::Result<Option<Cow<'a, [u8]>>> {
+    if let Some(reason) = codecs.unsupported_reason(entry) {
+        eprintln!("Can't decode {}: {}", entry.filename, reason);
+        return Ok(None);
+    }
+    let method: CompressionMethod = entry.method();
+    let Some(decompressor) = codecs.get(method) else {
+        limits.check_size(&entry.filename, compressed_data_buf.len() as u64)?;
+        verify_crc(entry, &compressed_data_buf)?;
+        return Ok(Some(compressed_data_buf));
+    };
+
+    let decoder: Box<dyn Read + '_> =
+        decompressor.decoder(entry, Box::new(&compressed_data_buf[..]))?;
+    let decompressed_data: Vec<u8> = inflate(decoder, entry, limits)?;
+    if decompressed_data.len() != entry.sizes().0 as usize {
+        eprintln!(
+            "Warning: Decompressed size {} differs from expected {}",
+            decompressed_data.len(),
+            entry.uncompressed_size
+        );
+    }
+    verify_crc(entry, &decompressed_data)?;
+    Ok(Some(Cow::Owned(decompressed_data)))
+}
+
+/// Reads a `decoder` to the end, enforcing `deadline`.
+pub(crate) fn inflate<D: Read>(
+    decoder: D,
+    entry: &ZipFileEntry,
+    limits: EntryLimits,
+) -> io::Result<Vec<u8>> {
+    let mut decoder: LimitReader<D> = LimitReader::new(decoder, limits.clone(), &entry.filename);
+    // The recorded size is only a hint, don't let it allocate past the cap,
+    // or much at all before the data shows up
+    let capacity: u64 = limits
+        .size_cap
+        .map_or(entry.sizes().0, |cap| cap.bytes.min(entry.sizes().0))
+        .min(MAX_CAPACITY_HINT);
+    let mut decompressed_data: Vec<u8> = Vec::with_capacity(capacity as usize);
+
+    match decoder.read_to_end(&mut decompressed_data) {
+        Ok(_) => Ok(decompressed_data),
+        Err(e) if XpackError::from_io(&e).is_some() => Err(e),
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+    }
+}
+
+pub(crate) fn verify_crc(entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+    if crc32fast::hash(data) != entry.crc32 {
+        eprintln!("CRC mismatch for {}", entry.filename);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "CRC mismatch",
+        ));
+    }
+    Ok(())
+}
+
+/// Moves `reader` to `position`. Seeking throws away the buffer of a
+/// `BufReader`, so short moves forward read past the bytes instead.
+fn seek_to<R: Read + Seek>(reader: &mut R, position: u64) -> io::Result<()> {
+    let current: u64 = reader.stream_position()?;
+    if position >= current && position - current <= READ_BUFFER_SIZE as u64 {
+        return skip(reader, position - current);
+    }
+    reader.seek(SeekFrom::Start(position))?;
+    Ok(())
+}
+
+fn skip<R: Read>(reader: &mut R, count: u64) -> io::Result<()> {
+    let skipped: u64 = io::copy(&mut reader.take(count), &mut io::sink())?;
+    if skipped < count {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Archive ends in the middle of an entry",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a data descriptor. Entries whose local header has a Zip64 extra
+/// field, `zip64`, have 8-byte sizes in theirs.
+pub(crate) fn read_data_descriptor<R: Read>(
+    reader: &mut R,
+    zip64: bool,
+) -> io::Result<DataDescriptor> {
+    // Data Descriptor:
+    // [4 bytes]  Optional signature (0x08074b50)
+    // [4 bytes]  CRC-32
+    // [4 or 8 bytes]  Compressed size
+    // [4 or 8 bytes]  Uncompressed size
+    //
+    // The signature is optional, so a leading value equal to it is taken as
+    // the signature and the record is read from the bytes that follow.
+    let size_len: usize = if zip64 { 8 } else { 4 };
+    let len: usize = 4 + 2 * size_len;
+    let mut buf: [u8; 24] = [0u8; 24];
+    reader.read_exact(&mut buf[0..len])?;
+    let fields: &[u8] = if buf[0..4] == DATA_DESCRIPTOR_SIGNATURE.to_le_bytes() {
+        reader.read_exact(&mut buf[len..len + 4])?;
+        &buf[4..len + 4]
+    } else {
+        &buf[0..len]
+    };
+
+    let size = |field: &[u8]| match zip64 {
+        true => u64::from_le_bytes(field.try_into().unwrap()),
+        false => u32::from_le_bytes(field.try_into().unwrap()) as u64,
+    };
+    Ok(DataDescriptor {
+        crc32: u32::from_le_bytes(fields[0..4].try_into().unwrap()),
+        compressed_size: size(&fields[4..4 + size_len]),
+        uncompressed_size: size(&fields[4 + size_len..len]),
+    })
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::sink::DiskSink;
+    use crate::test_util::get_test_file_path;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    fn read_file_contents<P: AsRef<Path>>(path: P) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn test_archive_multiple_files() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let mut f = File::open(test_path)?;
+        let res: Result<Option<CentralDirectory>, io::Error> = read_end_central_dir(&mut f);
+        let entries: Option<Vec<ZipFileEntry>> =
+            read_central_directory(&mut f, res.unwrap()).unwrap();
+        if let Some(entries_vec) = &entries {
+            assert_eq!(entries_vec.len(), 2);
+            assert_eq!(entries_vec.first().unwrap().filename, "test1.txt");
+            assert_eq!(entries_vec.last().unwrap().filename, "test2.txt");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_single_file() -> io::Result<()> {
+        let test_path = get_test_file_path("test_single.zip");
+        let mut f = File::open(test_path)?;
+        let res: Result<Option<CentralDirectory>, io::Error> = read_end_central_dir(&mut f);
+        let entries: Option<Vec<ZipFileEntry>> =
+            read_central_directory(&mut f, res.unwrap()).unwrap();
+        if let Some(entries_vec) = &entries {
+            assert_eq!(entries_vec.len(), 1);
+            assert_eq!(entries_vec.first().unwrap().filename, "test1.txt");
+        }
+        // fs::remove_file(test_path)?;  // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_e2e_single_file() -> io::Result<()> {
+        let test_path = get_test_file_path("test_single.zip");
+        let test_path_only: PathBuf = test_path.parent().unwrap().to_path_buf();
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.first().unwrap().filename, "test1.txt");
+        archive.extract_to(&mut DiskSink::new(test_path_only.to_str().unwrap()))?;
+        let files_in_folder: Vec<String> = fs::read_dir(&test_path_only)?
+            .filter_map(|f| Some(f.ok()?.file_name().to_str()?.to_owned()))
+            .collect();
+
+        assert!(files_in_folder.contains(&"test1.txt".to_string()));
+        let unarchived_file_path = test_path_only.join("test1.txt");
+        assert_eq!(
+            read_file_contents(&unarchived_file_path)?,
+            "this is test1\n"
+        );
+        fs::remove_file(unarchived_file_path)?; // Cleanup
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_data_descriptor_with_and_without_signature() -> io::Result<()> {
+        let mut record: Vec<u8> = vec![];
+        record.extend_from_slice(&0x3bd723b9u32.to_le_bytes());
+        record.extend_from_slice(&14u32.to_le_bytes());
+        record.extend_from_slice(&16u32.to_le_bytes());
+        let expected = DataDescriptor {
+            crc32: 0x3bd723b9,
+            compressed_size: 14,
+            uncompressed_size: 16,
+        };
+
+        let mut signed: Vec<u8> = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes().to_vec();
+        signed.extend_from_slice(&record);
+        assert_eq!(read_data_descriptor(&mut &signed[..], false)?, expected);
+        assert_eq!(read_data_descriptor(&mut &record[..], false)?, expected);
+
+        let mut zip64: Vec<u8> = 0x3bd723b9u32.to_le_bytes().to_vec();
+        zip64.extend_from_slice(&14u64.to_le_bytes());
+        zip64.extend_from_slice(&(16u64 << 32).to_le_bytes());
+        let expected = DataDescriptor {
+            uncompressed_size: 16 << 32,
+            ..expected
+        };
+        let mut signed: Vec<u8> = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes().to_vec();
+        signed.extend_from_slice(&zip64);
+        assert_eq!(read_data_descriptor(&mut &signed[..], true)?, expected);
+        assert_eq!(read_data_descriptor(&mut &zip64[..], true)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_e2e_data_descriptor_without_signature() -> io::Result<()> {
+        let test_path = get_test_file_path("test_descriptor_nosig.zip");
+        let mut f = File::open(test_path)?;
+        let res: Result<Option<CentralDirectory>, io::Error> = read_end_central_dir(&mut f);
+        let entries: Vec<ZipFileEntry> = read_central_directory(&mut f, res?)?.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_ne!(entries[0].flags & FLAG_DATA_DESCRIPTOR, 0);
+        let data = read_entry_data(
+            &mut f,
+            &entries[0],
+            EntryLimits::default(),
+            Codecs::builtin(),
+        )?
+        .unwrap();
+        assert_eq!(data, b"descriptor without signature\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_readonly_archive_refuses_disk_sink() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let out_dir = std::env::temp_dir().join("xpack_test_readonly/");
+        let archive = ZipArchive::open_readonly(test_path.to_str().unwrap())?;
+        assert!(archive.is_read_only());
+        archive.test()?;
+
+        let err = archive
+            .extract_to(&mut DiskSink::new(out_dir.to_str().unwrap()))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!out_dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_central_directory() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let mut names: Vec<String> = vec![];
+        walk_central_directory(File::open(&test_path)?, |record| {
+            names.push(record.decoded_filename().into_owned());
+            assert_eq!(record.uncompressed_size, 14);
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(names, ["test1.txt", "test2.txt"]);
+
+        let mut visited: usize = 0;
+        walk_central_directory(File::open(&test_path)?, |_| {
+            visited += 1;
+            ControlFlow::Break(())
+        })?;
+        assert_eq!(visited, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_eocd_after_maximal_comment() -> io::Result<()> {
+        // The comment is 65535 bytes long and embeds a fake EOCD signature
+        let test_path = get_test_file_path("test_long_comment.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].filename, "test1.txt");
+        archive.test()
+    }
+
+    #[test]
+    fn test_prepended_data() -> io::Result<()> {
+        // test_multiple.zip behind an executable stub, offsets left unchanged
+        let test_path = get_test_file_path("test_sfx.exe");
+        let mut f = File::open(&test_path)?;
+        let location: CentralDirectory = read_end_central_dir(&mut f)?.unwrap();
+        assert_eq!(location.prefix_len, 924);
+
+        let archive = ZipArchive::new(f)?;
+        let data: Vec<u8> = archive.by_name("test2.txt")?.read_to_vec()?.unwrap();
+        assert_eq!(data, b"this is test2\n");
+        archive.test()?;
+
+        let mut offsets: Vec<u64> = vec![];
+        walk_central_directory(File::open(&test_path)?, |record| {
+            offsets.push(record.file_offset);
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(offsets[0], 924);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dos_datetime() {
+        // 2023-11-14 22:13:20
+        let date: u16 = (43 << 9) | (11 << 5) | 14;
+        let time: u16 = (22 << 11) | (13 << 5) | 10;
+        assert_eq!(
+            dos_datetime(date, time),
+            Some(UNIX_EPOCH + Duration::from_secs(1700000000))
+        );
+        assert_eq!(dos_datetime(0, 0), None);
+    }
+
+    #[test]
+    fn test_eocd_consistency() {
+        let eocd = EndCentralDirectory {
+            disk_num: 0,
+            start_disk: 0,
+            dir_size: 50,
+            dir_offset: 100,
+            comment_length: 10,
+            zip64_position: None,
+        };
+        assert!(eocd.is_consistent(150, 182));
+        assert!(!eocd.is_consistent(150, 183));
+        assert!(!eocd.is_consistent(140, 172));
+
+        // Zip64 sizes that overflow or reach past the record
+        let eocd = EndCentralDirectory {
+            dir_size: u64::MAX,
+            ..eocd
+        };
+        assert!(!eocd.is_consistent(150, 182));
+        let directory: CentralDirectory = central_directory_at(&eocd, 150, &[], [0; 4]);
+        assert_eq!((directory.offset, directory.prefix_len), (100, 0));
+    }
+
+    #[test]
+    fn test_malformed_archives_return_errors() -> io::Result<()> {
+        assert!(ZipArchive::new(io::Cursor::new(vec![])).is_err());
+        assert!(ZipArchive::new(io::Cursor::new(b"PK\x05\x06".to_vec())).is_err());
+        let err = read_central_directory(&mut io::Cursor::new(vec![]), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Last disk number at its maximum
+        let mut eocd: Vec<u8> = END_CENTRAL_DIR_SIGNATURE.to_le_bytes().to_vec();
+        eocd.extend([0xff, 0xff]);
+        eocd.resize(EOCD_SIZE, 0);
+        assert!(ZipArchive::new(io::Cursor::new(eocd)).is_err());
+
+        // A compressed size far past the end of the archive
+        let mut bytes: Vec<u8> = fs::read(get_test_file_path("test_single.zip"))?;
+        let directory: usize = read_end_central_dir(&mut io::Cursor::new(&bytes))?
+            .unwrap()
+            .offset as usize;
+        bytes[directory + 20..directory + 24].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        let archive = ZipArchive::new(io::Cursor::new(bytes))?;
+        let entry: ZipFileEntry = archive.entries().next().unwrap()?;
+        let err = archive.read(&entry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip64_location() {
+        // Saturated compressed size and offset, then the disk number
+        let mut extra: Vec<u8> = vec![0x0a, 0x00, 0x04, 0x00, 1, 2, 3, 4];
+        extra.extend(ZIP64_EXTRA_ID.to_le_bytes());
+        extra.extend(20u16.to_le_bytes());
+        extra.extend(7u64.to_le_bytes());
+        extra.extend((5u64 << 32).to_le_bytes());
+        extra.extend(2u32.to_le_bytes());
+        assert_eq!(
+            zip64_location(&extra, [10, u32::MAX], u16::MAX, u32::MAX),
+            (2, 5 << 32)
+        );
+        assert_eq!(zip64_location(&extra, [10, 20], 1, 30), (1, 30));
+        // Saturated fields the extra field doesn't continue stay as they are
+        assert_eq!(
+            zip64_location(&[], [10, 20], u16::MAX, u32::MAX),
+            (u16::MAX as u32, u32::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn test_zip64_end_central_directory() -> io::Result<()> {
+        // zip -fz saturates the classic record and keeps the directory
+        // offset in the Zip64 record only
+        let bytes: Vec<u8> = fs::read(get_test_file_path("test_zip64.zip"))?;
+        let eocd: usize = bytes.len() - EOCD_SIZE;
+        assert_eq!(bytes[eocd + 16..eocd + 20], [0xff; 4]);
+        let archive = ZipArchive::new(io::Cursor::new(bytes.clone()))?;
+        let names: Vec<String> = archive
+            .entries()
+            .map(|entry| entry.map(|entry| entry.filename))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(names, ["a.txt", "d/b.txt"]);
+        assert_eq!(
+            archive.by_name("a.txt")?.read_to_vec()?,
+            Some(b"zip64 forced\n".to_vec())
+        );
+
+        // Data in front shifts the directory, as in a self-extractor
+        let mut prefixed: Vec<u8> = b"#!/bin/sh\nexit 0\n".to_vec();
+        prefixed.extend(&bytes);
+        let archive = ZipArchive::new(io::Cursor::new(prefixed))?;
+        assert_eq!(
+            archive.by_name("d/b.txt")?.read_to_vec()?,
+            Some(b"second entry, deflated deflated deflated\n".to_vec())
+        );
+
+        // A saturated directory size whose Zip64 value overflows the offset
+        let mut crafted: Vec<u8> = bytes.clone();
+        crafted[eocd + 12..eocd + 16].copy_from_slice(&[0xff; 4]);
+        let zip64: usize = eocd - ZIP64_LOCATOR_SIZE - ZIP64_EOCD_SIZE;
+        crafted[zip64 + 40..zip64 + 48].copy_from_slice(&[0xff; 8]);
+        let err = ZipArchive::new(io::Cursor::new(crafted.clone()))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            walk_central_directory(io::Cursor::new(crafted), |_| ControlFlow::Continue(()))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_local_headers() -> io::Result<()> {
+        for name in [
+            "test_multiple.zip",
+            "test_descriptor_nosig.zip",
+            "test_encodings.zip",
+            "test_sfx.exe",
+            "test_zip64.zip",
+            "test_zip64_local.zip",
+            "test_zip64_offsets.zip",
+            "test_zip64_descriptor.zip",
+        ] {
+            let archive = ZipArchive::open(get_test_file_path(name).to_str().unwrap())?;
+            assert_eq!(archive.check_local_headers()?, [], "{}", name);
+        }
+
+        // Rename the first entry and mark it stored in the local header only
+        let mut bytes: Vec<u8> = fs::read(get_test_file_path("test_multiple.zip"))?;
+        bytes[30..39].copy_from_slice(b"test9.txt");
+        bytes[8] = 0;
+        let archive = ZipArchive::new(io::Cursor::new(bytes))?;
+        let mismatches: Vec<HeaderMismatch> = archive.check_local_headers()?;
+        let fields: Vec<(&str, &str, &str)> = mismatches
+            .iter()
+            .map(|m| (m.filename.as_str(), m.field, m.local.as_str()))
+            .collect();
+        assert_eq!(fields[0], ("test1.txt", "filename", "test9.txt"));
+        assert_eq!(fields[1], ("test1.txt", "compression method", "0"));
+        assert_eq!(fields.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_entries() -> io::Result<()> {
+        use crate::writer::{EntryMetadata, ZipWriter};
+
+        let metadata: EntryMetadata = EntryMetadata::default();
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        writer.add_file("a.txt", b"first", &metadata)?;
+        writer.add_file("b.txt", b"other", &metadata)?;
+        writer.add_file("./a.txt", b"second", &metadata)?;
+        writer.add_file("a (1).txt", b"taken", &metadata)?;
+        let mut archive = ZipArchive::new(io::Cursor::new(writer.finish()?.into_inner()))?;
+
+        let mut extract = |duplicates: DuplicatePolicy| {
+            archive.set_options(ExtractOptions {
+                duplicates,
+                ..Default::default()
+            });
+            let mut sink: MemorySink = MemorySink::new();
+            archive.extract_to(&mut sink).map(|_| sink.into_entries())
+        };
+        assert_eq!(extract(DuplicatePolicy::FirstWins)?["a.txt"], b"first");
+        assert_eq!(extract(DuplicatePolicy::LastWins)?["./a.txt"], b"second");
+        let err = extract(DuplicatePolicy::Error).unwrap_err();
+        assert!(matches!(
+            XpackError::from_io(&err),
+            Some(XpackError::DuplicateEntry { entry }) if entry == "./a.txt"
+        ));
+        let renamed: HashMap<String, Vec<u8>> = extract(DuplicatePolicy::Rename)?;
+        assert_eq!(renamed["./a (2).txt"], b"second");
+        assert_eq!(renamed.len(), 4);
+
+        assert_eq!(numbered_name("dir/.hidden", |_| false), "dir/.hidden (1)");
+        assert_eq!(
+            numbered_name("x.tar.gz", |n| n == "x.tar (1).gz"),
+            "x.tar (2).gz"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_entries() -> io::Result<()> {
+        use crate::error::SkippedEntry;
+        use crate::writer::{EntryMetadata, NewEntry, ZipWriter};
+
+        let copy = |name: &str, flags: u16, compression_method: u16| NewEntry::Copy {
+            entry: ZipFileEntry {
+                filename: name.to_string(),
+                flags,
+                compression_method,
+                ..Default::default()
+            },
+            data: vec![0xa5; 40],
+            metadata: EntryMetadata::default(),
+        };
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        writer.add_file("a.txt", b"plain", &EntryMetadata::default())?;
+        let jobs = [copy("secret.txt", FLAG_ENCRYPTED, 8), copy("b.ppmd", 0, 98)];
+        writer.add_parallel(jobs.map(|job| move || Ok(job)), 1)?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+        let mut archive = ZipArchive::new(io::Cursor::new(bytes.clone()))?;
+
+        let mut extract = |unsupported: UnsupportedPolicy| {
+            archive.set_options(ExtractOptions {
+                unsupported,
+                ..Default::default()
+            });
+            let mut sink: MemorySink = MemorySink::new();
+            let result: io::Result<()> = archive.extract_to(&mut sink);
+            (result, sink.into_entries())
+        };
+        // Skipping an encrypted entry would leave it out without a word
+        for policy in [UnsupportedPolicy::Warn, UnsupportedPolicy::Error] {
+            let (result, entries) = extract(policy);
+            assert_eq!(entries.keys().collect::<Vec<_>>(), ["a.txt"]);
+            let err: io::Error = result.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(
+                err.to_string(),
+                "Can't extract secret.txt: encrypted with ZipCrypto, password required"
+            );
+        }
+
+        let (result, entries) = extract(UnsupportedPolicy::Collect);
+        assert_eq!(entries.len(), 1);
+        let err: io::Error = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let Some(XpackError::SkippedEntries { skipped }) = XpackError::from_io(&err) else {
+            panic!("expected skipped entries, got {}", err);
+        };
+        assert_eq!(
+            skipped,
+            &[
+                SkippedEntry {
+                    entry: "secret.txt".to_string(),
+                    reason: "encrypted with ZipCrypto, password required".to_string(),
+                },
+                SkippedEntry {
+                    entry: "b.ppmd".to_string(),
+                    reason: "unsupported compression method 98".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            err.to_string(),
+            "Skipped 2 entries that can't be extracted:\n  \
+             secret.txt: encrypted with ZipCrypto, password required\n  \
+             b.ppmd: unsupported compression method 98"
+        );
+
+        // Read as a stream, the entries are skipped the same way
+        let mut stream = crate::stream::ZipStreamReader::new(&bytes[..]);
+        stream.set_options(ExtractOptions {
+            unsupported: UnsupportedPolicy::Collect,
+            ..Default::default()
+        });
+        let err: io::Error = stream.extract_to(&mut MemorySink::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("b.ppmd: unsupported compression method 98"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_flags() {
+        let entry = |flags: u16, extra_fields: Vec<ExtraField>| ZipFileEntry {
+            flags,
+            extra_fields,
+            ..Default::default()
+        };
+        let aes: ExtraField = ExtraField::Aes {
+            vendor_version: 2,
+            strength: 3,
+            compression_method: 8,
+        };
+        assert_eq!(
+            entry(FLAG_ENCRYPTED, vec![aes]).encryption().to_string(),
+            "AES-256"
+        );
+        assert_eq!(
+            entry(FLAG_ENCRYPTED | FLAG_STRONG_ENCRYPTION, vec![]).unsupported_reason(),
+            Some("encrypted with PKWARE strong encryption, password required".to_string())
+        );
+        let patched: ZipFileEntry = entry(FLAG_PATCHED_DATA | FLAG_UTF8, vec![]);
+        assert!(patched.is_patched() && patched.has_utf8_name());
+        assert_eq!(
+            patched.unsupported_reason(),
+            Some("patched data, which needs the file it patches".to_string())
+        );
+        assert!(!entry(0, vec![]).is_patched() && !entry(0, vec![]).has_utf8_name());
+    }
+
+    #[test]
+    fn test_filename_encodings() -> io::Result<()> {
+        // UTF-8 flagged name, CP437 name and CP437 name with a 0x7075 field
+        let test_path = get_test_file_path("test_encodings.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let names: Vec<String> = archive
+            .entries()
+            .map(|e| e.map(|e| e.filename))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(names, ["naïve.txt", "café.txt", "über.txt"]);
+
+        let mut walked: Vec<String> = vec![];
+        walk_central_directory(File::open(&test_path)?, |record| {
+            walked.push(record.decoded_filename().into_owned());
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(walked, names);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_round_trip() -> io::Result<()> {
+        let data: Vec<u8> = fs::read(get_test_file_path("test_metadata.zip"))?;
+        let archive = ZipArchive::new_readonly(io::Cursor::new(data.clone()))?;
+        let parsed: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        let mut index: Vec<u8> = vec![];
+        archive.write_index(&mut index)?;
+
+        let mut reopened = ZipArchive::new_readonly(io::Cursor::new(data.clone()))?;
+        reopened.load_index(&mut index.as_slice())?;
+        let loaded: Vec<ZipFileEntry> = reopened.entries().collect::<io::Result<_>>()?;
+        assert_eq!(loaded, parsed);
+        let name: &str = &parsed[0].filename;
+        assert_eq!(
+            reopened.by_name(name)?.read_to_vec()?,
+            archive.by_name(name)?.read_to_vec()?
+        );
+
+        // Any change to the central directory makes the index stale
+        let mut changed: Vec<u8> = data;
+        let directory: usize = archive.directory.offset as usize;
+        changed[directory + 46] ^= 0x20;
+        let mut other = ZipArchive::new_readonly(io::Cursor::new(changed))?;
+        let e: io::Error = other.load_index(&mut index.as_slice()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(other.entries().next().unwrap()?.filename != *name);
+        assert!(reopened.load_index(&mut &index[..20]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_index() -> io::Result<()> {
+        let data: Vec<u8> = fs::read(get_test_file_path("test_metadata.zip"))?;
+        let mut archive = ZipArchive::new_readonly(io::Cursor::new(data))?;
+        let mut index: Vec<u8> = vec![];
+        archive.write_index(&mut index)?;
+        // The modification time of the first entry follows the 34-byte
+        // header and the fixed fields of its record
+        assert_eq!(index[68], 1);
+        for (secs, nanos) in [
+            (u64::MAX, 1_000_000_000u32),
+            (0, 1_000_000_000),
+            (u64::MAX, 0),
+        ] {
+            let mut crafted: Vec<u8> = index.clone();
+            crafted[69..77].copy_from_slice(&secs.to_le_bytes());
+            crafted[77..81].copy_from_slice(&nanos.to_le_bytes());
+            let e: io::Error = archive.load_index(&mut crafted.as_slice()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip64_sizes() {
+        let entry = |uncompressed_size: u32, compressed_size: u32, values: Vec<u64>| ZipFileEntry {
+            uncompressed_size,
+            compressed_size,
+            extra_fields: vec![ExtraField::Zip64 {
+                values,
+                disk_start: None,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(entry(10, 5, vec![]).sizes(), (10, 5));
+        assert_eq!(
+            entry(u32::MAX, u32::MAX, vec![5 << 32, 1 << 32]).sizes(),
+            (5 << 32, 1 << 32)
+        );
+        // Only the compressed size overflowed
+        assert_eq!(entry(10, u32::MAX, vec![1 << 32]).sizes(), (10, 1 << 32));
+    }
+
+    #[test]
+    fn test_entry_metadata() -> io::Result<()> {
+        // A text file with a UTF-8 comment made on Unix, a directory made on
+        // MS-DOS and a symlink with a CP437 comment
+        let test_path = get_test_file_path("test_metadata.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        let [notes, docs, link] = &entries[..] else {
+            panic!("expected 3 entries, got {}", entries.len());
+        };
+
+        assert_eq!(notes.version_made_by, 3 << 8 | 20);
+        assert_eq!(notes.version_needed, 20);
+        assert_eq!(notes.internal_attributes, 1);
+        assert_eq!(notes.comment, "première note");
+        assert_eq!(notes.method(), CompressionMethod::Deflated);
+        assert_eq!(notes.encryption(), Encryption::None);
+        assert!(!notes.is_dir() && !notes.is_symlink());
+
+        assert_eq!(docs.version_made_by >> 8, 0);
+        assert!(docs.is_dir() && !docs.is_symlink());
+
+        assert_eq!(link.comment, "café");
+        assert_eq!(link.method(), CompressionMethod::Stored);
+        assert!(link.is_symlink() && !link.is_dir());
+
+        // AES entries record method 99 and the real one in the extra field
+        let aes: ZipFileEntry = ZipFileEntry {
+            flags: FLAG_ENCRYPTED,
+            compression_method: AES_METHOD,
+            extra_fields: vec![ExtraField::Aes {
+                vendor_version: 2,
+                strength: 3,
+                compression_method: 8,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(aes.method(), CompressionMethod::Deflated);
+        assert_eq!(aes.encryption(), Encryption::Aes { strength: 3 });
+        assert_eq!(CompressionMethod::from_id(14).name(), "lzma");
+        assert_eq!(CompressionMethod::from_id(7).id(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_from_memory() -> io::Result<()> {
+        let bytes: Vec<u8> = fs::read(get_test_file_path("test_multiple.zip"))?;
+        let archive = ZipArchive::new(io::Cursor::new(bytes))?;
+        let names: Vec<String> = archive
+            .entries()
+            .map(|e| e.map(|e| e.filename))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(names, ["test1.txt", "test2.txt"]);
+        assert_eq!(
+            archive.by_name("test1.txt")?.read_to_vec()?.unwrap(),
+            b"this is test1\n"
+        );
+        archive.test()
+    }
+
+    #[test]
+    fn test_entries_are_parsed_lazily() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let mut entries = archive.entries();
+        assert_eq!(entries.next().unwrap()?.filename, "test1.txt");
+        assert_eq!(entries.next().unwrap()?.filename, "test2.txt");
+        assert!(entries.next().is_none());
+        assert!(entries.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_timeout() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let mut archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        archive.set_options(ExtractOptions {
+            entry_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        });
+
+        let err = archive.test().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        match XpackError::from_io(&err) {
+            Some(XpackError::Timeout {
+                entry, per_entry, ..
+            }) => {
+                assert_eq!(entry, "test1.txt");
+                assert!(per_entry);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        // Limits past what an Instant can hold never expire
+        let never: Option<Deadline> = Some(Deadline::after(Duration::MAX, false));
+        let soon: Option<Deadline> = Some(Deadline::after(Duration::ZERO, true));
+        assert!(Deadline::earliest(never, soon).is_some_and(|deadline| deadline.per_entry));
+        assert!(Deadline::earliest(soon, never).is_some_and(|deadline| deadline.per_entry));
+        archive.set_options(ExtractOptions {
+            timeout: Some(Duration::MAX),
+            entry_timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        });
+        archive.test()
+    }
+
+    #[test]
+    fn test_cancellation() -> io::Result<()> {
+        // Cancels once the first entry is received
+        struct CancellingSink(CancellationToken, Vec<String>);
+        impl Sink for CancellingSink {
+            fn is_read_only(&self) -> bool {
+                true
+            }
+
+            fn write_entry(&mut self, entry: &ZipFileEntry, _: &[u8]) -> io::Result<()> {
+                self.1.push(entry.filename.clone());
+                self.0.cancel();
+                Ok(())
+            }
+        }
+
+        let test_path = get_test_file_path("test_multiple.zip");
+        let mut archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let token: CancellationToken = CancellationToken::new();
+        archive.set_options(ExtractOptions {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        });
+        let mut sink = CancellingSink(token, vec![]);
+        let err = archive.extract_to(&mut sink).unwrap_err();
+        match XpackError::from_io(&err) {
+            Some(XpackError::Cancelled { entry }) => assert_eq!(entry, "test2.txt"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(sink.1, ["test1.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_by_name() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let archive = ZipArchive::open(test_path.to_str().unwrap())?;
+
+        let entry = archive.by_name("test2.txt")?;
+        assert_eq!(entry.name(), "test2.txt");
+        assert_eq!(entry.read_to_vec()?.unwrap(), b"this is test2\n");
+
+        let err = archive.by_name("missing.txt").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_reader() -> io::Result<()> {
+        use crate::writer::{Compression, EntryMetadata, ZipWriter};
+
+        let data: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut writer =
+            ZipWriter::new(io::Cursor::new(Vec::new())).with_rule("*.raw", Compression::stored());
+        writer.add_file("a.raw", &data, &EntryMetadata::default())?;
+        writer.add_file("b.bin", &data, &EntryMetadata::default())?;
+        let bytes: Vec<u8> = writer.finish()?.into_inner();
+        let archive = ZipArchive::new(io::Cursor::new(bytes.clone()))?;
+
+        for name in ["a.raw", "b.bin"] {
+            let entry = archive.by_name(name)?;
+            let mut reader = entry.reader()?;
+            let mut buf: [u8; 8] = [0u8; 8];
+            reader.seek(SeekFrom::Start(4 * 70_000))?;
+            reader.read_exact(&mut buf)?;
+            assert_eq!(buf, [0x70, 0x11, 1, 0, 0x71, 0x11, 1, 0], "{}", name);
+            // Back to the start, and the whole data from there
+            reader.seek(SeekFrom::End(-(data.len() as i64)))?;
+            let mut all: Vec<u8> = vec![];
+            reader.read_to_end(&mut all)?;
+            assert!(all == data, "{}", name);
+            assert_eq!(reader.stream_position()?, data.len() as u64);
+        }
+
+        // The CRC is checked once the data has been read in order
+        let mut archive = ZipArchive::new(io::Cursor::new(bytes))?;
+        let mut entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        entries[0].crc32 ^= 1;
+        let mut reader = EntryReader::new(&archive, &entries[0])?;
+        let err: io::Error = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), "CRC mismatch");
+        drop(reader);
+
+        archive.set_options(ExtractOptions {
+            codecs: Codecs::empty(),
+            ..Default::default()
+        });
+        let err = archive.by_name("b.bin")?.reader().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_all_to_memory() -> io::Result<()> {
+        let test_path = get_test_file_path("test_multiple.zip");
+        let mut archive = ZipArchive::open_readonly(test_path.to_str().unwrap())?;
+        let entries: HashMap<String, Vec<u8>> = archive.extract_all_to_memory()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["test1.txt"], b"this is test1\n");
+
+        // Both entries are 14 bytes, so a 20 byte cap fails before decoding
+        archive.set_options(ExtractOptions {
+            max_total_size: Some(20),
+            ..Default::default()
+        });
+        assert!(archive.extract_all_to_memory().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_readahead() -> io::Result<()> {
+        use crate::writer::{EntryMetadata, NewEntry, ZipWriter};
+
+        let data: Vec<u8> = (0..20_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for name in ["a.bin", "b.bin", "c/d.bin"] {
+            writer.add_file(name, &data, &EntryMetadata::default())?;
+        }
+        let corrupt = NewEntry::Copy {
+            entry: ZipFileEntry {
+                filename: "e.bin".to_string(),
+                compression_method: 8,
+                ..Default::default()
+            },
+            data: vec![0xff; 64],
+            metadata: EntryMetadata::default(),
+        };
+        writer.add_parallel([move || Ok(corrupt)], 1)?;
+        let mut archive = ZipArchive::new(io::Cursor::new(writer.finish()?.into_inner()))?;
+
+        struct Received(Vec<(String, Vec<u8>)>);
+
+        impl Sink for Received {
+            fn is_read_only(&self) -> bool {
+                true
+            }
+
+            fn write_entry(&mut self, entry: &ZipFileEntry, data: &[u8]) -> io::Result<()> {
+                self.0.push((entry.filename.clone(), data.to_vec()));
+                Ok(())
+            }
+        }
+
+        // Whether everything fits ahead or nothing does, entries arrive in
+        // order, and decoding errors end the extraction
+        for readahead in [1, 1 << 20] {
+            archive.set_options(ExtractOptions {
+                readahead: Some(readahead),
+                ..Default::default()
+            });
+            let mut sink: Received = Received(vec![]);
+            let err: io::Error = archive.extract_to(&mut sink).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(sink.0.len(), 3);
+            for (received, name) in sink.0.iter().zip(["a.bin", "b.bin", "c/d.bin"]) {
+                assert!(received.0 == name && received.1 == data, "{}", name);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_matches_seek_reader() -> io::Result<()> {
+        for filename in [
+            "test_multiple.zip",
+            "test_stored_descriptor.zip",
+            "test_encodings.zip",
+            "test_sfx.exe",
+        ] {
+            let test_path = get_test_file_path(filename);
+            let mapped = ZipArchive::open_mmap_readonly(test_path.to_str().unwrap())?;
+            assert!(mapped.map.is_some());
+            let read = ZipArchive::open_readonly(test_path.to_str().unwrap())?;
+            let names = |archive: &ZipArchive| -> io::Result<Vec<String>> {
+                archive.entries().map(|e| Ok(e?.filename)).collect()
+            };
+            assert_eq!(names(&mapped)?, names(&read)?, "{}", filename);
+            assert_eq!(
+                mapped.extract_all_to_memory()?,
+                read.extract_all_to_memory()?,
+                "{}",
+                filename
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompression_bomb_limits() -> io::Result<()> {
+        // zeros.bin inflates 4 MB from about 4 KB
+        let test_path = get_test_file_path("test_bomb.zip");
+        let mut archive = ZipArchive::open(test_path.to_str().unwrap())?;
+        let limit_hit = |archive: &ZipArchive| match archive.test() {
+            Err(e) => match XpackError::from_io(&e) {
+                Some(XpackError::LimitExceeded { entry, limit }) => (entry.clone(), *limit),
+                _ => panic!("unexpected error: {}", e),
+            },
+            Ok(()) => panic!("bomb was not stopped"),
+        };
+
+        for (options, limit) in [
+            (
+                ExtractOptions {
+                    max_ratio: Some(100.0),
+                    ..Default::default()
+                },
+                Limit::Ratio(100.0),
+            ),
+            (
+                ExtractOptions {
+                    max_entry_size: Some(1 << 20),
+                    ..Default::default()
+                },
+                Limit::EntrySize(1 << 20),
+            ),
+            (
+                ExtractOptions {
+                    max_total_size: Some(4 << 20),
+                    ..Default::default()
+                },
+                Limit::TotalSize(4 << 20),
+            ),
+        ] {
+            archive.set_options(options);
+            assert_eq!(limit_hit(&archive), ("zeros.bin".to_string(), limit));
+        }
+
+        archive.set_options(ExtractOptions {
+            max_entry_size: Some(4 << 20),
+            max_ratio: Some(2000.0),
+            ..Default::default()
+        });
+        archive.test()
+    }
+
+    #[test]
+    #[should_panic(expected = "No such file or directory")]
+    fn test_non_existent_archive() {
+        let test_path = get_test_file_path("non_existent.zip");
+        let mut f = File::open(test_path).unwrap();
+        let res: Result<Option<CentralDirectory>, io::Error> = read_end_central_dir(&mut f);
+        read_central_directory(&mut f, res.unwrap()).unwrap();
+    }
+}
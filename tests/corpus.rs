@@ -0,0 +1,157 @@
+//! Archives made by real-world tools, with the quirks readers trip on, read
+//! through every ZIP reader from memory and checked against their known
+//! contents.
+
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+use xpack::{ZipArchive, ZipFileEntry, ZipStreamReader};
+
+struct Case {
+    file: &'static str,
+    /// Every entry, in the order of the central directory
+    entries: &'static [(&'static str, &'static [u8])],
+    /// Whether the archive can be read front to back: a self-extractor
+    /// starts with its program, which the stream reader doesn't skip
+    streamable: bool,
+}
+
+const CORPUS: [Case; 8] = [
+    // zip -fz: Zip64 sizes in every header and a Zip64 end of central
+    // directory record holding the directory offset
+    Case {
+        file: "test_zip64.zip",
+        entries: &[
+            ("a.txt", b"zip64 forced\n"),
+            ("d/b.txt", b"second entry, deflated deflated deflated\n"),
+        ],
+        streamable: true,
+    },
+    // Python's zipfile with force_zip64: Zip64 sizes in the local header
+    // only
+    Case {
+        file: "test_zip64_local.zip",
+        entries: &[("big.txt", HELLO_600)],
+        streamable: true,
+    },
+    // Python's zipfile with ZIP64_LIMIT lowered to 16: local header offsets
+    // in the Zip64 extra field of the central directory records
+    Case {
+        file: "test_zip64_offsets.zip",
+        entries: &[
+            ("first.txt", b"offsets past the Zip64 limit\n"),
+            (
+                "dir/second.txt",
+                b"second entry, second entry, second entry\n",
+            ),
+            ("third.txt", b"third\n"),
+        ],
+        streamable: true,
+    },
+    // Python's zipfile with force_zip64 writing to a pipe: data descriptors
+    // with 8-byte sizes, after deflated and stored data
+    Case {
+        file: "test_zip64_descriptor.zip",
+        entries: &[
+            ("deflated.txt", STREAMED_3),
+            ("stored.txt", b"stored, zip64 descriptor\n"),
+        ],
+        streamable: true,
+    },
+    // Data descriptor without its optional signature
+    Case {
+        file: "test_descriptor_nosig.zip",
+        entries: &[("nosig.txt", b"descriptor without signature\n")],
+        streamable: true,
+    },
+    // Stored entries with data descriptors, one holding a fake descriptor
+    // signature
+    Case {
+        file: "test_stored_descriptor.zip",
+        entries: &[
+            ("a.txt", b"stored with descriptor\n"),
+            ("b.bin", b"PK\x07\x08 not a descriptor\n"),
+        ],
+        streamable: true,
+    },
+    // A UTF-8 flagged name, a CP437 name, and a CP437 name overridden by
+    // an Info-ZIP Unicode path field
+    Case {
+        file: "test_encodings.zip",
+        entries: &[
+            ("naïve.txt", b"utf8\n"),
+            ("café.txt", b"cp437\n"),
+            ("über.txt", b"unicode path\n"),
+        ],
+        streamable: true,
+    },
+    // Archive behind a self-extractor stub, with offsets not adjusted
+    Case {
+        file: "test_sfx.exe",
+        entries: &[
+            ("test1.txt", b"this is test1\n"),
+            ("test2.txt", b"this is test2\n"),
+        ],
+        streamable: false,
+    },
+];
+
+const STREAMED_3: &[u8] = b"streamed with a zip64 data descriptor\n\
+streamed with a zip64 data descriptor\n\
+streamed with a zip64 data descriptor\n";
+
+const HELLO_600: &[u8] = b"hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello \
+hello hello hello hello hello hello hello hello hello hello hello hello hello hello hello ";
+
+fn get_test_file_path(filename: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("test_files");
+    path.push(filename);
+    path
+}
+
+#[test]
+fn test_corpus_central_directory() -> io::Result<()> {
+    for case in &CORPUS {
+        let bytes: Vec<u8> = fs::read(get_test_file_path(case.file))?;
+        let archive: ZipArchive<Cursor<Vec<u8>>> = ZipArchive::new(Cursor::new(bytes))?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<io::Result<_>>()?;
+        let read: Vec<(String, Option<Vec<u8>>)> = entries
+            .iter()
+            .map(|entry| Ok((entry.filename.clone(), archive.read(entry)?)))
+            .collect::<io::Result<_>>()?;
+        let expected: Vec<(String, Option<Vec<u8>>)> = case
+            .entries
+            .iter()
+            .map(|(name, data)| (name.to_string(), Some(data.to_vec())))
+            .collect();
+        assert_eq!(read, expected, "{}", case.file);
+        assert_eq!(archive.check_local_headers()?, [], "{}", case.file);
+        archive.test()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_corpus_stream() -> io::Result<()> {
+    for case in CORPUS.iter().filter(|case| case.streamable) {
+        let bytes: Vec<u8> = fs::read(get_test_file_path(case.file))?;
+        let mut stream: ZipStreamReader<&[u8]> = ZipStreamReader::new(&bytes[..]);
+        let mut read: Vec<(String, Option<Vec<u8>>)> = vec![];
+        while let Some((entry, data)) = stream.next_entry()? {
+            read.push((entry.filename, data));
+        }
+        let expected: Vec<(String, Option<Vec<u8>>)> = case
+            .entries
+            .iter()
+            .map(|(name, data)| (name.to_string(), Some(data.to_vec())))
+            .collect();
+        assert_eq!(read, expected, "{}", case.file);
+    }
+    Ok(())
+}
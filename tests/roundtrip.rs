@@ -0,0 +1,342 @@
+//! Property-based round trips: random file trees are packed into ZIP and
+//! tar archives in memory, read back and compared byte for byte.
+//!
+//! Failing cases are shrunk by proptest and saved under
+//! `tests/roundtrip.proptest-regressions`, which later runs try first.
+//! `PROPTEST_CASES` sets how many cases each property runs, 64 by default.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::select;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xpack::tar::{TarArchive, TarEntryKind, TarHeader, TarWriter};
+use xpack::{
+    Compression, EntryMetadata, Method, NewEntry, ZipArchive, ZipFileEntry, ZipStreamReader,
+    ZipWriter,
+};
+
+const DEFAULT_CASES: u32 = 64;
+
+#[derive(Debug, Clone)]
+enum Node {
+    File {
+        name: String,
+        data: Vec<u8>,
+        metadata: EntryMetadata,
+    },
+    Directory {
+        name: String,
+        metadata: EntryMetadata,
+    },
+}
+
+impl Node {
+    fn name(&self) -> &str {
+        match self {
+            Node::File { name, .. } | Node::Directory { name, .. } => name,
+        }
+    }
+
+    fn metadata(&self) -> &EntryMetadata {
+        match self {
+            Node::File { metadata, .. } | Node::Directory { metadata, .. } => metadata,
+        }
+    }
+}
+
+const SEGMENTS: [&str; 12] = [
+    "a",
+    "src",
+    "read me",
+    ".hidden",
+    "café",
+    "naïve",
+    "日本語",
+    "über",
+    "x.tar.gz",
+    "🦀",
+    "Z",
+    "long-name-",
+];
+
+fn name() -> impl Strategy<Value = String> {
+    let segment = (
+        select(&SEGMENTS[..]),
+        prop::option::weighted(0.67, 0..100u32),
+    )
+        .prop_map(|(segment, number)| match number {
+            Some(number) => format!("{}{}", segment, number),
+            None => segment.to_string(),
+        });
+    vec(segment, 1..=4).prop_map(|segments| segments.join("/"))
+}
+
+/// Random bytes of a length in `len`, drawn as words, which is much
+/// faster than byte by byte for the larger files.
+fn bytes(len: Range<usize>) -> impl Strategy<Value = Vec<u8>> {
+    len.prop_flat_map(|len| {
+        vec(any::<u64>(), len.div_ceil(8)).prop_map(move |words| {
+            let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+            bytes.truncate(len);
+            bytes
+        })
+    })
+}
+
+fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        Just(vec![]),
+        bytes(0..4096),
+        (
+            select(&["hello ", "xpack\n", "0123456789", "\u{e9}t\u{e9} "][..]),
+            0..5000usize
+        )
+            .prop_map(|(word, count)| word.repeat(count).into_bytes()),
+        (0..70_000usize).prop_map(|len| vec![0; len]),
+        // Compressible and incompressible parts together
+        (0..20_000usize, bytes(0..20_000)).prop_map(|(len, random)| {
+            let mut data: Vec<u8> = vec![b'a'; len];
+            data.extend(random);
+            data
+        }),
+        bytes(1..300_000),
+    ]
+}
+
+fn metadata() -> impl Strategy<Value = EntryMetadata> {
+    // Between 1980, the first MS-DOS date, and 2038, the last the extended
+    // timestamp field records to the second
+    (
+        315_532_800..i32::MAX as u64,
+        select(&[0o644, 0o600, 0o755, 0o700, 0o444, 0o777][..]),
+        prop::option::of((0..70_000u32, 0..70_000u32)),
+    )
+        .prop_map(|(seconds, permissions, owner)| EntryMetadata {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(seconds)),
+            permissions: Some(permissions),
+            owner,
+        })
+}
+
+/// A tree of up to 40 entries. Every name is unique and no file is the
+/// parent of another entry; directories are listed or left implied.
+fn tree() -> impl Strategy<Value = Vec<Node>> {
+    let node = (name(), prop::bool::weighted(0.2), data(), metadata());
+    vec(node, 0..40).prop_map(|candidates| {
+        let mut nodes: Vec<Node> = vec![];
+        let mut files: HashSet<String> = HashSet::new();
+        let mut directories: HashSet<String> = HashSet::new();
+        for (name, directory, data, metadata) in candidates {
+            let parents: Vec<&str> = name.match_indices('/').map(|(i, _)| &name[..i]).collect();
+            if files.contains(&name)
+                || directories.contains(&name)
+                || parents.iter().any(|parent| files.contains(*parent))
+            {
+                continue;
+            }
+            directories.extend(parents.iter().map(|parent| parent.to_string()));
+            if directory {
+                directories.insert(name.clone());
+                nodes.push(Node::Directory {
+                    name: format!("{}/", name),
+                    metadata,
+                });
+            } else {
+                files.insert(name.clone());
+                nodes.push(Node::File {
+                    name,
+                    data,
+                    metadata,
+                });
+            }
+        }
+        nodes
+    })
+}
+
+fn compression() -> impl Strategy<Value = Compression> {
+    let mut methods: Vec<Method> = vec![Method::Store, Method::Deflate];
+    if cfg!(feature = "zstd") {
+        methods.push(Method::Zstd);
+    }
+    (select(methods), prop::option::weighted(0.7, 0..19u32)).prop_map(|(method, level)| {
+        let level: Option<u32> = level.map(|level| match method {
+            Method::Zstd => 1 + level,
+            _ => level % 10,
+        });
+        Compression::new(method, level)
+    })
+}
+
+/// How [`pack_zip`] writes an archive.
+#[derive(Debug, Clone)]
+struct ZipOptions {
+    compression: Compression,
+    store_compressed: bool,
+    reproducible: bool,
+    /// Threads for [`ZipWriter::add_parallel`], or entries added one by one
+    threads: Option<usize>,
+}
+
+fn zip_options() -> impl Strategy<Value = ZipOptions> {
+    (
+        compression(),
+        any::<bool>(),
+        prop::bool::weighted(0.2),
+        prop::option::of(1..=4usize),
+    )
+        .prop_map(
+            |(compression, store_compressed, reproducible, threads)| ZipOptions {
+                compression,
+                store_compressed,
+                reproducible,
+                threads,
+            },
+        )
+}
+
+fn pack_zip(tree: &[Node], options: &ZipOptions) -> std::io::Result<Vec<u8>> {
+    let mut writer: ZipWriter<Vec<u8>> = ZipWriter::new(vec![])
+        .with_compression(options.compression)
+        .with_store_compressed(options.store_compressed)
+        .with_reproducible(options.reproducible);
+    if let Some(threads) = options.threads {
+        let jobs = tree.iter().map(|node| {
+            move || {
+                Ok(match node.clone() {
+                    Node::File {
+                        name,
+                        data,
+                        metadata,
+                    } => NewEntry::File {
+                        name,
+                        data,
+                        metadata,
+                    },
+                    Node::Directory { name, metadata } => NewEntry::Directory { name, metadata },
+                })
+            }
+        });
+        writer.add_parallel(jobs, threads)?;
+    } else {
+        for node in tree {
+            match node {
+                Node::File {
+                    name,
+                    data,
+                    metadata,
+                } => writer.add_file(name, data, metadata)?,
+                Node::Directory { name, metadata } => writer.add_directory(name, metadata)?,
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Checks an entry read back against the node it was packed from.
+fn compare_zip_entry(
+    node: &Node,
+    entry: &ZipFileEntry,
+    data: Option<&[u8]>,
+    reproducible: bool,
+) -> Result<(), TestCaseError> {
+    prop_assert_eq!(&entry.filename, node.name());
+    if let Node::File { data: expected, .. } = node {
+        prop_assert!(
+            data == Some(&expected[..]),
+            "{} has different contents",
+            entry.filename
+        );
+    }
+    if reproducible {
+        return Ok(());
+    }
+    let metadata: &EntryMetadata = node.metadata();
+    prop_assert_eq!(entry.unix_permissions(), metadata.permissions);
+    prop_assert_eq!(entry.precise_modified(), metadata.modified);
+    prop_assert_eq!(entry.unix_owner(), metadata.owner);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(
+        std::env::var("PROPTEST_CASES")
+            .ok()
+            .and_then(|cases| cases.parse().ok())
+            .unwrap_or(DEFAULT_CASES)
+    ))]
+
+    #[test]
+    fn test_zip_round_trip(tree in tree(), options in zip_options()) {
+        let bytes: Vec<u8> = pack_zip(&tree, &options)?;
+
+        // Through the central directory
+        let archive: ZipArchive<Cursor<&[u8]>> = ZipArchive::new(Cursor::new(&bytes[..]))?;
+        let entries: Vec<ZipFileEntry> = archive.entries().collect::<std::io::Result<_>>()?;
+        prop_assert_eq!(entries.len(), tree.len());
+        for (node, entry) in tree.iter().zip(&entries) {
+            let data: Option<Vec<u8>> = archive.read(entry)?;
+            compare_zip_entry(node, entry, data.as_deref(), options.reproducible)?;
+        }
+        archive.test()?;
+
+        // Through the local headers, front to back
+        let mut stream: ZipStreamReader<&[u8]> = ZipStreamReader::new(&bytes[..]);
+        for node in &tree {
+            let streamed: Option<(ZipFileEntry, Option<Vec<u8>>)> = stream.next_entry()?;
+            let Some((entry, data)) = streamed else {
+                return Err(TestCaseError::fail(format!("Stream ends before {}", node.name())));
+            };
+            prop_assert_eq!(&entry.filename, node.name());
+            if let Node::File { data: expected, .. } = node {
+                prop_assert!(
+                    data.as_deref() == Some(&expected[..]),
+                    "{} streamed with different contents",
+                    entry.filename
+                );
+            }
+        }
+        prop_assert!(stream.next_entry()?.is_none(), "Stream has more entries");
+    }
+
+    #[test]
+    fn test_tar_round_trip(tree in tree()) {
+        let mut writer: TarWriter<Vec<u8>> = TarWriter::new(vec![]);
+        for node in &tree {
+            match node {
+                Node::File {
+                    name,
+                    data,
+                    metadata,
+                } => writer.add_file(name, data, metadata)?,
+                Node::Directory { name, metadata } => writer.add_directory(name, metadata)?,
+            }
+        }
+        let bytes: Vec<u8> = writer.finish()?;
+
+        let mut archive: TarArchive<&[u8]> = TarArchive::new(&bytes[..]);
+        for node in &tree {
+            let Some(header) = archive.next_header()? else {
+                return Err(TestCaseError::fail(format!("Archive ends before {}", node.name())));
+            };
+            let header: TarHeader = header;
+            prop_assert_eq!(&header.path, node.name());
+            let metadata: &EntryMetadata = node.metadata();
+            let modified: Option<SystemTime> = Some(UNIX_EPOCH + Duration::from_secs(header.mtime));
+            prop_assert_eq!(modified, metadata.modified);
+            prop_assert_eq!(Some(header.mode & 0o7777), metadata.permissions);
+            let data: Vec<u8> = archive.read_data()?;
+            match node {
+                Node::File { data: expected, .. } => {
+                    prop_assert_eq!(header.kind, TarEntryKind::File);
+                    prop_assert!(data == *expected, "{} has different contents", header.path);
+                }
+                Node::Directory { .. } => prop_assert_eq!(header.kind, TarEntryKind::Directory),
+            }
+        }
+        prop_assert!(archive.next_header()?.is_none(), "Archive has more entries");
+    }
+}